@@ -1,23 +1,115 @@
 use std::fs;
 
 use criterion::{criterion_group, criterion_main, Criterion, Throughput};
-use png::Decoder;
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
 
 fn load_all(c: &mut Criterion) {
-    for file in fs::read_dir("tests/benches/").unwrap() {
-        if let Ok(entry) = file {
-            match entry.path().extension() {
-                Some(st) if st == "png" => {}
-                _ => continue,
-            }
+    for entry in fs::read_dir("tests/benches/").unwrap().flatten() {
+        match entry.path().extension() {
+            Some(st) if st == "png" => {}
+            _ => continue,
+        }
+
+        let data = fs::read(entry.path()).unwrap();
+        bench_file(c, data, entry.file_name().into_string().unwrap());
+    }
+}
 
-            let data = fs::read(entry.path()).unwrap();
-            bench_file(c, data, entry.file_name().into_string().unwrap());
+/// The formats that the images of the corpus do not cover, encoded from one of them.
+fn load_variants(c: &mut Criterion) {
+    let data = fs::read("tests/benches/kodim23.png").unwrap();
+    let mut decoder = Decoder::new(&*data);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info().unwrap();
+    let mut rgb = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgb).unwrap();
+    assert_eq!(info.color_type, ColorType::Rgb);
+    let (width, height) = (info.width, info.height);
+
+    let gray: Vec<u8> = rgb
+        .chunks(3)
+        .map(|p| ((u32::from(p[0]) * 77 + u32::from(p[1]) * 150 + u32::from(p[2]) * 29) >> 8) as u8)
+        .collect();
+    let wide = |samples: &[u8]| -> Vec<u8> { samples.iter().flat_map(|&s| vec![s, s]).collect() };
+    let indexed: Vec<u8> = rgb
+        .chunks(3)
+        .map(|p| (p[0] & 0xe0) | (p[1] >> 3 & 0x1c) | p[2] >> 6)
+        .collect();
+
+    let encode = |data: &[u8], color: ColorType, depth: BitDepth, interlaced: bool| {
+        let mut out = Vec::new();
+        let mut encoder = Encoder::new(&mut out, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        encoder.set_interlaced(interlaced);
+        if color == ColorType::Indexed {
+            let palette = (0..=255u8).flat_map(|i| vec![i & 0xe0, i << 3 & 0xe0, i << 6]);
+            encoder.set_palette(palette.collect::<Vec<u8>>());
         }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        drop(writer);
+        out
+    };
+
+    let variants = [
+        (
+            "gray16",
+            encode(&wide(&gray), ColorType::Grayscale, BitDepth::Sixteen, false),
+        ),
+        (
+            "rgb16",
+            encode(&wide(&rgb), ColorType::Rgb, BitDepth::Sixteen, false),
+        ),
+        (
+            "indexed8",
+            encode(&indexed, ColorType::Indexed, BitDepth::Eight, false),
+        ),
+        (
+            "rgb8-interlaced",
+            encode(&rgb, ColorType::Rgb, BitDepth::Eight, true),
+        ),
+        (
+            "gray8-interlaced",
+            encode(&gray, ColorType::Grayscale, BitDepth::Eight, true),
+        ),
+    ];
+    for (name, data) in variants.iter() {
+        bench_file(c, data.clone(), format!("kodim23-{}", name));
     }
 }
 
-criterion_group!(benches, load_all);
+/// Decode the tiny images of the test suite, for the cost of setting up a decoder.
+fn load_tiny(c: &mut Criterion) {
+    let images: Vec<Vec<u8>> = glob::glob("tests/pngsuite/basn*.png")
+        .unwrap()
+        .map(|path| fs::read(path.unwrap()).unwrap())
+        .collect();
+    let pixels: usize = images
+        .iter()
+        .map(|data| {
+            Decoder::new(&data[..])
+                .read_info()
+                .unwrap()
+                .output_buffer_size()
+        })
+        .sum();
+
+    let mut group = c.benchmark_group("decode-tiny");
+    group.throughput(Throughput::Bytes(pixels as u64));
+    group.bench_function("pngsuite-basn", |b| {
+        b.iter(|| {
+            for data in &images {
+                let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+                let mut image = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut image).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, load_all, load_variants, load_tiny);
 criterion_main!(benches);
 
 fn bench_file(c: &mut Criterion, data: Vec<u8>, name: String) {
@@ -0,0 +1,138 @@
+use std::fs;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use png::{BitDepth, ColorType, Decoder, Encoder, FilterType, Transformations};
+
+fn load_all(c: &mut Criterion) {
+    for entry in fs::read_dir("tests/benches/").unwrap().flatten() {
+        match entry.path().extension() {
+            Some(st) if st == "png" => {}
+            _ => continue,
+        }
+
+        let data = fs::read(entry.path()).unwrap();
+        bench_file(c, data, entry.file_name().into_string().unwrap());
+    }
+}
+
+criterion_group!(benches, load_all);
+criterion_main!(benches);
+
+/// An indexed image like the ones of a palette quantizer.
+struct Indexed {
+    width: u32,
+    height: u32,
+    depth: BitDepth,
+    data: Vec<u8>,
+}
+
+impl Indexed {
+    /// Map the colors of an image to a 3-3-2 palette in arbitrary order, reduced to `depth`.
+    fn quantize(png: &[u8], depth: BitDepth) -> Indexed {
+        let mut decoder = Decoder::new(png);
+        decoder.set_transformations(
+            Transformations::EXPAND | Transformations::STRIP_16 | Transformations::RGBA8,
+        );
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+
+        // The order of the palette of a quantizer does not follow the colors.
+        let mut order: Vec<u8> = (0..=255).collect();
+        let mut seed = 0x5eed_u32;
+        for i in (1..order.len()).rev() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            order.swap(i, (seed >> 16) as usize % (i + 1));
+        }
+
+        let bits = depth as usize;
+        let row_len = (info.width as usize * bits + 7) / 8;
+        let mut data = vec![0; row_len * info.height as usize];
+        let rows = image[..info.buffer_size()].chunks(info.width as usize * 4);
+        for (row, out) in rows.zip(data.chunks_mut(row_len)) {
+            for (x, pixel) in row.chunks(4).enumerate() {
+                let color = (pixel[0] & 0xe0) | (pixel[1] >> 3 & 0x1c) | pixel[2] >> 6;
+                let index = order[usize::from(color)] >> (8 - bits);
+                out[x * bits / 8] |= index << (8 - bits - x * bits % 8);
+            }
+        }
+
+        Indexed {
+            width: info.width,
+            height: info.height,
+            depth,
+            data,
+        }
+    }
+
+    fn encode(&self, filter: Option<FilterType>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = Encoder::new(&mut out, self.width, self.height);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(self.depth);
+        encoder.set_palette(vec![0; 3 << (self.depth as u8)]);
+        if let Some(filter) = filter {
+            encoder.set_filter(filter);
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&self.data).unwrap();
+        drop(writer);
+        out
+    }
+}
+
+/// Encode the pixels of an image with the default settings, in its own color type.
+fn bench_reencode(c: &mut Criterion, data: &[u8], name: &str) {
+    let mut decoder = Decoder::new(data);
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info().unwrap();
+    let mut image = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut image).unwrap();
+
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(info.buffer_size() as u64));
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            let mut encoder = Encoder::new(&mut out, info.width, info.height);
+            encoder.set_color(info.color_type);
+            encoder.set_depth(info.bit_depth);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&image).unwrap();
+            drop(writer);
+            out
+        })
+    });
+    group.finish();
+}
+
+fn bench_file(c: &mut Criterion, data: Vec<u8>, name: String) {
+    bench_reencode(c, &data, &name);
+
+    let filters = [
+        ("default", None),
+        ("sub", Some(FilterType::Sub)),
+        ("paeth", Some(FilterType::Paeth)),
+    ];
+
+    for &depth in [BitDepth::Eight, BitDepth::Two].iter() {
+        let image = Indexed::quantize(&data, depth);
+        let mut group = c.benchmark_group(format!("encode-indexed{}", depth as u8));
+        group.sample_size(20);
+        group.throughput(Throughput::Bytes(image.data.len() as u64));
+
+        for &(filter_name, filter) in filters.iter() {
+            // The compressed size is the figure of merit for the filter choice.
+            let size = image.encode(filter).len();
+            println!(
+                "{} {}-bit {}: {} bytes",
+                name, depth as u8, filter_name, size
+            );
+            group.bench_function(format!("{}/{}", name, filter_name), |b| {
+                b.iter(|| image.encode(filter))
+            });
+        }
+        group.finish();
+    }
+}
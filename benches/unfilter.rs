@@ -0,0 +1,46 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use png::{unfilter_row, BitDepth, ColorType, FilterType, Info};
+
+/// The width of the rows in pixels, that of a large photo.
+const WIDTH: u32 = 4096;
+
+/// Reverse each filter for the pixel sizes that are unfiltered differently.
+fn unfilter_all(c: &mut Criterion) {
+    let formats = [
+        ("gray8", ColorType::Grayscale, BitDepth::Eight),
+        ("graya8", ColorType::GrayscaleAlpha, BitDepth::Eight),
+        ("rgb8", ColorType::Rgb, BitDepth::Eight),
+        ("rgba8", ColorType::Rgba, BitDepth::Eight),
+        ("rgb16", ColorType::Rgb, BitDepth::Sixteen),
+        ("rgba16", ColorType::Rgba, BitDepth::Sixteen),
+    ];
+    let filters = [
+        ("sub", FilterType::Sub),
+        ("up", FilterType::Up),
+        ("avg", FilterType::Avg),
+        ("paeth", FilterType::Paeth),
+    ];
+
+    for &(format_name, color_type, bit_depth) in formats.iter() {
+        let mut info = Info::with_size(WIDTH, 1);
+        info.color_type = color_type;
+        info.bit_depth = bit_depth;
+        let len = info.raw_row_length();
+
+        let mut group = c.benchmark_group(format!("unfilter-{}", format_name));
+        group.throughput(Throughput::Bytes(len as u64 - 1));
+        for &(filter_name, filter) in filters.iter() {
+            let previous: Vec<u8> = (0..len - 1).map(|i| (i * 7 % 251) as u8).collect();
+            let mut row: Vec<u8> = (0..len).map(|i| (i * 13 % 241) as u8).collect();
+            row[0] = filter as u8;
+            // The row is unfiltered in place again and again, which takes the same time.
+            group.bench_function(filter_name, |b| {
+                b.iter(|| unfilter_row(&info, Some(&previous), &mut row).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, unfilter_all);
+criterion_main!(benches);
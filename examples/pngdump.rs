@@ -0,0 +1,38 @@
+//! Print the chunks of PNG files with their fields, to inspect files that fail to decode.
+//!
+//! Usage: `cargo run --example pngdump -- [file ...]`
+use std::env;
+use std::fs::File;
+use std::process;
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("Usage: pngdump [file ...]");
+        process::exit(2);
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        if paths.len() > 1 {
+            println!("{}:", path);
+        }
+        let structure = match File::open(path).map_err(png::DecodingError::from) {
+            Ok(file) => png::Structure::read(file),
+            Err(err) => Err(err),
+        };
+        match structure {
+            Ok(structure) => {
+                failed |= structure.error.is_some();
+                print!("{}", structure);
+            }
+            Err(err) => {
+                failed = true;
+                println!("error: {}", err);
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
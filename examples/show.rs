@@ -53,7 +53,7 @@ fn load_image(path: &path::PathBuf) -> io::Result<RawImage2d<'static, u8>> {
         data: Cow::Owned(data),
         width: info.width,
         height: info.height,
-        format: format,
+        format,
     })
 }
 
@@ -95,7 +95,6 @@ fn main_loop(files: Vec<path::PathBuf>) -> io::Result<()> {
                     ..
                 } => {
                     stop = true;
-                    return;
                 }
                 Event::WindowEvent {
                     event:
@@ -112,7 +111,6 @@ fn main_loop(files: Vec<path::PathBuf>) -> io::Result<()> {
                 } => match code {
                     Some(VirtualKeyCode::Escape) => {
                         stop = true;
-                        return;
                     }
                     Some(VirtualKeyCode::Right) => match files.next() {
                         Some(path) => {
@@ -129,7 +127,6 @@ fn main_loop(files: Vec<path::PathBuf>) -> io::Result<()> {
                         }
                         None => {
                             stop = true;
-                            return;
                         }
                     },
                     _ => (),
@@ -154,8 +151,8 @@ where
     let src_rect = Rect {
         left: 0,
         bottom: 0,
-        width: src_dim.0 as u32,
-        height: src_dim.1 as u32,
+        width: src_dim.0,
+        height: src_dim.1,
     };
     let target_dim = target.get_dimensions();
     let target_rect = BlitTarget {
@@ -173,9 +170,6 @@ fn resize_window(display: &Display, image: &RawImage2d<'static, u8>) {
     if width < 50 && height < 50 {
         width *= 10;
         height *= 10;
-    } else if width < 5 && height < 5 {
-        width *= 10;
-        height *= 10;
     }
     display
         .gl_window()
@@ -192,7 +186,7 @@ fn main() {
         for file in args.iter().skip(1) {
             match if file.contains("*") {
                 (|| -> io::Result<_> {
-                    for entry in glob::glob(&file)
+                    for entry in glob::glob(file)
                         .map_err(|err| io::Error::new(io::ErrorKind::Other, err.msg))?
                     {
                         files.push(
@@ -0,0 +1,89 @@
+//! Decode and encode PNG images in the browser.
+//!
+//! The crate has no C dependencies by default and needs no filesystem, so it runs on
+//! `wasm32-unknown-unknown` as is. Build this example and generate its JavaScript bindings with
+//! [`wasm-bindgen`](https://rustwasm.github.io/docs/wasm-bindgen/):
+//!
+//! ```sh
+//! cargo build --release --example wasm --target wasm32-unknown-unknown
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/examples/wasm.wasm
+//! ```
+//!
+//! Then draw a PNG, fetched or read from a file input as an `ArrayBuffer`, on a canvas:
+//!
+//! ```js
+//! import init, { decode_png } from "./pkg/wasm.js";
+//!
+//! await init();
+//! const response = await fetch("image.png");
+//! const image = decode_png(await response.arrayBuffer());
+//! const data = new ImageData(image.pixels(), image.width, image.height);
+//! canvas.getContext("2d").putImageData(data, 0, 0);
+//! ```
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use js_sys::{ArrayBuffer, Uint8Array, Uint8ClampedArray};
+    use wasm_bindgen::prelude::*;
+
+    /// An image decoded to 8-bit RGBA, the layout of `ImageData`.
+    #[wasm_bindgen]
+    pub struct DecodedImage {
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    }
+
+    #[wasm_bindgen]
+    impl DecodedImage {
+        #[wasm_bindgen(getter)]
+        pub fn width(&self) -> u32 {
+            self.width
+        }
+
+        #[wasm_bindgen(getter)]
+        pub fn height(&self) -> u32 {
+            self.height
+        }
+
+        /// A copy of the pixels, suitable for `new ImageData(pixels, width, height)`.
+        pub fn pixels(&self) -> Uint8ClampedArray {
+            Uint8ClampedArray::from(&self.pixels[..])
+        }
+    }
+
+    /// Decode the first frame of the PNG in `buffer`.
+    #[wasm_bindgen]
+    pub fn decode_png(buffer: &ArrayBuffer) -> Result<DecodedImage, JsValue> {
+        let bytes = Uint8Array::new(buffer).to_vec();
+        let decoder =
+            png::Decoder::new_with_transformations(&bytes[..], png::Transformations::RGBA8);
+        let mut reader = decoder.read_info().map_err(to_js)?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).map_err(to_js)?;
+        pixels.truncate(info.buffer_size());
+        Ok(DecodedImage {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    /// Encode 8-bit RGBA pixels, such as the `data` of an `ImageData`, as a PNG.
+    #[wasm_bindgen]
+    pub fn encode_png(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().map_err(to_js)?;
+            writer.write_image_data(pixels).map_err(to_js)?;
+        }
+        Ok(png)
+    }
+
+    fn to_js<E: std::fmt::Display>(err: E) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
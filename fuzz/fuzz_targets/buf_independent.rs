@@ -40,7 +40,7 @@ impl<R: BufRead> BufRead for SmalBuf<R> {
 
 fuzz_target!(|data: &[u8]| {
     // Small limits, we don't need them hopefully.
-    let limits = png::Limits { bytes: 1 << 16 };
+    let limits = png::Limits { bytes: 1 << 16, ..png::Limits::default() };
 
     let reference = png::Decoder::new_with_limits(data, limits);
     let smal = png::Decoder::new_with_limits(SmalBuf::new(data, 1), limits);
@@ -4,7 +4,7 @@ extern crate png;
 
 #[inline(always)]
 fn png_decode(data: &[u8]) -> Result<(Option<png::OutputInfo>, Vec<u8>), ()> {
-    let limits = png::Limits { bytes: 1 << 16 };
+    let limits = png::Limits { bytes: 1 << 16, ..png::Limits::default() };
     let decoder = png::Decoder::new_with_limits(data, limits);
     let  mut reader = decoder.read_info().map_err(|_| ())?;
 
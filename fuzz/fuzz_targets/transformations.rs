@@ -0,0 +1,50 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate png;
+
+use png::{ChannelOrder, CrcPolicy, Transformations};
+
+/// Decode with the transformations and options selected by the first bytes of the input.
+///
+/// Every malformed input must be reported as an error, also when decoding is continued after it.
+fn png_decode(options: &[u8], data: &[u8]) {
+    let limits = png::Limits {
+        bytes: 1 << 20,
+        pixel_bytes: 1 << 22,
+        ..png::Limits::default()
+    };
+    let mut decoder = png::Decoder::new_with_limits(data, limits);
+    let transform = u32::from(options[0]) | u32::from(options[1]) << 8 | u32::from(options[2]) << 16;
+    decoder.set_transformations(Transformations::from_bits_truncate(transform));
+    let flags = options[3];
+    if flags & 1 != 0 {
+        decoder.set_crc_policy(CrcPolicy::Ignore);
+        decoder.set_ignore_adler32(true);
+    }
+    decoder.set_allow_truncated(flags & 2 != 0);
+    decoder.set_image_stream(flags & 4 != 0);
+    decoder.set_zlib_fallback(flags & 8 != 0);
+    decoder.set_transposed_output(flags & 16 != 0);
+    decoder.set_color_statistics(flags & 32 != 0);
+    if flags & 64 != 0 {
+        decoder.set_channel_order(ChannelOrder::Abgr);
+    }
+
+    let mut reader = match decoder.read_info() {
+        Ok(reader) => reader,
+        Err(_) => return,
+    };
+    let mut buf = vec![0; reader.output_buffer_size()];
+    for _ in 0..4 {
+        if let Ok(info) = reader.next_frame(&mut buf) {
+            assert!(info.buffer_size() <= buf.len());
+        }
+    }
+    while let Ok(Some(_)) = reader.next_row() {}
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() >= 4 {
+        png_decode(&data[..4], &data[4..]);
+    }
+});
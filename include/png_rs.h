@@ -0,0 +1,91 @@
+/*
+ * C interface to the decoder of the `png` crate, enabled by its `capi` feature.
+ *
+ * A decoder is fed the bytes of an image as they arrive. Reading its header or rows returns
+ * PNG_RS_NEED_DATA until enough bytes were fed, then the call is repeated:
+ *
+ *     png_rs_decoder *decoder = png_rs_decoder_new();
+ *     png_rs_info info;
+ *     while (png_rs_decoder_get_info(decoder, &info) == PNG_RS_NEED_DATA)
+ *         png_rs_decoder_feed(decoder, data, read_more(data, sizeof data));
+ *
+ * Functions that fail with PNG_RS_ERROR leave a message in png_rs_decoder_error.
+ */
+#ifndef PNG_RS_H
+#define PNG_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PNG_RS_OK 0
+#define PNG_RS_NEED_DATA 1
+#define PNG_RS_END 2
+#define PNG_RS_ERROR (-1)
+#define PNG_RS_INVALID (-2)
+
+/* The bits of png::Transformations accepted by png_rs_decoder_set_transformations. */
+#define PNG_RS_TRANSFORM_IDENTITY 0x0000
+#define PNG_RS_TRANSFORM_STRIP_16 0x0001
+#define PNG_RS_TRANSFORM_STRIP_ALPHA 0x0002
+#define PNG_RS_TRANSFORM_PACKING 0x0004
+#define PNG_RS_TRANSFORM_PACKSWAP 0x0008
+#define PNG_RS_TRANSFORM_EXPAND 0x0010
+#define PNG_RS_TRANSFORM_INVERT_MONO 0x0020
+#define PNG_RS_TRANSFORM_BGR 0x0080
+#define PNG_RS_TRANSFORM_SWAP_ALPHA 0x0100
+#define PNG_RS_TRANSFORM_SWAP_ENDIAN 0x0200
+#define PNG_RS_TRANSFORM_SCALE_16 0x8000
+#define PNG_RS_TRANSFORM_RGBA8 0x10000
+#define PNG_RS_TRANSFORM_RGBA16 0x20000
+#define PNG_RS_TRANSFORM_ALPHA_MASK 0x40000
+#define PNG_RS_TRANSFORM_SCALE_16_GAMMA 0x80000
+#define PNG_RS_TRANSFORM_ADD_ALPHA 0x100000
+#define PNG_RS_TRANSFORM_BACKGROUND 0x200000
+
+typedef struct png_rs_decoder png_rs_decoder;
+
+typedef struct png_rs_info {
+    uint32_t width;
+    uint32_t height;
+    /* The PNG color type and bit depth of the rows, after transformations. */
+    uint8_t color_type;
+    uint8_t bit_depth;
+    /* Whether the rows are Adam7 interlaced. */
+    uint8_t interlaced;
+    /* The bytes of a complete row, without interlacing. */
+    size_t row_bytes;
+} png_rs_info;
+
+/* Create a decoder, which is released with png_rs_decoder_destroy. */
+png_rs_decoder *png_rs_decoder_new(void);
+
+/* Set the transformations of the rows, before any other call reads from the decoder. */
+int png_rs_decoder_set_transformations(png_rs_decoder *decoder, uint32_t transformations);
+
+/* Copy the next len bytes of the image. Feeding zero bytes marks the end of the input. */
+int png_rs_decoder_feed(png_rs_decoder *decoder, const uint8_t *data, size_t len);
+
+/* Read the header of the image. */
+int png_rs_decoder_get_info(png_rs_decoder *decoder, png_rs_info *info);
+
+/*
+ * Read the next row into the len bytes at row, which must hold at least info.row_bytes, and store
+ * its length in written unless it is NULL. Rows of interlaced images are returned for each pass
+ * and only contain its pixels. Returns PNG_RS_END after the last row.
+ */
+int png_rs_decoder_read_row(png_rs_decoder *decoder, uint8_t *row, size_t len, size_t *written);
+
+/* The message of the last error, or NULL. It is valid until the next call with the decoder. */
+const char *png_rs_decoder_error(const png_rs_decoder *decoder);
+
+void png_rs_decoder_destroy(png_rs_decoder *decoder);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,220 @@
+//! The geometry of the passes of Adam7 interlacing.
+//!
+//! An interlaced image is stored as seven reduced images, the passes, each filtered on its own.
+//! Their pixels are spread over the image in this pattern that repeats every 8 by 8 pixels:
+//!
+//! ```text
+//! 16462646
+//! 77777777
+//! 56565656
+//! 77777777
+//! 36463646
+//! 77777777
+//! 56565656
+//! 77777777
+//! ```
+//!
+//! The functions of this module map the lines and columns of the passes to the pixels of the
+//! image, for a deinterlacer of its own such as one on a GPU. Passes are numbered from 1 to 7,
+//! all functions panic for other numbers.
+//!
+//! ```
+//! use png::adam7;
+//!
+//! // The second pass of an image of 10 by 9 pixels stores two lines of one pixel.
+//! assert_eq!(adam7::pass_size(2, 10, 9), (1, 2));
+//! assert_eq!(adam7::image_position(2, 1, 0), (4, 8));
+//! ```
+
+/// The first row of an image in a pass and the distance of the rows of the pass.
+fn rows(pass: u8) -> (u32, u32) {
+    match pass {
+        1 | 2 => (0, 8),
+        3 => (4, 8),
+        4 => (0, 4),
+        5 => (2, 4),
+        6 => (0, 2),
+        7 => (1, 2),
+        _ => panic!("Adam7 pass out of range: {}", pass),
+    }
+}
+
+/// The first column of the image in a `pass` and the distance of the columns of the pass.
+pub fn image_columns(pass: u8) -> (u32, u32) {
+    match pass {
+        1 => (0, 8),
+        2 => (4, 8),
+        3 => (0, 4),
+        4 => (2, 4),
+        5 => (0, 2),
+        6 => (1, 2),
+        7 => (0, 1),
+        _ => panic!("Adam7 pass out of range: {}", pass),
+    }
+}
+
+/// The row of the image that contains the `line` of a `pass`.
+pub fn image_row(pass: u8, line: u32) -> u32 {
+    let (first, step) = rows(pass);
+    step * line + first
+}
+
+/// The column and row of the image of the pixel in `column` of the `line` of a `pass`.
+pub fn image_position(pass: u8, line: u32, column: u32) -> (u32, u32) {
+    let (first, step) = image_columns(pass);
+    (step * column + first, image_row(pass, line))
+}
+
+/// The width and number of lines of a `pass` of an image of `width` by `height` pixels.
+///
+/// Either is zero if the pass is empty, its lines are then not stored.
+pub fn pass_size(pass: u8, width: u32, height: u32) -> (u32, u32) {
+    // The pixels from `start` on, in steps of `step`.
+    fn count(len: u32, (start, step): (u32, u32)) -> u32 {
+        let len = len.saturating_sub(start);
+        len / step + (len % step != 0) as u32
+    }
+    (count(width, image_columns(pass)), count(height, rows(pass)))
+}
+
+/// The block of pixels of the image that starts with a pixel of a `pass`.
+///
+/// Together with the pixels of the earlier passes these blocks cover the image, filling them
+/// gives an approximation of the image after each pass.
+pub fn pass_block(pass: u8) -> (u32, u32) {
+    match pass {
+        1 => (8, 8),
+        2 => (4, 8),
+        3 => (4, 4),
+        4 => (2, 4),
+        5 => (2, 2),
+        6 => (1, 2),
+        7 => (1, 1),
+        _ => panic!("Adam7 pass out of range: {}", pass),
+    }
+}
+
+/// Iterates over the lines of the passes of an image, in the order they are stored.
+///
+/// Yields the number of the pass, the line within the pass and the width of the pass. Empty
+/// passes are skipped.
+#[derive(Clone, Debug)]
+pub struct Adam7Iterator {
+    line: u32,
+    lines: u32,
+    line_width: u32,
+    current_pass: u8,
+    width: u32,
+    height: u32,
+}
+
+impl Adam7Iterator {
+    /// Iterate over the lines of an image of `width` by `height` pixels.
+    pub fn new(width: u32, height: u32) -> Adam7Iterator {
+        let mut this = Adam7Iterator {
+            line: 0,
+            lines: 0,
+            line_width: 0,
+            current_pass: 1,
+            width,
+            height,
+        };
+        this.init_pass();
+        this
+    }
+
+    /// Calculates the bounds of the current pass
+    fn init_pass(&mut self) {
+        let (line_width, lines) = pass_size(self.current_pass, self.width, self.height);
+        self.line_width = line_width;
+        self.lines = lines;
+        self.line = 0;
+    }
+
+    /// The current pass#.
+    pub fn current_pass(&self) -> u8 {
+        self.current_pass
+    }
+
+    /// The passes that still have lines to iterate over, with the number of those lines and the
+    /// width of the pass.
+    pub(crate) fn remaining_passes(&self) -> impl Iterator<Item = (u8, u32, u32)> {
+        let (width, height) = (self.width, self.height);
+        let current = (self.current_pass, self.lines - self.line, self.line_width);
+        let later = (self.current_pass + 1..=7).map(move |pass| {
+            let (line_width, lines) = pass_size(pass, width, height);
+            (pass, lines, line_width)
+        });
+        Some(current)
+            .into_iter()
+            .chain(later)
+            .filter(|&(_, lines, line_width)| lines > 0 && line_width > 0)
+    }
+}
+
+/// Iterates over the (passes, lines, widths)
+impl Iterator for Adam7Iterator {
+    type Item = (u8, u32, u32);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.line < self.lines && self.line_width > 0 {
+            let this_line = self.line;
+            self.line += 1;
+            Some((self.current_pass, this_line, self.line_width))
+        } else if self.current_pass < 7 {
+            self.current_pass += 1;
+            self.init_pass();
+            self.next()
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self
+            .remaining_passes()
+            .map(|(_, lines, _)| lines as usize)
+            .sum();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Adam7Iterator {}
+
+#[test]
+fn test_adam7() {
+    /*
+        1646
+        7777
+        5656
+        7777
+    */
+    let it = Adam7Iterator::new(4, 4);
+    assert_eq!(it.len(), 7);
+    let passes: Vec<_> = it.collect();
+    assert_eq!(
+        &*passes,
+        &[
+            (1, 0, 1),
+            (4, 0, 1),
+            (5, 0, 2),
+            (6, 0, 2),
+            (6, 1, 2),
+            (7, 0, 4),
+            (7, 1, 4)
+        ]
+    );
+}
+
+#[test]
+fn test_positions_cover_image() {
+    let (width, height) = (13, 11);
+    let mut covered = vec![0; (width * height) as usize];
+    for (pass, line, line_width) in Adam7Iterator::new(width, height) {
+        assert_eq!(pass_size(pass, width, height).0, line_width);
+        for column in 0..line_width {
+            let (x, y) = image_position(pass, line, column);
+            covered[(y * width + x) as usize] += 1;
+        }
+    }
+    assert!(covered.iter().all(|&count| count == 1));
+}
@@ -0,0 +1,415 @@
+//! A C interface to the decoder, for replacing libpng in simple uses.
+//!
+//! The functions are declared in `include/png_rs.h`. A decoder is created with
+//! `png_rs_decoder_new`, fed with the bytes of an image as they arrive and asked for its header
+//! and rows, which report `PNG_RS_NEED_DATA` until enough bytes were fed. This is built on the
+//! resumable `Reader`: its input is a queue of the fed bytes that returns `WouldBlock` while it is
+//! empty.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::io::{self, Read};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+use crate::{Decoder, DecodingError, Reader, Transformations};
+
+/// The call succeeded.
+pub const PNG_RS_OK: c_int = 0;
+/// More bytes have to be fed before the call can succeed.
+pub const PNG_RS_NEED_DATA: c_int = 1;
+/// All rows of the image have been read.
+pub const PNG_RS_END: c_int = 2;
+/// Decoding failed, `png_rs_decoder_error` describes the error.
+pub const PNG_RS_ERROR: c_int = -1;
+/// The function was called with invalid arguments or at the wrong time.
+pub const PNG_RS_INVALID: c_int = -2;
+
+/// The bytes fed to a decoder and not yet read by it.
+#[derive(Default)]
+struct Input {
+    data: VecDeque<u8>,
+    /// Whether the end of the image was fed, after which no more data arrives.
+    finished: bool,
+}
+
+/// The reader of a decoder, sharing the fed bytes.
+struct Source(Rc<RefCell<Input>>);
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.0.borrow_mut();
+        if input.data.is_empty() && !input.finished {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(input.data.len());
+        for (byte, data) in buf.iter_mut().zip(input.data.drain(..len)) {
+            *byte = data;
+        }
+        Ok(len)
+    }
+}
+
+// The state is only stored in the boxed decoder, a boxed reader would not save any memory.
+#[allow(clippy::large_enum_variant)]
+enum State {
+    /// The decoder has not read any data yet and can still be configured.
+    Configuring(Decoder<Source>),
+    Reading(Reader<Source>),
+    /// Taken while switching between the other states.
+    Empty,
+}
+
+/// A decoder of the C interface.
+pub struct PngRsDecoder {
+    input: Rc<RefCell<Input>>,
+    state: State,
+    /// The message of the last error, returned by `png_rs_decoder_error`.
+    error: Option<CString>,
+}
+
+/// The header of the image and the layout of its rows, as returned by `png_rs_decoder_get_info`.
+#[repr(C)]
+pub struct PngRsInfo {
+    pub width: u32,
+    pub height: u32,
+    /// The PNG color type of the rows, after transformations.
+    pub color_type: u8,
+    /// The bit depth of the rows, after transformations.
+    pub bit_depth: u8,
+    /// Whether the rows are Adam7 interlaced.
+    pub interlaced: u8,
+    /// The bytes of a complete row, without interlacing.
+    pub row_bytes: usize,
+}
+
+impl PngRsDecoder {
+    /// The reader, creating it from the configured decoder on first use.
+    fn reader(&mut self) -> &mut Reader<Source> {
+        if let State::Configuring(_) = self.state {
+            if let State::Configuring(decoder) = std::mem::replace(&mut self.state, State::Empty) {
+                self.state = State::Reading(decoder.into_reader());
+            }
+        }
+        match self.state {
+            State::Reading(ref mut reader) => reader,
+            _ => unreachable!("the decoder is configured"),
+        }
+    }
+
+    /// The status code of a failed call, remembering the message of errors.
+    fn status(&mut self, err: DecodingError) -> c_int {
+        match err {
+            DecodingError::IoError(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                PNG_RS_NEED_DATA
+            }
+            err => {
+                // Messages do not contain NUL bytes, the fallback only guards the conversion.
+                self.error = CString::new(err.to_string()).ok();
+                PNG_RS_ERROR
+            }
+        }
+    }
+}
+
+/// Create a decoder, which is released with `png_rs_decoder_destroy`.
+#[no_mangle]
+pub extern "C" fn png_rs_decoder_new() -> *mut PngRsDecoder {
+    let input = Rc::new(RefCell::new(Input::default()));
+    let decoder = Decoder::new(Source(input.clone()));
+    Box::into_raw(Box::new(PngRsDecoder {
+        input,
+        state: State::Configuring(decoder),
+        error: None,
+    }))
+}
+
+/// Set the transformations applied to the rows, the bits of `png::Transformations`.
+///
+/// This must be called before any other function reads from the decoder.
+///
+/// # Safety
+///
+/// `decoder` must be a decoder returned by `png_rs_decoder_new` that was not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn png_rs_decoder_set_transformations(
+    decoder: *mut PngRsDecoder,
+    transformations: u32,
+) -> c_int {
+    let decoder = match decoder.as_mut() {
+        Some(decoder) => decoder,
+        None => return PNG_RS_INVALID,
+    };
+    match decoder.state {
+        State::Configuring(ref mut config) => {
+            config.set_transformations(Transformations::from_bits_truncate(transformations));
+            PNG_RS_OK
+        }
+        _ => PNG_RS_INVALID,
+    }
+}
+
+/// Feed the next `len` bytes of the image at `data` to the decoder.
+///
+/// The bytes are copied. Feeding zero bytes marks the end of the input, so that a truncated image
+/// is reported as an error instead of waiting for more data.
+///
+/// # Safety
+///
+/// `decoder` must be a decoder returned by `png_rs_decoder_new` that was not destroyed, and
+/// `data` must point to `len` readable bytes unless `len` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn png_rs_decoder_feed(
+    decoder: *mut PngRsDecoder,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let decoder = match decoder.as_mut() {
+        Some(decoder) => decoder,
+        None => return PNG_RS_INVALID,
+    };
+    let mut input = decoder.input.borrow_mut();
+    if len == 0 {
+        input.finished = true;
+    } else if data.is_null() || input.finished {
+        return PNG_RS_INVALID;
+    } else {
+        input.data.extend(slice::from_raw_parts(data, len));
+    }
+    PNG_RS_OK
+}
+
+/// Read the header of the image into `info`.
+///
+/// # Safety
+///
+/// `decoder` must be a decoder returned by `png_rs_decoder_new` that was not destroyed, and
+/// `info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn png_rs_decoder_get_info(
+    decoder: *mut PngRsDecoder,
+    info: *mut PngRsInfo,
+) -> c_int {
+    let (decoder, info) = match (decoder.as_mut(), info.as_mut()) {
+        (Some(decoder), Some(info)) => (decoder, info),
+        _ => return PNG_RS_INVALID,
+    };
+    let reader = decoder.reader();
+    if let Err(err) = reader.read_info() {
+        return decoder.status(err);
+    }
+    let (color_type, bit_depth) = reader.output_color_type();
+    let header = reader.info();
+    *info = PngRsInfo {
+        width: header.width,
+        height: header.height,
+        color_type: color_type as u8,
+        bit_depth: bit_depth as u8,
+        interlaced: header.interlaced as u8,
+        row_bytes: reader.output_line_size(header.width),
+    };
+    PNG_RS_OK
+}
+
+/// Read the next row of the image into the `len` bytes at `row`.
+///
+/// The bytes of the row are stored in `written`, if it is not null. Rows of interlaced images are
+/// returned for each Adam7 pass in the order they are stored and only contain the pixels of their
+/// pass. Returns `PNG_RS_END` after the last row.
+///
+/// # Safety
+///
+/// `decoder` must be a decoder returned by `png_rs_decoder_new` that was not destroyed, `row`
+/// must point to `len` writable bytes and `written` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn png_rs_decoder_read_row(
+    decoder: *mut PngRsDecoder,
+    row: *mut u8,
+    len: usize,
+    written: *mut usize,
+) -> c_int {
+    let decoder = match decoder.as_mut() {
+        Some(decoder) if !row.is_null() => decoder,
+        _ => return PNG_RS_INVALID,
+    };
+    let reader = decoder.reader();
+    if let Err(err) = reader.read_info() {
+        return decoder.status(err);
+    }
+    if len < reader.output_line_size(reader.info().width) {
+        return PNG_RS_INVALID;
+    }
+    let data = match reader.next_row() {
+        Ok(Some(next)) => next.data(),
+        Ok(None) => return PNG_RS_END,
+        Err(err) => return decoder.status(err),
+    };
+    ptr::copy_nonoverlapping(data.as_ptr(), row, data.len());
+    if let Some(written) = written.as_mut() {
+        *written = data.len();
+    }
+    PNG_RS_OK
+}
+
+/// The message of the last error of the decoder, or null if there was none.
+///
+/// The string is valid until the next call with the decoder.
+///
+/// # Safety
+///
+/// `decoder` must be a decoder returned by `png_rs_decoder_new` that was not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn png_rs_decoder_error(decoder: *const PngRsDecoder) -> *const c_char {
+    match decoder.as_ref().and_then(|decoder| decoder.error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Release a decoder.
+///
+/// # Safety
+///
+/// `decoder` must be null or a decoder returned by `png_rs_decoder_new` that was not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn png_rs_decoder_destroy(decoder: *mut PngRsDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode an image fed in pieces, returning the rows.
+    fn decode(image: &[u8], piece: usize) -> (PngRsInfo, Vec<u8>) {
+        unsafe {
+            let decoder = png_rs_decoder_new();
+            let mut info: PngRsInfo = std::mem::zeroed();
+            let mut pieces = image.chunks(piece);
+            let mut feed = |decoder| match pieces.next() {
+                Some(piece) => png_rs_decoder_feed(decoder, piece.as_ptr(), piece.len()),
+                None => png_rs_decoder_feed(decoder, ptr::null(), 0),
+            };
+            assert_eq!(png_rs_decoder_set_transformations(decoder, 0x10), PNG_RS_OK);
+            loop {
+                match png_rs_decoder_get_info(decoder, &mut info) {
+                    PNG_RS_NEED_DATA => assert_eq!(feed(decoder), PNG_RS_OK),
+                    status => break assert_eq!(status, PNG_RS_OK),
+                }
+            }
+            assert_eq!(
+                png_rs_decoder_set_transformations(decoder, 0),
+                PNG_RS_INVALID
+            );
+            let mut rows = Vec::new();
+            let mut row = vec![0; info.row_bytes];
+            loop {
+                let mut written = 0;
+                match png_rs_decoder_read_row(decoder, row.as_mut_ptr(), row.len(), &mut written) {
+                    PNG_RS_NEED_DATA => assert_eq!(feed(decoder), PNG_RS_OK),
+                    PNG_RS_END => break,
+                    status => {
+                        assert_eq!(status, PNG_RS_OK);
+                        rows.extend_from_slice(&row[..written]);
+                    }
+                }
+            }
+            assert!(png_rs_decoder_error(decoder).is_null());
+            png_rs_decoder_destroy(decoder);
+            (info, rows)
+        }
+    }
+
+    #[test]
+    fn decode_fed_image() {
+        for path in &["basn3p04", "basn6a16", "basi2c08"] {
+            let image = std::fs::read(format!("tests/pngsuite/{}.png", path)).unwrap();
+            let mut decoder = Decoder::new(&image[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let mut expected = Vec::new();
+            while let Some(row) = reader.next_row().unwrap() {
+                expected.extend_from_slice(row.data());
+            }
+            let (color_type, bit_depth) = reader.output_color_type();
+
+            for &piece in &[1, 100, image.len()] {
+                let (info, rows) = decode(&image, piece);
+                assert_eq!((info.width, info.height), reader.info().size());
+                assert_eq!(info.color_type, color_type as u8);
+                assert_eq!(info.bit_depth, bit_depth as u8);
+                assert_eq!(info.interlaced != 0, reader.info().interlaced);
+                assert_eq!(rows, expected, "{} in pieces of {}", path, piece);
+            }
+        }
+    }
+
+    #[test]
+    fn header_transformations() {
+        let flags = [
+            ("IDENTITY", Transformations::IDENTITY),
+            ("STRIP_16", Transformations::STRIP_16),
+            ("STRIP_ALPHA", Transformations::STRIP_ALPHA),
+            ("PACKING", Transformations::PACKING),
+            ("PACKSWAP", Transformations::PACKSWAP),
+            ("EXPAND", Transformations::EXPAND),
+            ("INVERT_MONO", Transformations::INVERT_MONO),
+            ("BGR", Transformations::BGR),
+            ("SWAP_ALPHA", Transformations::SWAP_ALPHA),
+            ("SWAP_ENDIAN", Transformations::SWAP_ENDIAN),
+            ("SCALE_16", Transformations::SCALE_16),
+            ("RGBA8", Transformations::RGBA8),
+            ("RGBA16", Transformations::RGBA16),
+            ("ALPHA_MASK", Transformations::ALPHA_MASK),
+            ("SCALE_16_GAMMA", Transformations::SCALE_16_GAMMA),
+            ("ADD_ALPHA", Transformations::ADD_ALPHA),
+            ("BACKGROUND", Transformations::BACKGROUND),
+        ];
+        let header = std::fs::read_to_string("include/png_rs.h").unwrap();
+        let prefix = "#define PNG_RS_TRANSFORM_";
+        let mut declared = Transformations::empty();
+        for line in header.lines().filter(|line| line.starts_with(prefix)) {
+            let mut words = line[prefix.len()..].split_whitespace();
+            let (name, value) = (words.next().unwrap(), words.next().unwrap());
+            let value = u32::from_str_radix(value.trim_start_matches("0x"), 16).unwrap();
+            let &(_, flag) = flags.iter().find(|&&(flag, _)| flag == name).unwrap();
+            assert_eq!(value, flag.bits(), "{}", name);
+            declared |= flag;
+        }
+        // Every transformation is declared.
+        assert_eq!(declared, Transformations::all());
+    }
+
+    #[test]
+    fn report_errors() {
+        let image = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+        unsafe {
+            let decoder = png_rs_decoder_new();
+            let mut info: PngRsInfo = std::mem::zeroed();
+            // The image ends within its header.
+            png_rs_decoder_feed(decoder, image.as_ptr(), 20);
+            assert_eq!(
+                png_rs_decoder_get_info(decoder, &mut info),
+                PNG_RS_NEED_DATA
+            );
+            png_rs_decoder_feed(decoder, ptr::null(), 0);
+            assert_eq!(png_rs_decoder_get_info(decoder, &mut info), PNG_RS_ERROR);
+            assert!(!png_rs_decoder_error(decoder).is_null());
+            assert_eq!(
+                png_rs_decoder_feed(decoder, image.as_ptr(), 1),
+                PNG_RS_INVALID
+            );
+            png_rs_decoder_destroy(decoder);
+
+            assert_eq!(
+                png_rs_decoder_get_info(ptr::null_mut(), &mut info),
+                PNG_RS_INVALID
+            );
+            png_rs_decoder_destroy(ptr::null_mut());
+        }
+    }
+}
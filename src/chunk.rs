@@ -2,6 +2,15 @@
 #![allow(dead_code)]
 #![allow(non_upper_case_globals)]
 use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "std")]
+use crate::decoder::FormatErrorInner;
+#[cfg(feature = "std")]
+use crate::encoder::{self, EncodingError};
+#[cfg(feature = "std")]
+use crate::DecodingError;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChunkType(pub [u8; 4]);
@@ -23,6 +32,12 @@ pub const IEND: ChunkType = ChunkType([b'I', b'E', b'N', b'D']);
 pub const tRNS: ChunkType = ChunkType([b't', b'R', b'N', b'S']);
 /// Background colour
 pub const bKGD: ChunkType = ChunkType([b'b', b'K', b'G', b'D']);
+/// Palette histogram
+pub const hIST: ChunkType = ChunkType([b'h', b'I', b'S', b'T']);
+/// Significant bits
+pub const sBIT: ChunkType = ChunkType([b's', b'B', b'I', b'T']);
+/// Suggested palette
+pub const sPLT: ChunkType = ChunkType([b's', b'P', b'L', b'T']);
 /// Image last-modification time
 pub const tIME: ChunkType = ChunkType([b't', b'I', b'M', b'E']);
 /// Physical pixel dimensions
@@ -35,6 +50,12 @@ pub const gAMA: ChunkType = ChunkType([b'g', b'A', b'M', b'A']);
 pub const sRGB: ChunkType = ChunkType([b's', b'R', b'G', b'B']);
 /// ICC profile chunk
 pub const iCCP: ChunkType = ChunkType([b'i', b'C', b'C', b'P']);
+/// Latin-1 uncompressed textual data
+pub const tEXt: ChunkType = ChunkType([b't', b'E', b'X', b't']);
+/// Latin-1 compressed textual data
+pub const zTXt: ChunkType = ChunkType([b'z', b'T', b'X', b't']);
+/// UTF-8 textual data
+pub const iTXt: ChunkType = ChunkType([b'i', b'T', b'X', b't']);
 
 // -- Extension chunks --
 
@@ -45,6 +66,11 @@ pub const fcTL: ChunkType = ChunkType([b'f', b'c', b'T', b'L']);
 /// Frame data
 pub const fdAT: ChunkType = ChunkType([b'f', b'd', b'A', b'T']);
 
+/// The ancillary chunks that the encoder writes from the fields of `Info`, these are not
+/// retained by `Decoder::set_retain_chunks`.
+pub(crate) const INFO_CHUNKS: [ChunkType; 10] =
+    [tRNS, hIST, bKGD, gAMA, cHRM, sRGB, sPLT, acTL, fcTL, fdAT];
+
 // -- Chunk type determination --
 
 /// Returns true if the chunk is critical.
@@ -75,7 +101,7 @@ impl fmt::Debug for ChunkType {
         impl fmt::Debug for DebugType {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
                 for &c in &self.0[..] {
-                    write!(f, "{:?}", char::from(c).escape_debug())?;
+                    write!(f, "{}", char::from(c).escape_debug())?;
                 }
                 Ok(())
             }
@@ -90,3 +116,271 @@ impl fmt::Debug for ChunkType {
             .finish()
     }
 }
+
+/// The signature at the start of every PNG file.
+pub const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Whether `data` starts with the PNG signature.
+///
+/// Only the first eight bytes are checked, which is enough to tell a PNG file apart from other
+/// formats before decoding it.
+///
+/// ```
+/// assert!(png::is_png(&[137, 80, 78, 71, 13, 10, 26, 10, 0, 0]));
+/// assert!(!png::is_png(b"GIF89a"));
+/// ```
+pub fn is_png(data: &[u8]) -> bool {
+    data.starts_with(&SIGNATURE)
+}
+
+/// A chunk as it is stored in a file, with its data and checksum.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawChunk {
+    pub chunk_type: ChunkType,
+    pub data: Vec<u8>,
+    /// The CRC of the type and data, as stored. It is not verified when the chunk is read.
+    pub crc: u32,
+}
+
+#[cfg(feature = "std")]
+impl RawChunk {
+    /// Create a chunk with the checksum of its `chunk_type` and `data`.
+    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> RawChunk {
+        let crc = RawChunk::checksum(chunk_type, &data);
+        RawChunk {
+            chunk_type,
+            data,
+            crc,
+        }
+    }
+
+    /// Whether the stored checksum matches the type and data.
+    pub fn crc_is_valid(&self) -> bool {
+        self.crc == RawChunk::checksum(self.chunk_type, &self.data)
+    }
+
+    fn checksum(chunk_type: ChunkType, data: &[u8]) -> u32 {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&chunk_type.0);
+        crc.update(data);
+        crc.finalize()
+    }
+}
+
+/// Reads the chunks of a PNG file without decoding them.
+///
+/// The signature of the file is checked, then the chunks are returned one by one up to and
+/// including the `IEND` chunk, whatever their type or order. Any data after it is not read. This
+/// allows tools to strip metadata, reorder chunks or extract private chunks, and to write the
+/// result with a [`ChunkWriter`].
+///
+/// ```
+/// use png::chunk::{self, ChunkReader};
+///
+/// let file = std::fs::File::open("tests/pngsuite/basn0g01.png")?;
+/// let types = ChunkReader::new(file)
+///     .map(|chunk| chunk.map(|chunk| chunk.chunk_type))
+///     .collect::<Result<Vec<_>, _>>()?;
+/// assert_eq!(types.first(), Some(&chunk::IHDR));
+/// assert_eq!(types.last(), Some(&chunk::IEND));
+/// # Ok::<(), png::DecodingError>(())
+/// ```
+///
+/// [`ChunkWriter`]: struct.ChunkWriter.html
+#[cfg(feature = "std")]
+pub struct ChunkReader<R: Read> {
+    r: R,
+    signature_read: bool,
+    /// Whether the `IEND` chunk or an error was returned.
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> ChunkReader<R> {
+    pub fn new(r: R) -> ChunkReader<R> {
+        ChunkReader {
+            r,
+            signature_read: false,
+            done: false,
+        }
+    }
+
+    /// Reads the next chunk, or returns `None` after the `IEND` chunk.
+    pub fn next_chunk(&mut self) -> Result<Option<RawChunk>, DecodingError> {
+        if self.done {
+            return Ok(None);
+        }
+        // Stop after errors as well, the position in the file is unknown.
+        self.done = true;
+
+        if !self.signature_read {
+            let mut signature = [0; 8];
+            self.read_exact(&mut signature)?;
+            if signature != SIGNATURE {
+                return Err(DecodingError::Format(
+                    FormatErrorInner::InvalidSignature.into(),
+                ));
+            }
+            self.signature_read = true;
+        }
+
+        let mut header = [0; 8];
+        self.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk_type = ChunkType([header[4], header[5], header[6], header[7]]);
+        if length > 0x7FFF_FFFF {
+            return Err(DecodingError::Format(
+                FormatErrorInner::InvalidChunkLength {
+                    kind: chunk_type,
+                    length,
+                }
+                .into(),
+            ));
+        }
+
+        let mut data = Vec::new();
+        (&mut self.r)
+            .take(u64::from(length))
+            .read_to_end(&mut data)?;
+        if data.len() != length as usize {
+            return Err(DecodingError::Format(
+                FormatErrorInner::UnexpectedEof.into(),
+            ));
+        }
+        let mut crc = [0; 4];
+        self.read_exact(&mut crc)?;
+
+        self.done = chunk_type == IEND;
+        Ok(Some(RawChunk {
+            chunk_type,
+            data,
+            crc: u32::from_be_bytes(crc),
+        }))
+    }
+
+    /// Returns the underlying reader, positioned after the last chunk read.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        self.r.read_exact(buf).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                DecodingError::Format(FormatErrorInner::UnexpectedEof.into())
+            }
+            _ => DecodingError::IoError(err),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<RawChunk, DecodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+/// Writes chunks to a PNG file, framed with their length and checksum.
+///
+/// The signature is written before the first chunk. Chunks are written as given, the caller is
+/// responsible for a valid order that starts with `IHDR` and ends with `IEND`.
+///
+/// ```
+/// use png::chunk::{self, ChunkReader, ChunkWriter};
+///
+/// // Strip all textual chunks of an image.
+/// let file = std::fs::File::open("tests/pngsuite/ct1n0g04.png")?;
+/// let mut writer = ChunkWriter::new(Vec::new());
+/// for chunk in ChunkReader::new(file) {
+///     let chunk = chunk?;
+///     if ![chunk::tEXt, chunk::zTXt, chunk::iTXt].contains(&chunk.chunk_type) {
+///         writer.write_raw_chunk(&chunk)?;
+///     }
+/// }
+/// let stripped = writer.into_inner();
+/// let mut reader = png::Decoder::new(&stripped[..]).read_info()?;
+/// let mut buf = vec![0; reader.output_buffer_size()];
+/// reader.next_frame(&mut buf)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "std")]
+pub struct ChunkWriter<W: Write> {
+    w: W,
+    signature_written: bool,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> ChunkWriter<W> {
+    pub fn new(w: W) -> ChunkWriter<W> {
+        ChunkWriter {
+            w,
+            signature_written: false,
+        }
+    }
+
+    /// Writes a chunk of `chunk_type` with `data`, and its checksum.
+    pub fn write_chunk(&mut self, chunk_type: ChunkType, data: &[u8]) -> Result<(), EncodingError> {
+        self.write_signature()?;
+        encoder::write_chunk(&mut self.w, chunk_type, data)
+    }
+
+    /// Writes a chunk with its stored checksum, even if it does not match.
+    pub fn write_raw_chunk(&mut self, chunk: &RawChunk) -> Result<(), EncodingError> {
+        self.write_signature()?;
+        self.w.write_all(&(chunk.data.len() as u32).to_be_bytes())?;
+        self.w.write_all(&chunk.chunk_type.0)?;
+        self.w.write_all(&chunk.data)?;
+        self.w.write_all(&chunk.crc.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    fn write_signature(&mut self) -> Result<(), EncodingError> {
+        if !self.signature_written {
+            self.w.write_all(&SIGNATURE)?;
+            self.signature_written = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_chunks_roundtrip() {
+    let data = std::fs::read("tests/pngsuite/basn3p08.png").unwrap();
+    let mut writer = ChunkWriter::new(Vec::new());
+    let mut types = Vec::new();
+    for chunk in ChunkReader::new(&data[..]) {
+        let chunk = chunk.unwrap();
+        assert!(chunk.crc_is_valid());
+        writer.write_raw_chunk(&chunk).unwrap();
+        types.push(chunk.chunk_type);
+    }
+    assert!(writer.into_inner() == data);
+    assert_eq!(types, [IHDR, gAMA, PLTE, IDAT, IEND]);
+
+    // Checksums are kept as they are, the framing is recomputed for new chunks.
+    let mut corrupt = data.clone();
+    corrupt[8 + 8 + 13] ^= 1;
+    let chunk = ChunkReader::new(&corrupt[..])
+        .next_chunk()
+        .unwrap()
+        .unwrap();
+    assert!(!chunk.crc_is_valid());
+    let mut writer = ChunkWriter::new(Vec::new());
+    writer.write_chunk(chunk.chunk_type, &chunk.data).unwrap();
+    assert!(writer.into_inner()[..8 + 25] == data[..8 + 25]);
+
+    let mut reader = ChunkReader::new(&data[..40]);
+    assert!(reader.next_chunk().unwrap().is_some());
+    assert!(reader.next_chunk().is_err());
+    assert!(reader.next_chunk().unwrap().is_none());
+    assert!(ChunkReader::new(&data[1..]).next_chunk().is_err());
+}
@@ -1,7 +1,10 @@
 //! Common types shared between the encoder and decoder
+#[cfg(feature = "std")]
 use crate::{chunk, encoder};
-use io::Write;
-use std::{borrow::Cow, convert::TryFrom, fmt, io};
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::{convert::TryFrom, fmt, num::NonZeroU32};
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Describes how a pixel is encoded.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -123,6 +126,147 @@ impl BitDepth {
     }
 }
 
+/// International textual data, stored in an `iTXt` chunk.
+///
+/// The text is required to be UTF-8 but images in the wild do not always adhere to this. By
+/// default the decoder replaces invalid sequences with `U+FFFD REPLACEMENT CHARACTER` and marks
+/// the chunk with `invalid_utf8`, while the original bytes of the text are kept in `raw_text`.
+/// See [`Utf8Policy`](enum.Utf8Policy.html) for rejecting such chunks instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ITXtChunk {
+    /// The keyword, 1 to 79 bytes of Latin-1.
+    pub keyword: String,
+    /// Whether the text was stored compressed.
+    pub compressed: bool,
+    /// The language of the text, as an RFC 1766 language tag.
+    pub language_tag: String,
+    /// The keyword translated into the language of the text.
+    pub translated_keyword: String,
+    /// The text, with any invalid UTF-8 replaced.
+    pub text: String,
+    /// The decompressed text exactly as stored in the chunk.
+    pub raw_text: Vec<u8>,
+    /// Whether the translated keyword or the text contained invalid UTF-8.
+    pub invalid_utf8: bool,
+}
+
+/// A suggested palette, stored in an `sPLT` chunk.
+///
+/// Quantizers can use it to reduce the image to fewer colors, also for images that are not
+/// indexed. An image may suggest several palettes, each with a different name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    /// The name of the palette, 1 to 79 bytes of Latin-1.
+    pub name: String,
+    /// The depth of the samples of the entries, `Eight` or `Sixteen`.
+    pub sample_depth: BitDepth,
+    pub entries: Vec<SuggestedPaletteEntry>,
+}
+
+impl SuggestedPalette {
+    #[cfg(feature = "std")]
+    pub fn encode<W: Write>(&self, w: &mut W) -> encoder::Result<()> {
+        let mut data: Vec<u8> = self.name.chars().map(|c| c as u8).collect();
+        data.push(0);
+        data.push(self.sample_depth as u8);
+        for entry in &self.entries {
+            for &sample in &[entry.red, entry.green, entry.blue, entry.alpha] {
+                if self.sample_depth == BitDepth::Sixteen {
+                    data.extend_from_slice(&sample.to_be_bytes());
+                } else {
+                    data.push(sample as u8);
+                }
+            }
+            data.extend_from_slice(&entry.frequency.to_be_bytes());
+        }
+        encoder::write_chunk(w, chunk::sPLT, &data)
+    }
+}
+
+/// The background color of a `bKGD` chunk.
+///
+/// The samples have the bit depth of the image, the kind of color depends on its color type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundColor {
+    /// A gray sample, for grayscale images with or without alpha.
+    Gray(u16),
+    /// Red, green and blue samples, for RGB images with or without alpha.
+    Rgb(u16, u16, u16),
+    /// An entry of the palette, for indexed images.
+    Index(u8),
+}
+
+impl BackgroundColor {
+    #[cfg(feature = "std")]
+    pub fn encode<W: Write>(&self, w: &mut W) -> encoder::Result<()> {
+        match *self {
+            BackgroundColor::Gray(gray) => {
+                encoder::write_chunk(w, chunk::bKGD, &gray.to_be_bytes())
+            }
+            BackgroundColor::Rgb(red, green, blue) => {
+                let mut data = [0; 6];
+                data[..2].copy_from_slice(&red.to_be_bytes());
+                data[2..4].copy_from_slice(&green.to_be_bytes());
+                data[4..].copy_from_slice(&blue.to_be_bytes());
+                encoder::write_chunk(w, chunk::bKGD, &data)
+            }
+            BackgroundColor::Index(index) => encoder::write_chunk(w, chunk::bKGD, &[index]),
+        }
+    }
+
+    /// Whether the color is of the kind stored for images of a color type.
+    pub(crate) fn fits(&self, color_type: ColorType) -> bool {
+        use ColorType::*;
+        match (*self, color_type) {
+            (BackgroundColor::Gray(_), Grayscale)
+            | (BackgroundColor::Gray(_), GrayscaleAlpha)
+            | (BackgroundColor::Rgb(..), Rgb)
+            | (BackgroundColor::Rgb(..), Rgba)
+            | (BackgroundColor::Index(_), Indexed) => true,
+            _ => false,
+        }
+    }
+}
+
+/// An entry of a suggested palette.
+///
+/// With a sample depth of 8 bits the samples are less than 256. Alpha is not premultiplied and
+/// zero is fully transparent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SuggestedPaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    /// How often the color occurs in the image, relative to the other entries.
+    ///
+    /// Zero means that the frequency is not known or the color is unused.
+    pub frequency: u16,
+}
+
+/// Where a retained chunk is placed, relative to the critical chunks of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkLocation {
+    /// After `IHDR` and before `PLTE`.
+    BeforePlte,
+    /// After `PLTE`, if the image has one, and before the image data.
+    BeforeIdat,
+    /// After the image data and before `IEND`.
+    AfterIdat,
+}
+
+/// An ancillary chunk that is kept as it is, to rewrite an image without losing it.
+///
+/// These are all ancillary chunks that the encoder does not write from the other fields of
+/// `Info` itself, such as text, time stamps and private chunks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetainedChunk {
+    pub chunk_type: crate::chunk::ChunkType,
+    pub data: Vec<u8>,
+    /// Where the chunk appeared in the image, and is written to again.
+    pub location: ChunkLocation,
+}
+
 /// Pixel dimensions information
 #[derive(Clone, Copy, Debug)]
 pub struct PixelDimensions {
@@ -134,6 +278,33 @@ pub struct PixelDimensions {
     pub unit: Unit,
 }
 
+impl PixelDimensions {
+    /// The ratio of the width of a pixel to its height.
+    ///
+    /// This is meaningful even when the unit is unspecified. Returns `None` if either of the
+    /// pixel densities is zero.
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        if self.xppu == 0 || self.yppu == 0 {
+            return None;
+        }
+        Some((f64::from(self.yppu) / f64::from(self.xppu)) as f32)
+    }
+
+    /// Pixels per inch along the x and y axis.
+    ///
+    /// Returns `None` if the unit is unspecified, since no physical size can be derived then.
+    pub fn dpi(&self) -> Option<(f32, f32)> {
+        const METERS_PER_INCH: f64 = 0.0254;
+        match self.unit {
+            Unit::Meter => Some((
+                (f64::from(self.xppu) * METERS_PER_INCH) as f32,
+                (f64::from(self.yppu) * METERS_PER_INCH) as f32,
+            )),
+            Unit::Unspecified => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 /// Physical unit of the pixel dimensions
@@ -267,6 +438,7 @@ impl FrameControl {
         self.sequence_number += i;
     }
 
+    #[cfg(feature = "std")]
     pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
         let mut data = [0u8; 26];
         data[..4].copy_from_slice(&self.sequence_number.to_be_bytes());
@@ -283,20 +455,78 @@ impl FrameControl {
     }
 }
 
+/// How many times an animation is played.
+///
+/// In the `acTL` chunk this is stored as a plain integer where 0 means that the animation loops
+/// forever. Use [`PlayCount::from_u32`] and [`PlayCount::to_u32`] to convert from and to that
+/// representation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayCount {
+    /// The animation is repeated indefinitely.
+    Infinite,
+    /// The animation is played this many times and then stops on the last frame.
+    Finite(NonZeroU32),
+}
+
+impl PlayCount {
+    /// Interpret the raw `num_plays` field of an `acTL` chunk.
+    pub fn from_u32(n: u32) -> PlayCount {
+        match NonZeroU32::new(n) {
+            Some(n) => PlayCount::Finite(n),
+            None => PlayCount::Infinite,
+        }
+    }
+
+    /// The raw `num_plays` value as written to an `acTL` chunk.
+    pub fn to_u32(self) -> u32 {
+        match self {
+            PlayCount::Infinite => 0,
+            PlayCount::Finite(n) => n.get(),
+        }
+    }
+
+    /// Whether the animation loops forever.
+    pub fn is_infinite(self) -> bool {
+        self == PlayCount::Infinite
+    }
+}
+
+impl From<u32> for PlayCount {
+    fn from(n: u32) -> PlayCount {
+        PlayCount::from_u32(n)
+    }
+}
+
+impl From<NonZeroU32> for PlayCount {
+    fn from(n: NonZeroU32) -> PlayCount {
+        PlayCount::Finite(n)
+    }
+}
+
+impl fmt::Display for PlayCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlayCount::Infinite => write!(f, "infinite"),
+            PlayCount::Finite(n) => write!(f, "{}", n),
+        }
+    }
+}
+
 /// Animation control information
 #[derive(Clone, Copy, Debug)]
 pub struct AnimationControl {
     /// Number of frames
     pub num_frames: u32,
-    /// Number of times to loop this APNG.  0 indicates infinite looping.
-    pub num_plays: u32,
+    /// Number of times to play this APNG.
+    pub num_plays: PlayCount,
 }
 
 impl AnimationControl {
+    #[cfg(feature = "std")]
     pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
         let mut data = [0; 8];
         data[..4].copy_from_slice(&self.num_frames.to_be_bytes());
-        data[4..].copy_from_slice(&self.num_plays.to_be_bytes());
+        data[4..].copy_from_slice(&self.num_plays.to_u32().to_be_bytes());
         encoder::write_chunk(w, chunk::acTL, &data)
     }
 }
@@ -318,6 +548,72 @@ pub enum Compression {
     Rle,
 }
 
+/// The order of the color and alpha channels of the output.
+///
+/// The orders with blue first are those of many framebuffers, the orders with alpha first those
+/// of some graphics APIs. Channels an image does not have are skipped, so `Argb` orders
+/// grayscale with alpha as alpha and gray, and `Bgra` orders RGB without alpha as BGR. The output
+/// color type is still reported as RGB or RGBA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOrder {
+    Rgba,
+    Bgra,
+    Argb,
+    Abgr,
+}
+
+impl ChannelOrder {
+    /// Whether red and blue are swapped, and whether alpha comes first.
+    pub(crate) fn swaps(self) -> (bool, bool) {
+        match self {
+            ChannelOrder::Rgba => (false, false),
+            ChannelOrder::Bgra => (true, false),
+            ChannelOrder::Argb => (false, true),
+            ChannelOrder::Abgr => (true, true),
+        }
+    }
+}
+
+impl Default for ChannelOrder {
+    fn default() -> Self {
+        ChannelOrder::Rgba
+    }
+}
+
+/// The implementation of deflate that compresses the image data.
+///
+/// Both backends are always compiled in and produce valid streams for every `Compression`, but
+/// their speed and the size of the output differ. This allows comparing them and switching to
+/// the other if one does not suit an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateBackend {
+    /// The `deflate` crate, which has compressed the images of this library since its start.
+    Deflate,
+    /// The compressor of `miniz_oxide`, the crate that also decompresses the image data.
+    MinizOxide,
+}
+
+impl Default for DeflateBackend {
+    fn default() -> Self {
+        DeflateBackend::Deflate
+    }
+}
+
+/// How 8-bit grayscale samples are reduced to black and white.
+///
+/// Used by [`Writer::write_bilevel_image_data`] to write 1-bit grayscale images, for example of
+/// scanned documents.
+///
+/// [`Writer::write_bilevel_image_data`]: struct.Writer.html#method.write_bilevel_image_data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bilevel {
+    /// Samples at or above the threshold become white, all others black.
+    Threshold(u8),
+    /// Floyd-Steinberg dithering, which diffuses the difference of each sample to black or white
+    /// onto its neighbours. This keeps the gray levels of photos and halftones visible.
+    Dither,
+}
+
 /// An unsigned integer scaled version of a floating point value,
 /// equivalent to an integer quotient with fixed denominator (100_000)).
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -328,7 +624,8 @@ impl ScaledFloat {
 
     /// Gets whether the value is within the clamped range of this type.
     pub fn in_range(value: f32) -> bool {
-        value >= 0.0 && (value * Self::SCALING).floor() <= std::u32::MAX as f32
+        // Truncating a non-negative value rounds it down.
+        value >= 0.0 && value * Self::SCALING <= core::u32::MAX as f32
     }
 
     /// Gets whether the value can be exactly converted in round-trip.
@@ -340,7 +637,7 @@ impl ScaledFloat {
     }
 
     fn forward(value: f32) -> u32 {
-        (value.max(0.0) * Self::SCALING).floor() as u32
+        (value.max(0.0) * Self::SCALING) as u32
     }
 
     fn reverse(encoded: u32) -> f32 {
@@ -350,14 +647,12 @@ impl ScaledFloat {
     /// Slightly inaccurate scaling and quantization.
     /// Clamps the value into the representable range if it is negative or too large.
     pub fn new(value: f32) -> Self {
-        Self {
-            0: Self::forward(value),
-        }
+        Self(Self::forward(value))
     }
 
     /// Fully accurate construction from a value scaled as per specification.
     pub fn from_scaled(val: u32) -> Self {
-        Self { 0: val }
+        Self(val)
     }
 
     /// Get the accurate encoded value.
@@ -367,9 +662,10 @@ impl ScaledFloat {
 
     /// Get the unscaled value as a floating point.
     pub fn into_value(self) -> f32 {
-        Self::reverse(self.0) as f32
+        Self::reverse(self.0)
     }
 
+    #[cfg(feature = "std")]
     pub(crate) fn encode_gama<W: Write>(self, w: &mut W) -> encoder::Result<()> {
         encoder::write_chunk(w, chunk::gAMA, &self.into_scaled().to_be_bytes())
     }
@@ -416,6 +712,7 @@ impl SourceChromaticities {
         ]
     }
 
+    #[cfg(feature = "std")]
     pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
         encoder::write_chunk(w, chunk::cHRM, &self.to_be_bytes())
     }
@@ -452,6 +749,7 @@ impl SrgbRenderingIntent {
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
         encoder::write_chunk(w, chunk::sRGB, &[self.into_raw()])
     }
@@ -484,6 +782,16 @@ pub struct Info<'a> {
     pub srgb: Option<SrgbRenderingIntent>,
     /// The ICC profile for the image.
     pub icc_profile: Option<Cow<'a, [u8]>>,
+    /// International textual data of all `iTXt` chunks, in the order they appeared.
+    pub utf8_text: Vec<ITXtChunk>,
+    /// The palettes of all `sPLT` chunks, in the order they appeared.
+    pub suggested_palettes: Vec<SuggestedPalette>,
+    /// The `hIST` chunk, if present; the approximate number of uses of each palette entry.
+    pub histogram: Option<Vec<u16>>,
+    /// The background color of the `bKGD` chunk, if present.
+    pub background: Option<BackgroundColor>,
+    /// The chunks kept with `Decoder::set_retain_chunks`, in the order they appeared.
+    pub retained_chunks: Vec<RetainedChunk>,
     /// Private field to mark the struct as non-exhaustive.
     _extensible: (),
 }
@@ -508,13 +816,32 @@ impl Default for Info<'_> {
             source_chromaticities: None,
             srgb: None,
             icc_profile: None,
+            utf8_text: Vec::new(),
+            suggested_palettes: Vec::new(),
+            histogram: None,
+            background: None,
+            retained_chunks: Vec::new(),
             _extensible: (),
         }
     }
 }
 
-impl Info<'_> {
+impl<'a> Info<'a> {
     /// A utility constructor for a default info with width and height.
+    ///
+    /// The other fields of the header can be set in a chain, for an image to encode or to pass
+    /// to functions that take an info. The header is not checked until `validate` is called.
+    ///
+    /// ```
+    /// use png::{BitDepth, ColorType, Info};
+    /// let info = Info::with_size(5, 3)
+    ///     .color_type(ColorType::Rgba)
+    ///     .bit_depth(BitDepth::Sixteen)
+    ///     .interlaced(true);
+    /// assert_eq!(info.raw_row_length(), 1 + 5 * 8);
+    /// assert!(info.validate().is_ok());
+    /// assert!(info.bit_depth(BitDepth::Four).validate().is_err());
+    /// ```
     pub fn with_size(width: u32, height: u32) -> Self {
         Info {
             width,
@@ -523,6 +850,45 @@ impl Info<'_> {
         }
     }
 
+    /// Set the color type of the image.
+    pub fn color_type(mut self, color_type: ColorType) -> Self {
+        self.color_type = color_type;
+        self
+    }
+
+    /// Set the bit depth of the samples.
+    pub fn bit_depth(mut self, bit_depth: BitDepth) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    /// Set whether the image is interlaced with Adam7.
+    pub fn interlaced(mut self, interlaced: bool) -> Self {
+        self.interlaced = interlaced;
+        self
+    }
+
+    /// Set the palette, as RGB entries of 3 bytes each.
+    pub fn palette<T: Into<Cow<'a, [u8]>>>(mut self, palette: T) -> Self {
+        self.palette = Some(palette.into());
+        self
+    }
+
+    /// Set the transparency of the `tRNS` chunk.
+    pub fn trns<T: Into<Cow<'a, [u8]>>>(mut self, trns: T) -> Self {
+        self.trns = Some(trns.into());
+        self
+    }
+
+    /// Check that the image has a size, a valid combination of color type and bit depth, and a
+    /// palette and transparency that suit them.
+    ///
+    /// The encoder checks the same when it writes the header.
+    #[cfg(feature = "std")]
+    pub fn validate(&self) -> encoder::Result<()> {
+        encoder::validate_info(self)
+    }
+
     /// Size of the image, width then height.
     pub fn size(&self) -> (u32, u32) {
         (self.width, self.height)
@@ -543,6 +909,90 @@ impl Info<'_> {
         self.frame_control.as_ref()
     }
 
+    /// Returns a copy of the metadata that owns all of its data.
+    ///
+    /// Borrowed tables, such as a palette that was set from a slice, are copied so that the
+    /// snapshot does not depend on the lifetime of the reader, writer or buffer it was taken
+    /// from. The snapshot can be kept after dropping the reader right after reading the header:
+    ///
+    /// ```
+    /// let decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn3p08.png")?);
+    /// let info = decoder.read_info()?.info().to_owned_snapshot();
+    /// assert_eq!(info.size(), (32, 32));
+    /// assert!(info.palette.is_some());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn to_owned_snapshot(&self) -> Info<'static> {
+        fn owned(table: &Option<Cow<'_, [u8]>>) -> Option<Cow<'static, [u8]>> {
+            table.as_ref().map(|table| Cow::Owned(table.to_vec()))
+        }
+
+        Info {
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            interlaced: self.interlaced,
+            trns: owned(&self.trns),
+            pixel_dims: self.pixel_dims,
+            source_gamma: self.source_gamma,
+            palette: owned(&self.palette),
+            frame_control: self.frame_control,
+            animation_control: self.animation_control,
+            compression: self.compression,
+            source_chromaticities: self.source_chromaticities,
+            srgb: self.srgb,
+            icc_profile: owned(&self.icc_profile),
+            utf8_text: self.utf8_text.clone(),
+            suggested_palettes: self.suggested_palettes.clone(),
+            histogram: self.histogram.clone(),
+            background: self.background,
+            retained_chunks: self.retained_chunks.clone(),
+            _extensible: (),
+        }
+    }
+
+    /// Returns the palette as RGBA entries, if a `PLTE` chunk was present.
+    ///
+    /// The alpha of each entry is taken from the `tRNS` chunk of indexed images, entries it does
+    /// not cover are opaque.
+    pub fn rgba_palette(&self) -> Option<Vec<[u8; 4]>> {
+        let palette = self.palette.as_ref()?;
+        let trns = match self.trns {
+            Some(ref trns) if self.color_type == ColorType::Indexed => &trns[..],
+            _ => &[],
+        };
+        let entries = palette
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, rgb)| [rgb[0], rgb[1], rgb[2], trns.get(i).cloned().unwrap_or(0xff)])
+            .collect();
+        Some(entries)
+    }
+
+    /// Returns the ratio of pixel width to pixel height, if a `pHYs` chunk was present.
+    ///
+    /// See [`PixelDimensions::aspect_ratio`].
+    pub fn pixel_aspect_ratio(&self) -> Option<f32> {
+        self.pixel_dims.and_then(|dims| dims.aspect_ratio())
+    }
+
+    /// Returns the horizontal and vertical resolution in pixels per inch, if known.
+    ///
+    /// This requires a `pHYs` chunk which specifies its densities in pixels per meter.
+    ///
+    /// ```
+    /// use png::{Info, PixelDimensions, Unit};
+    /// let mut info = Info::default();
+    /// info.pixel_dims = Some(PixelDimensions { xppu: 11811, yppu: 11811, unit: Unit::Meter });
+    /// let (x, y) = info.dpi().unwrap();
+    /// assert_eq!((x.round(), y.round()), (300.0, 300.0));
+    /// assert_eq!(info.pixel_aspect_ratio(), Some(1.0));
+    /// ```
+    pub fn dpi(&self) -> Option<(f32, f32)> {
+        self.pixel_dims.and_then(|dims| dims.dpi())
+    }
+
     /// Returns the number of bits per pixel.
     pub fn bits_per_pixel(&self) -> usize {
         self.color_type.samples() * self.bit_depth as usize
@@ -598,7 +1048,9 @@ impl Info<'_> {
     /// Encode this header to the writer.
     ///
     /// Note that this does _not_ include the PNG signature, it starts with the IHDR chunk and then
-    /// includes other chunks that were added to the header.
+    /// includes other chunks that were added to the header. Retained chunks that belong after the
+    /// image data are not written.
+    #[cfg(feature = "std")]
     pub fn encode<W: Write>(&self, mut w: W) -> encoder::Result<()> {
         // Encode the IHDR chunk
         let mut data = [0; 13];
@@ -608,13 +1060,35 @@ impl Info<'_> {
         data[9] = self.color_type as u8;
         data[12] = self.interlaced as u8;
         encoder::write_chunk(&mut w, chunk::IHDR, &data)?;
+        self.encode_retained(&mut w, ChunkLocation::BeforePlte)?;
 
         if let Some(p) = &self.palette {
             encoder::write_chunk(&mut w, chunk::PLTE, p)?;
         };
 
+        if let Some(histogram) = &self.histogram {
+            let mut data = Vec::with_capacity(2 * histogram.len());
+            for n in histogram {
+                data.extend_from_slice(&n.to_be_bytes());
+            }
+            encoder::write_chunk(&mut w, chunk::hIST, &data)?;
+        }
+
         if let Some(t) = &self.trns {
-            encoder::write_chunk(&mut w, chunk::tRNS, t)?;
+            match (self.color_type, &t[..]) {
+                // The samples of 8 bits or less stored by the decoder.
+                (ColorType::Grayscale, &[gray]) => {
+                    encoder::write_chunk(&mut w, chunk::tRNS, &[0, gray])?
+                }
+                (ColorType::Rgb, &[r, g, b]) => {
+                    encoder::write_chunk(&mut w, chunk::tRNS, &[0, r, 0, g, 0, b])?
+                }
+                _ => encoder::write_chunk(&mut w, chunk::tRNS, t)?,
+            }
+        }
+
+        if let Some(background) = &self.background {
+            background.encode(&mut w)?;
         }
 
         // If specified, the sRGB information overrides the source gamma and chromaticities.
@@ -632,9 +1106,27 @@ impl Info<'_> {
                 chrms.encode(&mut w)?;
             }
         }
+        for palette in &self.suggested_palettes {
+            palette.encode(&mut w)?;
+        }
         if let Some(actl) = self.animation_control {
             actl.encode(&mut w)?;
         }
+        self.encode_retained(&mut w, ChunkLocation::BeforeIdat)
+    }
+
+    /// Encode the retained chunks that belong at `location`.
+    #[cfg(feature = "std")]
+    pub(crate) fn encode_retained<W: Write>(
+        &self,
+        mut w: W,
+        location: ChunkLocation,
+    ) -> encoder::Result<()> {
+        for retained in &self.retained_chunks {
+            if retained.location == location {
+                encoder::write_chunk(&mut w, retained.chunk_type, &retained.data)?;
+            }
+        }
         Ok(())
     }
 }
@@ -652,22 +1144,8 @@ bitflags! {
     ///
     #[doc = "
     ```c
-    /// Discard the alpha channel
-    const STRIP_ALPHA         = 0x0002; // read only
-    /// Expand 1; 2 and 4-bit samples to bytes
-    const PACKING             = 0x0004; // read and write
-    /// Change order of packed pixels to LSB first
-    const PACKSWAP            = 0x0008; // read and write
-    /// Invert monochrome images
-    const INVERT_MONO         = 0x0020; // read and write
     /// Normalize pixels to the sBIT depth
     const SHIFT               = 0x0040; // read and write
-    /// Flip RGB to BGR; RGBA to BGRA
-    const BGR                 = 0x0080; // read and write
-    /// Flip RGBA to ARGB or GA to AG
-    const SWAP_ALPHA          = 0x0100; // read and write
-    /// Byte-swap 16-bit samples
-    const SWAP_ENDIAN         = 0x0200; // read and write
     /// Change alpha from opacity to transparency
     const INVERT_ALPHA        = 0x0400; // read and write
     const STRIP_FILLER        = 0x0800; // write only
@@ -675,10 +1153,6 @@ bitflags! {
     const STRIP_FILLER_AFTER  = 0x1000; // write only
     const GRAY_TO_RGB         = 0x2000; // read only
     const EXPAND_16           = 0x4000; // read only
-    /// Similar to STRIP_16 but in libpng considering gamma?
-    /// Not entirely sure the documentation says it is more
-    /// accurate but doesn't say precisely how.
-    const SCALE_16            = 0x8000; // read only
     ```
     "]
     pub struct Transformations: u32 {
@@ -686,13 +1160,99 @@ bitflags! {
         const IDENTITY            = 0x0000; // read and write */
         /// Strip 16-bit samples to 8 bits
         const STRIP_16            = 0x0001; // read only */
+        /// Scale 16-bit samples to 8 bits, rounding to the nearest value.
+        ///
+        /// In contrast to `STRIP_16`, which keeps the high byte of each sample, this computes
+        /// `(v * 255 + 32895) >> 16` so that the result is the closest 8-bit value. It takes
+        /// precedence over `STRIP_16` if both are set.
+        const SCALE_16            = 0x8000; // read only */
+        /// Scale 16-bit samples to 8 bits as `SCALE_16`, converting colors to the sRGB gamma.
+        ///
+        /// The color samples are corrected from the gamma of the image, given by its gAMA or sRGB
+        /// chunk, to the gamma of `1/2.2` expected for 8-bit output. Images without gamma
+        /// information are assumed to use it already. Alpha samples are scaled linearly.
+        const SCALE_16_GAMMA      = 0x80000; // read only */
+        /// Discard the alpha channel.
+        ///
+        /// This is applied last, so that it also removes the alpha channel added by `EXPAND` for
+        /// images with a tRNS chunk or by `RGBA8` and `RGBA16`.
+        const STRIP_ALPHA         = 0x0002; // read only */
         /// Expand paletted images to RGB; expand grayscale images of
         /// less than 8-bit depth to 8-bit depth; and expand tRNS chunks
         /// to alpha channels.
+        ///
+        /// Images of 16-bit depth keep it, the alpha channel of a tRNS chunk then has 16 bits as
+        /// well. Combine this with `STRIP_16` or `SCALE_16` for 8-bit output.
         const EXPAND              = 0x0010; // read only */
+        /// Change order of packed pixels to LSB first.
+        ///
+        /// Without `EXPAND`, the pixels of grayscale and indexed images with less than 8 bits
+        /// stay packed. As in the PNG data, the leftmost pixel of each byte is in its most
+        /// significant bits. This reverses the order, placing the leftmost pixel in the least
+        /// significant bits, and has no effect on pixels of 8 bits or more.
+        const PACKSWAP            = 0x0008; // read only */
+        /// Unpack 1, 2 and 4-bit samples to one byte each, without scaling them.
+        ///
+        /// In contrast to `EXPAND`, indexed images keep their color type so that the output holds
+        /// one palette index per byte. The tables to look them up are in the `palette` and `trns`
+        /// fields of the `Info`, or combined by `Info::rgba_palette`. Grayscale samples keep their
+        /// range, e.g. 0 to 3 for 2-bit images. `EXPAND` takes precedence if both are set.
+        const PACKING             = 0x0004; // read only */
+        /// Invert grayscale samples, so that black becomes white.
+        ///
+        /// The alpha channel of grayscale images is not changed. This is applied before gray
+        /// samples are converted to RGB by `RGBA8` or `RGBA16`.
+        const INVERT_MONO         = 0x0020; // read only */
+        /// Flip RGB to BGR; RGBA to BGRA.
+        ///
+        /// The output color type is still reported as RGB or RGBA.
+        const BGR                 = 0x0080; // read only */
+        /// Flip RGBA to ARGB or GA to AG.
+        ///
+        /// Combined with `BGR` this produces ABGR. The output color type is not changed.
+        const SWAP_ALPHA          = 0x0100; // read only */
+        /// Byte-swap 16-bit samples, producing little endian output.
+        const SWAP_ENDIAN         = 0x0200; // read only */
+        /// Output every image as 8-bit RGBA.
+        ///
+        /// This implies `EXPAND` and `STRIP_16`. Grayscale samples are additionally converted
+        /// to RGB and an opaque alpha channel is added to images without one, so that the output
+        /// has the same layout for every input.
+        const RGBA8               = 0x10000; // read only */
+        /// Output every image as 16-bit RGBA.
+        ///
+        /// This implies `EXPAND`, samples of lower bit depth are scaled up to the full 16-bit
+        /// range and the color type is normalized as with `RGBA8`, which takes precedence if both
+        /// are set.
+        const RGBA16              = 0x20000; // read only */
+        /// Output only the alpha channel, as an 8-bit grayscale mask.
+        ///
+        /// Images without alpha channel or tRNS chunk produce an opaque mask. This replaces all
+        /// other transformations affecting the color type or the order of samples.
+        const ALPHA_MASK          = 0x40000; // read only */
+        /// Add an opaque alpha channel to grayscale and RGB output.
+        ///
+        /// This implies `EXPAND`, so that indexed and packed images gain one as well. Images with
+        /// an alpha channel, also one expanded from a tRNS chunk, are not changed. Unlike `RGBA8`
+        /// and `RGBA16` the bit depth is kept and grayscale is not converted to RGB.
+        const ADD_ALPHA           = 0x100000; // read only */
+        /// Composite the pixels onto a background color, removing the alpha channel.
+        ///
+        /// This implies `EXPAND`. The color is the one set with `Decoder::set_background` or else
+        /// that of the `bKGD` chunk, images without either are composited onto white. Other
+        /// transformations then see an image without alpha channel, so that `ADD_ALPHA`, `RGBA8`
+        /// and `RGBA16` produce opaque pixels.
+        const BACKGROUND          = 0x200000; // read only */
     }
 }
 
+/// The transformations of a `Decoder` that were not chosen explicitly.
+///
+/// The image data is returned as stored in the file, without any transformation, so that the
+/// output matches the `Info` of the image. Use `Decoder::new_with_transformations` or
+/// `Decoder::set_transformations` to select others.
+pub const DEFAULT_TRANSFORMATIONS: Transformations = Transformations::IDENTITY;
+
 impl Transformations {
     /// Transform every input to 8bit grayscale or color.
     ///
@@ -701,12 +1261,55 @@ impl Transformations {
     pub fn normalize_to_color8() -> Transformations {
         Transformations::EXPAND | Transformations::STRIP_16
     }
+
+    /// The order of channels selected by `BGR` and `SWAP_ALPHA`.
+    pub fn channel_order(self) -> ChannelOrder {
+        let bgr = self.contains(Transformations::BGR);
+        match (bgr, self.contains(Transformations::SWAP_ALPHA)) {
+            (false, false) => ChannelOrder::Rgba,
+            (true, false) => ChannelOrder::Bgra,
+            (false, true) => ChannelOrder::Argb,
+            (true, true) => ChannelOrder::Abgr,
+        }
+    }
+
+    /// Replace the order of channels, setting `BGR` and `SWAP_ALPHA` accordingly.
+    pub fn with_channel_order(mut self, order: ChannelOrder) -> Transformations {
+        let (bgr, alpha_first) = order.swaps();
+        self.set(Transformations::BGR, bgr);
+        self.set(Transformations::SWAP_ALPHA, alpha_first);
+        self
+    }
+
+    /// Add the transformations implied by the normalizing flags.
+    pub(crate) fn implied(mut self) -> Transformations {
+        if self.contains(Transformations::ALPHA_MASK) {
+            // The mask is extracted from the RGBA8 output.
+            self.remove(
+                Transformations::RGBA16
+                    | Transformations::STRIP_ALPHA
+                    | Transformations::BGR
+                    | Transformations::SWAP_ALPHA,
+            );
+            self |= Transformations::RGBA8;
+        }
+        if self.intersects(Transformations::ADD_ALPHA | Transformations::BACKGROUND) {
+            self |= Transformations::EXPAND;
+        }
+        if self.contains(Transformations::RGBA8) {
+            self | Transformations::normalize_to_color8()
+        } else if self.contains(Transformations::RGBA16) {
+            self | Transformations::EXPAND
+        } else {
+            self
+        }
+    }
 }
 
 /// Instantiate the default transformations, the identity transform.
 impl Default for Transformations {
     fn default() -> Transformations {
-        Transformations::IDENTITY
+        DEFAULT_TRANSFORMATIONS
     }
 }
 
@@ -723,6 +1326,9 @@ pub(crate) enum ParameterErrorKind {
     /// because they must react to a value produced by this library, which can have been subjected
     /// to limits.
     ImageBufferSize { expected: usize, actual: usize },
+    /// Only true color images with 8-bit samples can be reduced to a palette.
+    #[cfg(feature = "quantize")]
+    UnsupportedQuantization(ColorType),
     /// A bit like return `None` from an iterator.
     /// We use it to differentiate between failing to seek to the next image in a sequence and the
     /// absence of a next image. This is an error of the caller because they should have checked
@@ -730,6 +1336,38 @@ pub(crate) enum ParameterErrorKind {
     /// library will perform the checks necessary to ensure that data was accurate or error with a
     /// format error otherwise.
     PolledAfterEndOfImage,
+    /// Decoding was continued after an error in the data, which it can not recover from.
+    PolledAfterFatalError,
+    /// A sample of unpacked image data does not fit into the bit depth of the image.
+    SampleOutOfRange { sample: u8, bit_depth: u8 },
+    /// Transposed output was requested for pixels that are packed into less than a byte.
+    TransposedPackedPixels { bits_per_pixel: u8 },
+    /// Output in bands was requested for a frame whose rows are not written in order.
+    UnsupportedBands,
+    /// Bilevel image data was written for an image that is not 1-bit grayscale.
+    NotBilevel {
+        color_type: ColorType,
+        bit_depth: BitDepth,
+    },
+    /// The image data of an interlaced image was streamed, whose passes need the whole frame.
+    InterlacedStream,
+    /// A region was requested that does not lie within the frame.
+    RegionOutOfBounds,
+    /// Frames of a reduced resolution were requested in bands or transposed.
+    UnsupportedDownscale,
+    /// A whole frame was requested after some of its rows were read on their own.
+    FrameStarted { rows_read: u32 },
+    /// The stride of the output rows is less than their length.
+    StrideTooSmall { stride: usize, line_size: usize },
+    /// A stride of the output rows was given for transposed output.
+    TransposedStride,
+    /// Samples of 16 bits were requested for output of a lower bit depth.
+    NotSixteenBit { bit_depth: u8 },
+    /// Samples of 16 bits were requested for a frame that is downscaled, transposed or written in
+    /// bands.
+    UnsupportedSixteenBit,
+    /// The number of row filters differs from the number of rows stored for the frame.
+    RowFilterCount { expected: usize, actual: usize },
 }
 
 impl From<ParameterErrorKind> for ParameterError {
@@ -738,6 +1376,9 @@ impl From<ParameterErrorKind> for ParameterError {
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ParameterError {}
+
 impl fmt::Display for ParameterError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         use ParameterErrorKind::*;
@@ -745,7 +1386,72 @@ impl fmt::Display for ParameterError {
             ImageBufferSize { expected, actual } => {
                 write!(fmt, "wrong data size, expected {} got {}", expected, actual)
             }
+            #[cfg(feature = "quantize")]
+            UnsupportedQuantization(color_type) => {
+                write!(
+                    fmt,
+                    "can not quantize images of color type {:?}",
+                    color_type
+                )
+            }
             PolledAfterEndOfImage => write!(fmt, "End of image has been reached"),
+            PolledAfterFatalError => write!(fmt, "decoding can not continue after an error"),
+            SampleOutOfRange { sample, bit_depth } => write!(
+                fmt,
+                "sample {} does not fit into a bit depth of {}",
+                sample, bit_depth
+            ),
+            TransposedPackedPixels { bits_per_pixel } => write!(
+                fmt,
+                "pixels of {} bits can not be transposed, at least 8 bits are required",
+                bits_per_pixel
+            ),
+            UnsupportedBands => write!(
+                fmt,
+                "only frames without interlacing in row-major order can be written in bands"
+            ),
+            NotBilevel {
+                color_type,
+                bit_depth,
+            } => write!(
+                fmt,
+                "bilevel image data requires 1-bit grayscale, not color-type '{:?}' with bit-depth '{:?}'",
+                color_type, bit_depth
+            ),
+            InterlacedStream => write!(
+                fmt,
+                "interlaced image data can not be streamed, write the whole frame instead"
+            ),
+            RegionOutOfBounds => write!(fmt, "the region does not lie within the frame"),
+            UnsupportedDownscale => write!(
+                fmt,
+                "frames of a reduced resolution can not be transposed or written in bands"
+            ),
+            FrameStarted { rows_read } => write!(
+                fmt,
+                "{} rows of the frame were already read, read the remaining rows instead",
+                rows_read
+            ),
+            StrideTooSmall { stride, line_size } => write!(
+                fmt,
+                "a stride of {} bytes is less than the rows of {} bytes",
+                stride, line_size
+            ),
+            TransposedStride => write!(fmt, "transposed output can not be written with a stride"),
+            NotSixteenBit { bit_depth } => write!(
+                fmt,
+                "16-bit samples were requested for output of {} bits",
+                bit_depth
+            ),
+            UnsupportedSixteenBit => write!(
+                fmt,
+                "16-bit samples can only be written for whole frames at full resolution in row-major order"
+            ),
+            RowFilterCount { expected, actual } => write!(
+                fmt,
+                "expected a filter for each of the {} rows of the frame, got {}",
+                expected, actual
+            ),
         }
     }
 }
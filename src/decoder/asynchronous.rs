@@ -0,0 +1,166 @@
+use std::future::Future;
+use std::io::{self, BufRead, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::AsyncRead;
+
+use super::CHUNCK_BUFFER_SIZE;
+use super::{is_would_block, Decoder, DecodingError, Limits, OutputInfo, Reader};
+use crate::common::{Info, Transformations};
+
+/// The data read from the asynchronous source that the reader did not consume yet.
+///
+/// Reading from it returns `WouldBlock` when it is empty, the reader is then resumed after more
+/// data was read.
+struct Pending {
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    eof: bool,
+}
+
+impl Pending {
+    fn new() -> Self {
+        Pending {
+            buf: vec![0; CHUNCK_BUFFER_SIZE],
+            pos: 0,
+            len: 0,
+            eof: false,
+        }
+    }
+}
+
+impl Read for Pending {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for Pending {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.len && !self.eof {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Future reading the next piece of data from the source.
+struct Fill<'a, R> {
+    source: &'a mut R,
+    pending: &'a mut Pending,
+}
+
+impl<R: AsyncRead + Unpin> Future for Fill<'_, R> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let pending = &mut *this.pending;
+        // The reader only runs out of data once all of it was consumed.
+        debug_assert_eq!(pending.pos, pending.len);
+        let read = match Pin::new(&mut *this.source).poll_read(cx, &mut pending.buf) {
+            Poll::Ready(Ok(read)) => read,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        pending.pos = 0;
+        pending.len = read;
+        pending.eof = read == 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// PNG decoder reading from an asynchronous source.
+///
+/// This drives the same state machine as the `Reader`, reading more data from the source
+/// whenever it runs out of input instead of blocking. The source is any `futures::io::AsyncRead`,
+/// the readers of other runtimes such as Tokio can be used through their compatibility wrappers.
+///
+/// ```
+/// # async fn decode(data: &[u8]) -> Result<(), png::DecodingError> {
+/// let mut decoder = png::AsyncDecoder::new(data);
+/// decoder.read_info().await?;
+/// let mut buf = vec![0; decoder.output_buffer_size()];
+/// decoder.next_frame(&mut buf).await?;
+/// # Ok(()) }
+/// ```
+pub struct AsyncDecoder<R> {
+    source: R,
+    reader: Reader<Pending, Pending>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecoder<R> {
+    /// Create a new decoder reading from `source`.
+    pub fn new(source: R) -> AsyncDecoder<R> {
+        AsyncDecoder::new_with_limits(source, Limits::default())
+    }
+
+    /// Create a new decoder with custom limits.
+    pub fn new_with_limits(source: R, limits: Limits) -> AsyncDecoder<R> {
+        AsyncDecoder {
+            source,
+            reader: Decoder::new_with_limits(Pending::new(), limits).into_buffered_reader(),
+        }
+    }
+
+    /// Set the allowed and performed transformations.
+    ///
+    /// This must be set before reading the meta data.
+    pub fn set_transformations(&mut self, transform: Transformations) {
+        self.reader.transform = transform;
+    }
+
+    /// Reads all meta data until the first IDAT chunk.
+    pub async fn read_info(&mut self) -> Result<&Info<'static>, DecodingError> {
+        loop {
+            let result = self.reader.read_info().map(|_| ());
+            if is_would_block(&result) {
+                self.fill().await?;
+                continue;
+            }
+            result?;
+            return Ok(self.reader.info());
+        }
+    }
+
+    /// Decodes the next frame into `buf`.
+    ///
+    /// See [`Reader::next_frame`](struct.Reader.html#method.next_frame).
+    pub async fn next_frame(&mut self, buf: &mut [u8]) -> Result<OutputInfo, DecodingError> {
+        loop {
+            let result = self.reader.next_frame(buf);
+            if is_would_block(&result) {
+                self.fill().await?;
+                continue;
+            }
+            return result;
+        }
+    }
+
+    /// Get information on the image.
+    ///
+    /// This panics if the meta data has not been read yet.
+    pub fn info(&self) -> &Info<'static> {
+        self.reader.info()
+    }
+
+    /// Returns the number of bytes required to hold a deinterlaced image frame.
+    pub fn output_buffer_size(&self) -> usize {
+        self.reader.output_buffer_size()
+    }
+
+    fn fill(&mut self) -> Fill<'_, R> {
+        Fill {
+            source: &mut self.source,
+            pending: &mut self.reader.decoder.reader,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Reducing the resolution of frames while their rows are decoded.
+use crate::common::{BitDepth, ColorType};
+
+/// The factor by which `Reader::next_frame` reduces the resolution of frames.
+///
+/// Each block of `factor` by `factor` pixels becomes one pixel of the output, the blocks at the
+/// right and bottom edges may be smaller. The samples of a block are averaged, except for packed
+/// pixels and palette indices for which the top left pixel of the block is kept. Set it with
+/// `Decoder::set_downscale`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Downscale {
+    /// Decode frames at their full resolution. This is the default.
+    None,
+    /// Decode frames at half their width and height.
+    Half,
+    /// Decode frames at a quarter of their width and height.
+    Quarter,
+    /// Decode frames at an eighth of their width and height.
+    Eighth,
+}
+
+impl Default for Downscale {
+    fn default() -> Self {
+        Downscale::None
+    }
+}
+
+impl Downscale {
+    /// The number of pixels in each direction that are reduced to one.
+    pub fn factor(self) -> u32 {
+        match self {
+            Downscale::None => 1,
+            Downscale::Half => 2,
+            Downscale::Quarter => 4,
+            Downscale::Eighth => 8,
+        }
+    }
+
+    /// The size of a frame of `width` by `height` pixels after the reduction.
+    pub fn size(self, width: u32, height: u32) -> (u32, u32) {
+        let factor = self.factor();
+        let reduce = |len: u32| len / factor + (len % factor != 0) as u32;
+        (reduce(width), reduce(height))
+    }
+}
+
+/// Reduces the rows of a frame, in any order, to a frame of a lower resolution.
+pub(super) struct Accumulator {
+    factor: u32,
+    width: u32,
+    height: u32,
+    out_width: usize,
+    line_size: usize,
+    channels: usize,
+    bits_pp: usize,
+    /// Whether the samples of a block are averaged, otherwise the top left pixel is kept.
+    average: bool,
+    /// Whether the rows arrive from top to bottom, so that only one output row is summed.
+    progressive: bool,
+    /// The sums of the samples of each block of the output rows.
+    sums: Vec<u32>,
+}
+
+impl Accumulator {
+    /// Reduce a frame of `width` by `height` pixels, in the output color type and bit depth.
+    pub(super) fn new(
+        downscale: Downscale,
+        (width, height): (u32, u32),
+        (color_type, bit_depth): (ColorType, BitDepth),
+        line_size: usize,
+        progressive: bool,
+    ) -> Accumulator {
+        let (out_width, out_height) = downscale.size(width, height);
+        let channels = color_type.samples();
+        let bits_pp = channels * bit_depth as usize;
+        let average = color_type != ColorType::Indexed && bit_depth as u8 >= 8;
+        let rows = match (average, progressive) {
+            (false, _) => 0,
+            (true, true) => 1,
+            (true, false) => out_height as usize,
+        };
+        Accumulator {
+            factor: downscale.factor(),
+            width,
+            height,
+            out_width: out_width as usize,
+            line_size,
+            channels,
+            bits_pp,
+            average,
+            progressive,
+            sums: vec![0; rows * out_width as usize * channels],
+        }
+    }
+
+    /// Add the pixels of the row `y` that are in the columns from `x` on in steps of `step`.
+    pub(super) fn add_row(&mut self, row: &[u8], y: u32, (x, step): (u32, u32), out: &mut [u8]) {
+        let factor = self.factor;
+        let out_row = (y / factor) as usize;
+        let columns = (x..self.width).step_by(step as usize);
+
+        if !self.average {
+            if y % factor != 0 {
+                return;
+            }
+            let line = &mut out[out_row * self.line_size..][..self.line_size];
+            for (i, x) in columns.enumerate().filter(|&(_, x)| x % factor == 0) {
+                copy_pixel(row, i, line, (x / factor) as usize, self.bits_pp);
+            }
+            return;
+        }
+
+        let row_len = self.out_width * self.channels;
+        let sum_row = if self.progressive { 0 } else { out_row };
+        let sums = &mut self.sums[sum_row * row_len..][..row_len];
+        let wide = self.bits_pp / self.channels == 16;
+        for (i, x) in columns.enumerate() {
+            let block = (x / factor) as usize * self.channels;
+            for c in 0..self.channels {
+                sums[block + c] += sample(row, i * self.channels + c, wide);
+            }
+        }
+        if self.progressive && ((y + 1) % factor == 0 || y + 1 == self.height) {
+            self.write_row(0, out_row, out);
+        }
+    }
+
+    /// Write the output rows that are still summed.
+    pub(super) fn finish(&mut self, out: &mut [u8]) {
+        if self.average && !self.progressive {
+            let rows = self.sums.len() / (self.out_width * self.channels).max(1);
+            for row in 0..rows {
+                self.write_row(row, row, out);
+            }
+        }
+    }
+
+    /// Write the averages of the summed row `sum_row` to the output row `out_row`, and clear it.
+    fn write_row(&mut self, sum_row: usize, out_row: usize, out: &mut [u8]) {
+        let factor = self.factor;
+        let row_len = self.out_width * self.channels;
+        let sums = &mut self.sums[sum_row * row_len..][..row_len];
+        let line = &mut out[out_row * self.line_size..][..self.line_size];
+        let rows = factor.min(self.height - out_row as u32 * factor);
+        let wide = self.bits_pp / self.channels == 16;
+
+        for (block, pixel) in sums.chunks_mut(self.channels).enumerate() {
+            let columns = factor.min(self.width - block as u32 * factor);
+            let count = rows * columns;
+            for (c, sum) in pixel.iter_mut().enumerate() {
+                let average = (*sum + count / 2) / count;
+                let index = block * self.channels + c;
+                if wide {
+                    line[2 * index..][..2].copy_from_slice(&(average as u16).to_be_bytes());
+                } else {
+                    line[index] = average as u8;
+                }
+                *sum = 0;
+            }
+        }
+    }
+}
+
+/// The sample at `index` of a row of 8-bit or, if `wide`, 16-bit samples.
+fn sample(row: &[u8], index: usize, wide: bool) -> u32 {
+    if wide {
+        u32::from(u16::from_be_bytes([row[2 * index], row[2 * index + 1]]))
+    } else {
+        u32::from(row[index])
+    }
+}
+
+/// Copy the pixel `from` of `row` to the pixel `to` of `line`.
+fn copy_pixel(row: &[u8], from: usize, line: &mut [u8], to: usize, bits_pp: usize) {
+    if bits_pp % 8 == 0 {
+        let bytes = bits_pp / 8;
+        line[to * bytes..][..bytes].copy_from_slice(&row[from * bytes..][..bytes]);
+    } else {
+        let mask = (1u8 << bits_pp) - 1;
+        let (src, dst) = (from * bits_pp, to * bits_pp);
+        let pixel = (row[src / 8] >> (8 - src % 8 - bits_pp)) & mask;
+        let shift = 8 - dst % 8 - bits_pp;
+        line[dst / 8] = line[dst / 8] & !(mask << shift) | pixel << shift;
+    }
+}
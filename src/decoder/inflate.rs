@@ -0,0 +1,168 @@
+//! The backends that decompress the image data.
+//!
+//! By default the image data is decompressed by `miniz_oxide`, in pure Rust. With the `zlib`
+//! feature the C zlib library is used instead, through `flate2`. Compressed textual chunks are
+//! always decompressed by `miniz_oxide`.
+use core::fmt;
+
+use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+use miniz_oxide::inflate::TINFLStatus;
+
+/// The backend selected by the features of the crate.
+#[cfg(not(feature = "zlib"))]
+pub(super) type Backend = MinizInflater;
+/// The backend selected by the features of the crate.
+#[cfg(feature = "zlib")]
+pub(super) type Backend = ZlibInflater;
+
+/// The progress of a call to an inflater.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum InflateStatus {
+    /// The end of the compressed stream was reached.
+    Done,
+    /// All input was consumed without reaching the end of the stream.
+    NeedsMoreInput,
+    /// The output buffer is full.
+    HasMoreOutput,
+}
+
+/// An error of the compressed stream, as reported by the backend.
+#[derive(Debug)]
+pub(crate) enum InflateError {
+    Miniz(TINFLStatus),
+    #[cfg(feature = "zlib")]
+    Zlib(flate2::DecompressError),
+    /// The stream ended before its end was reached.
+    Incomplete,
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InflateError::Miniz(status) => write!(fmt, "{:?}", status),
+            #[cfg(feature = "zlib")]
+            InflateError::Zlib(err) => write!(fmt, "{}", err),
+            InflateError::Incomplete => write!(fmt, "the stream is incomplete"),
+        }
+    }
+}
+
+/// A decompressor of deflate streams.
+pub(super) trait Inflate {
+    /// Prepare for a new stream, with or without a zlib header and checksum.
+    fn reset(&mut self, zlib_header: bool);
+
+    /// Decompress `input` into `output` from `out_pos` on.
+    ///
+    /// The output before `out_pos` holds at least the last 32 KiB decompressed before, if there
+    /// are as many, which a backend may refer to instead of keeping its own window. Unless
+    /// `more_input` is set, the input is the end of the stream. Returns the status along with the
+    /// number of bytes consumed and produced.
+    fn inflate(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        out_pos: usize,
+        more_input: bool,
+    ) -> (Result<InflateStatus, InflateError>, usize, usize);
+}
+
+/// The pure Rust decompressor of `miniz_oxide`.
+#[cfg_attr(feature = "zlib", allow(dead_code))]
+pub(super) struct MinizInflater {
+    state: DecompressorOxide,
+    zlib_header: bool,
+}
+
+#[cfg_attr(feature = "zlib", allow(dead_code))]
+impl MinizInflater {
+    pub(super) fn new() -> Self {
+        MinizInflater {
+            state: DecompressorOxide::default(),
+            zlib_header: true,
+        }
+    }
+}
+
+impl Inflate for MinizInflater {
+    fn reset(&mut self, zlib_header: bool) {
+        self.state = DecompressorOxide::default();
+        self.zlib_header = zlib_header;
+    }
+
+    fn inflate(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        out_pos: usize,
+        more_input: bool,
+    ) -> (Result<InflateStatus, InflateError>, usize, usize) {
+        let mut flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        if more_input {
+            flags |= inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+        }
+        if self.zlib_header {
+            flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
+        }
+        let (status, in_consumed, out_consumed) =
+            decompress(&mut self.state, input, output, out_pos, flags);
+        let status = match status {
+            TINFLStatus::Done => Ok(InflateStatus::Done),
+            TINFLStatus::NeedsMoreInput => Ok(InflateStatus::NeedsMoreInput),
+            TINFLStatus::HasMoreOutput => Ok(InflateStatus::HasMoreOutput),
+            err => Err(InflateError::Miniz(err)),
+        };
+        (status, in_consumed, out_consumed)
+    }
+}
+
+/// The C zlib library, through `flate2`.
+#[cfg(feature = "zlib")]
+pub(super) struct ZlibInflater {
+    inner: flate2::Decompress,
+}
+
+#[cfg(feature = "zlib")]
+impl ZlibInflater {
+    pub(super) fn new() -> Self {
+        ZlibInflater {
+            inner: flate2::Decompress::new(true),
+        }
+    }
+}
+
+#[cfg(feature = "zlib")]
+impl Inflate for ZlibInflater {
+    fn reset(&mut self, zlib_header: bool) {
+        self.inner.reset(zlib_header);
+    }
+
+    fn inflate(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        out_pos: usize,
+        more_input: bool,
+    ) -> (Result<InflateStatus, InflateError>, usize, usize) {
+        use flate2::{FlushDecompress, Status};
+
+        // zlib keeps its own window and only needs the free part of the buffer.
+        let output = &mut output[out_pos..];
+        let (total_in, total_out) = (self.inner.total_in(), self.inner.total_out());
+        let flush = if more_input {
+            FlushDecompress::None
+        } else {
+            FlushDecompress::Finish
+        };
+        let result = self.inner.decompress(input, output, flush);
+        let in_consumed = (self.inner.total_in() - total_in) as usize;
+        let out_consumed = (self.inner.total_out() - total_out) as usize;
+        let status = match result {
+            Ok(Status::StreamEnd) => Ok(InflateStatus::Done),
+            Ok(_) if out_consumed == output.len() => Ok(InflateStatus::HasMoreOutput),
+            Ok(_) => Ok(InflateStatus::NeedsMoreInput),
+            Err(err) => Err(InflateError::Zlib(err)),
+        };
+        (status, in_consumed, out_consumed)
+    }
+}
@@ -1,30 +1,90 @@
+// The async feature raises the required Rust version.
+#[cfg(feature = "async")]
+#[clippy::msrv = "1.39"]
+mod asynchronous;
+#[cfg(feature = "std")]
+mod downscale;
+mod inflate;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "std")]
+mod progressive;
+mod smallbuf;
+mod statistics;
 mod stream;
+#[cfg(feature = "std")]
+mod structure;
 mod zlib;
 
-pub use self::stream::{Decoded, DecodingError, StreamingDecoder};
-use self::stream::{FormatErrorInner, CHUNCK_BUFFER_SIZE};
+#[cfg(feature = "async")]
+pub use self::asynchronous::AsyncDecoder;
+#[cfg(feature = "std")]
+use self::downscale::Accumulator;
+#[cfg(feature = "std")]
+pub use self::downscale::Downscale;
+#[cfg(feature = "std")]
+pub use self::progressive::{Progress, ProgressiveCallbacks, ProgressiveDecoder, RowBatch};
+#[cfg(feature = "std")]
+use self::smallbuf::SmallBuf;
+pub use self::statistics::ColorStatistics;
+pub(crate) use self::stream::FormatErrorInner;
+#[cfg(feature = "std")]
+use self::stream::CHUNCK_BUFFER_SIZE;
+pub use self::stream::{
+    CrcPolicy, Decoded, DecodingError, FormatError, FormatErrorKind, ShortImageData,
+    StreamingDecoder, Utf8Policy,
+};
+#[cfg(feature = "std")]
+pub use self::structure::{ChunkFields, ChunkRecord, Structure};
 
-use std::io::{BufRead, BufReader, Read, Write};
+// Only the streaming decoder is available without the standard library, the decoder of readers
+// and everything it depends on is enabled by the `std` feature.
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(feature = "std")]
+use std::marker::PhantomData;
+#[cfg(feature = "std")]
 use std::mem;
+#[cfg(feature = "std")]
 use std::ops::Range;
 
+#[cfg(feature = "std")]
+use crate::adam7;
+#[cfg(feature = "std")]
 use crate::chunk;
+#[cfg(feature = "std")]
 use crate::common::{
-    BitDepth, BytesPerPixel, ColorType, Info, ParameterErrorKind, Transformations,
+    BackgroundColor, BytesPerPixel, ChannelOrder, Info, ParameterErrorKind, Transformations,
+    DEFAULT_TRANSFORMATIONS,
 };
-use crate::filter::{unfilter, FilterType};
+use crate::common::{BitDepth, ColorType};
+use crate::filter::FilterType;
+#[cfg(feature = "std")]
+use crate::filter::{unfilter, unfilter_first_row};
+#[cfg(feature = "std")]
 use crate::utils;
 
-/*
+/// How `Reader::next_pass` writes the pass of an interlaced frame.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InterlaceHandling {
-    /// Outputs the raw rows
+    /// Outputs the raw rows.
+    ///
+    /// The rows of the pass are written one after another, as an image of the size of the pass.
     RawRows,
-    /// Fill missing the pixels from the existing ones
+    /// Fill the missing pixels from the existing ones.
+    ///
+    /// Each pixel of the pass fills the block of pixels that later passes refine, so that the
+    /// buffer holds a blocky approximation of the frame after every pass.
     Rectangle,
-    /// Only fill the needed pixels
-    Sparkle
+    /// Only fill the needed pixels.
+    ///
+    /// The pixels of the pass are written at their place in the frame, the other pixels of the
+    /// buffer are left unchanged.
+    Sparkle,
 }
-*/
 
 /// Output info.
 ///
@@ -52,38 +112,284 @@ impl OutputInfo {
     }
 }
 
+/// How far the current frame is decoded, see `Reader::progress`.
+///
+/// The rows are the rows of the image data as stored, for interlaced frames those of all passes.
+/// The bytes are those of the decompressed image data, including the filter type of each row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameProgress {
+    /// The rows of the frame not yet decoded.
+    pub rows_remaining: u32,
+    /// The number of rows of the frame.
+    pub rows_total: u32,
+    /// The bytes of image data of the frame decoded so far.
+    pub bytes_consumed: usize,
+    /// The bytes of image data of the whole frame.
+    pub bytes_expected: usize,
+    /// The Adam7 pass of the next row, `None` if the frame is not interlaced or complete.
+    pub pass: Option<u8>,
+}
+
 #[derive(Clone, Copy, Debug)]
 /// Limits on the resources the `Decoder` is allowed too use
+///
+/// Exceeding any of these limits results in `DecodingError::LimitsExceeded`, except for textual
+/// chunks longer than `text_bytes`, which are skipped. The checks happen as soon as the offending
+/// value is known, in particular the image dimensions and the size of the output buffer are
+/// validated after reading the `IHDR` chunk and before any image data is decoded.
 pub struct Limits {
     /// maximum number of bytes the decoder is allowed to allocate, default is 64Mib
     pub bytes: usize,
+    /// maximum width of the image in pixels, default is unlimited
+    pub width: u32,
+    /// maximum height of the image in pixels, default is unlimited
+    pub height: u32,
+    /// maximum size of a full output frame in bytes, as reported by
+    /// `Reader::output_buffer_size`, default is 1GiB
+    pub pixel_bytes: usize,
+    /// maximum length of a single ancillary chunk other than `fdAT`, default is 16MiB
+    pub ancillary_chunk_bytes: u32,
+    /// maximum length of a single textual chunk (`tEXt`, `zTXt` or `iTXt`) that is decoded, and
+    /// of its decompressed text, longer ones are skipped, default is 1MiB
+    pub text_bytes: u32,
 }
 
 impl Default for Limits {
     fn default() -> Limits {
         Limits {
             bytes: 1024 * 1024 * 64,
+            width: core::u32::MAX,
+            height: core::u32::MAX,
+            pixel_bytes: 1024 * 1024 * 1024,
+            ancillary_chunk_bytes: 1024 * 1024 * 16,
+            text_bytes: 1024 * 1024,
         }
     }
 }
 
+/// Decode the first frame of a PNG image.
+///
+/// The image is expanded to at least 8 bits per sample, with the palette and transparency
+/// applied, like with `Transformations::EXPAND`. The returned `OutputInfo` describes the color
+/// type, bit depth and size of the pixels. For other transformations, animations or a limit on
+/// the memory use configure a `Decoder` instead.
+///
+/// ```
+/// use std::fs::File;
+/// let (info, pixels) = png::decode(File::open("tests/pngsuite/basn3p04.png")?)?;
+/// assert_eq!(info.color_type, png::ColorType::Rgb);
+/// assert_eq!(pixels.len(), 3 * 32 * 32);
+/// # Ok::<(), png::DecodingError>(())
+/// ```
+#[cfg(feature = "std")]
+pub fn decode<R: Read>(r: R) -> Result<(OutputInfo, Vec<u8>), DecodingError> {
+    let mut decoder = Decoder::new(r);
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+    Ok((info, pixels))
+}
+
+/// The internal buffers of a reader, to decode further images without allocating them again.
+///
+/// Take them from a reader that is done with `Reader::into_buffers` and pass them to the decoder
+/// of the next image with `Decoder::set_buffers`. This saves allocations when many small images
+/// are decoded, such as the sprites of a game. The buffer of the input is not kept, to avoid it
+/// as well read with `Decoder::read_info_buffered`.
+#[cfg(feature = "std")]
+pub struct DecodingBuffers {
+    decoder: StreamingDecoder,
+    current: SmallBuf,
+    processed: Vec<u8>,
+}
+
 /// PNG Decoder
+#[cfg(feature = "std")]
 pub struct Decoder<R: Read> {
     /// Reader
     r: R,
     /// Output transformations
     transform: Transformations,
+    /// Replaces the order of channels selected by `transform`.
+    channel_order: Option<ChannelOrder>,
     /// Limits on resources the Decoder is allowed to use
     limits: Limits,
+    /// Which chunk checksums are verified
+    crc_policy: CrcPolicy,
+    /// How invalid UTF-8 in textual chunks is handled
+    utf8_policy: Utf8Policy,
+    /// Whether textual chunks are skipped
+    ignore_text: bool,
+    /// Whether ancillary chunks are retained in the info
+    retain_chunks: bool,
+    /// Whether violations of the specification are rejected
+    strict: bool,
+    /// Whether violations that allow decoding to continue are collected
+    collect_violations: bool,
+    /// Whether the Adler-32 checksum of the image data is ignored
+    ignore_adler32: bool,
+    /// Whether image data with an invalid zlib header is decoded as raw deflate stream
+    zlib_fallback: bool,
+    /// Whether truncated image data is reported with the rows decoded so far
+    allow_truncated: bool,
+    /// Whether images following the first in the stream are decoded as further frames
+    image_stream: bool,
+    /// Whether statistics on the colors of the decoded rows are collected
+    color_statistics: bool,
+    /// Whether frames are written in column-major order
+    transposed: bool,
+    band_height: Option<u32>,
+    downscale: Downscale,
+    /// Whether the passes of interlaced frames are unfiltered on several threads
+    #[cfg(feature = "parallel")]
+    parallel: bool,
+    /// Bytes of image data buffered before they are decompressed
+    chunk_buffer_size: usize,
+    /// Capacity of the buffer of the input
+    read_buffer_size: usize,
+    /// Whether image data is decompressed directly from the input
+    direct_input: bool,
+    /// The color that `BACKGROUND` composites onto instead of that of the bKGD chunk
+    background: Option<BackgroundColor>,
+    /// Buffers of an earlier reader that are reused
+    buffers: Option<DecodingBuffers>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> Decoder<&'a [u8]> {
+    /// Create a decoder of an image that is already in memory, such as a mapped file.
+    ///
+    /// Read it with [`read_info_buffered`], which reads from the slice itself instead of copying
+    /// it to a buffer first. The image data is also decompressed directly from the slice, while
+    /// other decoders copy it to a buffer of the chunk data.
+    ///
+    /// ```
+    /// let data = std::fs::read("tests/pngsuite/basn2c08.png").unwrap();
+    /// let mut reader = png::Decoder::new_from_slice(&data).read_info_buffered().unwrap();
+    /// let mut buf = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut buf).unwrap();
+    /// ```
+    ///
+    /// [`read_info_buffered`]: #method.read_info_buffered
+    pub fn new_from_slice(data: &'a [u8]) -> Self {
+        let mut decoder = Decoder::new(data);
+        decoder.direct_input = true;
+        decoder
+    }
+
+    /// Reads the meta data of an image in memory, up to the first IDAT chunk.
+    ///
+    /// Unlike `read_info` this does not prepare for decoding the image data. The palette and the
+    /// transparency of indexed images are borrowed from the input instead of being copied, which
+    /// saves allocations when only the meta data of many images is inspected.
+    ///
+    /// ```
+    /// use std::borrow::Cow;
+    /// let data = std::fs::read("tests/pngsuite/basn3p08.png").unwrap();
+    /// let info = png::Decoder::new(&data[..]).read_metadata().unwrap();
+    /// match info.palette {
+    ///     Some(Cow::Borrowed(palette)) => assert_eq!(palette.len(), 3 * 256),
+    ///     _ => panic!("expected a borrowed palette"),
+    /// }
+    /// ```
+    pub fn read_metadata(self) -> Result<Info<'a>, DecodingError> {
+        let data = self.r;
+        let mut decoder = self.streaming_decoder();
+        decoder.borrow_palette = true;
+
+        let (mut pos, mut chunk_start) = (0, 0);
+        let (mut palette, mut trns) = (None, None);
+        loop {
+            if pos == data.len() {
+                return Err(DecodingError::Format(
+                    FormatErrorInner::UnexpectedEof.into(),
+                ));
+            }
+            let (consumed, event) = decoder.update(&data[pos..], &mut Vec::new())?;
+            pos += consumed;
+            match event {
+                Decoded::ChunkBegin(_, chunk::IDAT) | Decoded::ImageEnd => break,
+                Decoded::ChunkBegin(..) => chunk_start = pos,
+                // The 4 bytes of the checksum follow the chunk data.
+                Decoded::ChunkComplete(_, chunk::PLTE) => {
+                    palette = Some(&data[chunk_start..pos - 4])
+                }
+                Decoded::ChunkComplete(_, chunk::tRNS) => trns = Some(&data[chunk_start..pos - 4]),
+                _ => {}
+            }
+        }
+
+        let mut info: Info<'a> = match decoder.info.take() {
+            Some(info) => info,
+            None => return Err(DecodingError::Format(FormatErrorInner::MissingIhdr.into())),
+        };
+        if info.color_type.is_combination_invalid(info.bit_depth) {
+            return Err(DecodingError::Format(
+                FormatErrorInner::InvalidColorBitDepth {
+                    color: info.color_type,
+                    depth: info.bit_depth,
+                }
+                .into(),
+            ));
+        }
+
+        info.palette = palette.map(Cow::Borrowed);
+        if info.color_type == ColorType::Indexed {
+            info.trns = trns.map(Cow::Borrowed);
+        }
+        Ok(info)
+    }
+}
+
+/// Row info.
+///
+/// This describes a row returned by the reader, as `OutputInfo` describes a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RowInfo {
+    /// The index of the row in its frame.
+    ///
+    /// Rows of interlaced images are counted in the complete frame, not within their pass.
+    pub row_index: u32,
+    /// The Adam7 pass of the row, from 1 to 7, or `None` without interlacing.
+    pub pass: Option<u8>,
+    /// The pixel width of the row.
+    pub width: u32,
+    /// The color type of the row data.
+    pub color_type: ColorType,
+    /// The bit depth of the row data.
+    pub bit_depth: BitDepth,
+    /// The filter that the encoder applied to the row, which the decoder has reversed.
+    pub filter: FilterType,
+}
+
+/// The format of the rows given to the transform of `Reader::set_row_transform`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg(feature = "std")]
+pub struct RowFormat {
+    /// The pixel width of the row.
+    pub width: u32,
+    /// The color type and bit depth of the image, as stored.
+    pub input: (ColorType, BitDepth),
+    /// The color type and bit depth of the row data, after the transformations.
+    pub output: (ColorType, BitDepth),
 }
 
+/// The transform set by `Reader::set_row_transform`.
+#[cfg(feature = "std")]
+type RowTransform = Box<dyn FnMut(&mut [u8], RowFormat) + Send>;
+
 /// A row of data with interlace information attached.
 #[derive(Clone, Copy, Debug)]
+#[cfg(feature = "std")]
 pub struct InterlacedRow<'data> {
     data: &'data [u8],
     interlace: InterlaceInfo,
+    info: RowInfo,
 }
 
+#[cfg(feature = "std")]
 impl<'data> InterlacedRow<'data> {
     pub fn data(&self) -> &'data [u8] {
         self.data
@@ -92,10 +398,16 @@ impl<'data> InterlacedRow<'data> {
     pub fn interlace(&self) -> InterlaceInfo {
         self.interlace
     }
+
+    /// The position and format of the row.
+    pub fn info(&self) -> RowInfo {
+        self.info
+    }
 }
 
 /// PNG (2003) specifies two interlace modes, but reserves future extensions.
 #[derive(Clone, Copy, Debug)]
+#[cfg(feature = "std")]
 pub enum InterlaceInfo {
     /// the null method means no interlacing
     Null,
@@ -115,31 +427,86 @@ pub enum InterlaceInfo {
 
 /// A row of data without interlace information.
 #[derive(Clone, Copy, Debug)]
+#[cfg(feature = "std")]
 pub struct Row<'data> {
     data: &'data [u8],
+    info: RowInfo,
 }
 
+#[cfg(feature = "std")]
 impl<'data> Row<'data> {
     pub fn data(&self) -> &'data [u8] {
         self.data
     }
+
+    /// The position and format of the row.
+    pub fn info(&self) -> RowInfo {
+        self.info
+    }
 }
 
+#[cfg(feature = "std")]
 impl<R: Read> Decoder<R> {
     /// Create a new decoder configuration with default limits.
     pub fn new(r: R) -> Decoder<R> {
         Decoder::new_with_limits(r, Limits::default())
     }
 
+    /// Create a new decoder configuration with custom transformations.
+    ///
+    /// This is the same as calling `set_transformations` on a new decoder, which otherwise uses
+    /// the `DEFAULT_TRANSFORMATIONS`.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use png::{Decoder, Transformations};
+    /// let file = File::open("tests/pngsuite/basn0g16.png").unwrap();
+    /// let decoder = Decoder::new_with_transformations(file, Transformations::normalize_to_color8());
+    /// let reader = decoder.read_info().unwrap();
+    /// assert_eq!(reader.output_color_type().1, png::BitDepth::Eight);
+    /// ```
+    pub fn new_with_transformations(r: R, transform: Transformations) -> Decoder<R> {
+        let mut decoder = Decoder::new(r);
+        decoder.set_transformations(transform);
+        decoder
+    }
+
     /// Create a new decoder configuration with custom limits.
     pub fn new_with_limits(r: R, limits: Limits) -> Decoder<R> {
         Decoder {
             r,
-            transform: Transformations::IDENTITY,
+            transform: DEFAULT_TRANSFORMATIONS,
+            channel_order: None,
             limits,
+            crc_policy: CrcPolicy::Verify,
+            utf8_policy: Utf8Policy::Lossy,
+            ignore_text: false,
+            retain_chunks: false,
+            strict: false,
+            collect_violations: false,
+            ignore_adler32: false,
+            zlib_fallback: false,
+            allow_truncated: false,
+            image_stream: false,
+            color_statistics: false,
+            transposed: false,
+            band_height: None,
+            downscale: Downscale::None,
+            #[cfg(feature = "parallel")]
+            parallel: false,
+            chunk_buffer_size: CHUNCK_BUFFER_SIZE,
+            read_buffer_size: CHUNCK_BUFFER_SIZE,
+            direct_input: false,
+            background: None,
+            buffers: None,
         }
     }
 
+    /// Return the reader the decoder was created with.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
     /// Limit resource usage.
     ///
     /// Note that your allocations, e.g. when reading into a pre-allocated buffer, are __NOT__
@@ -148,6 +515,22 @@ impl<R: Read> Decoder<R> {
     ///
     /// Note that this is a best-effort basis.
     ///
+    /// The image dimensions can be restricted independently of the memory limit, which rejects
+    /// images with absurd headers before any of their data is read:
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use png::{Decoder, DecodingError, Limits};
+    /// // This image is 32×32.
+    /// let mut limits = Limits::default();
+    /// limits.width = 16;
+    /// let decoder = Decoder::new_with_limits(File::open("tests/pngsuite/basi0g01.png").unwrap(), limits);
+    /// match decoder.read_info() {
+    ///     Err(DecodingError::LimitsExceeded) => {}
+    ///     _ => panic!("expected the width limit to be exceeded"),
+    /// }
+    /// ```
+    ///
     /// ```
     /// use std::fs::File;
     /// use png::{Decoder, Limits};
@@ -167,29 +550,380 @@ impl<R: Read> Decoder<R> {
         self.limits = limits;
     }
 
+    /// Choose how checksum mismatches in chunks are treated.
+    ///
+    /// By default every checksum is verified. Corrupt ancillary chunks are a common occurrence in
+    /// images found in the wild, while the image data itself is intact. For trusted input the
+    /// verification can also be skipped altogether to save some time.
+    ///
+    /// ```
+    /// use png::{CrcPolicy, Decoder};
+    /// let mut decoder = Decoder::new(std::fs::File::open("tests/pngsuite/basn0g01.png").unwrap());
+    /// decoder.set_crc_policy(CrcPolicy::SkipAncillary);
+    /// assert!(decoder.read_info().is_ok());
+    /// ```
+    pub fn set_crc_policy(&mut self, policy: CrcPolicy) {
+        self.crc_policy = policy;
+    }
+
+    /// Choose how checksum mismatches in chunks are treated.
+    #[deprecated(note = "use `set_crc_policy` instead")]
+    pub fn set_crc_check(&mut self, policy: CrcPolicy) {
+        self.set_crc_policy(policy);
+    }
+
+    /// Choose how textual chunks containing invalid UTF-8 are handled.
+    ///
+    /// By default invalid sequences are replaced and the chunk is marked, see
+    /// [`ITXtChunk`](struct.ITXtChunk.html), and malformed `iTXt` chunks are skipped. With
+    /// `Utf8Policy::Strict` such chunks result in an error instead.
+    pub fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.utf8_policy = policy;
+    }
+
+    /// Skip textual chunks instead of decoding them into `Info::utf8_text`.
+    ///
+    /// This saves the work of decompressing and validating text that is not needed, such as the
+    /// metadata of images that are only displayed. The length of the chunks is still limited by
+    /// `Limits::ancillary_chunk_bytes`.
+    pub fn set_ignore_text_chunks(&mut self, ignore: bool) {
+        self.ignore_text = ignore;
+    }
+
+    /// Keep the ancillary chunks that the encoder does not write from the other fields of the
+    /// info in `Info::retained_chunks`.
+    ///
+    /// These are chunks such as text, time stamps and private chunks, with their location in
+    /// the image. Together with `Encoder::set_retained_chunks` an image can be rewritten without
+    /// losing them. The chunks after the image data are read by `Reader::finish`.
+    pub fn set_retain_chunks(&mut self, retain: bool) {
+        self.retain_chunks = retain;
+    }
+
+    /// Reject images that violate the specification in ways that are otherwise tolerated.
+    ///
+    /// This checks the order of the chunks: IHDR must come first and IEND last, the IDAT chunks
+    /// must be consecutive and chunks such as PLTE, gAMA or tRNS must appear in their allowed
+    /// position and at most once. Additionally the transparency may not have more entries than
+    /// the palette and the width and height of the image must not be zero. Violations are
+    /// format errors, most of the kind `FormatErrorKind::InvalidChunkOrder`. Together with
+    /// decoding the whole image this makes the decoder usable for validating files.
+    ///
+    /// ```
+    /// use png::{Decoder, DecodingError, FormatErrorKind};
+    /// let image = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+    /// // Repeat the gAMA chunk that follows the header.
+    /// let mut twice = image[..49].to_vec();
+    /// twice.extend_from_slice(&image[33..]);
+    ///
+    /// assert!(Decoder::new(&twice[..]).read_info().is_ok());
+    /// let mut decoder = Decoder::new(&twice[..]);
+    /// decoder.set_strict(true);
+    /// match decoder.read_info() {
+    ///     Err(DecodingError::Format(err)) => {
+    ///         assert_eq!(err.kind(), FormatErrorKind::InvalidChunkOrder)
+    ///     }
+    ///     _ => panic!("expected the duplicate chunk to be rejected"),
+    /// }
+    /// ```
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Collect the violations found in strict mode instead of failing on the first one.
+    ///
+    /// Violations after which decoding can continue, as it would without strict mode, are
+    /// then available from [`Reader::violations`] with their chunk and offset. Only errors that
+    /// leave the rest of the image undecodable are still returned. To check all chunks of a
+    /// file, including the ones after the image data, see [`Structure`].
+    ///
+    /// [`Reader::violations`]: struct.Reader.html#method.violations
+    /// [`Structure`]: struct.Structure.html
+    pub fn set_collect_violations(&mut self, collect: bool) {
+        self.collect_violations = collect;
+    }
+
+    /// Skip verifying the Adler-32 checksum of the compressed image data.
+    ///
+    /// The checksum of every chunk already protects the integrity of the data so this saves some
+    /// time on large images, similar to `PNG_IGNORE_ADLER32` of libpng. A stream with a wrong
+    /// checksum is then decoded without an error.
+    pub fn set_ignore_adler32(&mut self, ignore: bool) {
+        self.ignore_adler32 = ignore;
+    }
+
+    /// Attempt to decode image data with an invalid zlib header as raw deflate stream.
+    ///
+    /// The zlib header of the image data is validated, an unknown compression method, a window
+    /// larger than 32 KiB, wrong check bits or a preset dictionary are reported as errors of the
+    /// kind `FormatErrorKind::InvalidZlibHeader`. When enabled, such data is instead decoded as
+    /// if the header was missing, which recovers images of encoders that omit it. Its checksum
+    /// can then not be verified.
+    pub fn set_zlib_fallback(&mut self, fallback: bool) {
+        self.zlib_fallback = fallback;
+    }
+
+    /// Decode as much as possible of images whose data ends prematurely.
+    ///
+    /// When enabled, `Reader::next_frame` fills the output buffer with all rows available before
+    /// the data ended and then returns `DecodingError::Truncated`, which reports the number of
+    /// rows decoded and whether the data ended within the next one. This allows showing partially
+    /// downloaded images. Other errors are not affected.
+    pub fn set_allow_truncated(&mut self, allow: bool) {
+        self.allow_truncated = allow;
+    }
+
+    /// Decode a stream of consecutive PNG images as frames of one image.
+    ///
+    /// Some cameras and HTTP endpoints send a video as PNG images that follow each other on the
+    /// same stream. When enabled, `Reader::next_frame` continues with the image after the end of
+    /// the previous one until the stream ends. All images must have the width, height, color type
+    /// and bit depth of the first image, the `info` of the reader describes the current image.
+    pub fn set_image_stream(&mut self, enabled: bool) {
+        self.image_stream = enabled;
+    }
+
+    /// Write the frames of `Reader::next_frame` in column-major order.
+    ///
+    /// The pixel in column `x` and row `y` of a frame is then stored at pixel index
+    /// `x * height + y` of the buffer. Rows are transposed in small bands while they are decoded,
+    /// so no second buffer of the frame is needed. Rows returned by `Reader::next_row` keep their
+    /// order.
+    ///
+    /// Pixels must have at least 8 bits, otherwise `next_frame` returns a parameter error. Images
+    /// with smaller pixels can be transposed together with `Transformations::PACKING`.
+    ///
+    /// ```
+    /// let mut decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn2c08.png")?);
+    /// decoder.set_transposed_output(true);
+    /// let mut reader = decoder.read_info()?;
+    /// let mut columns = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut columns)?;
+    /// let column_len = 32 * 3;
+    /// let first_column = &columns[..column_len];
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn set_transposed_output(&mut self, transposed: bool) {
+        self.transposed = transposed;
+    }
+
+    /// Write the frames of `Reader::next_frame` in horizontal bands of at most `rows` rows.
+    ///
+    /// Each call of `next_frame` then writes the next band of the current frame and returns its
+    /// dimensions, the last band of a frame may have fewer rows. `Reader::output_buffer_size`
+    /// is the size of a band and the memory limit applies to it instead of the whole frame, so
+    /// very tall images can be processed without a buffer for all of their rows.
+    /// `Reader::band_start` is the first row of the band that is written next.
+    ///
+    /// Only frames without interlacing can be written in bands and not together with
+    /// `set_transposed_output`, otherwise `next_frame` returns a parameter error.
+    ///
+    /// ```
+    /// let mut decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn0g08.png")?);
+    /// decoder.set_band_height(Some(10));
+    /// let mut reader = decoder.read_info()?;
+    /// let mut band = vec![0; reader.output_buffer_size()];
+    /// let mut heights = Vec::new();
+    /// loop {
+    ///     heights.push(reader.next_frame(&mut band)?.height);
+    ///     // The frame is complete when the next band starts at its top again.
+    ///     if reader.band_start() == 0 {
+    ///         break;
+    ///     }
+    /// }
+    /// assert_eq!(heights, [10, 10, 10, 2]);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn set_band_height(&mut self, rows: Option<u32>) {
+        self.band_height = rows.map(|rows| rows.max(1));
+    }
+
+    /// Reduce the resolution of the frames written by `Reader::next_frame`.
+    ///
+    /// The rows are reduced while they are decoded, so no buffer for the frame at its full
+    /// resolution is needed. The output info and buffer size of the reader describe the reduced
+    /// frames. Reduced frames can not be transposed or written in bands. The rows of `next_row`
+    /// and the regions of `Reader::read_region` keep the full resolution.
+    ///
+    /// ```
+    /// let mut decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn2c08.png")?);
+    /// decoder.set_downscale(png::Downscale::Quarter);
+    /// let mut reader = decoder.read_info()?;
+    /// let mut thumbnail = vec![0; reader.output_buffer_size()];
+    /// let info = reader.next_frame(&mut thumbnail)?;
+    /// assert_eq!((info.width, info.height), (8, 8));
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn set_downscale(&mut self, downscale: Downscale) {
+        self.downscale = downscale;
+    }
+
+    /// Decode the passes of interlaced frames on several threads with `Reader::next_frame`.
+    ///
+    /// The image data of a frame is decompressed on the calling thread, then the seven passes are
+    /// unfiltered on the threads of `rayon` and combined into the frame row by row. This needs a
+    /// buffer for all image data of the frame. Frames without interlacing can only be unfiltered
+    /// one row after the other and are decoded as before, as are frames that are transposed,
+    /// downscaled, or decoded with `Decoder::set_allow_truncated`.
+    ///
+    /// The default is `false`.
+    #[cfg(feature = "parallel")]
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    /// Set the color onto which `Transformations::BACKGROUND` composites the pixels.
+    ///
+    /// It takes precedence over the `bKGD` chunk of the image. As there, the samples have the bit
+    /// depth of the image and the color must be of the kind stored for its color type, except
+    /// that an RGB color of 8-bit samples may be given for indexed images. A color of another
+    /// kind, or an index outside the palette, is ignored.
+    ///
+    /// ```
+    /// use png::{BackgroundColor, Transformations};
+    /// let mut decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn6a08.png")?);
+    /// decoder.set_transformations(Transformations::BACKGROUND);
+    /// decoder.set_background(BackgroundColor::Rgb(0, 0, 0xFF));
+    /// let mut reader = decoder.read_info()?;
+    /// assert_eq!(reader.output_color_type().0, png::ColorType::Rgb);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn set_background(&mut self, background: BackgroundColor) {
+        self.background = Some(background);
+    }
+
+    /// Collect statistics on the colors of each frame while its rows are decoded.
+    ///
+    /// They are available from `Reader::color_statistics`.
+    pub fn set_color_statistics(&mut self, enabled: bool) {
+        self.color_statistics = enabled;
+    }
+
+    /// Set how many bytes of image data are buffered before they are decompressed.
+    ///
+    /// See [`StreamingDecoder::set_chunk_buffer_size`].
+    ///
+    /// [`StreamingDecoder::set_chunk_buffer_size`]: struct.StreamingDecoder.html#method.set_chunk_buffer_size
+    pub fn set_chunk_buffer_size(&mut self, size: usize) {
+        self.chunk_buffer_size = size.max(4);
+    }
+
+    /// The number of bytes of image data buffered before they are decompressed.
+    pub fn chunk_buffer_size(&self) -> usize {
+        self.chunk_buffer_size
+    }
+
+    /// Set the capacity of the buffer into which the input is read.
+    ///
+    /// This bounds how much data is read from the input ahead of decoding it, the default is
+    /// 32 KiB. It has no effect on a reader created with [`read_info_buffered`], which reads
+    /// directly from the buffer of the input.
+    ///
+    /// [`read_info_buffered`]: #method.read_info_buffered
+    pub fn set_read_buffer_size(&mut self, size: usize) {
+        self.read_buffer_size = size.max(1);
+    }
+
+    /// The capacity of the buffer into which the input is read.
+    pub fn read_buffer_size(&self) -> usize {
+        self.read_buffer_size
+    }
+
+    /// Reuse the buffers of an earlier reader for the reader of this decoder.
+    ///
+    /// ```
+    /// let mut buffers = None;
+    /// for name in &["basn0g08", "basn2c08", "basn3p08"] {
+    ///     let file = std::fs::File::open(format!("tests/pngsuite/{}.png", name)).unwrap();
+    ///     let mut decoder = png::Decoder::new(file);
+    ///     if let Some(buffers) = buffers.take() {
+    ///         decoder.set_buffers(buffers);
+    ///     }
+    ///     let mut reader = decoder.read_info().unwrap();
+    ///     let mut buf = vec![0; reader.output_buffer_size()];
+    ///     reader.next_frame(&mut buf).unwrap();
+    ///     buffers = Some(reader.into_buffers());
+    /// }
+    /// ```
+    pub fn set_buffers(&mut self, buffers: DecodingBuffers) {
+        self.buffers = Some(buffers);
+    }
+
+    /// A low-level decoder with the configuration of this decoder.
+    fn streaming_decoder(&self) -> StreamingDecoder {
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_limits(self.limits);
+        decoder.set_crc_policy(self.crc_policy);
+        decoder.set_utf8_policy(self.utf8_policy);
+        decoder.set_ignore_text_chunks(self.ignore_text);
+        decoder.set_retain_chunks(self.retain_chunks);
+        decoder.set_strict(self.strict);
+        decoder.set_collect_violations(self.collect_violations);
+        decoder.set_ignore_adler32(self.ignore_adler32);
+        decoder.set_zlib_fallback(self.zlib_fallback);
+        decoder.set_chunk_buffer_size(self.chunk_buffer_size);
+        decoder.direct_image_data = self.direct_input;
+        decoder
+    }
+
     /// Reads all meta data until the first IDAT chunk
     pub fn read_info(self) -> Result<Reader<R>, DecodingError> {
-        let mut reader = Reader::new(self.r, StreamingDecoder::new(), self.transform, self.limits);
-        reader.init()?;
+        let mut reader = self.into_reader();
+        reader.read_info()?;
+        Ok(reader)
+    }
 
-        let color_type = reader.info().color_type;
-        let bit_depth = reader.info().bit_depth;
-        if color_type.is_combination_invalid(bit_depth) {
-            return Err(DecodingError::Format(
-                FormatErrorInner::InvalidColorBitDepth {
-                    color: color_type,
-                    depth: bit_depth,
-                }
-                .into(),
-            ));
-        }
+    /// Reads the meta data up to the image header, see [`Reader::read_header_info`].
+    ///
+    /// [`Reader::read_header_info`]: struct.Reader.html#method.read_header_info
+    pub fn read_header_info(self) -> Result<Reader<R>, DecodingError> {
+        let mut reader = self.into_reader();
+        reader.read_header_info()?;
+        Ok(reader)
+    }
 
-        // Check if the output buffer can be represented at all.
-        if reader.checked_output_buffer_size().is_none() {
-            return Err(DecodingError::LimitsExceeded);
-        }
+    /// Create a reader without reading any data yet.
+    ///
+    /// The meta data is read with [`Reader::read_info`] afterwards. Unlike [`read_info`] this
+    /// keeps the reader when the underlying reader returns `WouldBlock`, so decoding from a
+    /// non-blocking source can be resumed.
+    ///
+    /// [`Reader::read_info`]: struct.Reader.html#method.read_info
+    /// [`read_info`]: #method.read_info
+    pub fn into_reader(self) -> Reader<R> {
+        let capacity = self.read_buffer_size;
+        self.reader_with(|r| BufReader::with_capacity(capacity, r))
+    }
 
-        Ok(reader)
+    /// Create a reader of the input provided by `buffered`.
+    fn reader_with<B: BufRead>(mut self, buffered: impl FnOnce(R) -> B) -> Reader<R, B> {
+        let buffers = self.buffers.take();
+        let decoder = self.streaming_decoder();
+        let input = buffered(self.r);
+        let transform = match self.channel_order {
+            Some(order) => self.transform.with_channel_order(order),
+            None => self.transform,
+        };
+        let mut reader = Reader::new(input, decoder, transform, self.limits);
+        reader.allow_truncated = self.allow_truncated;
+        reader.image_stream = self.image_stream;
+        if self.color_statistics {
+            reader.statistics = Some(ColorStatistics::new());
+        }
+        reader.band_height = self.band_height;
+        reader.downscale = self.downscale;
+        reader.background = self.background;
+        #[cfg(feature = "parallel")]
+        {
+            reader.parallel = self.parallel;
+        }
+        if self.transposed {
+            reader.transposed = Some(Vec::new());
+        }
+        if let Some(buffers) = buffers {
+            reader.reuse_buffers(buffers);
+        }
+        reader
     }
 
     /// Set the allowed and performed transformations.
@@ -199,18 +933,90 @@ impl<R: Read> Decoder<R> {
     pub fn set_transformations(&mut self, transform: Transformations) {
         self.transform = transform;
     }
+
+    /// Set the order of the color and alpha channels of the output.
+    ///
+    /// This replaces the `BGR` and `SWAP_ALPHA` transformations, whether they are set before or
+    /// after the order. The order is applied after all other transformations
+    /// affecting the channels, such as `RGBA8`, so that every image can be decoded to the
+    /// layout of a framebuffer.
+    ///
+    /// ```
+    /// use png::{ChannelOrder, Decoder, Transformations};
+    /// let mut decoder = Decoder::new(std::fs::File::open("tests/pngsuite/basn2c08.png")?);
+    /// decoder.set_transformations(Transformations::RGBA8);
+    /// decoder.set_channel_order(ChannelOrder::Bgra);
+    /// let mut reader = decoder.read_info()?;
+    /// let mut bgra = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut bgra)?;
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn set_channel_order(&mut self, order: ChannelOrder) {
+        self.channel_order = Some(order);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> Decoder<R> {
+    /// Whether the data of the reader starts with the PNG signature, without consuming any of it.
+    ///
+    /// Only the data the reader has buffered is looked at, so it needs to hold the first eight
+    /// bytes. This is the case for a `BufReader` or a slice unless the data is shorter. On a
+    /// mismatch the reader can be taken back with [`into_inner`] and given to another decoder.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use std::io::BufReader;
+    /// let file = File::open("tests/pngsuite/basn0g01.png").unwrap();
+    /// let mut decoder = png::Decoder::new(BufReader::new(file));
+    /// assert!(decoder.peek_signature().unwrap());
+    /// decoder.read_info_buffered().unwrap();
+    ///
+    /// let mut decoder = png::Decoder::new(&b"GIF89a"[..]);
+    /// assert!(!decoder.peek_signature().unwrap());
+    /// assert_eq!(decoder.into_inner(), b"GIF89a");
+    /// ```
+    ///
+    /// [`into_inner`]: #method.into_inner
+    pub fn peek_signature(&mut self) -> io::Result<bool> {
+        Ok(chunk::is_png(self.r.fill_buf()?))
+    }
+
+    /// Reads all meta data until the first IDAT chunk, from a reader that is already buffered.
+    ///
+    /// The decoder reads directly from the buffer of the reader instead of copying the data into
+    /// a buffer of its own first, as [`read_info`] does.
+    ///
+    /// [`read_info`]: #method.read_info
+    pub fn read_info_buffered(self) -> Result<Reader<R, R>, DecodingError> {
+        let mut reader = self.into_buffered_reader();
+        reader.read_info()?;
+        Ok(reader)
+    }
+
+    /// Create a reader of an already buffered reader without reading any data yet.
+    ///
+    /// See [`into_reader`] and [`read_info_buffered`].
+    ///
+    /// [`into_reader`]: #method.into_reader
+    /// [`read_info_buffered`]: #method.read_info_buffered
+    pub fn into_buffered_reader(self) -> Reader<R, R> {
+        self.reader_with(|r| r)
+    }
 }
 
-struct ReadDecoder<R: Read> {
-    reader: BufReader<R>,
+#[cfg(feature = "std")]
+struct ReadDecoder<B: BufRead> {
+    reader: B,
     decoder: StreamingDecoder,
     at_eof: bool,
 }
 
-impl<R: Read> ReadDecoder<R> {
+#[cfg(feature = "std")]
+impl<B: BufRead> ReadDecoder<B> {
     /// Returns the next decoded chunk. If the chunk is an ImageData chunk, its contents are written
     /// into image_data.
-    fn decode_next(&mut self, image_data: &mut Vec<u8>) -> Result<Option<Decoded>, DecodingError> {
+    fn decode_next(&mut self, image_data: &mut SmallBuf) -> Result<Option<Decoded>, DecodingError> {
         while !self.at_eof {
             let (consumed, result) = {
                 let buf = self.reader.fill_buf()?;
@@ -219,7 +1025,7 @@ impl<R: Read> ReadDecoder<R> {
                         FormatErrorInner::UnexpectedEof.into(),
                     ));
                 }
-                self.decoder.update(buf, image_data)?
+                self.decoder.update_buffer(buf, image_data)?
             };
             self.reader.consume(consumed);
             match result {
@@ -257,7 +1063,7 @@ impl<R: Read> ReadDecoder<R> {
         ))
     }
 
-    fn info(&self) -> Option<&Info> {
+    fn info(&self) -> Option<&Info<'static>> {
         self.decoder.info.as_ref()
     }
 }
@@ -265,8 +1071,16 @@ impl<R: Read> ReadDecoder<R> {
 /// PNG reader (mostly high-level interface)
 ///
 /// Provides a high level that iterates over lines or whole images.
-pub struct Reader<R: Read> {
-    decoder: ReadDecoder<R>,
+///
+/// The data of `R` is read through the buffered reader `B`. By default this is a buffer of the
+/// reader itself, a reader that is already buffered can be used directly with
+/// [`Decoder::read_info_buffered`].
+///
+/// [`Decoder::read_info_buffered`]: struct.Decoder.html#method.read_info_buffered
+#[cfg(feature = "std")]
+pub struct Reader<R: Read, B: BufRead = BufReader<R>> {
+    decoder: ReadDecoder<B>,
+    input: PhantomData<R>,
     bpp: BytesPerPixel,
     subframe: SubframeInfo,
     /// Number of frame control chunks read.
@@ -274,17 +1088,72 @@ pub struct Reader<R: Read> {
     /// control chunk. The IDAT image _may_ have such a chunk applying to it.
     fctl_read: u32,
     next_frame: SubframeIdx,
-    /// Previous raw line
-    prev: Vec<u8>,
-    /// Current raw line
-    current: Vec<u8>,
+    /// Whether the meta data of the next frame has been read up to its image data.
+    ///
+    /// Reading it may be interrupted, so this is tracked separately from `next_frame`.
+    frame_ready: bool,
+    /// Window of decompressed image data.
+    ///
+    /// Lines are unfiltered in place and stay in this buffer until more data is required, so the
+    /// previous line never needs to be copied. Only the previous and any partial lines are kept
+    /// when the buffer is compacted. The data of small images is kept inline.
+    current: SmallBuf,
     /// Start index of the current scan line.
     scan_start: usize,
+    /// Start index of the previous, already unfiltered, scan line of the current pass.
+    prev_start: Option<usize>,
+    /// The filter of the last unfiltered scan line.
+    row_filter: FilterType,
+    /// The next row of the current pass, if its data was not available yet.
+    pending_row: Option<(usize, InterlaceInfo)>,
+    /// The rows written by an interrupted `next_frame` and their length in bytes.
+    frame_progress: Option<(u32, usize)>,
     /// Output transformations
     transform: Transformations,
+    /// Whether the transformations leave the rows of the image unchanged.
+    identity: bool,
+    /// The scaled gamma of the image and the table converting its samples for `SCALE_16_GAMMA`.
+    gamma_table: Option<(u32, Vec<u8>)>,
+    /// The screen gamma and the default gamma of the image set by `set_gamma`.
+    display_gamma: Option<(f64, f64)>,
+    /// The exponent and the largest sample of the table correcting the samples for the display.
+    display_table: Option<((u64, u16), Vec<u16>)>,
+    /// The transform of the caller applied to every row after the others.
+    row_transform: Option<RowTransform>,
     /// Processed line
     processed: Vec<u8>,
     limits: Limits,
+    /// Report truncated frames with the rows decoded so far.
+    allow_truncated: bool,
+    /// Whether the input of the current frame ended and its remaining data was salvaged.
+    data_truncated: bool,
+    /// Whether images following the end of the current one are decoded.
+    image_stream: bool,
+    /// The header of the first image of a stream of images, once a following image is decoded.
+    stream_header: Option<(u32, u32, BitDepth, ColorType)>,
+    /// Statistics on the colors of the current frame, if they are collected.
+    statistics: Option<ColorStatistics>,
+    /// The rows of the current frame not yet written to the output, if it is transposed.
+    transposed: Option<Vec<u8>>,
+    /// The maximum height of the bands written by `next_frame`, if the frame is split.
+    band_height: Option<u32>,
+    /// The first row of the current frame not yet written by `next_frame`.
+    band_start: u32,
+    /// The reduction of the resolution of frames written by `next_frame`.
+    downscale: Downscale,
+    /// The reduced rows of the current frame, while it is written at a lower resolution.
+    accumulator: Option<Accumulator>,
+    /// The bytes of each row to unfilter while reading a region, the rest stays filtered.
+    row_prefix: Option<usize>,
+    /// The last pass written by `next_pass` and the number of its rows written so far.
+    pass_progress: Option<(u8, u32)>,
+    /// The distance of the rows written by the current call of `next_frame`, if they are padded.
+    row_stride: Option<usize>,
+    /// The color set by `Decoder::set_background`.
+    background: Option<BackgroundColor>,
+    /// Whether `next_frame` unfilters the passes of interlaced frames on several threads.
+    #[cfg(feature = "parallel")]
+    parallel: bool,
 }
 
 /// The subframe specific information.
@@ -293,6 +1162,7 @@ pub struct Reader<R: Read> {
 /// combination of `dispose_op` and `overlay_op`). These sub frames specify individual dimension
 /// information and reuse the global interlace options. This struct encapsulates the state of where
 /// in a particular IDAT-frame or subframe we are.
+#[cfg(feature = "std")]
 struct SubframeInfo {
     width: u32,
     height: u32,
@@ -302,16 +1172,16 @@ struct SubframeInfo {
 }
 
 #[derive(Clone)]
+#[cfg(feature = "std")]
 enum InterlaceIter {
     None(Range<u32>),
-    Adam7(utils::Adam7Iterator),
+    Adam7(adam7::Adam7Iterator),
 }
 
 /// Denote a frame as given by sequence numbers.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg(feature = "std")]
 enum SubframeIdx {
-    /// The info has not yet been decoded.
-    Uninit,
     /// The initial frame in an IDAT chunk without fcTL chunk applying to it.
     /// Note that this variant precedes `Some` as IDAT frames precede fdAT frames and all fdAT
     /// frames must have a fcTL applying to it.
@@ -322,47 +1192,79 @@ enum SubframeIdx {
     End,
 }
 
+#[cfg(feature = "std")]
 macro_rules! get_info(
     ($this:expr) => {
         $this.decoder.info().unwrap()
     }
 );
 
-impl<R: Read> Reader<R> {
+#[cfg(feature = "std")]
+impl<R: Read, B: BufRead> Reader<R, B> {
     /// Creates a new PNG reader
-    fn new(r: R, d: StreamingDecoder, t: Transformations, limits: Limits) -> Reader<R> {
+    fn new(reader: B, mut d: StreamingDecoder, t: Transformations, limits: Limits) -> Self {
+        d.set_limits(limits);
         Reader {
             decoder: ReadDecoder {
-                reader: BufReader::with_capacity(CHUNCK_BUFFER_SIZE, r),
+                reader,
                 decoder: d,
                 at_eof: false,
             },
+            input: PhantomData,
             bpp: BytesPerPixel::One,
             subframe: SubframeInfo::not_yet_init(),
             fctl_read: 0,
             next_frame: SubframeIdx::Initial,
-            prev: Vec::new(),
-            current: Vec::new(),
+            frame_ready: false,
+            current: SmallBuf::new(),
             scan_start: 0,
+            prev_start: None,
+            row_filter: FilterType::NoFilter,
+            pending_row: None,
+            frame_progress: None,
             transform: t,
+            identity: t == Transformations::IDENTITY,
+            gamma_table: None,
+            display_gamma: None,
+            display_table: None,
+            row_transform: None,
             processed: Vec::new(),
             limits,
+            allow_truncated: false,
+            data_truncated: false,
+            image_stream: false,
+            stream_header: None,
+            statistics: None,
+            transposed: None,
+            band_height: None,
+            band_start: 0,
+            downscale: Downscale::None,
+            accumulator: None,
+            row_prefix: None,
+            pass_progress: None,
+            row_stride: None,
+            background: None,
+            #[cfg(feature = "parallel")]
+            parallel: false,
         }
     }
 
     /// Reads all meta data until the next frame data starts.
     /// Requires IHDR before the IDAT and fcTL before fdAT.
     fn init(&mut self) -> Result<OutputInfo, DecodingError> {
-        if self.next_frame == self.subframe_idx() {
+        if self.frame_ready {
             return Ok(self.output_info());
-        } else if self.next_frame == SubframeIdx::End {
+        } else if self.next_frame == SubframeIdx::End && !self.next_image()? {
             return Err(DecodingError::Parameter(
                 ParameterErrorKind::PolledAfterEndOfImage.into(),
             ));
         }
 
         loop {
-            match self.decoder.decode_next(&mut Vec::new())? {
+            match self
+                .decoder
+                .decode_next(&mut SmallBuf::from_vec(Vec::new()))?
+            {
                 Some(Decoded::ChunkBegin(_, chunk::IDAT))
                 | Some(Decoded::ChunkBegin(_, chunk::fdAT)) => break,
                 Some(Decoded::FrameControl(_)) => {
@@ -379,8 +1281,8 @@ impl<R: Read> Reader<R> {
                         FormatErrorInner::MissingImageData.into(),
                     ))
                 }
-                Some(Decoded::Header { .. }) => {
-                    self.validate_buffer_sizes()?;
+                Some(Decoded::Header(width, height, bit_depth, color_type, _)) => {
+                    self.header_read((width, height, bit_depth, color_type))?
                 }
                 // Ignore all other chunk events. Any other chunk may be between IDAT chunks, fdAT
                 // chunks and their control chunks.
@@ -398,54 +1300,408 @@ impl<R: Read> Reader<R> {
             // TODO: reuse the results obtained during the above check.
             self.subframe = SubframeInfo::new(info);
         }
+        if let Some(ref mut statistics) = self.statistics {
+            statistics.clear();
+        }
         self.allocate_out_buf()?;
-        self.prev = vec![0; self.subframe.rowlen];
+        self.frame_ready = true;
         Ok(self.output_info())
     }
 
-    fn output_info(&self) -> OutputInfo {
-        let width = self.subframe.width;
-        let height = self.subframe.height;
+    /// Check the image header against the first image of a stream and the limits.
+    fn header_read(
+        &mut self,
+        header: (u32, u32, BitDepth, ColorType),
+    ) -> Result<(), DecodingError> {
+        if self.stream_header.map_or(false, |first| first != header) {
+            return Err(DecodingError::Format(
+                FormatErrorInner::StreamHeaderMismatch.into(),
+            ));
+        }
+        self.validate_buffer_sizes()
+    }
 
-        let (color_type, bit_depth) = self.output_color_type();
+    /// Prepare decoding the image following the current one, for a stream of images.
+    ///
+    /// Returns `false` if there is no further image.
+    fn next_image(&mut self) -> Result<bool, DecodingError> {
+        if !self.image_stream {
+            return Ok(false);
+        }
+        // Skip the remaining chunks of the current image.
+        while self
+            .decoder
+            .decode_next(&mut SmallBuf::from_vec(Vec::new()))?
+            .is_some()
+        {}
+        if self.decoder.reader.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
 
-        OutputInfo {
-            width,
-            height,
-            color_type,
-            bit_depth,
-            line_size: self.output_line_size(width),
+        if self.stream_header.is_none() {
+            let info = self.info();
+            self.stream_header = Some((info.width, info.height, info.bit_depth, info.color_type));
         }
+        self.decoder.decoder.reset();
+        self.decoder.at_eof = false;
+        self.fctl_read = 0;
+        self.next_frame = SubframeIdx::Initial;
+        Ok(true)
     }
 
-    fn reset_current(&mut self) {
-        self.current.clear();
-        self.scan_start = 0;
-    }
+    /// Reads all meta data until the first IDAT chunk.
+    ///
+    /// This is done by [`Decoder::read_info`] already, it is only needed for a reader created with
+    /// [`Decoder::into_reader`]. If the underlying reader returns an error of kind `WouldBlock`,
+    /// call this again once more data is available.
+    ///
+    /// All other methods of the reader resume in the same way: the error is returned but no data
+    /// is lost, and calling the method again continues where it stopped. For `next_frame` the
+    /// same buffer must be passed again.
+    ///
+    /// [`Decoder::read_info`]: struct.Decoder.html#method.read_info
+    /// [`Decoder::into_reader`]: struct.Decoder.html#method.into_reader
+    pub fn read_info(&mut self) -> Result<&Info<'static>, DecodingError> {
+        self.init()?;
+
+        let color_type = self.info().color_type;
+        let bit_depth = self.info().bit_depth;
+        if color_type.is_combination_invalid(bit_depth) {
+            return Err(DecodingError::Format(
+                FormatErrorInner::InvalidColorBitDepth {
+                    color: color_type,
+                    depth: bit_depth,
+                }
+                .into(),
+            ));
+        }
+
+        // Check if the output buffer can be represented at all, and is allowed.
+        match self.checked_output_buffer_size() {
+            Some(size) if size <= self.limits.pixel_bytes => {}
+            _ => return Err(DecodingError::LimitsExceeded),
+        }
+
+        Ok(self.info())
+    }
+
+    /// Reads the meta data up to the image header, the IHDR chunk, and no further.
+    ///
+    /// The size, color type, bit depth and interlacing of the image are then available from
+    /// `info`, before any other chunk is read. This allows rejecting images early, for example
+    /// uploads that are too large, without parsing all chunks before the image data.
+    /// [`read_info`] continues with the remaining meta data and, like it, this can be called again
+    /// after an error of kind `WouldBlock`.
+    ///
+    /// ```
+    /// let file = std::fs::File::open("tests/pngsuite/basn3p08.png")?;
+    /// let mut reader = png::Decoder::new(file).read_header_info()?;
+    /// let info = reader.info();
+    /// assert_eq!((info.width, info.height), (32, 32));
+    /// // The palette follows the header.
+    /// assert!(info.palette.is_none());
+    /// assert!(reader.read_info()?.palette.is_some());
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    ///
+    /// [`read_info`]: #method.read_info
+    pub fn read_header_info(&mut self) -> Result<&Info<'static>, DecodingError> {
+        while self.decoder.info().is_none() {
+            match self
+                .decoder
+                .decode_next(&mut SmallBuf::from_vec(Vec::new()))?
+            {
+                Some(Decoded::Header(width, height, bit_depth, color_type, _)) => {
+                    self.header_read((width, height, bit_depth, color_type))?
+                }
+                None => return Err(DecodingError::Format(FormatErrorInner::MissingIhdr.into())),
+                _ => {}
+            }
+        }
+
+        let info = self.info();
+        if info.color_type.is_combination_invalid(info.bit_depth) {
+            return Err(DecodingError::Format(
+                FormatErrorInner::InvalidColorBitDepth {
+                    color: info.color_type,
+                    depth: info.bit_depth,
+                }
+                .into(),
+            ));
+        }
+        Ok(self.info())
+    }
+
+    /// The output info of the current frame, without decoding it.
+    ///
+    /// After `read_info` this describes the first frame, after `next_frame` the frame that was
+    /// decoded until the next call reads the control chunks of the following one. The line size
+    /// and buffer size allow sizing an output buffer for exactly this frame, which is never larger
+    /// than `output_buffer_size`.
+    ///
+    /// ```
+    /// # let mut png = vec![];
+    /// # let mut encoder = png::Encoder::new(&mut png, 3, 2);
+    /// # encoder.set_color(png::ColorType::Rgb);
+    /// # encoder.write_header()?.write_image_data(&[0; 18])?;
+    /// let mut reader = png::Decoder::new(&png[..]).read_info()?;
+    /// let info = reader.output_info();
+    /// assert_eq!((info.width, info.height, info.line_size), (3, 2, 9));
+    ///
+    /// let mut buf = vec![0; info.buffer_size()];
+    /// assert_eq!(reader.next_frame(&mut buf)?, info);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn output_info(&self) -> OutputInfo {
+        let (width, height) = self
+            .downscale
+            .size(self.subframe.width, self.subframe.height);
+
+        let (color_type, bit_depth) = self.output_color_type();
+
+        OutputInfo {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            line_size: self.output_line_size(width),
+        }
+    }
+
+    fn reset_current(&mut self) {
+        self.current.clear();
+        self.scan_start = 0;
+        self.prev_start = None;
+        self.pending_row = None;
+        self.data_truncated = false;
+    }
+
+    /// Reads the remaining chunks of the image up to its end, after the last frame.
+    ///
+    /// The info then also holds the chunks that follow the image data, such as text and retained
+    /// chunks. Any image data that was not read yet is skipped.
+    pub fn finish(&mut self) -> Result<&Info<'static>, DecodingError> {
+        while self
+            .decoder
+            .decode_next(&mut SmallBuf::from_vec(Vec::new()))?
+            .is_some()
+        {}
+        Ok(self.info())
+    }
+
+    /// Return the buffered reader, positioned after the last chunk that was read.
+    ///
+    /// After `finish` this is the end of the image, so a container that stores images back to
+    /// back, of any size and format, can decode the following image from the same reader. The
+    /// data buffered but not yet decoded stays in the returned reader.
+    ///
+    /// ```
+    /// let mut stream = std::fs::read("tests/pngsuite/basn0g01.png")?;
+    /// stream.extend(std::fs::read("tests/pngsuite/basn2c08.png")?);
+    ///
+    /// let mut reader = png::Decoder::new(&stream[..]).read_info()?;
+    /// let mut first = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut first)?;
+    /// reader.finish()?;
+    ///
+    /// let rest = reader.into_inner();
+    /// let mut reader = png::Decoder::new(rest).read_info_buffered()?;
+    /// assert_eq!(reader.info().color_type, png::ColorType::Rgb);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn into_inner(self) -> B {
+        self.decoder.reader
+    }
+
+    /// Release the internal buffers of the reader to decode another image with them.
+    ///
+    /// See `Decoder::set_buffers`.
+    pub fn into_buffers(self) -> DecodingBuffers {
+        DecodingBuffers {
+            decoder: self.decoder.decoder,
+            current: self.current,
+            processed: self.processed,
+        }
+    }
+
+    /// Take over the buffers of an earlier reader, before anything is decoded.
+    fn reuse_buffers(&mut self, buffers: DecodingBuffers) {
+        let DecodingBuffers {
+            decoder,
+            mut current,
+            mut processed,
+        } = buffers;
+        self.decoder.decoder.reuse_buffers(decoder);
+        current.clear();
+        processed.clear();
+        self.current = current;
+        self.processed = processed;
+    }
 
     /// Get information on the image.
     ///
     /// The structure will change as new frames of an animated image are decoded.
-    pub fn info(&self) -> &Info {
+    pub fn info(&self) -> &Info<'static> {
         self.decoder.info().unwrap()
     }
 
-    /// Get the subframe index of the current info.
-    fn subframe_idx(&self) -> SubframeIdx {
-        let info = match self.decoder.info() {
-            None => return SubframeIdx::Uninit,
-            Some(info) => info,
-        };
+    /// The violations of strict mode found so far.
+    ///
+    /// This is empty unless enabled with `Decoder::set_collect_violations`.
+    pub fn violations(&self) -> &[FormatError] {
+        self.decoder.decoder.violations()
+    }
+
+    /// The number of rows of the current frame not yet decoded, see `progress`.
+    pub fn rows_remaining(&self) -> u32 {
+        self.progress().rows_remaining
+    }
+
+    /// How far the current frame is decoded, for example to show the progress of decoding a
+    /// large image row by row.
+    ///
+    /// ```
+    /// let data = std::fs::read("tests/pngsuite/basi0g01.png").unwrap();
+    /// let mut reader = png::Decoder::new(&data[..]).read_info().unwrap();
+    /// let progress = reader.progress();
+    /// // The seven passes of the interlaced image of 32 by 32 pixels store 60 rows.
+    /// assert_eq!((progress.rows_total, progress.bytes_consumed), (60, 0));
+    /// assert_eq!(progress.pass, Some(1));
+    ///
+    /// while let Some(_) = reader.next_row().unwrap() {
+    ///     let progress = reader.progress();
+    ///     assert!(progress.bytes_consumed <= progress.bytes_expected);
+    /// }
+    /// assert_eq!(reader.rows_remaining(), 0);
+    /// ```
+    pub fn progress(&self) -> FrameProgress {
+        let info = self.info();
+        let rowlen = |width| info.raw_row_length_from_width(width);
+        let (width, height) = (self.subframe.width, self.subframe.height);
+        let (mut rows_remaining, mut bytes_remaining, mut pass, rows_total, bytes_expected) =
+            match self.subframe.interlace {
+                InterlaceIter::None(ref rows) => {
+                    let remaining = rows.end - rows.start;
+                    let rowlen = self.subframe.rowlen;
+                    let total = height as usize * rowlen;
+                    (remaining, remaining as usize * rowlen, None, height, total)
+                }
+                InterlaceIter::Adam7(ref adam7) => {
+                    let (mut rows, mut bytes) = (0, 0);
+                    for (_, lines, width) in adam7.remaining_passes() {
+                        rows += lines;
+                        bytes += lines as usize * rowlen(width);
+                    }
+                    let (mut total_rows, mut total_bytes) = (0, 0);
+                    let all = adam7::Adam7Iterator::new(width, height);
+                    for (_, lines, width) in all.remaining_passes() {
+                        total_rows += lines;
+                        total_bytes += lines as usize * rowlen(width);
+                    }
+                    let pass = adam7.remaining_passes().next().map(|(pass, ..)| pass);
+                    (rows, bytes, pass, total_rows, total_bytes)
+                }
+            };
+        // The row taken last is not decoded while its data is missing.
+        if let Some((rowlen, interlace)) = self.pending_row {
+            rows_remaining += 1;
+            bytes_remaining += rowlen;
+            if let InterlaceInfo::Adam7 { pass: pending, .. } = interlace {
+                pass = Some(pending);
+            }
+        }
+        FrameProgress {
+            rows_remaining,
+            rows_total,
+            bytes_consumed: bytes_expected - bytes_remaining,
+            bytes_expected,
+            pass,
+        }
+    }
+
+    /// Correct the gamma of the gray and color samples for a display with `screen_gamma`.
+    ///
+    /// As in libpng, the gammas are exponents: a typical display has a gamma of 2.2 and stores
+    /// images with a gamma of `1/2.2`, the value of their gAMA chunk. The gamma of the image is
+    /// taken from its gAMA or sRGB chunk, or is `default_file_gamma` without either. Each sample
+    /// is then raised to `1 / (screen_gamma * file_gamma)`, using a table for its bit depth.
+    ///
+    /// Alpha samples are not changed, neither are palette indices and packed samples, which need
+    /// `EXPAND` to be corrected. This replaces the conversion of `SCALE_16_GAMMA`, whose samples
+    /// are then scaled linearly before their gamma is corrected. It takes effect with the next
+    /// row that is decoded.
+    ///
+    /// ```
+    /// let decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/g10n2c08.png")?);
+    /// let mut reader = decoder.read_info()?;
+    /// reader.set_gamma(2.2, 1.0 / 2.2);
+    /// let mut frame = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut frame)?;
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn set_gamma(&mut self, screen_gamma: f64, default_file_gamma: f64) {
+        self.display_gamma = Some((screen_gamma, default_file_gamma));
+        if !self.frame_ready {
+            // The tables are built when the next frame starts.
+            return;
+        }
+        self.update_gamma_table();
+        self.update_identity();
+    }
+
+    /// Apply a transform to the data of every row, after the transformations of the decoder.
+    ///
+    /// The transform is called with the data of each row and its format, and changes the samples
+    /// in place, such as for a conversion between color spaces or a look-up table. Rows are then
+    /// copied into a buffer of the reader also if the transformations would leave them unchanged.
+    /// The rows of interlaced frames are the rows of their passes, before the passes are combined.
+    /// It takes effect with the next row that is decoded and replaces any earlier transform.
+    ///
+    /// ```
+    /// let decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn0g08.png")?);
+    /// let mut reader = decoder.read_info()?;
+    /// reader.set_row_transform(|row, format| {
+    ///     assert_eq!(format.output, (png::ColorType::Grayscale, png::BitDepth::Eight));
+    ///     row.iter_mut().for_each(|sample| *sample = !*sample);
+    /// });
+    /// let mut inverted = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut inverted)?;
+    /// assert_eq!(inverted[0], 0xFF);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn set_row_transform<F>(&mut self, transform: F)
+    where
+        F: FnMut(&mut [u8], RowFormat) + Send + 'static,
+    {
+        self.row_transform = Some(Box::new(transform));
+        if self.frame_ready {
+            self.update_identity();
+        }
+    }
 
-        match info.frame_control() {
-            None => SubframeIdx::Initial,
-            Some(_) => SubframeIdx::Some(self.fctl_read - 1),
+    /// Copy the rows of the current frame into the processed line if they are no longer returned
+    /// unchanged.
+    fn update_identity(&mut self) {
+        if self.identity && !self.transform_is_identity() {
+            self.identity = false;
+            let len = self
+                .processed_line_size()
+                .expect("The row size was checked.");
+            self.processed.resize(len, 0);
         }
     }
 
+    /// Statistics on the colors of the rows of the current frame decoded so far.
+    ///
+    /// Returns `None` unless enabled with `Decoder::set_color_statistics`.
+    pub fn color_statistics(&self) -> Option<&ColorStatistics> {
+        self.statistics.as_ref()
+    }
+
     /// Call after decoding an image, to advance expected state to the next.
     fn finished_frame(&mut self) {
         // Should only be called after frame is done, so we have an info.
+        self.frame_ready = false;
         let info = self.info();
 
         let past_end_subframe = match info.animation_control() {
@@ -456,7 +1712,6 @@ impl<R: Read> Reader<R> {
         };
 
         self.next_frame = match self.next_frame {
-            SubframeIdx::Uninit => unreachable!("Next frame can never be initial"),
             SubframeIdx::End => unreachable!("Next frame called when already at image end"),
             // Reached the end of non-animated image.
             SubframeIdx::Initial if past_end_subframe == 0 => SubframeIdx::End,
@@ -482,494 +1737,4672 @@ impl<R: Read> Reader<R> {
     ///
     /// Output lines will be written in row-major, packed matrix with width and height of the read
     /// frame (or subframe), all samples are in big endian byte order where this matters.
+    ///
+    /// The returned `OutputInfo` describes the frame that was written, its `buffer_size` bytes
+    /// at the start of `buf` and their `line_size`. A frame of which rows were already read with
+    /// `next_row` or `next_interlaced_row` is rejected with a parameter error, as is a `buf` that
+    /// is too small.
     pub fn next_frame(&mut self, buf: &mut [u8]) -> Result<OutputInfo, DecodingError> {
-        // Advance until we've read the info / fcTL for this frame.
+        self.next_frame_strided(buf, None)
+    }
+
+    /// Decodes the next frame into `buf` like `next_frame`, with rows that start every `stride`
+    /// bytes.
+    ///
+    /// This writes frames directly into buffers whose rows are padded, for example to the
+    /// alignment of a texture or a window surface. The `stride` must be at least the
+    /// `line_size` of the frame and the buffer must hold `stride` bytes for every row but the
+    /// last one, which needs `line_size` bytes. The bytes between the rows may be overwritten.
+    /// The returned `OutputInfo` still gives the `line_size` of the rows without the padding.
+    /// Transposed output can not have a stride.
+    ///
+    /// ```
+    /// let file = std::fs::File::open("tests/pngsuite/basn0g08.png")?;
+    /// let mut reader = png::Decoder::new(file).read_info()?;
+    /// // Rows of 32 bytes padded to 64 bytes.
+    /// let mut buf = vec![0; 64 * 31 + 32];
+    /// let info = reader.next_frame_with_stride(&mut buf, 64)?;
+    /// assert_eq!(info.line_size, 32);
+    /// // The second row holds the gray values from 32 to 63.
+    /// assert_eq!(&buf[64..64 + 32], &(32..64).collect::<Vec<u8>>()[..]);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn next_frame_with_stride(
+        &mut self,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<OutputInfo, DecodingError> {
+        self.next_frame_strided(buf, Some(stride))
+    }
+
+    /// Decodes the next frame of 16-bit samples into `buf` like `next_frame`, in native byte
+    /// order.
+    ///
+    /// The output must have a bit depth of 16 after the transformations, otherwise a parameter
+    /// error is returned. The buffer must hold half of `output_buffer_size` samples. The rows are
+    /// converted into `buf` as they are decoded, so `SWAP_ENDIAN` has no effect here. Frames can
+    /// not be downscaled, transposed or written in bands.
+    ///
+    /// ```
+    /// let file = std::fs::File::open("tests/pngsuite/basn0g16.png")?;
+    /// let mut reader = png::Decoder::new(file).read_info()?;
+    /// let mut samples = vec![0u16; reader.output_buffer_size() / 2];
+    /// let info = reader.next_frame_u16(&mut samples)?;
+    /// assert_eq!((info.width, info.height), (32, 32));
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn next_frame_u16(&mut self, buf: &mut [u16]) -> Result<OutputInfo, DecodingError> {
         let info = self.init()?;
-        // TODO 16 bit
-        let (color_type, bit_depth) = self.output_color_type();
-        if buf.len() < self.output_buffer_size() {
+        let (_, bit_depth) = self.output_color_type();
+        if bit_depth != BitDepth::Sixteen {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::NotSixteenBit {
+                    bit_depth: bit_depth as u8,
+                }
+                .into(),
+            ));
+        }
+        if self.downscale != Downscale::None
+            || self.transposed.is_some()
+            || self.band_height.is_some()
+        {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::UnsupportedSixteenBit.into(),
+            ));
+        }
+        let size = self.output_buffer_size();
+        if buf.len() < size / 2 {
             return Err(DecodingError::Parameter(
                 ParameterErrorKind::ImageBufferSize {
-                    expected: buf.len(),
-                    actual: self.output_buffer_size(),
+                    expected: size / 2,
+                    actual: buf.len(),
                 }
                 .into(),
             ));
         }
 
-        self.reset_current();
-        let width = self.info().width;
-        if self.info().interlaced {
-            while let Some(InterlacedRow {
-                data: row,
-                interlace,
-                ..
-            }) = self.next_interlaced_row()?
-            {
-                let (line, pass) = match interlace {
-                    InterlaceInfo::Adam7 { line, pass, .. } => (line, pass),
-                    InterlaceInfo::Null => unreachable!("expected interlace information"),
-                };
-                let samples = color_type.samples() as u8;
-                utils::expand_pass(buf, width, row, pass, line, samples * (bit_depth as u8));
+        let mut rows_decoded = match self.frame_progress.take() {
+            Some((rows_decoded, _)) => rows_decoded,
+            None => {
+                let progress = self.progress();
+                if progress.rows_remaining < progress.rows_total {
+                    let rows_read = progress.rows_total - progress.rows_remaining;
+                    return Err(DecodingError::Parameter(
+                        ParameterErrorKind::FrameStarted { rows_read }.into(),
+                    ));
+                }
+                self.reset_current();
+                0
             }
-        } else {
-            let mut len = 0;
-            while let Some(Row { data: row, .. }) = self.next_row()? {
-                len += (&mut buf[len..]).write(row)?;
+        };
+        let result = self.next_frame_u16_rows(buf, &mut rows_decoded);
+        if is_would_block(&result) {
+            self.frame_progress = Some((rows_decoded, 0));
+        }
+        if self.allow_truncated {
+            let truncated = match &result {
+                Ok(()) => rows_decoded < self.subframe_rows(),
+                Err(err) => err.is_truncation(),
+            };
+            if truncated {
+                let end = self.short_image_data();
+                return Err(DecodingError::Truncated { rows_decoded, end });
             }
         }
-        // Advance over the rest of data for this (sub-)frame.
+        result?;
+
         if !self.subframe.consumed_and_flushed {
-            self.decoder.finished_decoding()?;
+            let result = self.decoder.finished_decoding();
+            if is_would_block(&result) {
+                self.frame_progress = Some((rows_decoded, 0));
+            }
+            result?;
         }
-        // Advance our state to expect the next frame.
         self.finished_frame();
-
         Ok(info)
     }
 
-    /// Returns the next processed row of the image
-    pub fn next_row(&mut self) -> Result<Option<Row>, DecodingError> {
-        self.next_interlaced_row()
-            .map(|v| v.map(|v| Row { data: v.data }))
+    /// Convert the remaining rows of the current frame into their samples in `buf`, counting
+    /// them in `rows_decoded`.
+    fn next_frame_u16_rows(
+        &mut self,
+        buf: &mut [u16],
+        rows_decoded: &mut u32,
+    ) -> Result<(), DecodingError> {
+        let samples = self.output_color_type().0.samples();
+        let line_len = self.subframe.width as usize * samples;
+        // The rows are already swapped to little endian.
+        let from_bytes: fn([u8; 2]) -> u16 =
+            if self.transform.contains(Transformations::SWAP_ENDIAN) {
+                u16::from_le_bytes
+            } else {
+                u16::from_be_bytes
+            };
+        while let Some(row) = self.next_interlaced_row()? {
+            let (y, x, step) = match row.interlace() {
+                InterlaceInfo::Adam7 { pass, line, .. } => {
+                    let (x, step) = adam7::image_columns(pass);
+                    (adam7::image_row(pass, line), x, step)
+                }
+                InterlaceInfo::Null => (*rows_decoded, 0, 1),
+            };
+            let line = &mut buf[y as usize * line_len..][..line_len];
+            let pixels = line
+                .chunks_mut(samples)
+                .skip(x as usize)
+                .step_by(step as usize);
+            for (pixel, data) in pixels.zip(row.data().chunks(2 * samples)) {
+                for (sample, bytes) in pixel.iter_mut().zip(data.chunks(2)) {
+                    *sample = from_bytes([bytes[0], bytes[1]]);
+                }
+            }
+            *rows_decoded += 1;
+        }
+        Ok(())
     }
 
-    /// Returns the next processed row of the image
-    pub fn next_interlaced_row(&mut self) -> Result<Option<InterlacedRow>, DecodingError> {
-        match self.next_interlaced_row_impl() {
-            Err(err) => Err(err),
-            Ok(None) => Ok(None),
-            Ok(s) => Ok(s),
+    /// Appends the compressed image data of the next frame to `buf`, without decompressing it.
+    ///
+    /// The data of the `IDAT` chunks, or the `fdAT` chunks of the frame without their sequence
+    /// numbers, is one zlib stream. It can be written again with
+    /// `Writer::write_compressed_image_data` to copy the image, for example with other metadata,
+    /// without decompressing and compressing it. The stream is not verified. Like `next_frame`
+    /// this rejects a frame of which rows were already read.
+    ///
+    /// ```
+    /// let file = std::fs::File::open("tests/pngsuite/basn0g01.png")?;
+    /// let mut reader = png::Decoder::new(file).read_info()?;
+    /// let mut zlib = vec![];
+    /// reader.next_frame_compressed(&mut zlib)?;
+    /// assert_eq!(zlib.len(), 0x5b);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn next_frame_compressed(&mut self, buf: &mut Vec<u8>) -> Result<(), DecodingError> {
+        self.init()?;
+        let progress = self.progress();
+        if progress.rows_remaining < progress.rows_total || self.frame_progress.is_some() {
+            let rows_read = progress.rows_total - progress.rows_remaining;
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::FrameStarted { rows_read }.into(),
+            ));
         }
+        self.decoder.decoder.set_compressed_image_data(true);
+        let result = self.read_compressed(buf);
+        self.decoder.decoder.set_compressed_image_data(false);
+        result?;
+        self.reset_current();
+        self.finished_frame();
+        Ok(())
     }
 
-    /// Fetch the next interlaced row and filter it according to our own transformations.
-    fn next_interlaced_row_impl(&mut self) -> Result<Option<InterlacedRow>, DecodingError> {
-        use crate::common::ColorType::*;
-        let transform = self.transform;
+    /// Appends the image data of the current frame to `buf` up to its end.
+    fn read_compressed(&mut self, buf: &mut Vec<u8>) -> Result<(), DecodingError> {
+        let mut data = SmallBuf::from_vec(mem::replace(buf, Vec::new()));
+        let result = self.read_compressed_into(&mut data);
+        *buf = data.into_vec();
+        result
+    }
 
-        if transform == Transformations::IDENTITY {
-            return self.next_raw_interlaced_row();
+    fn read_compressed_into(&mut self, buf: &mut SmallBuf) -> Result<(), DecodingError> {
+        loop {
+            match self.decoder.decode_next(buf)? {
+                Some(Decoded::ImageDataFlushed) => return Ok(()),
+                Some(_) => {}
+                None => {
+                    return Err(DecodingError::Format(
+                        FormatErrorInner::UnexpectedEof.into(),
+                    ))
+                }
+            }
         }
+    }
 
-        // swap buffer to circumvent borrow issues
-        let mut buffer = mem::replace(&mut self.processed, Vec::new());
-        let (got_next, adam7) = if let Some(row) = self.next_raw_interlaced_row()? {
-            (&mut buffer[..]).write_all(row.data)?;
-            (true, row.interlace)
-        } else {
-            (false, InterlaceInfo::Null)
+    fn next_frame_strided(
+        &mut self,
+        buf: &mut [u8],
+        stride: Option<usize>,
+    ) -> Result<OutputInfo, DecodingError> {
+        // Advance until we've read the info / fcTL for this frame.
+        let info = self.init()?;
+        let (color_type, bit_depth) = self.output_color_type();
+        let expected = match stride {
+            Some(stride) if stride < info.line_size => {
+                return Err(DecodingError::Parameter(
+                    ParameterErrorKind::StrideTooSmall {
+                        stride,
+                        line_size: info.line_size,
+                    }
+                    .into(),
+                ))
+            }
+            Some(_) if self.transposed.is_some() => {
+                return Err(DecodingError::Parameter(
+                    ParameterErrorKind::TransposedStride.into(),
+                ))
+            }
+            // The rows are first written without padding by all but the sequential output.
+            Some(stride) => {
+                let (width, height) = self.info().size();
+                let (width, height) = self.downscale.size(width, height);
+                let rows = self.band_rows(height) as usize;
+                let strided = stride * (rows - 1) + self.output_line_size(width);
+                strided.max(self.output_buffer_size())
+            }
+            None => self.output_buffer_size(),
         };
-        // swap back
-        let _ = mem::replace(&mut self.processed, buffer);
-
-        if !got_next {
-            return Ok(None);
+        if buf.len() < expected {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected,
+                    actual: buf.len(),
+                }
+                .into(),
+            ));
         }
-
-        let (color_type, bit_depth, trns) = {
-            let info = self.info();
-            (info.color_type, info.bit_depth as u8, info.trns.is_some())
-        };
-        let output_buffer = if let InterlaceInfo::Adam7 { width, .. } = adam7 {
-            let width = self
-                .line_size(width)
-                .expect("Adam7 interlaced rows are shorter than the buffer.");
-            &mut self.processed[..width]
-        } else {
-            &mut *self.processed
+        self.row_stride = stride;
+        let bits_per_pixel = color_type.samples() as u8 * bit_depth as u8;
+        if self.transposed.is_some() && bits_per_pixel < 8 {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::TransposedPackedPixels { bits_per_pixel }.into(),
+            ));
+        }
+        let downscaled = self.downscale != Downscale::None;
+        if downscaled && (self.band_height.is_some() || self.transposed.is_some()) {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::UnsupportedDownscale.into(),
+            ));
+        }
+        let band = match self.band_height {
+            Some(_) if self.info().interlaced || self.transposed.is_some() => {
+                return Err(DecodingError::Parameter(
+                    ParameterErrorKind::UnsupportedBands.into(),
+                ))
+            }
+            Some(rows) => Some(rows.min(self.subframe.height - self.band_start)),
+            None => None,
         };
 
-        let mut len = output_buffer.len();
-        if transform.contains(Transformations::EXPAND) {
-            match color_type {
-                Indexed => expand_paletted(output_buffer, get_info!(self))?,
-                Grayscale | GrayscaleAlpha if bit_depth < 8 => {
-                    expand_gray_u8(output_buffer, get_info!(self))
+        let (mut rows_decoded, mut len) = match self.frame_progress.take() {
+            Some(progress) => progress,
+            None => {
+                // The data of the rows read with `next_row` is gone, the frame can not be
+                // written from the start anymore.
+                let progress = self.progress();
+                if self.band_start == 0 && progress.rows_remaining < progress.rows_total {
+                    let rows_read = progress.rows_total - progress.rows_remaining;
+                    return Err(DecodingError::Parameter(
+                        ParameterErrorKind::FrameStarted { rows_read }.into(),
+                    ));
                 }
-                Grayscale | Rgb if trns => {
-                    let channels = color_type.samples();
-                    let trns = get_info!(self).trns.as_ref().unwrap();
-                    if bit_depth == 8 {
-                        utils::expand_trns_line(output_buffer, &*trns, channels);
-                    } else {
-                        utils::expand_trns_line16(output_buffer, &*trns, channels);
-                    }
+                if self.band_start == 0 {
+                    self.reset_current();
                 }
-                _ => (),
+                (0, 0)
             }
+        };
+        let result = self.next_frame_rows(
+            buf,
+            color_type,
+            bit_depth,
+            band,
+            &mut rows_decoded,
+            &mut len,
+        );
+        if is_would_block(&result) {
+            self.frame_progress = Some((rows_decoded, len));
         }
-
-        if bit_depth == 16 && transform.intersects(Transformations::STRIP_16) {
-            len /= 2;
-            for i in 0..len {
-                output_buffer[i] = output_buffer[2 * i];
+        if self.allow_truncated {
+            let truncated = match &result {
+                Ok(()) => rows_decoded < band.unwrap_or_else(|| self.subframe_rows()),
+                Err(err) => err.is_truncation(),
+            };
+            if truncated {
+                let rows_decoded = self.band_start + rows_decoded;
+                self.band_start = 0;
+                let end = self.short_image_data();
+                return Err(DecodingError::Truncated { rows_decoded, end });
             }
         }
+        result?;
 
-        Ok(Some(InterlacedRow {
-            data: &output_buffer[..len],
-            interlace: adam7,
-        }))
-    }
+        let info = match band {
+            Some(height) => {
+                self.band_start += height;
+                if self.band_start < self.subframe.height {
+                    return Ok(OutputInfo { height, ..info });
+                }
+                self.band_start = 0;
+                OutputInfo { height, ..info }
+            }
+            None => info,
+        };
 
-    /// Returns the color type and the number of bits per sample
-    /// of the data returned by `Reader::next_row` and Reader::frames`.
-    pub fn output_color_type(&self) -> (ColorType, BitDepth) {
-        use crate::common::ColorType::*;
-        let t = self.transform;
-        let info = self.info();
-        if t == Transformations::IDENTITY {
-            (info.color_type, info.bit_depth)
-        } else {
-            let bits = match info.bit_depth as u8 {
-                16 if t.intersects(Transformations::STRIP_16) => 8,
-                n if n < 8 && t.contains(Transformations::EXPAND) => 8,
-                n => n,
-            };
-            let color_type = if t.contains(Transformations::EXPAND) {
-                let has_trns = info.trns.is_some();
-                match info.color_type {
-                    Grayscale if has_trns => GrayscaleAlpha,
-                    Rgb if has_trns => Rgba,
-                    Indexed if has_trns => Rgba,
-                    Indexed => Rgb,
-                    ct => ct,
+        // Advance over the rest of data for this (sub-)frame.
+        if !self.subframe.consumed_and_flushed {
+            let result = self.decoder.finished_decoding();
+            if is_would_block(&result) {
+                self.frame_progress = Some((rows_decoded, len));
+            }
+            result?;
+        }
+        // Advance our state to expect the next frame.
+        self.finished_frame();
+
+        Ok(info)
+    }
+
+    /// Write all rows of the current frame into `buf`, counting them in `rows_decoded`.
+    ///
+    /// Rows of a frame without interlacing are written starting at the byte offset `len`. With a
+    /// `band` only as many rows are written.
+    fn next_frame_rows(
+        &mut self,
+        buf: &mut [u8],
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        band: Option<u32>,
+        rows_decoded: &mut u32,
+        len: &mut usize,
+    ) -> Result<(), DecodingError> {
+        if self.downscale != Downscale::None {
+            let result = self.next_frame_downscaled(buf, color_type, bit_depth, rows_decoded);
+            if let Some(bits) = self.swapped_bits() {
+                if !is_would_block(&result) {
+                    let size = self.output_buffer_size();
+                    utils::swap_packed_line(&mut buf[..size], bits);
                 }
+            }
+            result?;
+            self.spread_frame(buf);
+        } else if self.info().interlaced {
+            #[cfg(feature = "parallel")]
+            let result = if self.parallel && !self.allow_truncated && self.transposed.is_none() {
+                self.next_frame_parallel(buf, color_type, bit_depth, rows_decoded)
             } else {
-                info.color_type
+                self.next_frame_passes(buf, color_type, bit_depth, rows_decoded)
             };
-            (color_type, BitDepth::from_u8(bits).unwrap())
+            #[cfg(not(feature = "parallel"))]
+            let result = self.next_frame_passes(buf, color_type, bit_depth, rows_decoded);
+            // Passes are combined with the original order of packed pixels.
+            if let Some(bits) = self.swapped_bits() {
+                if !is_would_block(&result) {
+                    let size = self.output_buffer_size();
+                    utils::swap_packed_line(&mut buf[..size], bits);
+                }
+            }
+            result?;
+            self.spread_frame(buf);
+        } else if let Some(mut band) = self.transposed.take() {
+            let bytes_pp = color_type.samples() * (bit_depth as usize / 8);
+            let result = self.next_frame_transposed(buf, &mut band, bytes_pp, rows_decoded);
+            self.transposed = Some(band);
+            result?;
+        } else if let Some(band) = band {
+            while *rows_decoded < band {
+                match self.next_row()? {
+                    Some(Row { data: row, .. }) => *len += (&mut buf[*len..]).write(row)?,
+                    None => break,
+                }
+                *rows_decoded += 1;
+                if let Some(stride) = self.row_stride {
+                    *len = *rows_decoded as usize * stride;
+                }
+            }
+        } else {
+            while let Some(Row { data: row, .. }) = self.next_row()? {
+                *len += (&mut buf[*len..]).write(row)?;
+                *rows_decoded += 1;
+                if let Some(stride) = self.row_stride {
+                    *len = *rows_decoded as usize * stride;
+                }
+            }
         }
+        Ok(())
     }
 
-    /// Returns the number of bytes required to hold a deinterlaced image frame
-    /// that is decoded using the given input transformations.
-    pub fn output_buffer_size(&self) -> usize {
-        let (width, height) = self.info().size();
-        let size = self.output_line_size(width);
-        size * height as usize
+    /// Move the rows of a frame that was written without padding to the stride of the output.
+    fn spread_frame(&self, buf: &mut [u8]) {
+        if let Some(stride) = self.row_stride {
+            let info = self.output_info();
+            utils::spread_lines(buf, info.line_size, stride, info.height as usize);
+        }
     }
 
-    fn validate_buffer_sizes(&self) -> Result<(), DecodingError> {
-        // Check if the decoding buffer of a single raw line has a valid size.
-        if self.info().checked_raw_row_length().is_none() {
-            return Err(DecodingError::LimitsExceeded);
+    /// Decodes the next pass of the current frame into `buf`, returning its number.
+    ///
+    /// This allows displaying interlaced frames progressively: the `handling` defines how the
+    /// pixels of the pass are written. With all but `RawRows` the buffer must be able to hold the
+    /// whole frame, as `next_frame` requires, and must be kept between the calls. Passes without
+    /// pixels are skipped. A frame without interlacing is written at once, as if it was the last
+    /// pass `7`. Once all passes were written the next call finishes the frame and returns
+    /// `None`, the following call continues with the next frame. Passes are always written at
+    /// the full resolution of the frame and into the whole frame, also if a band height is set.
+    ///
+    /// ```
+    /// let file = std::fs::File::open("tests/pngsuite/basi0g08.png")?;
+    /// let mut reader = png::Decoder::new(file).read_info()?;
+    /// let mut buf = vec![0; reader.output_buffer_size()];
+    /// let mut passes = vec![];
+    /// while let Some(pass) = reader.next_pass(png::InterlaceHandling::Rectangle, &mut buf)? {
+    ///     // Display the approximation of the frame in `buf`.
+    ///     passes.push(pass);
+    /// }
+    /// assert_eq!(passes, [1, 2, 3, 4, 5, 6, 7]);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn next_pass(
+        &mut self,
+        handling: InterlaceHandling,
+        buf: &mut [u8],
+    ) -> Result<Option<u8>, DecodingError> {
+        let downscale = mem::replace(&mut self.downscale, Downscale::None);
+        let band_height = self.band_height.take();
+        let result = self.next_full_pass(handling, buf);
+        self.downscale = downscale;
+        self.band_height = band_height;
+        result
+    }
+
+    fn next_full_pass(
+        &mut self,
+        handling: InterlaceHandling,
+        buf: &mut [u8],
+    ) -> Result<Option<u8>, DecodingError> {
+        let frame = self.init()?;
+        if buf.len() < self.output_buffer_size() {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected: self.output_buffer_size(),
+                    actual: buf.len(),
+                }
+                .into(),
+            ));
         }
 
-        // Check if the output buffer has a valid size.
-        if self.checked_output_buffer_size().is_none() {
-            return Err(DecodingError::LimitsExceeded);
+        let interlaced = self.info().interlaced;
+        let (pass, mut rows_written) = match self.pass_progress {
+            Some((pass, rows)) if rows < self.pass_rows(pass) => (pass, rows),
+            last => match self.following_pass(last.map(|(pass, _)| pass)) {
+                Some(pass) => (pass, 0),
+                None => {
+                    self.pass_progress = None;
+                    if !self.subframe.consumed_and_flushed {
+                        self.decoder.finished_decoding()?;
+                    }
+                    self.finished_frame();
+                    return Ok(None);
+                }
+            },
+        };
+        if rows_written == 0 {
+            if pass == self.following_pass(None).unwrap() {
+                self.reset_current();
+            }
+            self.pass_progress = Some((pass, 0));
         }
 
-        Ok(())
+        let size = (frame.width, frame.height);
+        let bits_pp = frame.color_type.samples() * frame.bit_depth as usize;
+        let swapped = self.swapped_bits().is_some();
+        let (columns, block) = if interlaced {
+            (adam7::image_columns(pass), adam7::pass_block(pass))
+        } else {
+            ((0, 1), (1, 1))
+        };
+        let rows = self.pass_rows(pass);
+        let mut raw_len = rows_written as usize * self.pass_line_size(pass);
+        while rows_written < rows {
+            let row = match self.next_interlaced_row_impl()? {
+                Some(row) => row.data,
+                None => break,
+            };
+            let y = if interlaced {
+                adam7::image_row(pass, rows_written)
+            } else {
+                rows_written
+            };
+            match handling {
+                InterlaceHandling::RawRows => {
+                    buf[raw_len..][..row.len()].copy_from_slice(row);
+                    if swapped {
+                        let bits = bits_pp as u8;
+                        utils::swap_packed_line(&mut buf[raw_len..][..row.len()], bits);
+                    }
+                    raw_len += row.len();
+                }
+                InterlaceHandling::Rectangle => {
+                    let origin = (columns.0, y, columns.1);
+                    utils::fill_blocks(buf, size, row, origin, block, bits_pp, swapped);
+                }
+                InterlaceHandling::Sparkle => {
+                    let origin = (columns.0, y, columns.1);
+                    utils::fill_blocks(buf, size, row, origin, (1, 1), bits_pp, swapped);
+                }
+            }
+            rows_written += 1;
+            self.pass_progress = Some((pass, rows_written));
+        }
+        Ok(Some(pass))
     }
 
-    fn checked_output_buffer_size(&self) -> Option<usize> {
-        let (width, height) = self.info().size();
-        let (color, depth) = self.output_color_type();
-        let rowlen = color.checked_raw_row_length(depth, width)? - 1;
-        let height: usize = std::convert::TryFrom::try_from(height).ok()?;
-        rowlen.checked_mul(height)
+    /// The number of rows of a `pass` of the current frame.
+    fn pass_rows(&self, pass: u8) -> u32 {
+        let (width, height) = (self.subframe.width, self.subframe.height);
+        if self.info().interlaced {
+            adam7::Adam7Iterator::new(width, height)
+                .filter(|&(row_pass, _, _)| row_pass == pass)
+                .count() as u32
+        } else {
+            height
+        }
     }
 
-    /// Returns the number of bytes required to hold a deinterlaced row.
-    pub fn output_line_size(&self, width: u32) -> usize {
-        let (color, depth) = self.output_color_type();
-        color.raw_row_length_from_width(depth, width) - 1
+    /// The bytes of an output row of a `pass` of the current frame.
+    fn pass_line_size(&self, pass: u8) -> usize {
+        let width = self.subframe.width;
+        if self.info().interlaced {
+            let (start, step) = adam7::image_columns(pass);
+            let columns = (start..width).step_by(step as usize).count() as u32;
+            self.output_line_size(columns)
+        } else {
+            self.output_line_size(width)
+        }
     }
 
-    /// Returns the number of bytes required to decode a deinterlaced row.
-    fn line_size(&self, width: u32) -> Option<usize> {
-        use crate::common::ColorType::*;
-        let t = self.transform;
-        let info = self.info();
-        let trns = info.trns.is_some();
+    /// The pass with rows following the pass `last`, or the first one.
+    fn following_pass(&self, last: Option<u8>) -> Option<u8> {
+        if !self.info().interlaced {
+            return if last.is_none() { Some(7) } else { None };
+        }
+        let first = last.map_or(1, |pass| pass + 1);
+        (first..=7).find(|&pass| self.pass_rows(pass) > 0)
+    }
 
-        let expanded = if info.bit_depth == BitDepth::Sixteen {
-            BitDepth::Sixteen
-        } else {
-            BitDepth::Eight
+    /// Decodes the region of `width` by `height` pixels of the next frame whose top left pixel
+    /// is at `x`, `y` into `buf`.
+    ///
+    /// Rows above the region are decompressed and unfiltered without applying the output
+    /// transformations, and rows below it are skipped. Of a frame without interlacing only the
+    /// bytes up to the right edge of the region are unfiltered. An interlaced frame is decoded in
+    /// full and then cropped. The region is written in row-major order and the rest of the frame
+    /// is consumed as by `next_frame`, the returned output info describes the region.
+    ///
+    /// Returns an error if the region is empty, does not lie within the frame or `buf` can not
+    /// hold it.
+    /// Unlike `next_frame` a call interrupted by a reader that would block can not be resumed.
+    ///
+    /// ```
+    /// # let mut png = vec![];
+    /// # let mut encoder = png::Encoder::new(&mut png, 4, 3);
+    /// # let image: Vec<u8> = (0..12).collect();
+    /// # encoder.write_header()?.write_image_data(&image)?;
+    /// let mut reader = png::Decoder::new(&png[..]).read_info()?;
+    /// let mut corner = [0; 4];
+    /// let info = reader.read_region(2, 1, 2, 2, &mut corner)?;
+    /// assert_eq!((info.width, info.height), (2, 2));
+    /// assert_eq!(corner, [6, 7, 10, 11]);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn read_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        buf: &mut [u8],
+    ) -> Result<OutputInfo, DecodingError> {
+        let downscale = mem::replace(&mut self.downscale, Downscale::None);
+        let result = self.read_full_region(x, y, width, height, buf);
+        self.downscale = downscale;
+        result
+    }
+
+    fn read_full_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        buf: &mut [u8],
+    ) -> Result<OutputInfo, DecodingError> {
+        let frame = self.init()?;
+        let fits = |start: u32, len: u32, end: u32| {
+            len > 0 && start.checked_add(len).map_or(false, |last| last <= end)
         };
-        // The color type and depth representing the decoded line
-        // TODO 16 bit
-        let (color, depth) = match info.color_type {
-            Indexed if trns && t.contains(Transformations::EXPAND) => (Rgba, expanded),
-            Indexed if t.contains(Transformations::EXPAND) => (Rgb, expanded),
-            Rgb if trns && t.contains(Transformations::EXPAND) => (Rgba, expanded),
-            Grayscale if trns && t.contains(Transformations::EXPAND) => (GrayscaleAlpha, expanded),
-            Grayscale if t.contains(Transformations::EXPAND) => (Grayscale, expanded),
-            GrayscaleAlpha if t.contains(Transformations::EXPAND) => (GrayscaleAlpha, expanded),
-            other => (other, info.bit_depth),
+        if !fits(x, width, frame.width) || !fits(y, height, frame.height) {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::RegionOutOfBounds.into(),
+            ));
+        }
+        let region = OutputInfo {
+            width,
+            height,
+            line_size: self.output_line_size(width),
+            ..frame
         };
+        if buf.len() < region.buffer_size() {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected: region.buffer_size(),
+                    actual: buf.len(),
+                }
+                .into(),
+            ));
+        }
+        let bits_pp = frame.color_type.samples() * frame.bit_depth as usize;
+        let (x, rows) = (x as usize, y..y + height);
+        let buf = &mut buf[..region.buffer_size()];
 
-        // Without the filter method byte
-        color.checked_raw_row_length(depth, width).map(|n| n - 1)
+        if self.info().interlaced {
+            // The whole frame is decoded, not a band of it.
+            let (transposed, band_height) = (self.transposed.take(), self.band_height.take());
+            let mut frame_buf = vec![0; self.output_buffer_size()];
+            let result = self.next_frame(&mut frame_buf);
+            self.transposed = transposed;
+            self.band_height = band_height;
+            result?;
+            // Crop the pixels in their original order.
+            if let Some(bits) = self.swapped_bits() {
+                utils::swap_packed_line(&mut frame_buf, bits);
+            }
+            let lines = frame_buf.chunks(frame.line_size).skip(y as usize);
+            for (line, out) in lines.zip(buf.chunks_mut(region.line_size)) {
+                utils::crop_line(line, out, x, width as usize, bits_pp);
+            }
+        } else {
+            self.reset_current();
+            if self.statistics.is_none() {
+                let raw_bits = self.info().bits_per_pixel();
+                self.row_prefix = Some(((x + width as usize) * raw_bits + 7) / 8);
+            }
+            let result = self.read_region_rows(rows, x, &region, bits_pp, buf);
+            self.row_prefix = None;
+            result?;
+            if !self.subframe.consumed_and_flushed {
+                self.decoder.finished_decoding()?;
+            }
+            self.finished_frame();
+        }
+
+        if let Some(bits) = self.swapped_bits() {
+            utils::swap_packed_line(buf, bits);
+        }
+        Ok(region)
     }
 
-    fn allocate_out_buf(&mut self) -> Result<(), DecodingError> {
-        let width = self.subframe.width;
-        let bytes = self.limits.bytes;
-        let buflen = match self.line_size(width) {
-            Some(buflen) if buflen <= bytes => buflen,
-            // Should we differentiate between platform limits and others?
-            _ => return Err(DecodingError::LimitsExceeded),
+    /// Crop the `rows` of a frame without interlacing into `buf`, from pixel `x` on.
+    fn read_region_rows(
+        &mut self,
+        rows: Range<u32>,
+        x: usize,
+        region: &OutputInfo,
+        bits_pp: usize,
+        buf: &mut [u8],
+    ) -> Result<(), DecodingError> {
+        if self.skip_rows(rows.start)? < rows.start {
+            return Ok(());
+        }
+        for out in buf.chunks_mut(region.line_size).take(rows.len()) {
+            match self.next_interlaced_row_impl()? {
+                Some(row) => utils::crop_line(row.data, out, x, region.width as usize, bits_pp),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all rows of a frame without interlacing into `buf` in column-major order.
+    ///
+    /// Rows are collected in `band` and transposed together, so that each column of the output
+    /// is written in runs of several pixels. Rows of an interrupted call stay in the band.
+    fn next_frame_transposed(
+        &mut self,
+        buf: &mut [u8],
+        band: &mut Vec<u8>,
+        bytes_pp: usize,
+        rows_decoded: &mut u32,
+    ) -> Result<(), DecodingError> {
+        const BAND_ROWS: u32 = 16;
+        let (width, height) = (self.subframe.width, self.subframe.height);
+        if *rows_decoded == 0 {
+            band.clear();
+        }
+        let mut band_start = *rows_decoded - *rows_decoded % BAND_ROWS;
+        let result = loop {
+            match self.next_row() {
+                Ok(Some(Row { data: row, .. })) => band.extend_from_slice(row),
+                Ok(None) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+            *rows_decoded += 1;
+            if *rows_decoded % BAND_ROWS == 0 {
+                utils::transpose_band(buf, height, band, width, band_start, bytes_pp);
+                band.clear();
+                band_start = *rows_decoded;
+            }
+        };
+        if !is_would_block(&result) {
+            utils::transpose_band(buf, height, band, width, band_start, bytes_pp);
+            band.clear();
+        }
+        result
+    }
+
+    /// Write the rows of a frame, of any interlacing, into `buf` at a reduced resolution.
+    fn next_frame_downscaled(
+        &mut self,
+        buf: &mut [u8],
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        rows_decoded: &mut u32,
+    ) -> Result<(), DecodingError> {
+        if *rows_decoded == 0 || self.accumulator.is_none() {
+            let size = (self.subframe.width, self.subframe.height);
+            let line_size = self.output_info().line_size;
+            let progressive = !self.info().interlaced;
+            let output = (color_type, bit_depth);
+            let accumulator =
+                Accumulator::new(self.downscale, size, output, line_size, progressive);
+            self.accumulator = Some(accumulator);
+        }
+        let mut accumulator = self.accumulator.take().unwrap();
+        let result = loop {
+            let (row, interlace) = match self.next_interlaced_row_impl() {
+                Ok(Some(row)) => (row.data, row.interlace),
+                Ok(None) => break Ok(()),
+                Err(err) => break Err(err),
+            };
+            let (y, columns) = match interlace {
+                InterlaceInfo::Adam7 { pass, line, .. } => {
+                    (adam7::image_row(pass, line), adam7::image_columns(pass))
+                }
+                InterlaceInfo::Null => (*rows_decoded, (0, 1)),
+            };
+            accumulator.add_row(row, y, columns, buf);
+            *rows_decoded += 1;
         };
-        self.processed.resize(buflen, 0u8);
+        if is_would_block(&result) {
+            self.accumulator = Some(accumulator);
+        } else {
+            accumulator.finish(buf);
+        }
+        result
+    }
+
+    /// Write the rows of all passes of an interlaced frame into `buf`.
+    fn next_frame_passes(
+        &mut self,
+        buf: &mut [u8],
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        rows_decoded: &mut u32,
+    ) -> Result<(), DecodingError> {
+        let width = self.info().width;
+        let transposed = self.transposed.is_some();
+        let (frame_width, frame_height) = (self.subframe.width, self.subframe.height);
+        while let Some(InterlacedRow {
+            data: row,
+            interlace,
+            ..
+        }) = self.next_interlaced_row_impl()?
+        {
+            let (line, pass) = match interlace {
+                InterlaceInfo::Adam7 { line, pass, .. } => (line, pass),
+                InterlaceInfo::Null => unreachable!("expected interlace information"),
+            };
+            let samples = color_type.samples() as u8;
+            if transposed {
+                let bytes_pp = usize::from(samples * (bit_depth as u8) / 8);
+                let (width, height) = (frame_width, frame_height);
+                utils::expand_pass_transposed(buf, width, height, row, pass, line, bytes_pp);
+            } else {
+                utils::expand_pass(buf, width, row, pass, line, samples * (bit_depth as u8));
+            }
+            *rows_decoded += 1;
+        }
+        Ok(())
+    }
+
+    /// Decode all passes of the current, interlaced, frame into `buf` on several threads.
+    ///
+    /// Decodes the passes one after the other instead if the image data of the frame does not fit
+    /// into the limits.
+    #[cfg(feature = "parallel")]
+    fn next_frame_parallel(
+        &mut self,
+        buf: &mut [u8],
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        rows_decoded: &mut u32,
+    ) -> Result<(), DecodingError> {
+        let (width, height) = (self.subframe.width, self.subframe.height);
+        let passes: Vec<(u32, usize, u32)> = (1..=7)
+            .map(|pass| match adam7::pass_size(pass, width, height) {
+                (0, _) | (_, 0) => (0, 0, 0),
+                (pass_width, lines) => {
+                    let row_len = self.info().raw_row_length_from_width(pass_width);
+                    (pass_width, row_len, lines)
+                }
+            })
+            .collect();
+        let len: usize = passes
+            .iter()
+            .map(|&(_, row_len, lines)| row_len * lines as usize)
+            .sum();
+        // A frame without pixels has no rows to combine, the passes report the error of its data.
+        if len == 0 || len > self.limits.bytes {
+            return self.next_frame_passes(buf, color_type, bit_depth, rows_decoded);
+        }
+
+        self.read_image_data(len)?;
+        let start = self.scan_start;
+        let filtered: Vec<_> = passes
+            .iter()
+            .map(|&(_, row_len, lines)| (row_len, lines))
+            .collect();
+        parallel::unfilter_passes(&mut self.current[start..start + len], &filtered, self.bpp)?;
+        self.scan_start += len;
+        // All rows of the frame are taken at once.
+        if let InterlaceIter::Adam7(ref mut adam7) = self.subframe.interlace {
+            adam7.for_each(drop);
+        }
+
+        let mut rows = Vec::with_capacity(passes.len());
+        let mut row_start = start;
+        for &(pass_width, row_len, lines) in &passes {
+            let mut pass = parallel::PassRows {
+                row_len: 0,
+                data: Vec::new(),
+            };
+            for _ in 0..lines {
+                let row = &self.current[row_start + 1..row_start + row_len];
+                row_start += row_len;
+                if let Some(ref mut statistics) = self.statistics {
+                    statistics.add_row(row, pass_width, self.decoder.info().unwrap());
+                }
+                if self.identity {
+                    pass.row_len = row.len();
+                    pass.data.extend_from_slice(row);
+                } else {
+                    self.processed[..row.len()].copy_from_slice(row);
+                    pass.row_len = self.transform_processed(pass_width)?;
+                    pass.data.extend_from_slice(&self.processed[..pass.row_len]);
+                }
+            }
+            rows.push(pass);
+        }
+
+        let line_size = self.output_line_size(width);
+        let frame = &mut buf[..line_size * height as usize];
+        let bits_pp = color_type.samples() * bit_depth as usize;
+        parallel::deinterlace(frame, (width, line_size), &rows, bits_pp);
+        *rows_decoded = self.subframe_rows();
+        Ok(())
+    }
+
+    /// Decompress image data of the current frame until `len` bytes of it are available.
+    #[cfg(feature = "parallel")]
+    fn read_image_data(&mut self, len: usize) -> Result<(), DecodingError> {
+        while self.current.len() - self.scan_start < len {
+            if self.subframe.consumed_and_flushed {
+                let end = self.short_image_data();
+                return Err(DecodingError::Format(
+                    FormatErrorInner::NoMoreImageData { end }.into(),
+                ));
+            }
+            match self.decoder.decode_next(&mut self.current)? {
+                Some(Decoded::ImageDataFlushed) => self.subframe.consumed_and_flushed = true,
+                None => {
+                    return Err(DecodingError::Format(
+                        FormatErrorInner::UnexpectedEndOfChunk.into(),
+                    ))
+                }
+                _ => (),
+            }
+        }
         Ok(())
     }
 
-    fn next_pass(&mut self) -> Option<(usize, InterlaceInfo)> {
-        match self.subframe.interlace {
-            InterlaceIter::Adam7(ref mut adam7) => {
-                let last_pass = adam7.current_pass();
-                let (pass, line, width) = adam7.next()?;
-                let rowlen = self.info().raw_row_length_from_width(width);
-                if last_pass != pass {
-                    self.prev.clear();
-                    self.prev.resize(rowlen, 0u8);
+    /// The bits per pixel if the order of packed pixels is swapped.
+    fn swapped_bits(&self) -> Option<u8> {
+        let (color_type, bit_depth) = self.output_color_type();
+        let bits = color_type.samples() as u8 * bit_depth as u8;
+        if self.transform.contains(Transformations::PACKSWAP) && bits < 8 {
+            Some(bits)
+        } else {
+            None
+        }
+    }
+
+    /// The number of rows in the current subframe, counting the rows of all interlace passes.
+    fn subframe_rows(&self) -> u32 {
+        let (width, height) = (self.subframe.width, self.subframe.height);
+        if self.info().interlaced {
+            adam7::Adam7Iterator::new(width, height).count() as u32
+        } else {
+            height
+        }
+    }
+
+    /// Returns the next processed row of the image
+    pub fn next_row(&mut self) -> Result<Option<Row<'_>>, DecodingError> {
+        self.next_interlaced_row().map(|v| {
+            v.map(|v| Row {
+                data: v.data,
+                info: v.info,
+            })
+        })
+    }
+
+    /// Returns the next processed row of the image
+    pub fn next_interlaced_row(&mut self) -> Result<Option<InterlacedRow<'_>>, DecodingError> {
+        let bits = match self.swapped_bits() {
+            Some(bits) => bits,
+            None => return self.next_interlaced_row_impl(),
+        };
+        let (len, interlace, info) = match self.next_interlaced_row_impl()? {
+            Some(row) => (row.data.len(), row.interlace, row.info),
+            None => return Ok(None),
+        };
+        // Any transformation other than the identity returns the processed row.
+        let data = &mut self.processed[..len];
+        utils::swap_packed_line(data, bits);
+        Ok(Some(InterlacedRow {
+            data,
+            interlace,
+            info,
+        }))
+    }
+
+    /// Calls `f` with each row of the current frame and its index, after deinterlacing it.
+    ///
+    /// This avoids a buffer for the whole frame. Rows of frames without interlacing are passed on
+    /// in order, continuing after any rows read with `next_row`. Interlaced frames keep only the
+    /// first five passes, which hold a quarter of the pixels, and one row. Their even rows are
+    /// then completed by the sixth pass and the odd rows are the rows of the seventh pass, so that
+    /// the even rows come first. The rows of an interlaced frame must not have been read yet.
+    ///
+    /// ```
+    /// let file = std::fs::File::open("tests/pngsuite/basi0g08.png")?;
+    /// let mut reader = png::Decoder::new(file).read_info()?;
+    /// let mut frame = vec![0; reader.output_buffer_size()];
+    /// let line_size = reader.output_line_size(32);
+    /// reader.for_each_output_row(|y, row| {
+    ///     frame[y as usize * line_size..][..line_size].copy_from_slice(row)
+    /// })?;
+    ///
+    /// let file = std::fs::File::open("tests/pngsuite/basn0g08.png")?;
+    /// let mut reader = png::Decoder::new(file).read_info()?;
+    /// let mut expected = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut expected)?;
+    /// assert_eq!(frame, expected);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn for_each_output_row<F>(&mut self, mut f: F) -> Result<(), DecodingError>
+    where
+        F: FnMut(u32, &[u8]),
+    {
+        if !self.info().interlaced {
+            while let Some(row) = self.next_row()? {
+                f(row.info().row_index, row.data());
+            }
+            return Ok(());
+        }
+
+        let progress = self.progress();
+        if progress.rows_remaining < progress.rows_total {
+            let rows_read = progress.rows_total - progress.rows_remaining;
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::FrameStarted { rows_read }.into(),
+            ));
+        }
+
+        let (width, height) = (self.subframe.width, self.subframe.height);
+        let (color_type, bit_depth) = self.output_color_type();
+        let bits_pp = color_type.samples() * bit_depth as usize;
+        let swapped = self.swapped_bits().is_some();
+        let pass_widths: Vec<u32> = (1..=7)
+            .map(|pass| adam7::pass_size(pass, width, height).0)
+            .collect();
+        let pass_lines: Vec<usize> = pass_widths
+            .iter()
+            .map(|&width| self.output_line_size(width))
+            .collect();
+        let mut passes = vec![Vec::new(); 5];
+        let mut line = vec![0; self.output_line_size(width)];
+
+        // Fill the even row `y` from the first five passes and, if present, a row of the sixth.
+        let even_row = |line: &mut [u8], passes: &[Vec<u8>], y: u32, sixth: Option<&[u8]>| {
+            for (rows, pass) in passes.iter().zip(1..) {
+                let (first, step) = (adam7::image_row(pass, 0), adam7::image_row(pass, 1));
+                let step = step - first;
+                let len = pass_lines[usize::from(pass) - 1];
+                if len == 0 || y < first || (y - first) % step != 0 {
+                    continue;
+                }
+                let row = &rows[((y - first) / step) as usize * len..][..len];
+                let (x, step) = adam7::image_columns(pass);
+                utils::fill_blocks(
+                    line,
+                    (width, 1),
+                    row,
+                    (x, 0, step),
+                    (1, 1),
+                    bits_pp,
+                    swapped,
+                );
+            }
+            if let Some(row) = sixth {
+                let (x, step) = adam7::image_columns(6);
+                utils::fill_blocks(
+                    line,
+                    (width, 1),
+                    row,
+                    (x, 0, step),
+                    (1, 1),
+                    bits_pp,
+                    swapped,
+                );
+            }
+        };
+        // Without a sixth pass, in images of one column, the even rows are complete after the
+        // fifth pass.
+        let mut even_done = pass_widths[5] > 0;
+        let finish_even = |line: &mut [u8], passes: &[Vec<u8>], f: &mut F| {
+            for y in (0..height).step_by(2) {
+                even_row(line, passes, y, None);
+                f(y, line);
+            }
+        };
+
+        while let Some(row) = self.next_interlaced_row()? {
+            let (pass, line_no) = match row.interlace() {
+                InterlaceInfo::Adam7 { pass, line, .. } => (pass, line),
+                InterlaceInfo::Null => unreachable!("interlaced frame without passes"),
+            };
+            let y = adam7::image_row(pass, line_no);
+            match pass {
+                1..=5 => passes[usize::from(pass) - 1].extend_from_slice(row.data()),
+                6 => {
+                    even_row(&mut line, &passes, y, Some(row.data()));
+                    f(y, &line);
+                }
+                _ => {
+                    if !even_done {
+                        finish_even(&mut line, &passes, &mut f);
+                        even_done = true;
+                    }
+                    f(y, row.data());
+                }
+            }
+        }
+        if !even_done {
+            finish_even(&mut line, &passes, &mut f);
+        }
+        Ok(())
+    }
+
+    /// Skips the next `n` rows of the image, returning how many rows were skipped.
+    ///
+    /// The rows are unfiltered, since the following rows are predicted from them, but the output
+    /// transformations are not applied. This counts rows like `next_row`, the rows of all passes
+    /// of an interlaced image. Fewer rows are skipped if the frame ends before.
+    ///
+    /// ```
+    /// # let mut png = vec![];
+    /// # let mut encoder = png::Encoder::new(&mut png, 2, 4);
+    /// # encoder.write_header()?.write_image_data(&[0, 0, 1, 1, 2, 2, 3, 3])?;
+    /// let mut reader = png::Decoder::new(&png[..]).read_info()?;
+    /// // Only every second row.
+    /// while let Some(row) = reader.next_row()? {
+    ///     assert!(row.data()[0] % 2 == 0);
+    ///     reader.skip_rows(1)?;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn skip_rows(&mut self, n: u32) -> Result<u32, DecodingError> {
+        for skipped in 0..n {
+            if self.next_raw_interlaced_row()?.is_none() {
+                return Ok(skipped);
+            }
+        }
+        Ok(n)
+    }
+
+    /// Fetch the next interlaced row and filter it according to our own transformations.
+    fn next_interlaced_row_impl(&mut self) -> Result<Option<InterlacedRow<'_>>, DecodingError> {
+        if self.identity {
+            return self.next_raw_interlaced_row();
+        }
+
+        // swap buffer to circumvent borrow issues
+        let mut buffer = mem::replace(&mut self.processed, Vec::new());
+        let next = match self.next_raw_interlaced_row() {
+            Ok(Some(row)) => {
+                (&mut buffer[..]).write_all(row.data)?;
+                Ok((true, row.interlace))
+            }
+            Ok(None) => Ok((false, InterlaceInfo::Null)),
+            Err(err) => Err(err),
+        };
+        // swap back, also on errors since decoding may be resumed.
+        let _ = mem::replace(&mut self.processed, buffer);
+        let (got_next, adam7) = next?;
+
+        if !got_next {
+            return Ok(None);
+        }
+
+        let width = if let InterlaceInfo::Adam7 { width, .. } = adam7 {
+            width
+        } else {
+            self.subframe.width
+        };
+        let len = self.transform_processed(width)?;
+
+        Ok(Some(InterlacedRow {
+            data: &self.processed[..len],
+            interlace: adam7,
+            info: self.row_info(adam7),
+        }))
+    }
+
+    /// Apply the transformations to the row of `width` pixels in the processed line.
+    ///
+    /// Returns the length of the transformed row.
+    fn transform_processed(&mut self, width: u32) -> Result<usize, DecodingError> {
+        use crate::common::ColorType::*;
+        let transform = self.transform;
+        let (color_type, bit_depth, trns) = {
+            let info = self.info();
+            (info.color_type, info.bit_depth as u8, info.trns.is_some())
+        };
+        let expanded = self
+            .line_size(width)
+            .expect("Rows are shorter than the buffer.");
+        let channels = self.expanded_color_type().0.samples();
+        let transform = transform.implied();
+        // The color channels of pixels with alpha that are composited and their background.
+        let background = if transform.contains(Transformations::BACKGROUND) {
+            let without = transform - Transformations::BACKGROUND;
+            match self.expanded_color_type_with(without).0 {
+                alpha_type @ GrayscaleAlpha | alpha_type @ Rgba => Some((
+                    alpha_type.samples() - 1,
+                    self.background_samples(bit_depth == 16),
+                )),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let output_buffer = &mut self.processed[..expanded];
+
+        let mut len = output_buffer.len();
+        if transform.contains(Transformations::EXPAND) {
+            match color_type {
+                Indexed => expand_paletted(output_buffer, get_info!(self))?,
+                Grayscale | GrayscaleAlpha if bit_depth < 8 => {
+                    expand_gray_u8(output_buffer, get_info!(self))
+                }
+                Grayscale | Rgb if trns => {
+                    let channels = color_type.samples();
+                    let trns = get_info!(self).trns.as_ref().unwrap();
+                    if bit_depth == 8 {
+                        utils::expand_trns_line(output_buffer, trns, channels);
+                    } else {
+                        utils::expand_trns_line16(output_buffer, trns, channels);
+                    }
+                }
+                _ => (),
+            }
+        } else if transform.contains(Transformations::PACKING) && bit_depth < 8 {
+            utils::unpack_bits(output_buffer, 1, bit_depth, |sample, chunk| {
+                chunk[0] = sample
+            });
+        }
+
+        if let Some((colors, background)) = background {
+            len = utils::composite_line(output_buffer, &background[..colors], bit_depth == 16);
+        }
+
+        if bit_depth == 16 && transform.contains(Transformations::SCALE_16_GAMMA) {
+            let table = self.gamma_table.as_ref().map(|(_, table)| &table[..]);
+            len = utils::scale_16_line(&mut output_buffer[..len], channels, table);
+        } else if bit_depth == 16 && transform.contains(Transformations::SCALE_16) {
+            len = utils::scale_16_line(&mut output_buffer[..len], channels, None);
+        } else if bit_depth == 16 && transform.intersects(Transformations::STRIP_16) {
+            len /= 2;
+            for i in 0..len {
+                output_buffer[i] = output_buffer[2 * i];
+            }
+        }
+
+        if let Some((_, ref table)) = self.display_table {
+            let wide = self.expanded_color_type().1 == BitDepth::Sixteen;
+            utils::gamma_line(&mut self.processed[..len], channels, wide, table);
+        }
+
+        if transform.contains(Transformations::INVERT_MONO) {
+            let (color_type, bit_depth) = self.expanded_color_type();
+            let unpacked = self.info().bit_depth as u8;
+            if color_type == Grayscale
+                && !transform.contains(Transformations::EXPAND)
+                && unpacked < bit_depth as u8
+            {
+                // Unpacked samples keep their range.
+                let max = (1 << unpacked) - 1;
+                for sample in &mut self.processed[..len] {
+                    *sample = max - *sample;
+                }
+            } else if color_type == Grayscale || color_type == GrayscaleAlpha {
+                let sample = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+                let channels = color_type.samples();
+                utils::invert_gray_line(&mut self.processed[..len], channels, sample);
+            }
+        }
+
+        if transform.intersects(Transformations::RGBA8 | Transformations::RGBA16) {
+            let (color_type, bit_depth) = self.expanded_color_type();
+            let channels = color_type.samples();
+            let pixels = width as usize;
+            if transform.contains(Transformations::RGBA8) {
+                len = 4 * pixels;
+                utils::expand_rgba8_line(&mut self.processed, channels, pixels);
+            } else {
+                len = 8 * pixels;
+                let wide = bit_depth == BitDepth::Sixteen;
+                utils::expand_rgba16_line(&mut self.processed, channels, wide, pixels);
+            }
+        } else if transform.contains(Transformations::ADD_ALPHA) {
+            let (color_type, bit_depth) = self.expanded_color_type();
+            if color_type == Grayscale || color_type == Rgb {
+                let sample = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+                let pixel = color_type.samples() * sample;
+                len = utils::add_alpha_line(&mut self.processed, pixel, sample, width as usize);
+            }
+        }
+
+        if transform.contains(Transformations::ALPHA_MASK) {
+            len = width as usize;
+            utils::alpha_mask_line(&mut self.processed, len);
+        }
+
+        if transform.contains(Transformations::STRIP_ALPHA) {
+            let (color_type, bit_depth) = self.normalized_color_type();
+            if color_type == Rgba || color_type == GrayscaleAlpha {
+                let sample = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+                let pixel = color_type.samples() * sample;
+                len = utils::strip_alpha_line(&mut self.processed[..len], pixel, sample);
+            }
+        }
+
+        let order = transform.channel_order();
+        if order != ChannelOrder::Rgba {
+            let (color_type, bit_depth) = self.output_color_type();
+            let sample = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+            if color_type.samples() > 1 {
+                let (bgr, alpha_first) = order.swaps();
+                utils::reorder_channels_line(
+                    &mut self.processed[..len],
+                    color_type.samples(),
+                    sample,
+                    bgr,
+                    alpha_first,
+                );
+            }
+        }
+
+        if transform.contains(Transformations::SWAP_ENDIAN)
+            && self.output_color_type().1 == BitDepth::Sixteen
+        {
+            utils::swap_endian_line(&mut self.processed[..len]);
+        }
+
+        if self.row_transform.is_some() {
+            let format = RowFormat {
+                width,
+                input: (color_type, self.info().bit_depth),
+                output: self.output_color_type(),
+            };
+            if let Some(ref mut row_transform) = self.row_transform {
+                row_transform(&mut self.processed[..len], format);
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// Returns the color type and the number of bits per sample
+    /// of the data returned by `Reader::next_row` and Reader::frames`.
+    pub fn output_color_type(&self) -> (ColorType, BitDepth) {
+        self.output_color_type_with(self.transform)
+    }
+
+    /// Whether the image can be decoded to samples of the color type and bit depth.
+    ///
+    /// This is the case if some combination of the supported transformations produces this
+    /// output from the color type and bit depth of the image, when it is set with
+    /// `Decoder::set_transformations` before reading the header. A caller can use this to agree
+    /// on a format before decoding, instead of reading the output of the current transformations
+    /// with `output_color_type`.
+    ///
+    /// ```
+    /// use png::{BitDepth, ColorType};
+    /// let decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn3p02.png")?);
+    /// let reader = decoder.read_info()?;
+    /// assert!(reader.can_output(ColorType::Indexed, BitDepth::Eight));
+    /// assert!(reader.can_output(ColorType::Rgb, BitDepth::Eight));
+    /// assert!(reader.can_output(ColorType::Rgba, BitDepth::Sixteen));
+    /// assert!(!reader.can_output(ColorType::Grayscale, BitDepth::Two));
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn can_output(&self, color_type: ColorType, bit_depth: BitDepth) -> bool {
+        if color_type.is_combination_invalid(bit_depth) {
+            return false;
+        }
+        // Only these transformations change the color type or bit depth.
+        let shaping = [
+            Transformations::EXPAND,
+            Transformations::PACKING,
+            Transformations::STRIP_16,
+            Transformations::STRIP_ALPHA,
+        ];
+        let normalizing = [
+            Transformations::IDENTITY,
+            Transformations::RGBA8,
+            Transformations::RGBA16,
+            Transformations::ALPHA_MASK,
+            Transformations::ADD_ALPHA,
+        ];
+        (0..1 << shaping.len()).any(|set: usize| {
+            let shape = shaping
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| set & 1 << i != 0)
+                .fold(Transformations::IDENTITY, |t, (_, &flag)| t | flag);
+            normalizing.iter().any(|&normalize| {
+                self.output_color_type_with(shape | normalize) == (color_type, bit_depth)
+            })
+        })
+    }
+
+    fn output_color_type_with(&self, t: Transformations) -> (ColorType, BitDepth) {
+        let t = t.implied();
+        if t.contains(Transformations::ALPHA_MASK) {
+            return (ColorType::Grayscale, BitDepth::Eight);
+        }
+        let (color_type, bit_depth) = self.normalized_color_type_with(t);
+        if !t.contains(Transformations::STRIP_ALPHA) {
+            return (color_type, bit_depth);
+        }
+        let color_type = match color_type {
+            ColorType::Rgba => ColorType::Rgb,
+            ColorType::GrayscaleAlpha => ColorType::Grayscale,
+            other => other,
+        };
+        (color_type, bit_depth)
+    }
+
+    /// Returns the color type and bit depth before the alpha channel is stripped.
+    fn normalized_color_type(&self) -> (ColorType, BitDepth) {
+        self.normalized_color_type_with(self.transform)
+    }
+
+    fn normalized_color_type_with(&self, t: Transformations) -> (ColorType, BitDepth) {
+        let t = t.implied();
+        if t.contains(Transformations::RGBA8) {
+            (ColorType::Rgba, BitDepth::Eight)
+        } else if t.contains(Transformations::RGBA16) {
+            (ColorType::Rgba, BitDepth::Sixteen)
+        } else if t.contains(Transformations::ADD_ALPHA) {
+            match self.expanded_color_type_with(t) {
+                (ColorType::Grayscale, depth) => (ColorType::GrayscaleAlpha, depth),
+                (ColorType::Rgb, depth) => (ColorType::Rgba, depth),
+                other => other,
+            }
+        } else {
+            self.expanded_color_type_with(t)
+        }
+    }
+
+    /// Returns the color type and bit depth after expanding and stripping samples.
+    fn expanded_color_type(&self) -> (ColorType, BitDepth) {
+        self.expanded_color_type_with(self.transform)
+    }
+
+    fn expanded_color_type_with(&self, t: Transformations) -> (ColorType, BitDepth) {
+        use crate::common::ColorType::*;
+        let t = t.implied();
+        let info = self.info();
+        if t == Transformations::IDENTITY {
+            (info.color_type, info.bit_depth)
+        } else {
+            let bits = match info.bit_depth as u8 {
+                16 if t.intersects(
+                    Transformations::STRIP_16
+                        | Transformations::SCALE_16
+                        | Transformations::SCALE_16_GAMMA,
+                ) =>
+                {
+                    8
+                }
+                n if n < 8 && t.intersects(Transformations::EXPAND | Transformations::PACKING) => 8,
+                n => n,
+            };
+            let color_type = if t.contains(Transformations::EXPAND) {
+                let has_trns = info.trns.is_some();
+                match info.color_type {
+                    Grayscale if has_trns => GrayscaleAlpha,
+                    Rgb if has_trns => Rgba,
+                    Indexed if has_trns => Rgba,
+                    Indexed => Rgb,
+                    ct => ct,
+                }
+            } else {
+                info.color_type
+            };
+            let color_type = match color_type {
+                GrayscaleAlpha if t.contains(Transformations::BACKGROUND) => Grayscale,
+                Rgba if t.contains(Transformations::BACKGROUND) => Rgb,
+                ct => ct,
+            };
+            (color_type, BitDepth::from_u8(bits).unwrap())
+        }
+    }
+
+    /// The background color of `BACKGROUND` for the expanded samples, of 8 bits or of 16 bits if
+    /// `wide`.
+    ///
+    /// The color set for the decoder is preferred over that of the bKGD chunk, without either or
+    /// if neither fits the image the background is white.
+    fn background_samples(&self, wide: bool) -> [u16; 3] {
+        let info = self.info();
+        let max = if wide { 0xFFFF } else { 0xFF };
+        let samples = |color: BackgroundColor| match color {
+            BackgroundColor::Gray(gray) => {
+                let gray = match info.bit_depth as u8 {
+                    depth if depth < 8 => {
+                        let depth_max = (1 << depth) - 1;
+                        (gray & depth_max) * (0xFF / depth_max)
+                    }
+                    _ => gray.min(max),
+                };
+                Some([gray; 3])
+            }
+            BackgroundColor::Rgb(red, green, blue) => {
+                Some([red.min(max), green.min(max), blue.min(max)])
+            }
+            BackgroundColor::Index(index) => {
+                let palette = info.palette.as_ref()?;
+                let entry = palette.get(3 * usize::from(index)..3 * usize::from(index) + 3)?;
+                Some([entry[0].into(), entry[1].into(), entry[2].into()])
+            }
+        };
+        let fits = |color: &BackgroundColor| match (*color, info.color_type) {
+            (BackgroundColor::Rgb(..), ColorType::Indexed) => true,
+            (color, color_type) => color.fits(color_type),
+        };
+        self.background
+            .into_iter()
+            .chain(info.background)
+            .filter(fits)
+            .filter_map(samples)
+            .next()
+            .unwrap_or([max; 3])
+    }
+
+    /// The info of the row that was just decoded.
+    fn row_info(&self, interlace: InterlaceInfo) -> RowInfo {
+        let (color_type, bit_depth) = self.output_color_type();
+        let (row_index, pass, width) = self.subframe.last_row(interlace);
+        RowInfo {
+            row_index,
+            pass,
+            width,
+            color_type,
+            bit_depth,
+            filter: self.row_filter,
+        }
+    }
+
+    /// Returns the number of bytes required to hold a deinterlaced image frame
+    /// that is decoded using the given input transformations.
+    ///
+    /// If the frame is written in bands, this is the size of a band instead.
+    pub fn output_buffer_size(&self) -> usize {
+        let (width, height) = self.info().size();
+        let (width, height) = self.downscale.size(width, height);
+        let size = self.output_line_size(width);
+        size * self.band_rows(height) as usize
+    }
+
+    /// The first row of the current frame that the next call of `next_frame` writes.
+    ///
+    /// This is always 0 unless the frame is written in bands, see `Decoder::set_band_height`.
+    pub fn band_start(&self) -> u32 {
+        self.band_start
+    }
+
+    /// The rows of a buffer for a frame of the given height.
+    fn band_rows(&self, height: u32) -> u32 {
+        match self.band_height {
+            Some(rows) => rows.min(height),
+            None => height,
+        }
+    }
+
+    fn validate_buffer_sizes(&self) -> Result<(), DecodingError> {
+        // Check if the decoding buffer of a single raw line has a valid size.
+        if self.info().checked_raw_row_length().is_none() {
+            return Err(DecodingError::LimitsExceeded);
+        }
+
+        // Check if the output buffer has a valid size.
+        if self.checked_output_buffer_size().is_none() {
+            return Err(DecodingError::LimitsExceeded);
+        }
+
+        Ok(())
+    }
+
+    fn checked_output_buffer_size(&self) -> Option<usize> {
+        let (width, height) = self.info().size();
+        let (width, height) = self.downscale.size(width, height);
+        let (color, depth) = self.output_color_type();
+        let rowlen = color.checked_raw_row_length(depth, width)? - 1;
+        let height: usize = std::convert::TryFrom::try_from(self.band_rows(height)).ok()?;
+        rowlen.checked_mul(height)
+    }
+
+    /// Returns the number of bytes required to hold a deinterlaced row.
+    pub fn output_line_size(&self, width: u32) -> usize {
+        let (color, depth) = self.output_color_type();
+        color.raw_row_length_from_width(depth, width) - 1
+    }
+
+    /// Returns the number of bytes required to decode a deinterlaced row.
+    fn line_size(&self, width: u32) -> Option<usize> {
+        use crate::common::ColorType::*;
+        let t = self.transform.implied();
+        let info = self.info();
+        let trns = info.trns.is_some();
+
+        let expanded = if info.bit_depth == BitDepth::Sixteen {
+            BitDepth::Sixteen
+        } else {
+            BitDepth::Eight
+        };
+        // The color type and depth representing the decoded line
+        let (color, depth) = match info.color_type {
+            Indexed if trns && t.contains(Transformations::EXPAND) => (Rgba, expanded),
+            Indexed if t.contains(Transformations::EXPAND) => (Rgb, expanded),
+            Rgb if trns && t.contains(Transformations::EXPAND) => (Rgba, expanded),
+            Grayscale if trns && t.contains(Transformations::EXPAND) => (GrayscaleAlpha, expanded),
+            Grayscale if t.contains(Transformations::EXPAND) => (Grayscale, expanded),
+            GrayscaleAlpha if t.contains(Transformations::EXPAND) => (GrayscaleAlpha, expanded),
+            other if (info.bit_depth as u8) < 8 && t.contains(Transformations::PACKING) => {
+                (other, BitDepth::Eight)
+            }
+            other => (other, info.bit_depth),
+        };
+
+        // Without the filter method byte
+        color.checked_raw_row_length(depth, width).map(|n| n - 1)
+    }
+
+    /// The size of the processed line, which also holds the intermediate rows of transformations
+    /// that shrink the row again, such as `RGBA8` with `STRIP_ALPHA`.
+    fn processed_line_size(&self) -> Option<usize> {
+        let width = self.subframe.width;
+        let line_size = self.line_size(width);
+        if self.transform.implied().intersects(
+            Transformations::RGBA8 | Transformations::RGBA16 | Transformations::ADD_ALPHA,
+        ) {
+            // Converting to RGBA or adding alpha may need more room than the expanded row.
+            let (color, depth) = self.normalized_color_type();
+            let rgba = color
+                .checked_raw_row_length(depth, width)
+                .map(|len| len - 1);
+            line_size.and_then(|len| rgba.map(|rgba| len.max(rgba)))
+        } else {
+            line_size
+        }
+    }
+
+    fn allocate_out_buf(&mut self) -> Result<(), DecodingError> {
+        let bytes = self.limits.bytes;
+        let buflen = match self.processed_line_size() {
+            Some(buflen) if buflen <= bytes => buflen,
+            // Should we differentiate between platform limits and others?
+            _ => return Err(DecodingError::LimitsExceeded),
+        };
+        self.update_gamma_table();
+        // Rows are returned straight from the decompressed data without transformations.
+        self.identity = self.transform_is_identity();
+        if !self.identity {
+            self.processed.resize(buflen, 0u8);
+        }
+        Ok(())
+    }
+
+    /// Build the table of `SCALE_16_GAMMA` for the gamma of the image, if it needs one.
+    fn update_gamma_table(&mut self) {
+        self.update_display_table();
+        let info = self.info();
+        let srgb = crate::srgb::substitute_gamma().into_scaled();
+        let gamma = match info.source_gamma.map(|gamma| gamma.into_scaled()) {
+            // There is nothing to convert for the sRGB gamma.
+            Some(gamma) if gamma != 0 && gamma != srgb => gamma,
+            _ => {
+                self.gamma_table = None;
+                return;
+            }
+        };
+        if !self.transform.contains(Transformations::SCALE_16_GAMMA)
+            || info.bit_depth != BitDepth::Sixteen
+            || self.display_table.is_some()
+        {
+            self.gamma_table = None;
+        } else if self.gamma_table.as_ref().map(|(scaled, _)| *scaled) != Some(gamma) {
+            let exponent = f64::from(srgb) / f64::from(gamma);
+            self.gamma_table = Some((gamma, utils::scale_16_gamma_table(exponent)));
+        }
+    }
+
+    /// Build the table of `set_gamma` for the gamma and the expanded samples of the image.
+    fn update_display_table(&mut self) {
+        let (screen_gamma, default_file_gamma) = match self.display_gamma {
+            Some(gamma) => gamma,
+            None => return,
+        };
+        let file_gamma = self
+            .info()
+            .source_gamma
+            .map(|gamma| f64::from(gamma.into_value()))
+            .filter(|&gamma| gamma > 0.0)
+            .unwrap_or(default_file_gamma);
+        let exponent = 1.0 / (screen_gamma * file_gamma);
+        let unpacked = (self.info().bit_depth as u8) < 8
+            && !self.transform.implied().contains(Transformations::EXPAND);
+        let max = match self.expanded_color_type() {
+            // Unpacked samples keep their range, palette indices are not samples.
+            _ if unpacked => None,
+            (ColorType::Indexed, _) => None,
+            (_, BitDepth::Eight) => Some(0xFF),
+            (_, BitDepth::Sixteen) => Some(0xFFFF),
+            _ => None,
+        };
+        let max = match max {
+            // An exponent close to one leaves every sample unchanged.
+            Some(max) if exponent.is_finite() && (exponent - 1.0).abs() > 1e-6 => max,
+            _ => {
+                self.display_table = None;
+                return;
+            }
+        };
+        let key = (exponent.to_bits(), max);
+        if self.display_table.as_ref().map(|(cached, _)| *cached) != Some(key) {
+            self.display_table = Some((key, utils::gamma_table(exponent, max)));
+        }
+    }
+
+    /// Whether the transformations leave the rows of this image unchanged.
+    ///
+    /// For example, `EXPAND` has no effect on an 8-bit RGBA image. Rows are then returned without
+    /// copying them.
+    fn transform_is_identity(&self) -> bool {
+        use crate::common::ColorType::*;
+        let t = self.transform.implied();
+        let info = self.info();
+        let (color_type, bit_depth) = (info.color_type, info.bit_depth);
+        if self.output_color_type() != (color_type, bit_depth) {
+            return false;
+        }
+        let has_alpha = color_type == Rgba || color_type == GrayscaleAlpha;
+        let gray = color_type == Grayscale || color_type == GrayscaleAlpha;
+        let bits = color_type.samples() * bit_depth as usize;
+        !(t.contains(Transformations::ALPHA_MASK)
+            || t.contains(Transformations::BGR) && color_type.samples() >= 3
+            || t.contains(Transformations::SWAP_ALPHA) && has_alpha
+            || t.contains(Transformations::BACKGROUND) && has_alpha
+            || t.contains(Transformations::INVERT_MONO) && gray
+            || t.contains(Transformations::SWAP_ENDIAN) && bit_depth == BitDepth::Sixteen
+            || t.contains(Transformations::PACKSWAP) && bits < 8
+            || self.display_table.is_some()
+            || self.row_transform.is_some())
+    }
+
+    /// Where the image data ended, given that no more data is available.
+    fn short_image_data(&self) -> ShortImageData {
+        if self.current.len() > self.scan_start {
+            ShortImageData::WithinRow
+        } else {
+            ShortImageData::AtRowBoundary
+        }
+    }
+
+    fn next_pass_row(&mut self) -> Option<(usize, InterlaceInfo)> {
+        let info = self.decoder.info().unwrap();
+        let (rowlen, interlace, new_pass) = self.subframe.next_row(info)?;
+        if new_pass {
+            self.prev_start = None;
+        }
+        Some((rowlen, interlace))
+    }
+
+    /// Returns the next raw scanline of the image interlace pass.
+    /// The scanline is filtered against the previous scanline according to the specification.
+    fn next_raw_interlaced_row(&mut self) -> Result<Option<InterlacedRow<'_>>, DecodingError> {
+        let bpp = self.bpp;
+        let (rowlen, passdata) = match self.pending_row {
+            Some(row) => row,
+            None => match self.next_pass_row() {
+                Some(row) => row,
+                None => return Ok(None),
+            },
+        };
+        // Kept until the data of the row is available, reading more data may be interrupted.
+        self.pending_row = Some((rowlen, passdata));
+        loop {
+            if self.current.len() - self.scan_start >= rowlen {
+                self.pending_row = None;
+                let row_start = self.scan_start;
+                let unfilter_len = match self.row_prefix {
+                    Some(prefix) => rowlen.min(prefix + 1),
+                    None => rowlen,
+                };
+                let unfiltered = unfilter_row(
+                    &mut self.current,
+                    row_start,
+                    self.prev_start,
+                    unfilter_len,
+                    bpp,
+                );
+                match unfiltered {
+                    Ok(filter) => self.row_filter = filter,
+                    Err(err) => {
+                        self.scan_start += rowlen;
+                        return Err(err);
+                    }
+                }
+
+                self.prev_start = Some(row_start);
+                self.scan_start += rowlen;
+
+                let data = &self.current[row_start + 1..row_start + rowlen];
+                if let Some(ref mut statistics) = self.statistics {
+                    let width = match passdata {
+                        InterlaceInfo::Adam7 { width, .. } => width,
+                        InterlaceInfo::Null => self.subframe.width,
+                    };
+                    statistics.add_row(data, width, self.decoder.info().unwrap());
+                }
+
+                return Ok(Some(InterlacedRow {
+                    data: &self.current[row_start + 1..row_start + rowlen],
+                    interlace: passdata,
+                    info: self.row_info(passdata),
+                }));
+            } else {
+                if self.subframe.consumed_and_flushed {
+                    let end = self.short_image_data();
+                    return Err(DecodingError::Format(
+                        FormatErrorInner::NoMoreImageData { end }.into(),
+                    ));
+                }
+
+                // Discard consumed lines, except the previous one, before appending more data.
+                let keep = self.prev_start.unwrap_or(self.scan_start);
+                if keep > 0 {
+                    self.current.remove_front(keep);
+                    self.scan_start -= keep;
+                    self.prev_start = self.prev_start.map(|start| start - keep);
+                }
+
+                let val = match self.decoder.decode_next(&mut self.current) {
+                    Err(err)
+                        if self.allow_truncated && !self.data_truncated && err.is_truncation() =>
+                    {
+                        // Use the data that was decompressed before the input ended.
+                        self.decoder.decoder.salvage_image_data(&mut self.current)?;
+                        self.data_truncated = true;
+                        continue;
+                    }
+                    val => val?,
+                };
+                match val {
+                    Some(Decoded::ImageData) => {}
+                    Some(Decoded::ImageDataFlushed) => {
+                        self.subframe.consumed_and_flushed = true;
+                    }
+                    None => {
+                        if self.scan_start < self.current.len() {
+                            return Err(DecodingError::Format(
+                                FormatErrorInner::UnexpectedEndOfChunk.into(),
+                            ));
+                        } else {
+                            return Ok(None);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// Unfilter the row of `rowlen` bytes starting at `row_start` in place, returning its filter.
+///
+/// The row is predicted from the already unfiltered row at `prev_start`, or from a zero line if
+/// it is the first row of its pass.
+#[cfg(feature = "std")]
+fn unfilter_row(
+    current: &mut [u8],
+    row_start: usize,
+    prev_start: Option<usize>,
+    rowlen: usize,
+    bpp: BytesPerPixel,
+) -> Result<FilterType, DecodingError> {
+    let (before, row) = current.split_at_mut(row_start);
+    let row = &mut row[..rowlen];
+    let filter = match FilterType::from_u8(row[0]) {
+        None => {
+            return Err(DecodingError::Format(
+                FormatErrorInner::UnknownFilterType(row[0]).into(),
+            ))
+        }
+        Some(filter) => filter,
+    };
+
+    let unfiltered = match prev_start {
+        Some(start) => {
+            let prev = &before[start..start + rowlen];
+            unfilter(filter, bpp, &prev[1..], &mut row[1..])
+        }
+        None => unfilter_first_row(filter, bpp, &mut row[1..]),
+    };
+
+    unfiltered
+        .map(|()| filter)
+        .map_err(|message| DecodingError::Format(FormatErrorInner::BadFilter(message).into()))
+}
+
+/// Whether the `result` is an error of the underlying reader that does not have data available.
+#[cfg(feature = "std")]
+fn is_would_block<T>(result: &Result<T, DecodingError>) -> bool {
+    match result {
+        Err(DecodingError::IoError(err)) => err.kind() == io::ErrorKind::WouldBlock,
+        _ => false,
+    }
+}
+
+#[cfg(feature = "std")]
+impl SubframeInfo {
+    fn not_yet_init() -> Self {
+        SubframeInfo {
+            width: 0,
+            height: 0,
+            rowlen: 0,
+            interlace: InterlaceIter::None(0..0),
+            consumed_and_flushed: false,
+        }
+    }
+
+    fn new(info: &Info) -> Self {
+        // The apng fctnl overrides width and height.
+        // All other data is set by the main info struct.
+        let (width, height) = if let Some(fc) = info.frame_control {
+            (fc.width, fc.height)
+        } else {
+            (info.width, info.height)
+        };
+
+        let interlace = if info.interlaced {
+            InterlaceIter::Adam7(adam7::Adam7Iterator::new(width, height))
+        } else {
+            InterlaceIter::None(0..height)
+        };
+
+        SubframeInfo {
+            width,
+            height,
+            rowlen: info.raw_row_length_from_width(width),
+            interlace,
+            consumed_and_flushed: false,
+        }
+    }
+
+    /// Advance to the next row, returning its raw length, its interlace information and whether
+    /// it is the first row of a new pass.
+    fn next_row(&mut self, info: &Info) -> Option<(usize, InterlaceInfo, bool)> {
+        match self.interlace {
+            InterlaceIter::Adam7(ref mut adam7) => {
+                let last_pass = adam7.current_pass();
+                let (pass, line, width) = adam7.next()?;
+                let rowlen = info.raw_row_length_from_width(width);
+                let interlace = InterlaceInfo::Adam7 { pass, line, width };
+                Some((rowlen, interlace, last_pass != pass))
+            }
+            InterlaceIter::None(ref mut height) => {
+                let line = height.next()?;
+                Some((self.rowlen, InterlaceInfo::Null, line == 0))
+            }
+        }
+    }
+
+    /// The index, pass and width of the last row returned by `next_row`.
+    fn last_row(&self, interlace: InterlaceInfo) -> (u32, Option<u8>, u32) {
+        match (interlace, &self.interlace) {
+            (InterlaceInfo::Adam7 { pass, line, width }, _) => {
+                (adam7::image_row(pass, line), Some(pass), width)
+            }
+            (InterlaceInfo::Null, InterlaceIter::None(height)) => {
+                (height.start - 1, None, self.width)
+            }
+            (InterlaceInfo::Null, InterlaceIter::Adam7(_)) => {
+                unreachable!("expected interlace information")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn expand_paletted(buffer: &mut [u8], info: &Info) -> Result<(), DecodingError> {
+    if let Some(palette) = info.palette.as_ref() {
+        if let BitDepth::Sixteen = info.bit_depth {
+            // This should have been caught earlier but let's check again. Can't hurt.
+            Err(DecodingError::Format(
+                FormatErrorInner::InvalidColorBitDepth {
+                    color: ColorType::Indexed,
+                    depth: BitDepth::Sixteen,
+                }
+                .into(),
+            ))
+        } else {
+            let black = [0, 0, 0];
+            if let Some(ref trns) = info.trns {
+                utils::unpack_bits(buffer, 4, info.bit_depth as u8, |i, chunk| {
+                    let (rgb, a) = (
+                        palette
+                            .get(3 * i as usize..3 * i as usize + 3)
+                            .unwrap_or(&black),
+                        *trns.get(i as usize).unwrap_or(&0xFF),
+                    );
+                    chunk[0] = rgb[0];
+                    chunk[1] = rgb[1];
+                    chunk[2] = rgb[2];
+                    chunk[3] = a;
+                });
+            } else {
+                utils::unpack_bits(buffer, 3, info.bit_depth as u8, |i, chunk| {
+                    let rgb = palette
+                        .get(3 * i as usize..3 * i as usize + 3)
+                        .unwrap_or(&black);
+                    chunk[0] = rgb[0];
+                    chunk[1] = rgb[1];
+                    chunk[2] = rgb[2];
+                })
+            }
+            Ok(())
+        }
+    } else {
+        Err(DecodingError::Format(
+            FormatErrorInner::PaletteRequired.into(),
+        ))
+    }
+}
+
+#[cfg(feature = "std")]
+fn expand_gray_u8(buffer: &mut [u8], info: &Info) {
+    let rescale = true;
+    let scaling_factor = if rescale {
+        (255) / ((1u16 << info.bit_depth as u8) - 1) as u8
+    } else {
+        1
+    };
+    if let Some(ref trns) = info.trns {
+        utils::unpack_bits(buffer, 2, info.bit_depth as u8, |pixel, chunk| {
+            if pixel == trns[0] {
+                chunk[1] = 0
+            } else {
+                chunk[1] = 0xFF
+            }
+            chunk[0] = pixel * scaling_factor
+        })
+    } else {
+        utils::unpack_bits(buffer, 1, info.bit_depth as u8, |val, chunk| {
+            chunk[0] = val * scaling_factor
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "std")]
+mod tests {
+    use super::{
+        BitDepth, ColorType, CrcPolicy, Decoder, DecodingError, Info, Limits, Progress,
+        ProgressiveCallbacks, ProgressiveDecoder, RowBatch, ShortImageData, Transformations,
+    };
+    use crate::chunk::ChunkType;
+    use std::borrow::Cow;
+    use std::fs::File;
+    use std::io::{BufRead, Read, Result};
+    use std::mem::discriminant;
+
+    /// A reader that reads at most `n` bytes.
+    struct SmalBuf<R: BufRead> {
+        inner: R,
+        cap: usize,
+    }
+
+    impl<R: BufRead> SmalBuf<R> {
+        fn new(inner: R, cap: usize) -> Self {
+            SmalBuf { inner, cap }
+        }
+    }
+
+    impl<R: BufRead> Read for SmalBuf<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = buf.len().min(self.cap);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    impl<R: BufRead> BufRead for SmalBuf<R> {
+        fn fill_buf(&mut self) -> Result<&[u8]> {
+            let buf = self.inner.fill_buf()?;
+            let len = buf.len().min(self.cap);
+            Ok(&buf[..len])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            assert!(amt <= self.cap);
+            self.inner.consume(amt)
+        }
+    }
+
+    /// A reader that alternates between not having data available and reading at most 3 bytes.
+    struct WouldBlock<R: Read> {
+        inner: R,
+        blocked: bool,
+    }
+
+    impl<R: Read> Read for WouldBlock<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            let len = buf.len().min(3);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn resume_after_would_block() {
+        fn retry<T>(mut f: impl FnMut() -> std::result::Result<T, DecodingError>) -> T {
+            loop {
+                match f() {
+                    Err(DecodingError::IoError(ref err))
+                        if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                    result => return result.unwrap(),
+                }
+            }
+        }
+
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in [Transformations::IDENTITY, Transformations::EXPAND].iter() {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let mut expected = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut expected).unwrap();
+
+                let mut decoder = Decoder::new(WouldBlock {
+                    inner: &data[..],
+                    blocked: false,
+                });
+                decoder.set_transformations(transform);
+                let mut reader = decoder.into_reader();
+                retry(|| reader.read_info().map(|_| ()));
+                let mut buf = vec![0; reader.output_buffer_size()];
+                retry(|| reader.next_frame(&mut buf));
+                assert!(buf == expected, "{:?}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn buffered_matches_reader() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut expected = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut expected).unwrap();
+
+            let mut reader = Decoder::new(SmalBuf::new(&data[..], 7))
+                .read_info_buffered()
+                .unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn rgba8_output() {
+        use crate::common::{BitDepth, ColorType};
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::normalize_to_color8());
+            let mut reader = decoder.read_info().unwrap();
+            let (color_type, _) = reader.output_color_type();
+            let mut color8 = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut color8).unwrap();
+            let expected: Vec<u8> = color8
+                .chunks(color_type.samples())
+                .flat_map(|pixel| match *pixel {
+                    [v] => vec![v, v, v, 0xFF],
+                    [v, a] => vec![v, v, v, a],
+                    [r, g, b] => vec![r, g, b, 0xFF],
+                    _ => pixel.to_vec(),
+                })
+                .collect();
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::RGBA8);
+            let mut reader = decoder.read_info().unwrap();
+            assert_eq!(
+                reader.output_color_type(),
+                (ColorType::Rgba, BitDepth::Eight)
+            );
+            let (width, height) = reader.info().size();
+            assert_eq!(reader.output_buffer_size(), 4 * (width * height) as usize);
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn rgba16_output() {
+        use crate::common::{BitDepth, ColorType};
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let (color_type, bit_depth) = reader.output_color_type();
+            let mut expanded = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut expanded).unwrap();
+            let samples: Vec<u16> = if bit_depth == BitDepth::Sixteen {
+                expanded
+                    .chunks(2)
+                    .map(|s| u16::from(s[0]) << 8 | u16::from(s[1]))
+                    .collect()
+            } else {
+                expanded.iter().map(|&s| u16::from(s) * 257).collect()
+            };
+            let expected: Vec<u8> = samples
+                .chunks(color_type.samples())
+                .flat_map(|pixel| match *pixel {
+                    [v] => vec![v, v, v, 0xFFFF],
+                    [v, a] => vec![v, v, v, a],
+                    [r, g, b] => vec![r, g, b, 0xFFFF],
+                    _ => pixel.to_vec(),
+                })
+                .flat_map(|sample| sample.to_be_bytes().to_vec())
+                .collect();
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::RGBA16);
+            let mut reader = decoder.read_info().unwrap();
+            assert_eq!(
+                reader.output_color_type(),
+                (ColorType::Rgba, BitDepth::Sixteen)
+            );
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn strip_alpha() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in [Transformations::EXPAND, Transformations::RGBA8].iter() {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let (color_type, bit_depth) = reader.output_color_type();
+                let mut with_alpha = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut with_alpha).unwrap();
+                let expected: Vec<u8> = if color_type.samples() % 2 == 0 {
+                    let sample = bit_depth as usize / 8;
+                    let pixel = color_type.samples() * sample;
+                    with_alpha
+                        .chunks(pixel)
+                        .flat_map(|p| p[..pixel - sample].to_vec())
+                        .collect()
+                } else {
+                    with_alpha
+                };
+
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(transform | Transformations::STRIP_ALPHA);
+                let mut reader = decoder.read_info().unwrap();
+                assert_eq!(reader.output_color_type().0.samples() % 2, 1);
+                let mut buf = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut buf).unwrap();
+                assert!(buf == expected, "{:?}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn image_stream() {
+        let first = std::fs::read("tests/pngsuite/basn2c08.png").unwrap();
+        let second = std::fs::read("tests/pngsuite/basi2c08.png").unwrap();
+        let mut stream = first.clone();
+        stream.extend_from_slice(&second);
+        stream.extend_from_slice(&first);
+
+        let mut decoder = Decoder::new(&stream[..]);
+        decoder.set_image_stream(true);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let mut frames = vec![];
+        loop {
+            match reader.next_frame(&mut buf) {
+                Ok(_) => frames.push(buf.clone()),
+                Err(DecodingError::Parameter(_)) => break,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+        assert_eq!(frames.len(), 3);
+        // Both images show the same content, once with interlacing.
+        assert!(frames.iter().all(|frame| *frame == frames[0]));
+
+        // Without the option only the first image is decoded.
+        let mut reader = Decoder::new(&stream[..]).read_info().unwrap();
+        reader.next_frame(&mut buf).unwrap();
+        assert!(reader.next_frame(&mut buf).is_err());
+    }
+
+    #[test]
+    fn image_stream_header_mismatch() {
+        let mut stream = std::fs::read("tests/pngsuite/basn2c08.png").unwrap();
+        stream.extend(std::fs::read("tests/pngsuite/basn0g08.png").unwrap());
+
+        let mut decoder = Decoder::new(&stream[..]);
+        decoder.set_image_stream(true);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        match reader.next_frame(&mut buf) {
+            Err(DecodingError::Format(_)) => {}
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn channel_order() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let (color_type, bit_depth) = reader.output_color_type();
+            let mut rgba = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut rgba).unwrap();
+
+            let sample = bit_depth as usize / 8;
+            let samples = color_type.samples();
+            let expected: Vec<u8> = rgba
+                .chunks(samples * sample)
+                .flat_map(|pixel| {
+                    let mut order: Vec<_> = (0..samples).collect();
+                    if samples >= 3 {
+                        order.swap(0, 2);
+                    }
+                    if samples % 2 == 0 {
+                        order.rotate_right(1);
+                    }
+                    order
+                        .into_iter()
+                        .flat_map(|k| pixel[k * sample..(k + 1) * sample].to_vec())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(
+                Transformations::EXPAND | Transformations::BGR | Transformations::SWAP_ALPHA,
+            );
+            let mut reader = decoder.read_info().unwrap();
+            assert_eq!(reader.output_color_type(), (color_type, bit_depth));
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn set_channel_order() {
+        use crate::common::ChannelOrder;
+        let orders = [
+            (ChannelOrder::Rgba, [0, 1, 2, 3]),
+            (ChannelOrder::Bgra, [2, 1, 0, 3]),
+            (ChannelOrder::Argb, [3, 0, 1, 2]),
+            (ChannelOrder::Abgr, [3, 2, 1, 0]),
+        ];
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::RGBA8);
+            let mut reader = decoder.read_info().unwrap();
+            let mut rgba = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut rgba).unwrap();
+
+            for &(order, channels) in orders.iter() {
+                let expected: Vec<u8> = rgba
+                    .chunks(4)
+                    .flat_map(|pixel| channels.iter().map(move |&c| pixel[c]))
+                    .collect();
+                // The order replaces the flags, even those set afterwards.
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_channel_order(order);
+                decoder.set_transformations(Transformations::RGBA8 | Transformations::BGR);
+                let mut reader = decoder.read_info().unwrap();
+                let mut buf = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut buf).unwrap();
+                assert!(buf == expected, "{:?} {:?}", path, order);
+                let flags = Transformations::SWAP_ALPHA.with_channel_order(order);
+                assert_eq!(flags.channel_order(), order);
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_mask() {
+        use crate::common::{BitDepth, ColorType};
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::RGBA8);
+            let mut reader = decoder.read_info().unwrap();
+            let mut rgba = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut rgba).unwrap();
+            let expected: Vec<u8> = rgba.chunks(4).map(|pixel| pixel[3]).collect();
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::ALPHA_MASK | Transformations::BGR);
+            let mut reader = decoder.read_info().unwrap();
+            assert_eq!(
+                reader.output_color_type(),
+                (ColorType::Grayscale, BitDepth::Eight)
+            );
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn small_buffers() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut expected = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut expected).unwrap();
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_chunk_buffer_size(0);
+            decoder.set_read_buffer_size(13);
+            assert_eq!(decoder.chunk_buffer_size(), 4);
+            assert_eq!(decoder.read_buffer_size(), 13);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn packswap() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let bits = reader.info().bits_per_pixel();
+            let mut packed = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut packed).unwrap();
+            let expected: Vec<u8> = if bits < 8 {
+                let pixels = 8 / bits;
+                packed
+                    .iter()
+                    .map(|&byte| {
+                        (0..pixels).fold(0, |swapped, i| {
+                            let pixel = (byte >> (8 - bits * (i + 1))) & ((1 << bits) - 1);
+                            swapped | pixel << (bits * i)
+                        })
+                    })
+                    .collect()
+            } else {
+                packed
+            };
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::PACKSWAP);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn packing() {
+        use crate::common::{BitDepth, ColorType};
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let (color_type, bit_depth) = reader.output_color_type();
+            let bits = reader.info().bits_per_pixel();
+            let (width, _) = reader.info().size();
+            let mut packed = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut packed).unwrap();
+            let expected: Vec<u8> = if bits < 8 {
+                let stride = (width as usize * bits + 7) / 8;
+                packed
+                    .chunks(stride)
+                    .flat_map(|row| {
+                        (0..width as usize).map(move |x| {
+                            let shift = 8 - bits * (x % (8 / bits) + 1);
+                            (row[x * bits / 8] >> shift) & ((1 << bits) - 1)
+                        })
+                    })
+                    .collect()
+            } else {
+                packed
+            };
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::PACKING);
+            let mut reader = decoder.read_info().unwrap();
+            let depth = if bits < 8 { BitDepth::Eight } else { bit_depth };
+            assert_eq!(reader.output_color_type(), (color_type, depth));
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+
+            // The indices look up the colors of the fully expanded image.
+            if color_type == ColorType::Indexed {
+                let palette = reader.info().rgba_palette().unwrap();
+                let alpha = reader.info().trns.is_some();
+                let colors: Vec<u8> = buf
+                    .iter()
+                    .flat_map(|&index| {
+                        let entry = palette[index as usize];
+                        entry[..if alpha { 4 } else { 3 }].to_vec()
+                    })
+                    .collect();
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(Transformations::EXPAND);
+                let mut reader = decoder.read_info().unwrap();
+                let mut rgb = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut rgb).unwrap();
+                assert!(rgb == colors, "{:?}", path);
+            }
+
+            if color_type == ColorType::Grayscale && bits < 8 {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder
+                    .set_transformations(Transformations::PACKING | Transformations::INVERT_MONO);
+                let mut reader = decoder.read_info().unwrap();
+                let mut inverted = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut inverted).unwrap();
+                let max = (1 << bits) - 1;
+                assert!(
+                    inverted.iter().zip(&buf).all(|(&i, &v)| i == max - v),
+                    "{:?}",
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn invert_mono_and_swap_endian() {
+        use crate::common::{BitDepth, ColorType};
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let (color_type, bit_depth) = reader.output_color_type();
+            let mut raw = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut raw).unwrap();
+
+            let sample = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+            let pixel = color_type.samples() * sample;
+            let gray =
+                color_type == ColorType::Grayscale || color_type == ColorType::GrayscaleAlpha;
+            let mut expected = raw.clone();
+            if gray {
+                for (i, byte) in expected.iter_mut().enumerate() {
+                    if i % pixel < sample {
+                        *byte = !*byte;
+                    }
+                }
+            }
+            if sample == 2 {
+                for pair in expected.chunks_mut(2) {
+                    let (high, low) = (pair[0], pair[1]);
+                    pair[0] = low;
+                    pair[1] = high;
+                }
+            }
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder
+                .set_transformations(Transformations::INVERT_MONO | Transformations::SWAP_ENDIAN);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn expand_trns_16() {
+        use crate::common::{BitDepth, ColorType};
+        let mut tested = 0;
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let info = reader.info();
+            if info.bit_depth != BitDepth::Sixteen || info.trns.is_none() {
+                continue;
+            }
+            let trns = info.trns.as_ref().unwrap().to_vec();
+            let color_type = info.color_type;
+            let mut raw = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut raw).unwrap();
+
+            let mut expected = vec![];
+            for pixel in raw.chunks(2 * color_type.samples()) {
+                expected.extend_from_slice(pixel);
+                let alpha = if pixel == &trns[..] { 0 } else { 0xff };
+                expected.extend_from_slice(&[alpha, alpha]);
+            }
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let expanded = match color_type {
+                ColorType::Grayscale => ColorType::GrayscaleAlpha,
+                _ => ColorType::Rgba,
+            };
+            assert_eq!(
+                reader.output_color_type(),
+                (expanded, BitDepth::Sixteen),
+                "{:?}",
+                path
+            );
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == expected, "{:?}", path);
+
+            // The alpha channel is computed from the full samples before they are stripped.
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            let stripped: Vec<u8> = expected.iter().step_by(2).cloned().collect();
+            assert!(buf == stripped, "{:?}", path);
+            tested += 1;
+        }
+        assert!(tested > 0);
+    }
+
+    #[test]
+    fn scale_16() {
+        use crate::common::BitDepth;
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let (color_type, bit_depth) = reader.output_color_type();
+            let gamma = reader.info().source_gamma.map(|gamma| gamma.into_value());
+            let mut raw = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut raw).unwrap();
+
+            let channels = color_type.samples();
+            let (scaled, corrected) = if bit_depth == BitDepth::Sixteen {
+                let exponent = gamma.map_or(1.0, |gamma| 0.45455 / f64::from(gamma));
+                raw.chunks(2)
+                    .enumerate()
+                    .map(|(i, pair)| {
+                        let sample = u16::from_be_bytes([pair[0], pair[1]]);
+                        let value = f64::from(sample) / 65535.0;
+                        let scaled = (value * 255.0).round() as u8;
+                        let alpha = channels % 2 == 0 && i % channels == channels - 1;
+                        if alpha {
+                            (scaled, scaled)
+                        } else {
+                            (scaled, (value.powf(exponent) * 255.0).round() as u8)
+                        }
+                    })
+                    .unzip()
+            } else {
+                (raw.clone(), raw.clone())
+            };
+
+            for &(transform, expected) in [
+                (Transformations::SCALE_16, &scaled),
+                (Transformations::SCALE_16_GAMMA, &corrected),
+                (
+                    Transformations::SCALE_16 | Transformations::STRIP_16,
+                    &scaled,
+                ),
+            ]
+            .iter()
+            {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(Transformations::EXPAND | transform);
+                let mut reader = decoder.read_info().unwrap();
+                assert_eq!(reader.output_color_type(), (color_type, BitDepth::Eight));
+                let mut buf = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut buf).unwrap();
+                assert!(&buf == expected, "{:?} {:?}", path, transform);
+            }
+        }
+    }
+
+    #[test]
+    fn color_statistics() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::RGBA8);
+            let mut reader = decoder.read_info().unwrap();
+            assert!(reader.color_statistics().is_none());
+            let mut rgba = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut rgba).unwrap();
+
+            let mut histogram = vec![0; 4096];
+            let (mut alpha, mut weighted) = (0, [0u64; 3]);
+            for pixel in rgba.chunks(4) {
+                let a = u64::from(pixel[3]);
+                alpha += a;
+                for c in 0..3 {
+                    weighted[c] += u64::from(pixel[c]) * a;
+                }
+                if a > 0 {
+                    let index = (pixel[0] as usize >> 4) << 8
+                        | (pixel[1] as usize >> 4) << 4
+                        | pixel[2] as usize >> 4;
+                    histogram[index] += 1;
+                }
+            }
+            let pixels = rgba.len() as u64 / 4;
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_color_statistics(true);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            let statistics = reader.color_statistics().unwrap();
+            assert_eq!(statistics.pixels(), pixels, "{:?}", path);
+            assert!(statistics.histogram() == histogram, "{:?}", path);
+            let average = statistics.average().unwrap();
+            assert_eq!(u64::from(average[3]), alpha / pixels, "{:?}", path);
+            for c in 0..3 {
+                let expected = weighted[c].checked_div(alpha).unwrap_or(0);
+                assert_eq!(u64::from(average[c]), expected, "{:?}", path);
+            }
+
+            let dominant = statistics.dominant_colors(3);
+            let most = histogram.iter().cloned().max().unwrap();
+            assert!(dominant.len() <= 3);
+            assert_eq!(dominant.first().map_or(0, |&(_, count)| count), most);
+            assert!(dominant.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        }
+    }
+
+    #[test]
+    fn row_info() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in [Transformations::IDENTITY, Transformations::RGBA8].iter() {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let (color_type, bit_depth) = reader.output_color_type();
+                let (width, height) = reader.info().size();
+                let interlaced = reader.info().interlaced;
+                let mut rows = vec![];
+                while let Some(row) = reader.next_row().unwrap() {
+                    rows.push((row.info(), row.data().len()));
+                }
+
+                if !interlaced {
+                    assert_eq!(rows.len(), height as usize);
+                }
+                let mut last = (0, None);
+                for (index, &(info, len)) in rows.iter().enumerate() {
+                    assert_eq!((info.color_type, info.bit_depth), (color_type, bit_depth));
+                    assert_eq!(len, reader.output_line_size(info.width), "{:?}", path);
+                    assert!(info.row_index < height);
+                    if interlaced {
+                        assert!(info.width <= width);
+                        // Rows are ordered within each pass.
+                        assert!(info.pass > last.1 || info.row_index > last.0 || index == 0);
+                        last = (info.row_index, info.pass);
+                    } else {
+                        assert_eq!(info.pass, None);
+                        assert_eq!(info.width, width);
+                        assert_eq!(info.row_index, index as u32);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_data_dup_on_finish() {
+        const IMG: &[u8] = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/bugfixes/x_issue#214.png"
+        ));
+
+        let mut normal = Decoder::new(IMG).read_info().unwrap();
+
+        let mut buffer = vec![0; normal.output_buffer_size()];
+        let normal = normal.next_frame(&mut buffer).unwrap_err();
+
+        let smal = Decoder::new(SmalBuf::new(IMG, 1))
+            .read_info()
+            .unwrap()
+            .next_frame(&mut buffer)
+            .unwrap_err();
+
+        assert_eq!(discriminant(&normal), discriminant(&smal));
+    }
+
+    #[test]
+    fn limits_exceeded() {
+        let decode = |limits: Limits| {
+            let file = File::open("tests/pngsuite/basn0g01.png").unwrap();
+            Decoder::new_with_limits(file, limits)
+                .read_info()
+                .map(|_| ())
+        };
+
+        assert!(decode(Limits::default()).is_ok());
+
+        assert!(matches_limits(decode(Limits {
+            height: 31,
+            ..Limits::default()
+        })));
+
+        // The image contains a `gAMA` chunk of 4 bytes.
+        assert!(matches_limits(decode(Limits {
+            ancillary_chunk_bytes: 3,
+            ..Limits::default()
+        })));
+
+        // The 32×32 1-bit image requires 128 bytes.
+        assert!(matches_limits(decode(Limits {
+            pixel_bytes: 127,
+            ..Limits::default()
+        })));
+
+        // Longer textual chunks are skipped.
+        let noise: Vec<u8> = (0..64u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let mut image = vec![];
+        {
+            let mut encoder = crate::Encoder::new(&mut image, 8, 8);
+            encoder.set_animated(2, 0).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_chunk(crate::chunk::tEXt, b"Comment\0long text")
+                .unwrap();
+            writer
+                .write_chunk(crate::chunk::iTXt, b"Comment\0\0\0\0\0long text")
+                .unwrap();
+            writer.write_image_data(&[0; 64]).unwrap();
+            writer.write_image_data(&noise).unwrap();
+        }
+        let limits = Limits {
+            text_bytes: 16,
+            ..Limits::default()
+        };
+        let mut decoder = Decoder::new_with_limits(&image[..], limits);
+        decoder.set_retain_chunks(true);
+        let reader = decoder.read_info().unwrap();
+        assert!(reader.info().utf8_text.is_empty());
+        assert!(reader.info().retained_chunks.is_empty());
+
+        // The image data of animation frames is not limited like the other ancillary chunks.
+        let limits = Limits {
+            ancillary_chunk_bytes: 30,
+            ..Limits::default()
+        };
+        let mut reader = Decoder::new_with_limits(&image[..], limits)
+            .read_info()
+            .unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf, noise);
+    }
+
+    fn matches_limits(result: std::result::Result<(), DecodingError>) -> bool {
+        match result {
+            Err(DecodingError::LimitsExceeded) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn crc_policy() {
+        const IMG: &[u8] = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/pngsuite/basn0g01.png"
+        ));
+        // Offsets of the checksums of the `gAMA` and `IDAT` chunks.
+        const GAMA_CRC: usize = 45;
+        const IDAT_CRC: usize = 148;
+
+        let decode = |image: &[u8], policy: CrcPolicy| {
+            let mut decoder = Decoder::new(image);
+            decoder.set_crc_policy(policy);
+            let mut reader = decoder.read_info()?;
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buffer).map(|_| ())
+        };
+
+        let mut bad_ancillary = IMG.to_vec();
+        bad_ancillary[GAMA_CRC] ^= 1;
+        assert!(decode(&bad_ancillary, CrcPolicy::Verify).is_err());
+        assert!(decode(&bad_ancillary, CrcPolicy::SkipAncillary).is_ok());
+        assert!(decode(&bad_ancillary, CrcPolicy::Ignore).is_ok());
+
+        let mut bad_critical = IMG.to_vec();
+        bad_critical[IDAT_CRC] ^= 1;
+        assert!(decode(&bad_critical, CrcPolicy::Verify).is_err());
+        assert!(decode(&bad_critical, CrcPolicy::SkipAncillary).is_err());
+        assert!(decode(&bad_critical, CrcPolicy::Ignore).is_ok());
+    }
+
+    #[test]
+    fn format_error_context() {
+        use crate::chunk;
+        use crate::FormatErrorKind;
+        const IMG: &[u8] = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/pngsuite/basn0g01.png"
+        ));
+        let decode = |image: &[u8]| {
+            let mut reader = Decoder::new(image).read_info()?;
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buffer).map(|_| ())
+        };
+        let format_error = |image: &[u8]| match decode(image) {
+            Err(DecodingError::Format(err)) => err,
+            other => panic!("expected a format error, got {:?}", other),
+        };
+
+        let mut bad_signature = IMG.to_vec();
+        bad_signature[1] = b'J';
+        let err = format_error(&bad_signature);
+        assert_eq!(err.kind(), FormatErrorKind::InvalidSignature);
+        assert_eq!(err.chunk(), None);
+        assert_eq!(err.offset(), Some(7));
+
+        // The checksum of the `IDAT` chunk is stored at 148.
+        let mut bad_crc = IMG.to_vec();
+        bad_crc[148] ^= 1;
+        let err = format_error(&bad_crc);
+        match err.kind() {
+            FormatErrorKind::CrcMismatch {
+                chunk: chunk::IDAT,
+                expected,
+                found,
+            } => assert_eq!(expected ^ found, 1 << 24),
+            other => panic!("expected a CRC mismatch, got {:?}", other),
+        }
+        assert_eq!(err.chunk(), Some(chunk::IDAT));
+        assert_eq!(err.offset(), Some(151));
+        assert!(err.to_string().ends_with(" (in IDAT chunk at byte 151)"));
+
+        // A length of `IDAT` beyond 2^31 - 1, stored at 49.
+        let mut bad_length = IMG.to_vec();
+        bad_length[49] = 0x80;
+        let err = format_error(&bad_length);
+        assert_eq!(err.kind(), FormatErrorKind::InvalidChunkLength);
+        assert_eq!(err.chunk(), Some(chunk::IDAT));
+        assert_eq!(err.offset(), Some(56));
+
+        // The output buffer is one byte too small.
+        let mut reader = Decoder::new(IMG).read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size() - 1];
+        match reader.next_frame(&mut buffer) {
+            Err(DecodingError::Parameter(err)) => assert_eq!(
+                err.to_string(),
+                format!(
+                    "wrong data size, expected {} got {}",
+                    buffer.len() + 1,
+                    buffer.len()
+                )
+            ),
+            other => panic!("expected a parameter error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn zlib_header() {
+        use crate::FormatErrorKind;
+        const IMG: &[u8] = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/pngsuite/basn0g01.png"
+        ));
+        // The only `IDAT` chunk starts at 49 and contains 91 bytes of zlib stream.
+        let with_image_data = |data: &[u8]| {
+            let mut image = IMG[..49].to_vec();
+            image.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            image.extend_from_slice(b"IDAT");
+            image.extend_from_slice(data);
+            image.extend_from_slice(&crc32fast::hash(&image[53..]).to_be_bytes());
+            image.extend_from_slice(&IMG[152..]);
+            image
+        };
+        let decode = |image: &[u8], fallback| {
+            let mut decoder = Decoder::new(image);
+            decoder.set_zlib_fallback(fallback);
+            let mut reader = decoder.read_info()?;
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buffer).map(|_| buffer)
+        };
+        let expected = decode(IMG, false).unwrap();
+        let stream = &IMG[57..148];
+        assert_eq!(decode(&with_image_data(stream), false).unwrap(), expected);
+
+        let headers: [&[u8]; 4] = [
+            // Not deflate, a window of 2^16 bytes, check bits off by one and a preset dictionary.
+            &[0x79, 0xda],
+            &[0x88, 0x1c],
+            &[0x78, 0xdb],
+            &[0x78, 0xbb],
+        ];
+        for header in headers.iter() {
+            let image = with_image_data(&[header, &stream[2..]].concat());
+            match decode(&image, false) {
+                Err(DecodingError::Format(err)) => {
+                    assert_eq!(err.kind(), FormatErrorKind::InvalidZlibHeader)
+                }
+                other => panic!("expected an invalid header, got {:?}", other),
+            }
+        }
+
+        // A raw deflate stream, if it does not happen to start like a valid header.
+        let raw = with_image_data(&stream[2..stream.len() - 4]);
+        assert!(decode(&raw, false).is_err());
+        assert_eq!(decode(&raw, true).unwrap(), expected);
+        assert_eq!(decode(IMG, true).unwrap(), expected);
+    }
+
+    #[test]
+    fn ignore_adler32() {
+        const IMG: &[u8] = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/pngsuite/basn0g01.png"
+        ));
+        // The `IDAT` chunk starts at 49 and contains 91 bytes, ending with the Adler-32 checksum.
+        let mut image = IMG.to_vec();
+        image[147] ^= 1;
+        let crc = crc32fast::hash(&image[53..148]);
+        image[148..152].copy_from_slice(&crc.to_be_bytes());
+
+        let decode = |image: &[u8], ignore| {
+            let mut decoder = Decoder::new(image);
+            decoder.set_ignore_adler32(ignore);
+            let mut reader = decoder.read_info()?;
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buffer).map(|_| buffer)
+        };
+
+        assert!(decode(&image, false).is_err());
+        assert_eq!(decode(&image, true).unwrap(), decode(IMG, false).unwrap());
+        assert_eq!(decode(IMG, true).unwrap(), decode(IMG, false).unwrap());
+    }
+
+    #[test]
+    fn truncated_image_data() {
+        const IMG: &[u8] = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/pngsuite/basn2c08.png"
+        ));
+        // Cut the 72 bytes of the only `IDAT` chunk, starting at 49, in half.
+        let truncated = &IMG[..49 + 8 + 36];
+
+        let mut full = Decoder::new(IMG).read_info().unwrap();
+        let mut expected = vec![0; full.output_buffer_size()];
+        full.next_frame(&mut expected).unwrap();
+
+        let mut reader = Decoder::new(truncated).read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        assert!(reader.next_frame(&mut buffer).unwrap_err().is_truncation());
+
+        let mut decoder = Decoder::new(truncated);
+        decoder.set_allow_truncated(true);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let rows_decoded = match reader.next_frame(&mut buffer) {
+            Err(DecodingError::Truncated { rows_decoded, .. }) => rows_decoded as usize,
+            other => panic!("expected truncation, got {:?}", other),
+        };
+        assert!(rows_decoded > 0 && rows_decoded < 32);
+        let row_len = reader.output_line_size(32);
+        assert_eq!(
+            buffer[..rows_decoded * row_len],
+            expected[..rows_decoded * row_len]
+        );
+    }
+
+    #[test]
+    fn short_image_data() {
+        // Encode 3 rows of 4 pixels, then claim another size in the header. All bytes are zero
+        // so that they remain valid filter types when the rows are split differently.
+        let mut image = vec![];
+        {
+            let mut encoder = crate::Encoder::new(&mut image, 4, 3);
+            encoder.set_depth(BitDepth::Eight);
+            encoder.set_filter(crate::FilterType::NoFilter);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0; 12]).unwrap();
+        }
+        let resize = |width: u32, height: u32| {
+            let mut image = image.clone();
+            image[16..20].copy_from_slice(&width.to_be_bytes());
+            image[20..24].copy_from_slice(&height.to_be_bytes());
+            let crc = crc32fast::hash(&image[12..29]);
+            image[29..33].copy_from_slice(&crc.to_be_bytes());
+            image
+        };
+        let decode = |image: &[u8], allow_truncated| {
+            let mut decoder = Decoder::new(image);
+            decoder.set_allow_truncated(allow_truncated);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buffer).unwrap_err()
+        };
+
+        // The 15 bytes of the image data fill 3 rows of 5 bytes.
+        let boundary = resize(4, 4);
+        let err = decode(&boundary, false);
+        assert_eq!(err.short_image_data(), Some(ShortImageData::AtRowBoundary));
+        match decode(&boundary, true) {
+            DecodingError::Truncated {
+                rows_decoded: 3,
+                end: ShortImageData::AtRowBoundary,
+            } => {}
+            other => panic!("expected truncation after 3 rows, got {:?}", other),
+        }
+
+        // Or 2 rows of 6 bytes and half of the third.
+        let within = resize(5, 3);
+        let err = decode(&within, false);
+        assert_eq!(err.short_image_data(), Some(ShortImageData::WithinRow));
+        match decode(&within, true) {
+            DecodingError::Truncated {
+                rows_decoded: 2,
+                end: ShortImageData::WithinRow,
+            } => {}
+            other => panic!("expected truncation within the third row, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode() {
+        for path in crate::test_utils::pngsuite_files() {
+            let mut decoder = Decoder::new(File::open(&path).unwrap());
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let mut expected = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut expected).unwrap();
+            expected.truncate(info.buffer_size());
+
+            let (output_info, pixels) = super::decode(File::open(&path).unwrap()).unwrap();
+            assert_eq!(output_info, info, "{:?}", path);
+            assert!(pixels == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn metadata_matches_reader() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let reader = Decoder::new(&data[..]).read_info().unwrap();
+            let info = Decoder::new(&data[..]).read_metadata().unwrap();
+            assert_eq!(info.size(), reader.info().size());
+            assert_eq!(info.palette, reader.info().palette, "{:?}", path);
+            assert_eq!(info.trns, reader.info().trns, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn progressive_matches_reader() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut expected = vec![];
+            while let Some(row) = reader.next_row().unwrap() {
+                expected.push(row.data().to_vec());
+            }
+
+            let mut decoder = ProgressiveDecoder::new();
+            let mut rows = vec![];
+            let (mut headers, mut frames, mut ends) = (0, 0, 0);
+            for piece in data.chunks(7) {
+                let consumed = decoder
+                    .push(piece, |event| match event {
+                        Progress::Header(info) => {
+                            assert_eq!(info.size(), reader.info().size());
+                            headers += 1;
+                        }
+                        Progress::Row(row) => rows.push(row.data().to_vec()),
+                        Progress::FrameComplete => frames += 1,
+                        Progress::ImageEnd => ends += 1,
+                    })
+                    .unwrap();
+                assert_eq!(consumed, piece.len());
+            }
+
+            assert_eq!(rows, expected, "{:?}", path);
+            assert_eq!((headers, frames, ends), (1, 1, 1), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn row_batches_between_threads() {
+        use std::sync::mpsc;
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut expected = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut expected).unwrap();
+            let (width, _) = reader.info().size();
+
+            let (send_rows, rows) = mpsc::channel();
+            let (recycle, recycled) = mpsc::channel();
+            for _ in 0..2 {
+                recycle.send(RowBatch::new()).unwrap();
+            }
+            let decoding = std::thread::spawn(move || {
+                let mut decoder = ProgressiveDecoder::new();
+                for piece in data.chunks(64) {
+                    let mut batch: RowBatch = recycled.recv().unwrap();
+                    decoder
+                        .push(piece, |event| {
+                            if let Progress::Row(row) = event {
+                                batch.push(&row);
+                            }
+                        })
+                        .unwrap();
+                    send_rows.send(batch).unwrap();
+                }
+            });
+
+            let mut frame = vec![0; expected.len()];
+            let mut count = 0;
+            for mut batch in rows {
+                count += batch.len();
+                assert_eq!(batch.rows().count(), batch.len());
+                batch.write_into(&mut frame, width);
+                batch.clear();
+                assert!(batch.is_empty());
+                let _ = recycle.send(batch);
+            }
+            decoding.join().unwrap();
+            assert!(count > 0);
+            assert!(frame == expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn progressive_callbacks() {
+        #[derive(Default)]
+        struct Recorder {
+            infos: u32,
+            rows: Vec<(u32, u8)>,
+            ends: u32,
+        }
+
+        impl ProgressiveCallbacks for Recorder {
+            fn info(&mut self, _: &Info<'static>) {
+                self.infos += 1;
+            }
+
+            fn row(&mut self, _: &[u8], row_num: u32, pass: u8) {
+                self.rows.push((row_num, pass));
+            }
+
+            fn end(&mut self, _: &Info<'static>) {
+                self.ends += 1;
+            }
+        }
+
+        let record = |path: &str| {
+            let data = std::fs::read(path).unwrap();
+            let mut decoder = ProgressiveDecoder::new();
+            let mut recorder = Recorder::default();
+            for piece in data.chunks(11) {
+                decoder.process_data(piece, &mut recorder).unwrap();
+            }
+            assert_eq!((recorder.infos, recorder.ends), (1, 1));
+            recorder.rows
+        };
+
+        let rows = record("tests/pngsuite/basn0g01.png");
+        assert_eq!(rows, (0..32).map(|row| (row, 0)).collect::<Vec<_>>());
+
+        // The first rows of the passes of a 32x32 image, and the last row of the last pass.
+        let rows = record("tests/pngsuite/basi0g01.png");
+        assert_eq!(rows.len(), 60);
+        for &(row, pass) in [(0, 0), (0, 1), (4, 2), (0, 3), (2, 4), (0, 5), (1, 6)].iter() {
+            assert!(rows.contains(&(row, pass)), "({}, {})", row, pass);
+        }
+        assert_eq!(rows.last(), Some(&(31, 6)));
+    }
+
+    #[test]
+    fn owned_snapshot() {
+        let palette = [1, 2, 3, 4, 5, 6];
+        let snapshot = {
+            let mut info = Info::with_size(2, 1);
+            info.color_type = ColorType::Indexed;
+            info.palette = Some(Cow::Borrowed(&palette[..]));
+            info.to_owned_snapshot()
+        };
+        match snapshot.palette {
+            Some(Cow::Owned(ref owned)) => assert_eq!(owned[..], palette[..]),
+            _ => panic!("palette not owned"),
+        }
+
+        let file = File::open("tests/pngsuite/basn3p08.png").unwrap();
+        let snapshot = Decoder::new(file)
+            .read_info()
+            .unwrap()
+            .info()
+            .to_owned_snapshot();
+        assert_eq!(snapshot.size(), (32, 32));
+        assert_eq!(snapshot.palette.unwrap().len(), 256 * 3);
+    }
+
+    #[test]
+    fn error_conversions() {
+        use std::error::Error;
+        use std::io::ErrorKind;
+
+        struct Interrupted;
+        impl Read for Interrupted {
+            fn read(&mut self, _: &mut [u8]) -> Result<usize> {
+                Err(ErrorKind::Interrupted.into())
+            }
+        }
+
+        fn read_info<R: Read>(r: R) -> std::result::Result<(), Box<dyn Error>> {
+            Decoder::new(r).read_info()?;
+            Ok(())
+        }
+
+        let err = read_info(Interrupted).unwrap_err();
+        let err = err.downcast::<DecodingError>().unwrap();
+        let source = err.source().unwrap().downcast_ref::<std::io::Error>();
+        assert_eq!(source.unwrap().kind(), ErrorKind::Interrupted);
+        assert_eq!(std::io::Error::from(*err).kind(), ErrorKind::Interrupted);
+
+        let err = Decoder::new(&b"\x89PNG\r\n\x1a\x0b"[..])
+            .read_info()
+            .err()
+            .unwrap();
+        assert!(err.source().unwrap().is::<super::FormatError>());
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.into_inner().unwrap().is::<DecodingError>());
+
+        let err = Decoder::new(&b"\x89PNG"[..]).read_info().err().unwrap();
+        assert_eq!(std::io::Error::from(err).kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn can_output() {
+        use crate::ColorType;
+
+        let open = |name: &str, transform| {
+            let file = File::open(format!("tests/pngsuite/{}.png", name)).unwrap();
+            let mut decoder = Decoder::new(file);
+            decoder.set_transformations(transform);
+            decoder.read_info().unwrap()
+        };
+
+        let reader = open("basn0g16", Transformations::IDENTITY);
+        assert!(reader.can_output(ColorType::Grayscale, BitDepth::Sixteen));
+        assert!(reader.can_output(ColorType::Grayscale, BitDepth::Eight));
+        assert!(reader.can_output(ColorType::Rgba, BitDepth::Eight));
+        assert!(!reader.can_output(ColorType::Indexed, BitDepth::Eight));
+        assert!(reader.can_output(ColorType::Rgb, BitDepth::Sixteen));
+        // With an alpha channel added by `ADD_ALPHA`.
+        assert!(reader.can_output(ColorType::GrayscaleAlpha, BitDepth::Sixteen));
+        assert!(!reader.can_output(ColorType::Grayscale, BitDepth::Four));
+
+        let reader = open("basn6a08", Transformations::IDENTITY);
+        assert!(reader.can_output(ColorType::Rgb, BitDepth::Eight));
+        assert!(!reader.can_output(ColorType::GrayscaleAlpha, BitDepth::Eight));
+
+        // Every output that was negotiated decodes into a buffer of the reported size.
+        for &name in ["basn0g01", "basn3p04", "tbrn2c08", "basn4a16"].iter() {
+            let reader = open(name, Transformations::IDENTITY);
+            for &transform in [
+                Transformations::IDENTITY,
+                Transformations::EXPAND,
+                Transformations::PACKING,
+                Transformations::STRIP_16 | Transformations::STRIP_ALPHA,
+                Transformations::RGBA8,
+                Transformations::RGBA16 | Transformations::STRIP_ALPHA,
+                Transformations::ALPHA_MASK,
+            ]
+            .iter()
+            {
+                let mut output = open(name, transform);
+                let (color_type, bit_depth) = output.output_color_type();
+                assert!(reader.can_output(color_type, bit_depth));
+                let mut buf = vec![0; output.output_buffer_size()];
+                output.next_frame(&mut buf).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn transposed_output() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let mut rows = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut rows).unwrap();
+
+            // Interrupt the decoding to check that bands of rows are resumed.
+            let mut decoder = Decoder::new(WouldBlock {
+                inner: &data[..],
+                blocked: false,
+            });
+            decoder.set_transformations(Transformations::EXPAND);
+            decoder.set_transposed_output(true);
+            let mut reader = decoder.into_reader();
+            while reader.read_info().is_err() {}
+            let mut columns = vec![0; reader.output_buffer_size()];
+            let transposed_info = loop {
+                match reader.next_frame(&mut columns) {
+                    Err(DecodingError::IoError(_)) => {}
+                    result => break result.unwrap(),
+                }
+            };
+            assert_eq!(info, transposed_info);
+            let (width, height) = (info.width as usize, info.height as usize);
+            let bytes_pp = info.line_size / width;
+            for (y, row) in rows[..info.buffer_size()]
+                .chunks(info.line_size)
+                .enumerate()
+            {
+                for (x, pixel) in row.chunks(bytes_pp).enumerate() {
+                    let start = (x * height + y) * bytes_pp;
+                    assert_eq!(pixel, &columns[start..start + bytes_pp], "{:?}", path);
+                }
+            }
+        }
+
+        let mut decoder = Decoder::new(File::open("tests/pngsuite/basn0g01.png").unwrap());
+        decoder.set_transposed_output(true);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        match reader.next_frame(&mut buf) {
+            Err(DecodingError::Parameter(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn banded_output() {
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            if reader.info().interlaced {
+                continue;
+            }
+            let mut frame = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut frame).unwrap();
+
+            // Interrupt the decoding to check that bands are resumed.
+            let mut decoder = Decoder::new(WouldBlock {
+                inner: &data[..],
+                blocked: false,
+            });
+            decoder.set_band_height(Some(7));
+            let mut reader = decoder.into_reader();
+            while reader.read_info().is_err() {}
+            let mut band = vec![0; reader.output_buffer_size()];
+            let mut bands = Vec::new();
+            loop {
+                let start = reader.band_start();
+                let band_info = match reader.next_frame(&mut band) {
+                    Err(DecodingError::IoError(_)) => continue,
+                    result => result.unwrap(),
+                };
+                assert_eq!(band_info.height, (info.height - start).min(7), "{:?}", path);
+                bands.extend_from_slice(&band[..band_info.buffer_size()]);
+                if reader.band_start() == 0 {
+                    break;
+                }
+            }
+            assert_eq!(bands, &frame[..info.buffer_size()], "{:?}", path);
+        }
+
+        // Only a band has to fit into the memory limit.
+        let limits = Limits {
+            pixel_bytes: 32 * 10,
+            ..Limits::default()
+        };
+        let file = File::open("tests/pngsuite/basn0g08.png").unwrap();
+        match Decoder::new_with_limits(file, limits).read_info() {
+            Err(DecodingError::LimitsExceeded) => {}
+            _ => panic!("expected the frame to exceed the limit"),
+        }
+        let file = File::open("tests/pngsuite/basn0g08.png").unwrap();
+        let mut decoder = Decoder::new_with_limits(file, limits);
+        decoder.set_band_height(Some(10));
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.output_buffer_size(), 32 * 10);
+
+        let mut decoder = Decoder::new(File::open("tests/pngsuite/basi0g08.png").unwrap());
+        decoder.set_band_height(Some(10));
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        match reader.next_frame(&mut buf) {
+            Err(DecodingError::Parameter(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    /// Split an image into its chunks.
+    fn chunks(image: &[u8]) -> Vec<(ChunkType, Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut rest = &image[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let kind = ChunkType([rest[4], rest[5], rest[6], rest[7]]);
+            chunks.push((kind, rest[8..8 + len].to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    /// Build an image from chunks, with valid checksums.
+    fn assemble(chunks: &[(ChunkType, Vec<u8>)]) -> Vec<u8> {
+        let mut image = vec![137, 80, 78, 71, 13, 10, 26, 10];
+        for (kind, data) in chunks {
+            image.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            let start = image.len();
+            image.extend_from_slice(&kind.0);
+            image.extend_from_slice(data);
+            let crc = crc32fast::hash(&image[start..]);
+            image.extend_from_slice(&crc.to_be_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn strict_mode() {
+        use super::StreamingDecoder;
+        use crate::chunk;
+        use crate::FormatErrorKind;
+
+        // Feed the whole input, including anything after IEND.
+        fn validate(mut image: &[u8]) -> std::result::Result<(), DecodingError> {
+            let mut decoder = StreamingDecoder::new();
+            decoder.set_strict(true);
+            let mut data = Vec::new();
+            while !image.is_empty() {
+                let (consumed, _) = decoder.update(image, &mut data)?;
+                image = &image[consumed..];
+            }
+            Ok(())
+        }
+        fn error_kind(image: &[u8]) -> FormatErrorKind {
+            match validate(image) {
+                Err(DecodingError::Format(err)) => err.kind(),
+                other => panic!("expected a format error, got {:?}", other),
+            }
+        }
+
+        for path in crate::test_utils::pngsuite_files() {
+            let image = std::fs::read(&path).unwrap();
+            if let Err(err) = validate(&image) {
+                panic!("{} is rejected: {}", path.display(), err);
+            }
+        }
+
+        // Chunks of a grayscale image: IHDR, gAMA, IDAT, IEND.
+        let gray = chunks(&std::fs::read("tests/pngsuite/basn0g01.png").unwrap());
+        let with = |index: usize, chunk: (ChunkType, Vec<u8>)| {
+            let mut chunks = gray.clone();
+            chunks.insert(index, chunk);
+            assemble(&chunks)
+        };
+        let order = FormatErrorKind::InvalidChunkOrder;
+        assert_eq!(error_kind(&with(0, gray[1].clone())), order);
+        assert_eq!(error_kind(&with(1, gray[0].clone())), order);
+        assert_eq!(error_kind(&with(2, gray[1].clone())), order);
+        assert_eq!(error_kind(&with(4, gray[3].clone())), order);
+        assert_eq!(error_kind(&with(3, (chunk::pHYs, vec![0; 9]))), order);
+        assert_eq!(error_kind(&with(4, (chunk::tIME, vec![0; 7]))), order);
+        // A grayscale palette is allowed, but not after the image data.
+        assert!(validate(&with(2, (chunk::PLTE, vec![0; 6]))).is_ok());
+        assert_eq!(error_kind(&with(3, (chunk::PLTE, vec![0; 6]))), order);
+        // Ordering constraints relative to the palette.
+        let after_plte = {
+            let mut chunks = gray.clone();
+            chunks.insert(1, (chunk::PLTE, vec![0; 6]));
+            assemble(&chunks)
+        };
+        assert_eq!(error_kind(&after_plte), order);
+        assert_eq!(error_kind(&with(2, (chunk::hIST, vec![0; 4]))), order);
+
+        // Image data split into consecutive chunks, and interrupted by another chunk.
+        let mut split = gray.clone();
+        let idat = split[2].1.clone();
+        split[2].1 = idat[..10].to_vec();
+        split.insert(3, (chunk::IDAT, idat[10..].to_vec()));
+        assert!(validate(&assemble(&split)).is_ok());
+        let mut interrupted = gray.clone();
+        interrupted.insert(3, (chunk::tEXt, b"Comment\0split".to_vec()));
+        interrupted.insert(4, (chunk::IDAT, Vec::new()));
+        assert!(Decoder::new(&assemble(&interrupted)[..])
+            .read_info()
+            .is_ok());
+        assert_eq!(error_kind(&assemble(&interrupted)), order);
+
+        // Transparency of a gray image is a single sample.
+        assert!(validate(&with(2, (chunk::tRNS, vec![0; 2]))).is_ok());
+        assert_eq!(
+            error_kind(&with(2, (chunk::tRNS, vec![0; 3]))),
+            FormatErrorKind::InvalidPalette
+        );
+        // The palette of this image has two entries.
+        let indexed = chunks(&std::fs::read("tests/pngsuite/basn3p01.png").unwrap());
+        let plte = indexed.iter().position(|c| c.0 == chunk::PLTE).unwrap();
+        let with_trns = |len: usize| {
+            let mut chunks = indexed.clone();
+            chunks.insert(plte + 1, (chunk::tRNS, vec![0; len]));
+            assemble(&chunks)
+        };
+        assert!(validate(&with_trns(2)).is_ok());
+        assert_eq!(error_kind(&with_trns(3)), FormatErrorKind::InvalidPalette);
+        let mut before_plte = indexed.clone();
+        before_plte.insert(plte, (chunk::tRNS, vec![0; 1]));
+        assert_eq!(error_kind(&assemble(&before_plte)), order);
+
+        // An image without pixels.
+        let mut empty = gray.clone();
+        empty[0].1[..4].copy_from_slice(&[0; 4]);
+        let empty = assemble(&empty);
+        assert!(Decoder::new(&empty[..]).read_info().is_ok());
+        let mut decoder = Decoder::new(&empty[..]);
+        decoder.set_strict(true);
+        match decoder.read_info() {
+            Err(DecodingError::Format(err)) => {
+                assert_eq!(err.kind(), FormatErrorKind::InvalidValue)
+            }
+            _ => panic!("expected the empty image to be rejected"),
+        }
+    }
+
+    #[test]
+    fn polled_after_error() {
+        let mut image = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+        // The checksum of the `IDAT` chunk.
+        image[148] ^= 1;
+        for &image_stream in [false, true].iter() {
+            let mut decoder = Decoder::new(&image[..]);
+            decoder.set_image_stream(image_stream);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            match reader.next_frame(&mut buf) {
+                Err(DecodingError::Format(_)) => {}
+                other => panic!("expected a format error, got {:?}", other),
+            }
+            // Continuing must neither panic nor loop.
+            for _ in 0..3 {
+                assert!(reader.next_frame(&mut buf).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn structure() {
+        use crate::{ChunkFields, Structure};
+
+        let mut image = std::fs::read("tests/animated/basic_f20.png").unwrap();
+        image.extend_from_slice(b"trailing");
+        let structure = Structure::read(&image[..]).unwrap();
+        assert_eq!(structure.error, None);
+        assert_eq!(structure.trailing_bytes, 8);
+        let first = &structure.chunks[0];
+        assert_eq!(
+            (&first.name[..], first.offset, first.length),
+            ("IHDR", 8, 13)
+        );
+        match first.fields {
+            Some(ChunkFields::Header { bit_depth, .. }) => assert_eq!(bit_depth, 8),
+            ref other => panic!("unexpected fields {:?}", other),
+        }
+        // Each chunk follows the previous one.
+        for pair in structure.chunks.windows(2) {
+            assert_eq!(
+                pair[1].offset,
+                pair[0].offset + 12 + u64::from(pair[0].length)
+            );
+        }
+        let frames = structure
+            .chunks
+            .iter()
+            .filter(|chunk| match chunk.fields {
+                Some(ChunkFields::FrameControl { .. }) => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(frames, 20);
+        assert_eq!(structure.chunks.last().unwrap().name, "IEND");
+
+        // The chunks before an error are kept.
+        let mut image = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+        image[148] ^= 1;
+        let structure = Structure::read(&image[..]).unwrap();
+        assert!(structure.error.unwrap().starts_with("CRC error"));
+        assert_eq!(structure.chunks.last().unwrap().name, "IDAT");
+        assert_eq!(structure.chunks.last().unwrap().crc, None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn structure_serde() {
+        let file = File::open("tests/pngsuite/basn3p08.png").unwrap();
+        let structure = crate::Structure::read(file).unwrap();
+        let json = serde_json::to_string(&structure).unwrap();
+        assert!(json.contains(r#""Palette":{"entries":256}"#));
+        assert_eq!(
+            serde_json::from_str::<crate::Structure>(&json).unwrap(),
+            structure
+        );
+    }
+
+    #[test]
+    fn collect_violations() {
+        use super::{StreamingDecoder, Structure};
+        use crate::chunk;
+        use crate::FormatErrorKind;
+
+        // Chunks of a grayscale image: IHDR, gAMA, IDAT, IEND.
+        let gray = chunks(&std::fs::read("tests/pngsuite/basn0g01.png").unwrap());
+        let mut chunks = gray.clone();
+        chunks[0].1[..4].copy_from_slice(&[0; 4]);
+        chunks.insert(2, gray[1].clone());
+        chunks.insert(3, (chunk::tRNS, vec![0; 3]));
+        let image = assemble(&chunks);
+
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_strict(true);
+        decoder.set_collect_violations(true);
+        let structure = Structure::read_with_decoder(&image[..], decoder).unwrap();
+        assert_eq!(structure.error, None);
+        assert_eq!(structure.chunks.len(), 6);
+        assert_eq!(structure.violations.len(), 3);
+
+        // Without strict mode nothing is collected.
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_collect_violations(true);
+        let structure = Structure::read_with_decoder(&image[..], decoder).unwrap();
+        assert!(structure.violations.is_empty());
+
+        // The repeated gAMA chunk starts at byte 49, its type ends at 56.
+        let mut chunks = gray.clone();
+        chunks.insert(2, gray[1].clone());
+        let image = assemble(&chunks);
+        let mut decoder = Decoder::new(&image[..]);
+        decoder.set_strict(true);
+        decoder.set_collect_violations(true);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        let violations = reader.violations();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].kind(), FormatErrorKind::InvalidChunkOrder);
+        assert_eq!(violations[0].chunk(), Some(chunk::gAMA));
+        assert_eq!(violations[0].offset(), Some(56));
+
+        // A chunk that lenient decoding rejects is still an error.
+        let mut chunks = gray.clone();
+        chunks.insert(3, gray[1].clone());
+        let image = assemble(&chunks);
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_strict(true);
+        decoder.set_collect_violations(true);
+        let structure = Structure::read_with_decoder(&image[..], decoder).unwrap();
+        assert!(structure.error.is_some());
+        assert!(structure.violations.is_empty());
+    }
+
+    #[test]
+    fn output_info_of_frames() {
+        let mut png = vec![];
+        {
+            let mut encoder = crate::Encoder::new(&mut png, 4, 4);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_animated(2, 0).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0; 48]).unwrap();
+            writer.set_frame_dimension(2, 3).unwrap();
+            writer.write_image_data(&[0; 18]).unwrap();
+        }
+
+        let mut reader = Decoder::new(&png[..]).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let first = reader.output_info();
+        assert_eq!((first.width, first.height, first.line_size), (4, 4, 12));
+        assert_eq!(reader.next_frame(&mut buf).unwrap(), first);
+        assert_eq!(reader.output_info(), first);
+
+        let second = reader.next_frame(&mut buf).unwrap();
+        assert_eq!((second.width, second.height, second.line_size), (2, 3, 6));
+        assert_eq!(reader.output_info(), second);
+        assert!(second.buffer_size() <= reader.output_buffer_size());
+    }
+
+    #[test]
+    fn read_region_crops_frame() {
+        let transforms = [
+            Transformations::IDENTITY,
+            Transformations::EXPAND,
+            Transformations::PACKSWAP,
+            Transformations::RGBA8,
+        ];
+        let mut tested = 0;
+        for path in crate::test_utils::pngsuite_files_with(&["b", "t"]) {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in &transforms {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let mut frame = vec![0; reader.output_buffer_size()];
+                let info = reader.next_frame(&mut frame).unwrap();
+                let bits_pp = info.color_type.samples() * info.bit_depth as usize;
+                // Swapped pixels are cropped in their original order.
+                let swapped = transform == Transformations::PACKSWAP && bits_pp < 8;
+                if swapped {
+                    crate::utils::swap_packed_line(&mut frame, bits_pp as u8);
+                }
+
+                let (x, y) = (info.width / 3, info.height / 4);
+                let (width, height) = (info.width / 2 + 1, info.height / 2);
+                let mut expected = vec![];
+                for line in frame
+                    .chunks(info.line_size)
+                    .skip(y as usize)
+                    .take(height as usize)
+                {
+                    let mut out = vec![0; reader.output_line_size(width)];
+                    crate::utils::crop_line(line, &mut out, x as usize, width as usize, bits_pp);
+                    expected.extend_from_slice(&out);
+                }
+                if swapped {
+                    crate::utils::swap_packed_line(&mut expected, bits_pp as u8);
+                }
+
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let mut region = vec![0; expected.len()];
+                let out = reader
+                    .read_region(x, y, width, height, &mut region)
+                    .unwrap();
+                assert_eq!((out.width, out.height), (width, height));
+                assert_eq!(out.buffer_size(), expected.len());
+                assert!(region == expected, "{:?} {:?}", path, transform);
+                tested += 1;
+            }
+        }
+        assert!(tested > 40);
+
+        let data = std::fs::read("tests/pngsuite/basn0g08.png").unwrap();
+        let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+        let mut buf = vec![0; 64];
+        assert!(reader.read_region(30, 0, 3, 1, &mut buf).is_err());
+        assert!(reader.read_region(0, 0, 0, 1, &mut buf).is_err());
+        assert!(reader.read_region(0, 0, 8, 9, &mut buf).is_err());
+        reader.read_region(0, 31, 32, 1, &mut buf).unwrap();
+        assert!(reader.next_frame(&mut buf).is_err());
+
+        // Regions of interlaced images are cropped from the whole frame, also with bands.
+        let data = std::fs::read("tests/pngsuite/basi0g08.png").unwrap();
+        let mut frame = vec![0; 32 * 32];
+        Decoder::new(&data[..])
+            .read_info()
+            .unwrap()
+            .next_frame(&mut frame)
+            .unwrap();
+        let mut decoder = Decoder::new(&data[..]);
+        decoder.set_band_height(Some(4));
+        let mut reader = decoder.read_info().unwrap();
+        let mut region = vec![0; 32 * 8];
+        reader.read_region(0, 20, 32, 8, &mut region).unwrap();
+        assert!(region[..] == frame[20 * 32..28 * 32]);
+    }
+
+    #[test]
+    fn skip_rows_keeps_prediction() {
+        let mut tested = 0;
+        for path in crate::test_utils::pngsuite_files_with(&["b", "f"]) {
+            let data = std::fs::read(&path).unwrap();
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let mut rows = vec![];
+            while let Some(row) = reader.next_row().unwrap() {
+                rows.push(row.data().to_vec());
+            }
+
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::EXPAND);
+            let mut reader = decoder.read_info().unwrap();
+            let mut index = 0;
+            loop {
+                index += reader.skip_rows(2).unwrap() as usize;
+                match reader.next_row().unwrap() {
+                    Some(row) => assert!(row.data() == &rows[index][..], "{:?}", path),
+                    None => break,
+                }
+                index += 1;
+            }
+            assert_eq!(index, rows.len());
+            assert_eq!(reader.skip_rows(1).unwrap(), 0);
+            tested += 1;
+        }
+        assert!(tested > 40);
+    }
+
+    #[test]
+    fn downscale_averages_blocks() {
+        use super::Downscale;
+
+        /// Reduce a decoded frame by averaging or subsampling, like the decoder.
+        fn reduce(frame: &[u8], info: &super::OutputInfo, factor: usize) -> Vec<u8> {
+            let (width, height) = (info.width as usize, info.height as usize);
+            let channels = info.color_type.samples();
+            let bits = info.bit_depth as usize;
+            let average = info.color_type != ColorType::Indexed && bits >= 8;
+            let sample = |x: usize, y: usize, c: usize| -> u32 {
+                let line = &frame[y * info.line_size..];
+                match bits {
+                    16 => {
+                        u32::from(line[2 * (x * channels + c)]) << 8
+                            | u32::from(line[2 * (x * channels + c) + 1])
+                    }
+                    8 => u32::from(line[x * channels + c]),
+                    _ => {
+                        u32::from(line[x * bits / 8] >> (8 - x * bits % 8 - bits))
+                            & ((1 << bits) - 1)
+                    }
+                }
+            };
+            let (out_width, out_height) = (
+                (width + factor - 1) / factor,
+                (height + factor - 1) / factor,
+            );
+            let line_size = (out_width * channels * bits + 7) / 8;
+            let mut out = vec![0; line_size * out_height];
+            for by in 0..out_height {
+                for bx in 0..out_width {
+                    for c in 0..channels {
+                        let value = if average {
+                            let (ys, xs) = (
+                                by * factor..height.min(by * factor + factor),
+                                bx * factor..width.min(bx * factor + factor),
+                            );
+                            let count = (ys.len() * xs.len()) as u32;
+                            let sum: u32 = ys
+                                .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                                .map(|(x, y)| sample(x, y, c))
+                                .sum();
+                            (sum + count / 2) / count
+                        } else {
+                            sample(bx * factor, by * factor, c)
+                        };
+                        let line = &mut out[by * line_size..];
+                        match bits {
+                            16 => {
+                                line[2 * (bx * channels + c)] = (value >> 8) as u8;
+                                line[2 * (bx * channels + c) + 1] = value as u8;
+                            }
+                            8 => line[bx * channels + c] = value as u8,
+                            _ => line[bx * bits / 8] |= (value as u8) << (8 - bx * bits % 8 - bits),
+                        }
+                    }
+                }
+            }
+            out
+        }
+
+        let mut tested = 0;
+        for path in crate::test_utils::pngsuite_files_with(&["bas"]) {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in &[Transformations::IDENTITY, Transformations::EXPAND] {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let mut frame = vec![0; reader.output_buffer_size()];
+                let info = reader.next_frame(&mut frame).unwrap();
+
+                for &downscale in &[Downscale::Half, Downscale::Quarter, Downscale::Eighth] {
+                    let expected = reduce(&frame, &info, downscale.factor() as usize);
+                    let mut decoder = Decoder::new(&data[..]);
+                    decoder.set_transformations(transform);
+                    decoder.set_downscale(downscale);
+                    let mut reader = decoder.read_info().unwrap();
+                    assert_eq!(reader.output_buffer_size(), expected.len());
+                    let mut reduced = vec![0; reader.output_buffer_size()];
+                    let out = reader.next_frame(&mut reduced).unwrap();
+                    assert_eq!(
+                        (out.width, out.height),
+                        downscale.size(info.width, info.height)
+                    );
+                    assert!(
+                        reduced == expected,
+                        "{:?} {:?} {:?}",
+                        path,
+                        transform,
+                        downscale
+                    );
+                    tested += 1;
+                }
+            }
+        }
+        assert!(tested > 80);
+
+        let data = std::fs::read("tests/pngsuite/basn0g08.png").unwrap();
+        let mut decoder = Decoder::new(&data[..]);
+        decoder.set_downscale(Downscale::Half);
+        decoder.set_band_height(Some(4));
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        assert!(reader.next_frame(&mut buf).is_err());
+    }
+
+    #[test]
+    fn next_pass_handling() {
+        use super::InterlaceHandling;
+
+        let transforms = [
+            Transformations::IDENTITY,
+            Transformations::EXPAND,
+            Transformations::PACKSWAP,
+        ];
+        let mut tested = 0;
+        for path in crate::test_utils::pngsuite_files_with(&["bas"]) {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in &transforms {
+                let reader = |data| {
+                    let mut decoder = Decoder::new(data);
+                    decoder.set_transformations(transform);
+                    decoder.read_info().unwrap()
+                };
+                let mut frame_reader = reader(&data[..]);
+                let mut frame = vec![0; frame_reader.output_buffer_size()];
+                frame_reader.next_frame(&mut frame).unwrap();
+                let mut pass_rows = vec![];
+                let mut rows_reader = reader(&data[..]);
+                while let Some(row) = rows_reader.next_interlaced_row().unwrap() {
+                    pass_rows.extend_from_slice(row.data());
+                }
+
+                for &handling in &[InterlaceHandling::Sparkle, InterlaceHandling::Rectangle] {
+                    let mut reader = reader(&data[..]);
+                    let mut buf = vec![0; reader.output_buffer_size()];
+                    let mut last = 0;
+                    while let Some(pass) = reader.next_pass(handling, &mut buf).unwrap() {
+                        assert!(pass > last);
+                        last = pass;
+                    }
+                    assert_eq!(last, 7);
+                    assert!(buf == frame, "{:?} {:?} {:?}", path, transform, handling);
+                    assert!(reader.next_pass(handling, &mut buf).is_err());
+                }
+
+                let mut reader = reader(&data[..]);
+                let mut buf = vec![0; reader.output_buffer_size()];
+                let mut raw_rows = vec![];
+                let handling = InterlaceHandling::RawRows;
+                while reader.next_pass(handling, &mut buf).unwrap().is_some() {
+                    let (info, size) = (
+                        reader.info(),
+                        (reader.subframe.width, reader.subframe.height),
+                    );
+                    let pass = reader.pass_progress.unwrap().0;
+                    let len = if info.interlaced {
+                        reader.pass_rows(pass) as usize * reader.pass_line_size(pass)
+                    } else {
+                        reader.output_line_size(size.0) * size.1 as usize
+                    };
+                    raw_rows.extend_from_slice(&buf[..len]);
                 }
-                Some((rowlen, InterlaceInfo::Adam7 { pass, line, width }))
+                assert!(raw_rows == pass_rows, "{:?} {:?}", path, transform);
+                tested += 1;
             }
-            InterlaceIter::None(ref mut height) => {
-                let _ = height.next()?;
-                Some((self.subframe.rowlen, InterlaceInfo::Null))
+        }
+        assert!(tested > 50);
+
+        // The first pass of an image fills the blocks of 8 by 8 pixels.
+        let data = std::fs::read("tests/pngsuite/basi0g08.png").unwrap();
+        let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        assert_eq!(
+            reader
+                .next_pass(InterlaceHandling::Rectangle, &mut buf)
+                .unwrap(),
+            Some(1)
+        );
+        for y in 0..32 {
+            for x in 0..32 {
+                assert_eq!(buf[y * 32 + x], buf[(y / 8 * 8) * 32 + x / 8 * 8]);
             }
         }
+
+        // A band height does not apply to the passes.
+        let mut frame = vec![0; reader.output_buffer_size()];
+        Decoder::new(&data[..])
+            .read_info()
+            .unwrap()
+            .next_frame(&mut frame)
+            .unwrap();
+        let mut decoder = Decoder::new(&data[..]);
+        decoder.set_band_height(Some(4));
+        let mut reader = decoder.read_info().unwrap();
+        let mut band = vec![0; reader.output_buffer_size()];
+        assert!(reader
+            .next_pass(InterlaceHandling::Sparkle, &mut band)
+            .is_err());
+        let mut buf = vec![0; 32 * 32];
+        while reader
+            .next_pass(InterlaceHandling::Sparkle, &mut buf)
+            .unwrap()
+            .is_some()
+        {}
+        assert!(buf == frame);
     }
 
-    /// Returns the next raw scanline of the image interlace pass.
-    /// The scanline is filtered against the previous scanline according to the specification.
-    fn next_raw_interlaced_row(&mut self) -> Result<Option<InterlacedRow<'_>>, DecodingError> {
-        let bpp = self.bpp;
-        let (rowlen, passdata) = match self.next_pass() {
-            Some((rowlen, passdata)) => (rowlen, passdata),
-            None => return Ok(None),
-        };
-        loop {
-            if self.current.len() - self.scan_start >= rowlen {
-                let row = &mut self.current[self.scan_start..];
-                let filter = match FilterType::from_u8(row[0]) {
-                    None => {
-                        self.scan_start += rowlen;
-                        return Err(DecodingError::Format(
-                            FormatErrorInner::UnknownFilterMethod(row[0]).into(),
-                        ));
-                    }
-                    Some(filter) => filter,
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel_passes_match() {
+        let transforms = [
+            Transformations::IDENTITY,
+            Transformations::EXPAND,
+            Transformations::PACKSWAP,
+            Transformations::RGBA8 | Transformations::STRIP_16,
+        ];
+        for path in crate::test_utils::pngsuite_files_with(&["basi"]) {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in &transforms {
+                let decode = |parallel| {
+                    let mut decoder = Decoder::new(&data[..]);
+                    decoder.set_transformations(transform);
+                    decoder.set_color_statistics(true);
+                    decoder.set_parallel(parallel);
+                    let mut reader = decoder.read_info().unwrap();
+                    let mut buf = vec![0; reader.output_buffer_size()];
+                    reader.next_frame(&mut buf).unwrap();
+                    assert_eq!(reader.rows_remaining(), 0);
+                    let statistics = format!("{:?}", reader.color_statistics().unwrap());
+                    (buf, statistics)
                 };
+                let (frame, statistics) = decode(true);
+                let (expected, expected_statistics) = decode(false);
+                assert!(frame == expected, "{:?} {:?}", path, transform);
+                assert_eq!(statistics, expected_statistics);
+            }
+        }
 
-                if let Err(message) =
-                    unfilter(filter, bpp, &self.prev[1..rowlen], &mut row[1..rowlen])
-                {
-                    return Err(DecodingError::Format(
-                        FormatErrorInner::BadFilter(message).into(),
-                    ));
-                }
+        // Broken images fail the same way.
+        for path in glob::glob("tests/bugfixes/x_*.png")
+            .unwrap()
+            .map(|r| r.unwrap())
+        {
+            let data = std::fs::read(&path).unwrap();
+            let decode = |parallel| -> std::result::Result<(), DecodingError> {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_parallel(parallel);
+                let mut reader = decoder.read_info()?;
+                let mut buf = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut buf)?;
+                reader.finish().map(drop)
+            };
+            let error = decode(true).unwrap_err();
+            let expected = decode(false).unwrap_err();
+            assert_eq!(discriminant(&error), discriminant(&expected), "{:?}", path);
+        }
+    }
 
-                self.prev[..rowlen].copy_from_slice(&row[..rowlen]);
-                self.scan_start += rowlen;
+    #[test]
+    fn header_info_before_chunks() {
+        let data = std::fs::read("tests/pngsuite/basi3p02.png").unwrap();
+        // Only the signature and the IHDR chunk.
+        let header = &data[..8 + 25];
+        let mut reader = Decoder::new(header).read_header_info().unwrap();
+        {
+            let info = reader.info();
+            assert_eq!((info.width, info.height), (32, 32));
+            assert_eq!(
+                (info.color_type, info.bit_depth),
+                (ColorType::Indexed, BitDepth::Two)
+            );
+            assert!(info.interlaced);
+        }
+        assert!(reader.read_info().is_err());
 
-                return Ok(Some(InterlacedRow {
-                    data: &self.prev[1..rowlen],
-                    interlace: passdata,
-                }));
-            } else {
-                if self.subframe.consumed_and_flushed {
-                    return Err(DecodingError::Format(
-                        FormatErrorInner::NoMoreImageData.into(),
-                    ));
-                }
+        let mut reader = Decoder::new(&data[..]).read_header_info().unwrap();
+        assert!(reader.read_header_info().is_ok());
+        reader.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let mut expected = vec![0; buf.len()];
+        Decoder::new(&data[..])
+            .read_info()
+            .unwrap()
+            .next_frame(&mut expected)
+            .unwrap();
+        reader.next_frame(&mut buf).unwrap();
+        assert!(buf == expected);
 
-                // Clear the current buffer before appending more data.
-                if self.scan_start > 0 {
-                    self.current.drain(..self.scan_start).for_each(drop);
-                    self.scan_start = 0;
-                }
+        assert!(Decoder::new(&data[..8]).read_header_info().is_err());
+    }
 
-                let val = self.decoder.decode_next(&mut self.current)?;
-                match val {
-                    Some(Decoded::ImageData) => {}
-                    Some(Decoded::ImageDataFlushed) => {
-                        self.subframe.consumed_and_flushed = true;
-                    }
-                    None => {
-                        if !self.current.is_empty() {
-                            return Err(DecodingError::Format(
-                                FormatErrorInner::UnexpectedEndOfChunk.into(),
-                            ));
-                        } else {
-                            return Ok(None);
-                        }
-                    }
-                    _ => (),
-                }
+    #[test]
+    fn next_frame_after_rows_rejected() {
+        for path in &["tests/pngsuite/basi0g08.png", "tests/pngsuite/basn0g08.png"] {
+            let data = std::fs::read(path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_row().unwrap();
+            reader.next_row().unwrap();
+            match reader.next_frame(&mut buf) {
+                Err(DecodingError::Parameter(_)) => {}
+                other => panic!("unexpected result {:?}", other),
             }
+            assert_eq!(reader.rows_remaining(), reader.progress().rows_total - 2);
         }
     }
-}
 
-impl SubframeInfo {
-    fn not_yet_init() -> Self {
-        SubframeInfo {
-            width: 0,
-            height: 0,
-            rowlen: 0,
-            interlace: InterlaceIter::None(0..0),
-            consumed_and_flushed: false,
+    #[test]
+    fn next_frame_with_stride() {
+        let paths = [
+            "tests/pngsuite/basn0g01.png",
+            "tests/pngsuite/basi0g01.png",
+            "tests/pngsuite/basn2c16.png",
+            "tests/pngsuite/basi3p04.png",
+        ];
+        for path in &paths {
+            let data = std::fs::read(path).unwrap();
+            for &downscale in &[super::Downscale::None, super::Downscale::Half] {
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_downscale(downscale);
+                let mut reader = decoder.read_info().unwrap();
+                let mut frame = vec![0; reader.output_buffer_size()];
+                let info = reader.next_frame(&mut frame).unwrap();
+
+                let mut decoder = Decoder::new(&data[..]);
+                decoder.set_downscale(downscale);
+                let mut reader = decoder.read_info().unwrap();
+                let stride = info.line_size + 5;
+                let mut padded = vec![0; stride * (info.height as usize - 1) + info.line_size];
+                assert!(reader.next_frame_with_stride(&mut padded, 1).is_err());
+                let short = padded.len() - 1;
+                assert!(reader
+                    .next_frame_with_stride(&mut padded[..short], stride)
+                    .is_err());
+                assert_eq!(
+                    reader.next_frame_with_stride(&mut padded, stride).unwrap(),
+                    info
+                );
+                for (row, line) in frame.chunks(info.line_size).enumerate() {
+                    assert_eq!(&padded[row * stride..][..info.line_size], line, "{}", path);
+                }
+            }
         }
+
+        // Bands are written with the stride as well.
+        let data = std::fs::read("tests/pngsuite/basn0g08.png").unwrap();
+        let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+        let mut frame = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut frame).unwrap();
+        let mut decoder = Decoder::new(&data[..]);
+        decoder.set_band_height(Some(20));
+        let mut reader = decoder.read_info().unwrap();
+        let mut band = vec![0; 40 * 19 + 32];
+        assert_eq!(
+            reader.next_frame_with_stride(&mut band, 40).unwrap().height,
+            20
+        );
+        assert_eq!(
+            reader.next_frame_with_stride(&mut band, 40).unwrap().height,
+            12
+        );
+        assert_eq!(&band[40 * 11..][..32], &frame[32 * 31..]);
     }
 
-    fn new(info: &Info) -> Self {
-        // The apng fctnl overrides width and height.
-        // All other data is set by the main info struct.
-        let (width, height) = if let Some(fc) = info.frame_control {
-            (fc.width, fc.height)
-        } else {
-            (info.width, info.height)
-        };
+    #[test]
+    fn next_frame_u16() {
+        for path in &["tests/pngsuite/basn2c16.png", "tests/pngsuite/basi4a16.png"] {
+            let data = std::fs::read(path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut bytes = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut bytes).unwrap();
 
-        let interlace = if info.interlaced {
-            InterlaceIter::Adam7(utils::Adam7Iterator::new(width, height))
-        } else {
-            InterlaceIter::None(0..height)
-        };
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut samples = vec![0; bytes.len() / 2];
+            assert!(reader.next_frame_u16(&mut samples[1..]).is_err());
+            reader.next_frame_u16(&mut samples).unwrap();
+            let expected: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            assert_eq!(samples, expected);
 
-        SubframeInfo {
-            width,
-            height,
-            rowlen: info.raw_row_length_from_width(width),
-            interlace,
-            consumed_and_flushed: false,
+            // Swapping the byte order of the rows does not change the samples.
+            let mut decoder = Decoder::new(&data[..]);
+            decoder.set_transformations(Transformations::SWAP_ENDIAN);
+            let mut reader = decoder.read_info().unwrap();
+            let mut swapped = vec![0; samples.len()];
+            reader.next_frame_u16(&mut swapped).unwrap();
+            assert_eq!(swapped, expected, "{}", path);
         }
+
+        let data = std::fs::read("tests/pngsuite/basn2c16.png").unwrap();
+        let mut decoder = Decoder::new(&data[..]);
+        decoder.set_downscale(super::Downscale::Half);
+        let mut reader = decoder.read_info().unwrap();
+        let mut samples = vec![0; reader.output_buffer_size() / 2];
+        assert!(reader.next_frame_u16(&mut samples).is_err());
+
+        let data = std::fs::read("tests/pngsuite/basn2c08.png").unwrap();
+        let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+        let mut samples = vec![0; reader.output_buffer_size()];
+        assert!(reader.next_frame_u16(&mut samples).is_err());
     }
-}
 
-fn expand_paletted(buffer: &mut [u8], info: &Info) -> Result<(), DecodingError> {
-    if let Some(palette) = info.palette.as_ref() {
-        if let BitDepth::Sixteen = info.bit_depth {
-            // This should have been caught earlier but let's check again. Can't hurt.
-            Err(DecodingError::Format(
-                FormatErrorInner::InvalidColorBitDepth {
-                    color: ColorType::Indexed,
-                    depth: BitDepth::Sixteen,
-                }
-                .into(),
-            ))
-        } else {
-            let black = [0, 0, 0];
-            if let Some(ref trns) = info.trns {
-                utils::unpack_bits(buffer, 4, info.bit_depth as u8, |i, chunk| {
-                    let (rgb, a) = (
-                        palette
-                            .get(3 * i as usize..3 * i as usize + 3)
-                            .unwrap_or(&black),
-                        *trns.get(i as usize).unwrap_or(&0xFF),
-                    );
-                    chunk[0] = rgb[0];
-                    chunk[1] = rgb[1];
-                    chunk[2] = rgb[2];
-                    chunk[3] = a;
-                });
-            } else {
-                utils::unpack_bits(buffer, 3, info.bit_depth as u8, |i, chunk| {
-                    let rgb = palette
-                        .get(3 * i as usize..3 * i as usize + 3)
-                        .unwrap_or(&black);
-                    chunk[0] = rgb[0];
-                    chunk[1] = rgb[1];
-                    chunk[2] = rgb[2];
+    #[test]
+    fn new_from_slice() {
+        fn decode_frames<B: BufRead>(reader: &mut super::Reader<&[u8], B>) -> Vec<Vec<u8>> {
+            let frames = reader
+                .info()
+                .animation_control
+                .map_or(1, |ac| ac.num_frames);
+            (0..frames)
+                .map(|_| {
+                    let mut buf = vec![0; reader.output_buffer_size()];
+                    let info = reader.next_frame(&mut buf).unwrap();
+                    buf.truncate(info.buffer_size());
+                    buf
                 })
-            }
-            Ok(())
+                .collect()
+        }
+
+        let paths = crate::test_utils::pngsuite_files().chain(crate::test_utils::animated_files());
+        for path in paths {
+            let data = std::fs::read(&path).unwrap();
+            let expected = decode_frames(&mut Decoder::new(&data[..]).read_info().unwrap());
+            let mut reader = Decoder::new_from_slice(&data).read_info_buffered().unwrap();
+            assert_eq!(decode_frames(&mut reader), expected, "{:?}", path);
         }
-    } else {
-        Err(DecodingError::Format(
-            FormatErrorInner::PaletteRequired.into(),
-        ))
     }
-}
 
-fn expand_gray_u8(buffer: &mut [u8], info: &Info) {
-    let rescale = true;
-    let scaling_factor = if rescale {
-        (255) / ((1u16 << info.bit_depth as u8) - 1) as u8
-    } else {
-        1
-    };
-    if let Some(ref trns) = info.trns {
-        utils::unpack_bits(buffer, 2, info.bit_depth as u8, |pixel, chunk| {
-            if pixel == trns[0] {
-                chunk[1] = 0
-            } else {
-                chunk[1] = 0xFF
+    #[test]
+    fn reuse_buffers() {
+        let mut buffers: Option<super::DecodingBuffers> = None;
+        for path in crate::test_utils::pngsuite_files() {
+            let data = std::fs::read(&path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut expected = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut expected).unwrap();
+
+            let mut decoder = Decoder::new(&data[..]);
+            let reused = buffers.take().map(|buffers| {
+                let capacity = buffers.current.capacity();
+                decoder.set_buffers(buffers);
+                capacity
+            });
+            let mut reader = decoder.read_info().unwrap();
+            if let Some(capacity) = reused {
+                assert!(reader.current.capacity() >= capacity);
             }
-            chunk[0] = pixel * scaling_factor
-        })
-    } else {
-        utils::unpack_bits(buffer, 1, info.bit_depth as u8, |val, chunk| {
-            chunk[0] = val * scaling_factor
-        })
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert_eq!(buf, expected, "{:?}", path);
+            buffers = Some(reader.into_buffers());
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::Decoder;
-    use std::io::{BufRead, Read, Result};
-    use std::mem::discriminant;
+    #[test]
+    fn for_each_output_row() {
+        for path in crate::test_utils::pngsuite_files() {
+            for &transformations in &[Transformations::IDENTITY, Transformations::EXPAND] {
+                let mut decoder = Decoder::new(File::open(&path).unwrap());
+                decoder.set_transformations(transformations);
+                let mut reader = decoder.read_info().unwrap();
+                let mut expected = vec![0; reader.output_buffer_size()];
+                let info = reader.next_frame(&mut expected).unwrap();
 
-    /// A reader that reads at most `n` bytes.
-    struct SmalBuf<R: BufRead> {
-        inner: R,
-        cap: usize,
+                let mut decoder = Decoder::new(File::open(&path).unwrap());
+                decoder.set_transformations(transformations);
+                let mut reader = decoder.read_info().unwrap();
+                let mut frame = vec![0; reader.output_buffer_size()];
+                let mut seen = vec![false; info.height as usize];
+                reader
+                    .for_each_output_row(|y, row| {
+                        assert!(!seen[y as usize], "{:?} row {} twice", path, y);
+                        seen[y as usize] = true;
+                        let line = &mut frame[y as usize * info.line_size..][..info.line_size];
+                        line.copy_from_slice(row);
+                    })
+                    .unwrap();
+                assert!(seen.iter().all(|&seen| seen), "{:?}", path);
+                assert_eq!(frame, expected, "{:?}", path);
+            }
+        }
     }
 
-    impl<R: BufRead> SmalBuf<R> {
-        fn new(inner: R, cap: usize) -> Self {
-            SmalBuf { inner, cap }
+    #[test]
+    fn row_filter() {
+        use crate::FilterType::*;
+        for &filter in &[NoFilter, Sub, Up, Avg, Paeth] {
+            for &interlaced in &[false, true] {
+                let mut image = vec![];
+                {
+                    let mut encoder = crate::Encoder::new(&mut image, 9, 7);
+                    encoder.set_color(ColorType::Rgb);
+                    encoder.set_interlaced(interlaced);
+                    encoder.set_filter(filter);
+                    let mut writer = encoder.write_header().unwrap();
+                    let data: Vec<u8> = (0..9 * 7 * 3).map(|i| (i * 37 % 251) as u8).collect();
+                    writer.write_image_data(&data).unwrap();
+                }
+                for &transform in &[Transformations::IDENTITY, Transformations::RGBA8] {
+                    let mut decoder = Decoder::new(&image[..]);
+                    decoder.set_transformations(transform);
+                    let mut reader = decoder.read_info().unwrap();
+                    let mut rows = 0;
+                    while let Some(row) = reader.next_row().unwrap() {
+                        assert_eq!(row.info().filter, filter);
+                        rows += 1;
+                    }
+                    assert!(rows >= 7);
+                }
+            }
         }
     }
 
-    impl<R: BufRead> Read for SmalBuf<R> {
-        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-            let len = buf.len().min(self.cap);
-            self.inner.read(&mut buf[..len])
+    #[test]
+    fn add_alpha() {
+        let decode = |path: &std::path::Path, transform| {
+            let mut decoder = Decoder::new(File::open(path).unwrap());
+            decoder.set_transformations(transform);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buf).unwrap();
+            (buf, info.color_type, info.bit_depth)
+        };
+        for path in crate::test_utils::pngsuite_files() {
+            let (expanded, color_type, bit_depth) = decode(&path, Transformations::EXPAND);
+            let (alpha, alpha_type, alpha_depth) = decode(&path, Transformations::ADD_ALPHA);
+            assert_eq!(alpha_depth, bit_depth);
+            let sample = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+            match color_type {
+                ColorType::Grayscale | ColorType::Rgb => {
+                    let pixel = color_type.samples() * sample;
+                    assert_eq!(alpha_type.samples(), color_type.samples() + 1);
+                    assert_eq!(alpha.len(), expanded.len() / pixel * (pixel + sample));
+                    for (color, with_alpha) in
+                        expanded.chunks(pixel).zip(alpha.chunks(pixel + sample))
+                    {
+                        assert_eq!(&with_alpha[..pixel], color, "{:?}", path);
+                        assert!(with_alpha[pixel..].iter().all(|&a| a == 0xFF));
+                    }
+                }
+                _ => assert_eq!((alpha, alpha_type), (expanded.clone(), color_type)),
+            }
+
+            let strip = Transformations::ADD_ALPHA | Transformations::STRIP_ALPHA;
+            let (stripped, _, _) = decode(&path, strip);
+            let (plain, _, _) = decode(
+                &path,
+                Transformations::EXPAND | Transformations::STRIP_ALPHA,
+            );
+            assert_eq!(stripped, plain, "{:?}", path);
         }
     }
 
-    impl<R: BufRead> BufRead for SmalBuf<R> {
-        fn fill_buf(&mut self) -> Result<&[u8]> {
-            let buf = self.inner.fill_buf()?;
-            let len = buf.len().min(self.cap);
-            Ok(&buf[..len])
-        }
+    #[test]
+    fn background() {
+        use crate::BackgroundColor;
 
-        fn consume(&mut self, amt: usize) {
-            assert!(amt <= self.cap);
-            self.inner.consume(amt)
+        let decode = |name: &str, transform, background| {
+            let path = format!("tests/pngsuite/{}.png", name);
+            let mut decoder = Decoder::new(File::open(path).unwrap());
+            decoder.set_transformations(transform);
+            if let Some(background) = background {
+                decoder.set_background(background);
+            }
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buf).unwrap();
+            (buf, info.color_type, info.bit_depth)
+        };
+
+        let cases: &[(&str, Option<BackgroundColor>, &[u8])] = &[
+            ("bgwn6a08", None, &[0xFF, 0xFF, 0xFF]),
+            ("bgyn6a16", None, &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0]),
+            ("bgbn4a08", None, &[0]),
+            ("bggn4a16", None, &[0xAB, 0x84]),
+            ("bgan6a08", None, &[0xFF, 0xFF, 0xFF]),
+            ("bgwn6a08", Some(BackgroundColor::Rgb(1, 2, 3)), &[1, 2, 3]),
+            ("bgbn4a08", Some(BackgroundColor::Gray(9)), &[9]),
+            // A color of the wrong kind is ignored.
+            ("bgbn4a08", Some(BackgroundColor::Rgb(1, 2, 3)), &[0]),
+        ];
+        for &(name, background, expected) in cases {
+            let (rgba, color_type, bit_depth) = decode(name, Transformations::EXPAND, None);
+            let transform = Transformations::BACKGROUND;
+            let (opaque, opaque_type, opaque_depth) = decode(name, transform, background);
+            assert_eq!(opaque_depth, bit_depth);
+            assert_eq!(opaque_type.samples() + 1, color_type.samples(), "{}", name);
+
+            let sample = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+            let (alpha_pixel, pixel) = (color_type.samples() * sample, expected.len());
+            for (with_alpha, composited) in rgba.chunks(alpha_pixel).zip(opaque.chunks(pixel)) {
+                let alpha = &with_alpha[pixel..];
+                if alpha.iter().all(|&a| a == 0xFF) {
+                    assert_eq!(composited, &with_alpha[..pixel], "{}", name);
+                } else if alpha.iter().all(|&a| a == 0) {
+                    assert_eq!(composited, expected, "{}", name);
+                }
+            }
+
+            // Alpha added afterwards is opaque.
+            let (rgba8, _, _) = decode(name, transform | Transformations::RGBA8, background);
+            assert!(rgba8.chunks(4).all(|pixel| pixel[3] == 0xFF), "{}", name);
         }
     }
 
     #[test]
-    fn no_data_dup_on_finish() {
-        const IMG: &[u8] = include_bytes!(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/tests/bugfixes/x_issue#214.png"
-        ));
+    fn set_gamma() {
+        let decode = |name: &str, transform, gamma: Option<(f64, f64)>| {
+            let path = format!("tests/pngsuite/{}.png", name);
+            let mut decoder = Decoder::new(File::open(path).unwrap());
+            decoder.set_transformations(transform);
+            let mut reader = decoder.read_info().unwrap();
+            if let Some((screen, default)) = gamma {
+                reader.set_gamma(screen, default);
+            }
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            buf
+        };
+        let correct =
+            |sample: f64, max: f64, exponent: f64| ((sample / max).powf(exponent) * max).round();
 
-        let mut normal = Decoder::new(IMG).read_info().unwrap();
+        // The gAMA chunk of 1.0 is used instead of the default.
+        let plain = decode("g10n2c08", Transformations::IDENTITY, None);
+        let corrected = decode("g10n2c08", Transformations::IDENTITY, Some((2.2, 0.5)));
+        for (&plain, &corrected) in plain.iter().zip(&corrected) {
+            assert_eq!(
+                f64::from(corrected),
+                correct(f64::from(plain), 255.0, 1.0 / 2.2)
+            );
+        }
 
-        let mut buffer = vec![0; normal.output_buffer_size()];
-        let normal = normal.next_frame(&mut buffer).unwrap_err();
+        let plain = decode("g10n0g16", Transformations::IDENTITY, None);
+        let corrected = decode("g10n0g16", Transformations::IDENTITY, Some((2.2, 0.5)));
+        for (plain, corrected) in plain.chunks(2).zip(corrected.chunks(2)) {
+            let plain = f64::from(u16::from_be_bytes([plain[0], plain[1]]));
+            let corrected = u16::from_be_bytes([corrected[0], corrected[1]]);
+            assert_eq!(f64::from(corrected), correct(plain, 65535.0, 1.0 / 2.2));
+        }
 
-        let smal = Decoder::new(SmalBuf::new(IMG, 1))
-            .read_info()
-            .unwrap()
-            .next_frame(&mut buffer)
-            .unwrap_err();
+        // Alpha is not changed.
+        let plain = decode("basn6a08", Transformations::IDENTITY, None);
+        let corrected = decode("basn6a08", Transformations::IDENTITY, Some((2.0, 0.5)));
+        for (plain, corrected) in plain.chunks(4).zip(corrected.chunks(4)) {
+            assert_eq!(plain[3], corrected[3]);
+            for k in 0..3 {
+                let expected = correct(f64::from(plain[k]), 255.0, 0.5);
+                assert_eq!(f64::from(corrected[k]), expected);
+            }
+        }
 
-        assert_eq!(discriminant(&normal), discriminant(&smal));
+        // Without gAMA the default is used.
+        let mut image = vec![];
+        {
+            let encoder = crate::Encoder::new(&mut image, 4, 1);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0, 64, 128, 255]).unwrap();
+        }
+        let mut reader = Decoder::new(&image[..]).read_info().unwrap();
+        reader.set_gamma(2.0, 1.0);
+        let mut buf = [0; 4];
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf, [0, 128, 181, 255]);
+
+        // Matching gammas and palette indices leave the image unchanged, expanded palettes not.
+        let plain = decode("basn2c08", Transformations::IDENTITY, None);
+        let matched = decode("basn2c08", Transformations::IDENTITY, Some((1.0, 0.5)));
+        assert_eq!(matched, plain);
+        let plain = decode("g10n3p04", Transformations::IDENTITY, None);
+        let corrected = Some((2.2, 0.5));
+        assert_eq!(
+            decode("g10n3p04", Transformations::IDENTITY, corrected),
+            plain
+        );
+        let plain = decode("g10n3p04", Transformations::EXPAND, None);
+        assert_ne!(
+            decode("g10n3p04", Transformations::EXPAND, corrected),
+            plain
+        );
+
+        // Transformations that shrink the row again keep the room of the intermediate row.
+        let shrinking = Transformations::RGBA8 | Transformations::STRIP_ALPHA;
+        let plain = decode("basn2c08", shrinking, None);
+        assert_ne!(decode("basn2c08", shrinking, corrected), plain);
+        let shrinking = Transformations::ADD_ALPHA | Transformations::STRIP_ALPHA;
+        let plain = decode("tp0n0g08", shrinking, None);
+        assert_ne!(decode("tp0n0g08", shrinking, corrected), plain);
+    }
+
+    #[test]
+    fn row_transform() {
+        for path in crate::test_utils::pngsuite_files() {
+            for &transform in &[
+                Transformations::EXPAND,
+                Transformations::RGBA8,
+                Transformations::RGBA8 | Transformations::STRIP_ALPHA,
+                Transformations::ADD_ALPHA | Transformations::STRIP_ALPHA,
+            ] {
+                let mut decoder = Decoder::new(File::open(&path).unwrap());
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let mut expected = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut expected).unwrap();
+
+                let mut decoder = Decoder::new(File::open(&path).unwrap());
+                decoder.set_transformations(transform);
+                let mut reader = decoder.read_info().unwrap();
+                let input = (reader.info().color_type, reader.info().bit_depth);
+                let output = reader.output_color_type();
+                let width = reader.info().width;
+                let interlaced = reader.info().interlaced;
+                reader.set_row_transform(move |row, format| {
+                    assert_eq!((format.input, format.output), (input, output));
+                    assert!(format.width == width || interlaced && format.width < width);
+                    row.iter_mut().for_each(|sample| *sample = !*sample);
+                });
+                let mut inverted = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut inverted).unwrap();
+                let expected: Vec<u8> = expected.iter().map(|&sample| !sample).collect();
+                assert!(inverted == expected, "{:?} {:?}", path, transform);
+            }
+        }
+    }
+
+    #[test]
+    fn into_inner() {
+        let paths = [
+            "tests/pngsuite/basn0g01.png",
+            "tests/pngsuite/basi3p02.png",
+            "tests/pngsuite/basn6a16.png",
+        ];
+        let mut stream = vec![];
+        let mut expected = vec![];
+        for path in &paths {
+            let data = std::fs::read(path).unwrap();
+            let mut reader = Decoder::new(&data[..]).read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            expected.push(buf);
+            stream.extend(data);
+        }
+
+        let mut rest = std::io::BufReader::new(&stream[..]);
+        for image in &expected {
+            let mut reader = Decoder::new(rest).read_info_buffered().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf == *image);
+            reader.finish().unwrap();
+            rest = reader.into_inner();
+        }
+        assert!(rest.fill_buf().unwrap().is_empty());
     }
 }
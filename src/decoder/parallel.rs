@@ -0,0 +1,72 @@
+//! Unfiltering and combining the passes of interlaced frames on several threads.
+//!
+//! Each pass is filtered on its own, so once the image data of a frame is decompressed the passes
+//! can be unfiltered independently. The rows of the frame are then assembled from the passes,
+//! every row of the frame on its own as well.
+use rayon::prelude::*;
+
+use super::{unfilter_row, DecodingError};
+use crate::adam7;
+use crate::common::BytesPerPixel;
+use crate::utils;
+
+/// The transformed rows of a pass.
+pub(super) struct PassRows {
+    /// The length of each row in bytes, zero if the pass is empty.
+    pub(super) row_len: usize,
+    pub(super) data: Vec<u8>,
+}
+
+/// Unfilter the passes of a frame in place, each given by the length of its rows, including the
+/// filter type, and their number.
+pub(super) fn unfilter_passes(
+    mut data: &mut [u8],
+    passes: &[(usize, u32)],
+    bpp: BytesPerPixel,
+) -> Result<(), DecodingError> {
+    let mut pass_data = Vec::with_capacity(passes.len());
+    for &(row_len, lines) in passes {
+        let (pass, rest) = data.split_at_mut(row_len * lines as usize);
+        pass_data.push((row_len, pass));
+        data = rest;
+    }
+
+    pass_data
+        .into_par_iter()
+        .filter(|&(row_len, _)| row_len > 0)
+        .try_for_each(|(row_len, pass)| {
+            let mut prev_start = None;
+            for row_start in (0..pass.len()).step_by(row_len) {
+                unfilter_row(pass, row_start, prev_start, row_len, bpp)?;
+                prev_start = Some(row_start);
+            }
+            Ok(())
+        })
+}
+
+/// Write the rows of the seven `passes` into the rows of `line_size` bytes of a `frame` that is
+/// `width` pixels wide.
+pub(super) fn deinterlace(
+    frame: &mut [u8],
+    (width, line_size): (u32, usize),
+    passes: &[PassRows],
+    bits_pp: usize,
+) {
+    frame
+        .par_chunks_mut(line_size)
+        .enumerate()
+        .for_each(|(y, line)| {
+            let y = y as u32;
+            for (rows, pass) in passes.iter().zip(1..=7) {
+                let first = adam7::image_row(pass, 0);
+                let step = adam7::image_row(pass, 1) - first;
+                if rows.row_len == 0 || y < first || (y - first) % step != 0 {
+                    continue;
+                }
+                let line_no = ((y - first) / step) as usize;
+                let row = &rows.data[line_no * rows.row_len..][..rows.row_len];
+                let (x, step) = adam7::image_columns(pass);
+                utils::fill_blocks(line, (width, 1), row, (x, 0, step), (1, 1), bits_pp, false);
+            }
+        });
+}
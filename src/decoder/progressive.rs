@@ -0,0 +1,410 @@
+use super::stream::{FormatErrorInner, ShortImageData};
+use super::SubframeInfo;
+use super::{
+    unfilter_row, Decoded, DecodingError, InterlaceInfo, InterlacedRow, RowInfo, StreamingDecoder,
+};
+
+use crate::chunk;
+use crate::common::{BytesPerPixel, Info};
+use crate::utils;
+
+/// An event of the progressive decoder.
+#[derive(Debug)]
+pub enum Progress<'data> {
+    /// All metadata preceding the image data has been decoded.
+    ///
+    /// This is reported once, before the first row.
+    Header(&'data Info<'static>),
+    /// A row of the current frame was decoded.
+    ///
+    /// The row is unfiltered but no transformations are applied. Interlaced images report the
+    /// rows of each pass in the order in which they are stored.
+    Row(InterlacedRow<'data>),
+    /// All image data of the current frame has been decoded.
+    FrameComplete,
+    /// The end of the image was reached.
+    ImageEnd,
+}
+
+/// Callbacks of the progressive decoder, in the model of libpng's progressive reader.
+///
+/// This mirrors the info, row and end callbacks registered with `png_set_progressive_read_fn`,
+/// to ease porting progressive rendering code. All methods do nothing by default.
+pub trait ProgressiveCallbacks {
+    /// Called once all metadata preceding the image data has been decoded.
+    fn info(&mut self, _info: &Info<'static>) {}
+
+    /// Called for every decoded row.
+    ///
+    /// As in libpng, `row_num` is the row of the full image and `pass` counts the Adam7 passes
+    /// from zero. It is always zero for images without interlacing. The row is unfiltered but no
+    /// transformations are applied, rows of interlaced images only contain the pixels of their
+    /// pass.
+    fn row(&mut self, _row: &[u8], _row_num: u32, _pass: u8) {}
+
+    /// Called when the end of the image was reached.
+    fn end(&mut self, _info: &Info<'static>) {}
+}
+
+/// PNG decoder that is fed with data as it becomes available.
+///
+/// In contrast to the `Reader` this does not need a blocking `Read` but consumes whatever bytes
+/// are passed to `push`, reporting the decoding progress to a handler. Decoding is resumed with
+/// the next call, so it is suitable for data arriving from the network or a non-blocking source.
+///
+/// ```
+/// use png::{Progress, ProgressiveDecoder};
+/// # let png = std::fs::read("tests/pngsuite/basi0g01.png").unwrap();
+///
+/// let mut decoder = ProgressiveDecoder::new();
+/// let mut rows = 0;
+/// // Feed the data in small pieces, as it would arrive over a network.
+/// for piece in png.chunks(100) {
+///     decoder.push(piece, |event| {
+///         if let Progress::Row(_) = event {
+///             rows += 1;
+///         }
+///     })?;
+/// }
+/// // The 32 rows of the image, spread over the seven interlace passes.
+/// assert_eq!(rows, 60);
+/// # Ok::<(), png::DecodingError>(())
+/// ```
+pub struct ProgressiveDecoder {
+    decoder: StreamingDecoder,
+    bpp: BytesPerPixel,
+    subframe: SubframeInfo,
+    /// Whether the image data of a frame is being decoded.
+    in_frame: bool,
+    /// Whether the header event was reported.
+    header_reported: bool,
+    /// The next row, whose data has not been completely decoded yet.
+    next_row: Option<(usize, InterlaceInfo)>,
+    /// Window of decompressed image data, as in the `Reader`.
+    current: Vec<u8>,
+    /// Start index of the current scan line.
+    scan_start: usize,
+    /// Start index of the previous, already unfiltered, scan line of the current pass.
+    prev_start: Option<usize>,
+}
+
+impl ProgressiveDecoder {
+    /// Create a new progressive decoder with the default configuration.
+    pub fn new() -> ProgressiveDecoder {
+        ProgressiveDecoder::with_decoder(StreamingDecoder::new())
+    }
+
+    /// Create a progressive decoder from a configured streaming decoder.
+    ///
+    /// The streaming decoder must not have decoded any data yet.
+    pub fn with_decoder(decoder: StreamingDecoder) -> ProgressiveDecoder {
+        ProgressiveDecoder {
+            decoder,
+            bpp: BytesPerPixel::One,
+            subframe: SubframeInfo::not_yet_init(),
+            in_frame: false,
+            header_reported: false,
+            next_row: None,
+            current: Vec::new(),
+            scan_start: 0,
+            prev_start: None,
+        }
+    }
+
+    /// Get information on the image decoded so far.
+    pub fn info(&self) -> Option<&Info<'static>> {
+        self.decoder.info.as_ref()
+    }
+
+    /// Decode the next piece of `data`, reporting the progress to `handler`.
+    ///
+    /// All of `data` is consumed up to the end of the image, any data after it is ignored.
+    /// Returns the number of bytes consumed.
+    pub fn push<F>(&mut self, data: &[u8], mut handler: F) -> Result<usize, DecodingError>
+    where
+        F: FnMut(Progress<'_>),
+    {
+        let mut buf = data;
+        while !buf.is_empty() {
+            let (consumed, event) = self.decoder.update(buf, &mut self.current)?;
+            buf = &buf[consumed..];
+            match event {
+                Decoded::ChunkBegin(_, chunk::IDAT) | Decoded::ChunkBegin(_, chunk::fdAT) => {
+                    self.begin_frame(&mut handler)?;
+                }
+                Decoded::ImageData => self.decode_rows(&mut handler)?,
+                Decoded::ImageDataFlushed => {
+                    self.decode_rows(&mut handler)?;
+                    self.finish_frame(&mut handler)?;
+                }
+                Decoded::ImageEnd => {
+                    handler(Progress::ImageEnd);
+                    break;
+                }
+                Decoded::Nothing if consumed == 0 => break,
+                _ => {}
+            }
+        }
+
+        Ok(data.len() - buf.len())
+    }
+
+    /// Decode the next piece of `data`, reporting the progress to the `callbacks`.
+    ///
+    /// This is the equivalent of libpng's `png_process_data`. Frame boundaries of animated
+    /// images are not reported. Returns the number of bytes consumed, as with `push`.
+    pub fn process_data<C>(
+        &mut self,
+        data: &[u8],
+        callbacks: &mut C,
+    ) -> Result<usize, DecodingError>
+    where
+        C: ProgressiveCallbacks + ?Sized,
+    {
+        let mut ended = false;
+        let consumed = self.push(data, |event| match event {
+            Progress::Header(info) => callbacks.info(info),
+            Progress::Row(row) => {
+                let info = row.info();
+                let pass = info.pass.map_or(0, |pass| pass - 1);
+                callbacks.row(row.data(), info.row_index, pass)
+            }
+            Progress::FrameComplete => {}
+            Progress::ImageEnd => ended = true,
+        })?;
+
+        if ended {
+            callbacks.end(self.info().unwrap());
+        }
+        Ok(consumed)
+    }
+
+    fn begin_frame<F>(&mut self, handler: &mut F) -> Result<(), DecodingError>
+    where
+        F: FnMut(Progress<'_>),
+    {
+        // Every consecutive IDAT or fdAT chunk begins, only the first starts the frame.
+        if self.in_frame {
+            return Ok(());
+        }
+
+        let info = match self.decoder.info.as_ref() {
+            Some(info) => info,
+            None => return Err(DecodingError::Format(FormatErrorInner::MissingIhdr.into())),
+        };
+
+        if !self.header_reported {
+            self.header_reported = true;
+            handler(Progress::Header(info));
+        }
+
+        self.bpp = info.bpp_in_prediction();
+        self.subframe = SubframeInfo::new(info);
+        self.in_frame = true;
+        self.next_row = None;
+        self.current.clear();
+        self.scan_start = 0;
+        self.prev_start = None;
+        Ok(())
+    }
+
+    fn finish_frame<F>(&mut self, handler: &mut F) -> Result<(), DecodingError>
+    where
+        F: FnMut(Progress<'_>),
+    {
+        let info = self.decoder.info.as_ref().unwrap();
+        if self.next_row.is_some() || self.subframe.next_row(info).is_some() {
+            let end = if self.current.len() > self.scan_start {
+                ShortImageData::WithinRow
+            } else {
+                ShortImageData::AtRowBoundary
+            };
+            return Err(DecodingError::Format(
+                FormatErrorInner::NoMoreImageData { end }.into(),
+            ));
+        }
+
+        self.in_frame = false;
+        handler(Progress::FrameComplete);
+        Ok(())
+    }
+
+    /// Report all rows that are completely decoded.
+    fn decode_rows<F>(&mut self, handler: &mut F) -> Result<(), DecodingError>
+    where
+        F: FnMut(Progress<'_>),
+    {
+        let info = self.decoder.info.as_ref().unwrap();
+        loop {
+            let (rowlen, interlace) = match self.next_row.take() {
+                Some(row) => row,
+                None => match self.subframe.next_row(info) {
+                    Some((rowlen, interlace, new_pass)) => {
+                        if new_pass {
+                            self.prev_start = None;
+                        }
+                        (rowlen, interlace)
+                    }
+                    None => return Ok(()),
+                },
+            };
+
+            if self.current.len() - self.scan_start < rowlen {
+                self.next_row = Some((rowlen, interlace));
+                // Discard consumed lines, except the previous one, before more data is appended.
+                let keep = self.prev_start.unwrap_or(self.scan_start);
+                if keep > 0 {
+                    self.current.drain(..keep).for_each(drop);
+                    self.scan_start -= keep;
+                    self.prev_start = self.prev_start.map(|start| start - keep);
+                }
+                return Ok(());
+            }
+
+            let row_start = self.scan_start;
+            let filter = unfilter_row(
+                &mut self.current,
+                row_start,
+                self.prev_start,
+                rowlen,
+                self.bpp,
+            )?;
+            self.prev_start = Some(row_start);
+            self.scan_start += rowlen;
+
+            let (row_index, pass, width) = self.subframe.last_row(interlace);
+            handler(Progress::Row(InterlacedRow {
+                data: &self.current[row_start + 1..row_start + rowlen],
+                interlace,
+                info: RowInfo {
+                    row_index,
+                    pass,
+                    width,
+                    color_type: info.color_type,
+                    bit_depth: info.bit_depth,
+                    filter,
+                },
+            }));
+        }
+    }
+}
+
+impl Default for ProgressiveDecoder {
+    fn default() -> Self {
+        ProgressiveDecoder::new()
+    }
+}
+
+/// Decoded rows collected to hand them from the decoding thread to another one.
+///
+/// The rows are copied into a single buffer. A batch is `Send`, so it can be passed through a
+/// channel to a render thread which writes it into a frame it owns with `write_into`. Sending the
+/// cleared batch back reuses its allocations, two batches then double-buffer the progress without
+/// sharing the frame or copying all of it for every update.
+///
+/// ```
+/// use std::sync::mpsc;
+/// use std::thread;
+/// use png::{Progress, ProgressiveDecoder, RowBatch};
+/// # let png = std::fs::read("tests/pngsuite/basi0g08.png").unwrap();
+///
+/// let (send_rows, rows) = mpsc::channel::<RowBatch>();
+/// let (recycle, recycled) = mpsc::channel::<RowBatch>();
+/// for _ in 0..2 {
+///     recycle.send(RowBatch::new()).unwrap();
+/// }
+///
+/// let decoding = thread::spawn(move || -> Result<(), png::DecodingError> {
+///     let mut decoder = ProgressiveDecoder::new();
+///     for piece in png.chunks(256) {
+///         let mut batch = recycled.recv().unwrap();
+///         decoder.push(piece, |event| {
+///             if let Progress::Row(row) = event {
+///                 batch.push(&row);
+///             }
+///         })?;
+///         send_rows.send(batch).unwrap();
+///     }
+///     Ok(())
+/// });
+///
+/// // The render thread, 32x32 pixels with 8 bits each.
+/// let mut frame = vec![0; 32 * 32];
+/// for mut batch in rows {
+///     batch.write_into(&mut frame, 32);
+///     // Draw the frame.
+///     batch.clear();
+///     let _ = recycle.send(batch);
+/// }
+/// decoding.join().unwrap()?;
+/// # Ok::<(), png::DecodingError>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RowBatch {
+    data: Vec<u8>,
+    /// The rows and the end of their data.
+    rows: Vec<(RowInfo, InterlaceInfo, usize)>,
+}
+
+impl RowBatch {
+    /// Create an empty batch.
+    pub fn new() -> RowBatch {
+        RowBatch::default()
+    }
+
+    /// Append a copy of the `row`.
+    pub fn push(&mut self, row: &InterlacedRow<'_>) {
+        self.data.extend_from_slice(row.data());
+        self.rows
+            .push((row.info(), row.interlace(), self.data.len()));
+    }
+
+    /// The number of rows in the batch.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Whether the batch contains no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Remove all rows, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.rows.clear();
+    }
+
+    /// The rows of the batch, in the order in which they were pushed.
+    pub fn rows(&self) -> impl Iterator<Item = (RowInfo, &[u8])> + '_ {
+        let ends = self.rows.iter().map(|&(_, _, end)| end);
+        let starts = Some(0).into_iter().chain(ends);
+        self.rows
+            .iter()
+            .zip(starts)
+            .map(move |(&(info, _, end), start)| (info, &self.data[start..end]))
+    }
+
+    /// Write all rows to their place in a deinterlaced `frame` of `width` pixels.
+    ///
+    /// The rows of interlaced images are spread to the pixels of their pass. Their pixels of less
+    /// than 8 bits are combined with what is in the frame already, so it should be zeroed before
+    /// the first pass.
+    ///
+    /// Panics if the frame is too small for the rows.
+    pub fn write_into(&self, frame: &mut [u8], width: u32) {
+        for (&(info, interlace, _), (_, data)) in self.rows.iter().zip(self.rows()) {
+            let bits_pp = info.color_type.samples() as u8 * info.bit_depth as u8;
+            match interlace {
+                InterlaceInfo::Null => {
+                    let stride = (width as usize * bits_pp as usize + 7) / 8;
+                    let start = info.row_index as usize * stride;
+                    frame[start..start + data.len()].copy_from_slice(data);
+                }
+                InterlaceInfo::Adam7 { pass, line, .. } => {
+                    utils::expand_pass(frame, width, data, pass, line, bits_pp)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! A byte buffer that keeps small contents inline instead of allocating them.
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+/// The number of bytes kept inline, enough for the image data of a 16×16 RGBA image with 16 bits
+/// per sample and for any palette.
+pub(crate) const INLINE_CAPACITY: usize = 4096;
+
+/// A growable buffer of bytes, which is only allocated once its contents exceed
+/// `INLINE_CAPACITY`.
+///
+/// The rows, chunks and decompression window of small images then fit into the decoder itself.
+/// An allocation is kept when the buffer is cleared, so that it is reused for the next image.
+pub(crate) struct SmallBuf(Repr);
+
+// Keeping the data inline is the point, it is not boxed.
+#[allow(clippy::large_enum_variant)]
+enum Repr {
+    Inline {
+        data: [u8; INLINE_CAPACITY],
+        len: usize,
+    },
+    Heap(Vec<u8>),
+}
+
+impl SmallBuf {
+    /// An empty buffer, with its contents inline.
+    pub(crate) fn new() -> Self {
+        SmallBuf(Repr::Inline {
+            data: [0; INLINE_CAPACITY],
+            len: 0,
+        })
+    }
+
+    /// A buffer with the contents of `vec`, which keeps growing in its allocation.
+    ///
+    /// An empty vector is not allocated until data is appended, so this is also a cheap buffer
+    /// for data that is discarded.
+    pub(crate) fn from_vec(vec: Vec<u8>) -> Self {
+        SmallBuf(Repr::Heap(vec))
+    }
+
+    /// The contents as vector, which is allocated if they were inline.
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        match self.0 {
+            Repr::Inline { data, len } => data[..len].to_vec(),
+            Repr::Heap(vec) => vec,
+        }
+    }
+
+    /// The number of bytes the buffer holds without allocating.
+    pub(crate) fn capacity(&self) -> usize {
+        match &self.0 {
+            Repr::Inline { .. } => INLINE_CAPACITY,
+            Repr::Heap(vec) => vec.capacity(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Shorten the contents to `new_len` bytes, without effect if they are not longer.
+    pub(crate) fn truncate(&mut self, new_len: usize) {
+        match &mut self.0 {
+            Repr::Inline { len, .. } => *len = new_len.min(*len),
+            Repr::Heap(vec) => vec.truncate(new_len),
+        }
+    }
+
+    /// Make room for at least `additional` more bytes, moving the contents to the heap if they
+    /// no longer fit inline.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        let vec = match &mut self.0 {
+            Repr::Inline { data, len } => {
+                if additional <= INLINE_CAPACITY - *len {
+                    return;
+                }
+                let mut vec = Vec::with_capacity(len.saturating_add(additional));
+                vec.extend_from_slice(&data[..*len]);
+                vec
+            }
+            Repr::Heap(vec) => return vec.reserve(additional),
+        };
+        self.0 = Repr::Heap(vec);
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, other: &[u8]) {
+        self.reserve(other.len());
+        match &mut self.0 {
+            Repr::Inline { data, len } => {
+                data[*len..][..other.len()].copy_from_slice(other);
+                *len += other.len();
+            }
+            Repr::Heap(vec) => vec.extend_from_slice(other),
+        }
+    }
+
+    /// Change the length to `new_len`, filling new bytes with `value`.
+    pub(crate) fn resize(&mut self, new_len: usize, value: u8) {
+        let old_len = self.len();
+        if new_len <= old_len {
+            return self.truncate(new_len);
+        }
+        self.reserve(new_len - old_len);
+        match &mut self.0 {
+            Repr::Inline { data, len } => {
+                for byte in &mut data[*len..new_len] {
+                    *byte = value;
+                }
+                *len = new_len;
+            }
+            Repr::Heap(vec) => vec.resize(new_len, value),
+        }
+    }
+
+    /// Remove the first `count` bytes, moving the remaining ones to the front.
+    pub(crate) fn remove_front(&mut self, count: usize) {
+        match &mut self.0 {
+            Repr::Inline { data, len } => {
+                data[..*len].rotate_left(count);
+                *len -= count;
+            }
+            Repr::Heap(vec) => {
+                vec.drain(..count);
+            }
+        }
+    }
+}
+
+impl Deref for SmallBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Inline { data, len } => &data[..*len],
+            Repr::Heap(vec) => vec,
+        }
+    }
+}
+
+impl DerefMut for SmallBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.0 {
+            Repr::Inline { data, len } => &mut data[..*len],
+            Repr::Heap(vec) => vec,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SmallBuf, INLINE_CAPACITY};
+
+    #[test]
+    fn moves_to_heap_when_full() {
+        let mut buf = SmallBuf::new();
+        buf.extend_from_slice(&[1, 2, 3]);
+        buf.resize(INLINE_CAPACITY, 4);
+        assert_eq!(buf.capacity(), INLINE_CAPACITY);
+        buf.remove_front(2);
+        assert_eq!(&buf[..2], &[3, 4]);
+        assert_eq!(buf.len(), INLINE_CAPACITY - 2);
+
+        buf.extend_from_slice(&[5, 6, 7]);
+        assert!(buf.capacity() > INLINE_CAPACITY);
+        assert_eq!(&buf[buf.len() - 4..], &[4, 5, 6, 7]);
+        buf.clear();
+        assert!(buf.capacity() > INLINE_CAPACITY);
+
+        let vec = vec![1, 2, 3];
+        let mut buf = SmallBuf::from_vec(vec);
+        buf.remove_front(1);
+        assert_eq!(buf.into_vec(), vec![2, 3]);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::common::{BitDepth, ColorType, Info};
+use alloc::{vec, vec::Vec};
+
+/// The number of bits per channel that distinguish the colors of the histogram.
+const HISTOGRAM_BITS: u32 = 4;
+const HISTOGRAM_LEN: usize = 1 << (3 * HISTOGRAM_BITS);
+
+/// Statistics on the colors of a frame, accumulated while its rows are decoded.
+///
+/// They are computed from the pixels of the image as stored, independent of the output
+/// transformations, so that a placeholder color or a palette preview is available without
+/// another pass over the decoded frame. Enable them with `Decoder::set_color_statistics`.
+///
+/// ```
+/// let mut decoder = png::Decoder::new(std::fs::File::open("tests/pngsuite/basn2c08.png")?);
+/// decoder.set_color_statistics(true);
+/// let mut reader = decoder.read_info()?;
+/// let mut buf = vec![0; reader.output_buffer_size()];
+/// reader.next_frame(&mut buf)?;
+///
+/// let statistics = reader.color_statistics().unwrap();
+/// assert_eq!(statistics.pixels(), 32 * 32);
+/// let placeholder = statistics.average().unwrap();
+/// let (dominant, count) = statistics.dominant_colors(1)[0];
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ColorStatistics {
+    pixels: u64,
+    /// The sum of the alpha samples and of the color samples weighted by them.
+    alpha: u64,
+    weighted: [u64; 3],
+    /// The number of visible pixels and the sum of their colors per histogram bucket.
+    buckets: Vec<(u64, [u64; 3])>,
+}
+
+impl ColorStatistics {
+    pub(crate) fn new() -> ColorStatistics {
+        ColorStatistics {
+            pixels: 0,
+            alpha: 0,
+            weighted: [0; 3],
+            buckets: vec![(0, [0; 3]); HISTOGRAM_LEN],
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pixels = 0;
+        self.alpha = 0;
+        self.weighted = [0; 3];
+        for bucket in self.buckets.iter_mut() {
+            *bucket = (0, [0; 3]);
+        }
+    }
+
+    /// The number of pixels that were decoded.
+    pub fn pixels(&self) -> u64 {
+        self.pixels
+    }
+
+    /// The average color in 8-bit RGBA.
+    ///
+    /// The colors are weighted by their alpha, so that invisible pixels do not shift the color,
+    /// while the alpha is the average over all pixels. Returns `None` if no pixel was decoded.
+    pub fn average(&self) -> Option<[u8; 4]> {
+        if self.pixels == 0 {
+            return None;
+        }
+        let alpha = (self.alpha / self.pixels) as u8;
+        if self.alpha == 0 {
+            return Some([0, 0, 0, alpha]);
+        }
+        let [r, g, b] = self.weighted;
+        let average = |sum: u64| (sum / self.alpha) as u8;
+        Some([average(r), average(g), average(b), alpha])
+    }
+
+    /// The number of visible pixels per color, in buckets of the 4 most significant bits of each
+    /// channel.
+    ///
+    /// The bucket of a color is at index `(r >> 4) << 8 | (g >> 4) << 4 | b >> 4`. Completely
+    /// transparent pixels are not counted.
+    pub fn histogram(&self) -> Vec<u64> {
+        self.buckets.iter().map(|&(count, _)| count).collect()
+    }
+
+    /// The at most `n` most frequent colors of the histogram and their number of pixels.
+    ///
+    /// Each color is the average of the pixels in its bucket. They are ordered by decreasing
+    /// frequency.
+    pub fn dominant_colors(&self, n: usize) -> Vec<([u8; 3], u64)> {
+        let mut colors: Vec<_> = self
+            .buckets
+            .iter()
+            .filter(|&&(count, _)| count > 0)
+            .map(|&(count, [r, g, b])| {
+                let average = |sum: u64| (sum / count) as u8;
+                ([average(r), average(g), average(b)], count)
+            })
+            .collect();
+        // Ties are broken by color, to be independent of the order of the buckets.
+        colors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        colors.truncate(n);
+        colors
+    }
+
+    /// Add the first `width` pixels of an unfiltered `row` of the image described by `info`.
+    pub(crate) fn add_row(&mut self, row: &[u8], width: u32, info: &Info<'_>) {
+        let trns = info.trns.as_ref().map(|trns| &trns[..]);
+        let palette = info
+            .palette
+            .as_ref()
+            .map_or(&[][..], |palette| &palette[..]);
+        let width = width as usize;
+        match (info.color_type, info.bit_depth) {
+            (color, depth) if (depth as u8) < 8 => {
+                let bits = depth as usize;
+                let max = (1 << bits) - 1;
+                for x in 0..width {
+                    let shift = 8 - bits * (x % (8 / bits) + 1);
+                    let sample = (row[x * bits / 8] >> shift) & max;
+                    if color == ColorType::Indexed {
+                        self.add_indexed(sample, palette, trns);
+                    } else {
+                        let alpha = if trns.map_or(false, |trns| trns[0] == sample) {
+                            0
+                        } else {
+                            0xff
+                        };
+                        let gray = (u16::from(sample) * 255 / u16::from(max)) as u8;
+                        self.add([gray, gray, gray, alpha]);
+                    }
+                }
+            }
+            (ColorType::Indexed, _) => {
+                for &index in &row[..width] {
+                    self.add_indexed(index, palette, trns);
+                }
+            }
+            (color, depth) => {
+                let sample = if depth == BitDepth::Sixteen { 2 } else { 1 };
+                let pixel = color.samples() * sample;
+                for pixel in row[..width * pixel].chunks(pixel) {
+                    // The high byte of each sample.
+                    let at = |channel: usize| pixel[channel * sample];
+                    let transparent = trns == Some(pixel);
+                    let opaque = if transparent { 0 } else { 0xff };
+                    let rgba = match color {
+                        ColorType::Grayscale => [at(0), at(0), at(0), opaque],
+                        ColorType::GrayscaleAlpha => [at(0), at(0), at(0), at(1)],
+                        ColorType::Rgb => [at(0), at(1), at(2), opaque],
+                        _ => [at(0), at(1), at(2), at(3)],
+                    };
+                    self.add(rgba);
+                }
+            }
+        }
+    }
+
+    fn add_indexed(&mut self, index: u8, palette: &[u8], trns: Option<&[u8]>) {
+        let index = usize::from(index);
+        let alpha = trns
+            .and_then(|trns| trns.get(index))
+            .cloned()
+            .unwrap_or(0xff);
+        match palette.get(3 * index..3 * index + 3) {
+            Some(rgb) => self.add([rgb[0], rgb[1], rgb[2], alpha]),
+            // Indices beyond the palette are an error when expanding the image.
+            None => self.add([0, 0, 0, alpha]),
+        }
+    }
+
+    fn add(&mut self, [r, g, b, a]: [u8; 4]) {
+        self.pixels += 1;
+        self.alpha += u64::from(a);
+        for (sum, &sample) in self.weighted.iter_mut().zip(&[r, g, b]) {
+            *sum += u64::from(sample) * u64::from(a);
+        }
+        if a == 0 {
+            return;
+        }
+        let shift = 8 - HISTOGRAM_BITS;
+        let index = (usize::from(r) >> shift) << (2 * HISTOGRAM_BITS)
+            | (usize::from(g) >> shift) << HISTOGRAM_BITS
+            | usize::from(b) >> shift;
+        let bucket = &mut self.buckets[index];
+        bucket.0 += 1;
+        for (sum, &sample) in bucket.1.iter_mut().zip(&[r, g, b]) {
+            *sum += u64::from(sample);
+        }
+    }
+}
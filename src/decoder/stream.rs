@@ -1,21 +1,31 @@
 extern crate crc32fast;
 
-use std::convert::From;
-use std::default::Default;
-use std::error;
-use std::fmt;
-use std::io;
-use std::{borrow::Cow, cmp::min};
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::cmp::min;
+use core::convert::From;
+use core::default::Default;
+use core::fmt;
+use core::mem;
+use core::str;
+#[cfg(feature = "std")]
+use std::{error, io};
 
 use crc32fast::Hasher as Crc32;
 
+use super::inflate::InflateError;
+use super::smallbuf::SmallBuf;
 use super::zlib::ZlibStream;
+use super::Limits;
 use crate::chunk::{self, ChunkType, IDAT, IEND, IHDR};
 use crate::common::{
-    AnimationControl, BitDepth, BlendOp, ColorType, DisposeOp, FrameControl, Info, ParameterError,
-    PixelDimensions, ScaledFloat, SourceChromaticities, Unit,
+    AnimationControl, BackgroundColor, BitDepth, BlendOp, ChunkLocation, ColorType, DisposeOp,
+    FrameControl, ITXtChunk, Info, ParameterError, ParameterErrorKind, PixelDimensions, PlayCount,
+    RetainedChunk, ScaledFloat, SourceChromaticities, SuggestedPalette, SuggestedPaletteEntry,
+    Unit,
 };
 use crate::traits::ReadBytesExt;
+#[cfg(not(feature = "std"))]
+use crate::traits::UnexpectedEof;
 
 /// TODO check if these size are reasonable
 pub const CHUNCK_BUFFER_SIZE: usize = 32 * 1024;
@@ -26,12 +36,51 @@ pub const CHUNCK_BUFFER_SIZE: usize = 32 * 1024;
 /// be used to detect that build.
 const CHECKSUM_DISABLED: bool = cfg!(fuzzing);
 
+/// How the checksums of chunks are treated while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcPolicy {
+    /// Compute the checksum of every chunk and fail on any mismatch. This is the default.
+    Verify,
+    /// Only verify the checksums of critical chunks.
+    ///
+    /// The checksums of ancillary chunks are not computed and mismatches are ignored.
+    SkipAncillary,
+    /// Do not compute or verify any checksum.
+    ///
+    /// This is useful for trusted input or to salvage images whose data is otherwise intact.
+    Ignore,
+}
+
+impl Default for CrcPolicy {
+    fn default() -> CrcPolicy {
+        CrcPolicy::Verify
+    }
+}
+
+/// How invalid UTF-8 and malformed `iTXt` chunks are treated while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Replace invalid sequences and mark the chunk, keeping its raw bytes, and skip malformed
+    /// `iTXt` chunks. This is the default.
+    Lossy,
+    /// Fail decoding with an error, also for malformed `iTXt` chunks.
+    Strict,
+}
+
+impl Default for Utf8Policy {
+    fn default() -> Utf8Policy {
+        Utf8Policy::Lossy
+    }
+}
+
 #[derive(Debug)]
 enum U32Value {
     // CHUNKS
     Length,
     Type(u32),
     Crc(ChunkType),
+    /// The checksum whose verification was already reported.
+    CheckedCrc(ChunkType),
 }
 
 #[derive(Debug)]
@@ -64,6 +113,21 @@ pub enum Decoded {
     /// not that no additional IDAT chunk follows.
     ImageDataFlushed,
     PartialChunk(ChunkType),
+    /// The last bytes consumed by the call were data of a chunk, as many as given.
+    ///
+    /// The data is not copied, it is at the end of the input that was consumed. This is only
+    /// reported when enabled with `StreamingDecoder::set_chunk_events`.
+    ChunkData(ChunkType, usize),
+    /// The checksum of a chunk matched its data.
+    ///
+    /// This is only reported when enabled with `StreamingDecoder::set_chunk_events` and if the
+    /// checksum of the chunk is verified. It precedes the `ChunkComplete` of the chunk.
+    ChunkCrcOk(ChunkType),
+    /// The checksum of a chunk, stored and computed, did not match its data.
+    ///
+    /// This replaces the error for the mismatch when reported, see `ChunkCrcOk`. The chunk was
+    /// already decoded, the decoder continues with its `ChunkComplete` if called again.
+    ChunkCrcError(ChunkType, u32, u32),
     ImageEnd,
 }
 
@@ -75,13 +139,19 @@ pub enum Decoded {
 #[derive(Debug)]
 pub enum DecodingError {
     /// An error in IO of the underlying reader.
+    ///
+    /// The error is passed on as it was returned by the reader. In particular, a decoder whose
+    /// reader failed with `WouldBlock` or `Interrupted` can be called again once the reader is
+    /// ready.
+    ///
+    /// This requires the `std` feature.
+    #[cfg(feature = "std")]
     IoError(io::Error),
     /// The input image was not a valid PNG.
     ///
     /// There isn't a lot that can be done here, except if the program itself was responsible for
-    /// creating this image then investigate the generator. This is internally implemented with a
-    /// large Enum. If You are interested in accessing some of the more exact information on the
-    /// variant then we can discuss in an issue.
+    /// creating this image then investigate the generator. The `FormatErrorKind` of the error
+    /// classifies the problem, and where known the chunk and byte offset of it are reported.
     Format(FormatError),
     /// An interface was used incorrectly.
     ///
@@ -107,19 +177,195 @@ pub enum DecodingError {
     ///
     /// Note that this is a best-effort basis.
     LimitsExceeded,
+    /// The image data ended before the frame was complete.
+    ///
+    /// This is only returned when decoding truncated images was allowed with
+    /// `Decoder::set_allow_truncated`. The rows decoded until then have been written to the
+    /// output buffer, which is otherwise left untouched.
+    Truncated {
+        /// The number of complete rows that were decoded. For interlaced images this counts the
+        /// rows of all passes.
+        rows_decoded: u32,
+        /// Whether the data ended after the last decoded row or within the following one.
+        end: ShortImageData,
+    },
 }
 
+/// Where the image data of a frame ended, when it ended before the last row.
+///
+/// A stream that ends exactly after a row was often written by an encoder that stopped early,
+/// while data that ends within a row was more likely cut off in transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShortImageData {
+    /// The data ended after a complete row.
+    AtRowBoundary,
+    /// The data ended within a row, whose bytes are discarded.
+    WithinRow,
+}
+
+impl DecodingError {
+    /// Whether this error was caused by the input ending prematurely.
+    pub(crate) fn is_truncation(&self) -> bool {
+        match self {
+            #[cfg(feature = "std")]
+            DecodingError::IoError(err) => err.kind() == io::ErrorKind::UnexpectedEof,
+            DecodingError::Format(FormatError { inner, .. }) => match inner {
+                FormatErrorInner::UnexpectedEof
+                | FormatErrorInner::UnexpectedEndOfChunk
+                | FormatErrorInner::NoMoreImageData { .. } => true,
+                _ => false,
+            },
+            DecodingError::Truncated { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Where the image data ended, if this error was caused by a frame with too few rows.
+    ///
+    /// This is the case when the compressed image data completed before it inflated to all rows
+    /// of the frame, and for frames that were decoded as far as possible with
+    /// `Decoder::set_allow_truncated`.
+    pub fn short_image_data(&self) -> Option<ShortImageData> {
+        match self {
+            DecodingError::Format(FormatError {
+                inner: FormatErrorInner::NoMoreImageData { end },
+                ..
+            }) => Some(*end),
+            DecodingError::Truncated { end, .. } => Some(*end),
+            _ => None,
+        }
+    }
+}
+
+/// An error in the input data.
+///
+/// Its `kind` allows handling errors programmatically, while `Display` describes them in more
+/// detail. Errors found by the `StreamingDecoder` also report the chunk and the byte of the input
+/// at which they occurred.
 #[derive(Debug)]
 pub struct FormatError {
     inner: FormatErrorInner,
+    /// The chunk that was decoded when the error occurred.
+    chunk: Option<ChunkType>,
+    /// The offset of the byte that was decoded, from the start of the input.
+    offset: Option<u64>,
+}
+
+impl FormatError {
+    /// The kind of the error.
+    pub fn kind(&self) -> FormatErrorKind {
+        use FormatErrorInner::*;
+        match self.inner {
+            InvalidSignature => FormatErrorKind::InvalidSignature,
+            CrcMismatch {
+                crc_val,
+                crc_sum,
+                chunk,
+            } => FormatErrorKind::CrcMismatch {
+                chunk,
+                expected: crc_val,
+                found: crc_sum,
+            },
+            UnexpectedEof | UnexpectedEndOfChunk => FormatErrorKind::UnexpectedEof,
+            InvalidChunkLength { .. } => FormatErrorKind::InvalidChunkLength,
+            MissingIhdr
+            | MissingImageData
+            | ChunkBeforeIhdr { .. }
+            | AfterIdat { .. }
+            | AfterPlte { .. }
+            | OutsidePlteIdat { .. }
+            | DuplicateChunk { .. }
+            | AfterIend { .. }
+            | NonConsecutiveIdat => FormatErrorKind::InvalidChunkOrder,
+            InvalidColorBitDepth { .. } | InvalidBitDepth(_) | InvalidColorType(_) => {
+                FormatErrorKind::UnsupportedColorTypeDepth
+            }
+            ShortPalette { .. }
+            | PaletteRequired
+            | ColorWithBadTrns(_)
+            | LongTransparency { .. }
+            | HistogramLength { .. }
+            | BackgroundLength { .. } => FormatErrorKind::InvalidPalette,
+            MissingFctl
+            | ApngOrder { .. }
+            | BadSubFrameBounds {}
+            | StreamHeaderMismatch
+            | InvalidDisposeOp(_)
+            | InvalidBlendOp(_) => FormatErrorKind::InvalidFrame,
+            InvalidUnit(_)
+            | InvalidSrgbRenderingIntent(_)
+            | MalformedSuggestedPalette
+            | InvalidDimensions
+            | UnknownCompressionMethod(_)
+            | UnknownFilterMethod(_)
+            | UnknownInterlaceMethod(_) => FormatErrorKind::InvalidValue,
+            ZlibCompressionMethod(_)
+            | ZlibWindowSize(_)
+            | ZlibHeaderCheck(_)
+            | ZlibPresetDictionary => FormatErrorKind::InvalidZlibHeader,
+            CorruptFlateStream { .. } | UnknownFilterType(_) | BadFilter(_) => {
+                FormatErrorKind::CorruptImageData
+            }
+            NoMoreImageData { end } => FormatErrorKind::ShortImageData(end),
+            MalformedText { .. } | InvalidUtf8Text { .. } => FormatErrorKind::InvalidText,
+        }
+    }
+
+    /// The chunk in which the error occurred.
+    ///
+    /// Errors of image data that spans several chunks report the chunk type of the data, errors
+    /// outside of chunks such as an invalid signature report none.
+    pub fn chunk(&self) -> Option<ChunkType> {
+        self.chunk
+    }
+
+    /// The offset in bytes from the start of the input at which the error occurred.
+    ///
+    /// This is not known for errors found while processing the decompressed image data.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+}
+
+/// The kind of a `FormatError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatErrorKind {
+    /// The input does not start with the PNG signature.
+    InvalidSignature,
+    /// The CRC stored after a chunk does not match the one computed from its data.
+    CrcMismatch {
+        chunk: ChunkType,
+        expected: u32,
+        found: u32,
+    },
+    /// The input ended before the end of the image.
+    UnexpectedEof,
+    /// A chunk is longer than the 2^31 - 1 bytes allowed.
+    InvalidChunkLength,
+    /// A chunk is missing, repeated or in a position it may not appear in.
+    InvalidChunkOrder,
+    /// The header contains a color type or bit depth that is not defined, or not for each other.
+    UnsupportedColorTypeDepth,
+    /// The palette or the transparency chunk is missing or invalid for the image.
+    InvalidPalette,
+    /// The animation or the frames of the image are invalid.
+    InvalidFrame,
+    /// A field of a chunk has a value that is not defined.
+    InvalidValue,
+    /// The zlib header of the image data is invalid.
+    InvalidZlibHeader,
+    /// The image data can not be decompressed or unfiltered.
+    CorruptImageData,
+    /// The image data ended before the last row of a frame.
+    ShortImageData(ShortImageData),
+    /// A textual chunk is malformed.
+    InvalidText,
 }
 
 #[derive(Debug)]
 pub(crate) enum FormatErrorInner {
     /// Bad framing.
     CrcMismatch {
-        /// bytes to skip to try to recover from this error
-        recover: usize,
         /// Stored CRC32 value
         crc_val: u32,
         /// Calculated CRC32 sum
@@ -132,6 +378,11 @@ pub(crate) enum FormatErrorInner {
     UnexpectedEof,
     /// End of file, while expecting more image data.
     UnexpectedEndOfChunk,
+    /// The length of a chunk exceeds the maximum of 2^31 - 1.
+    InvalidChunkLength {
+        kind: ChunkType,
+        length: u32,
+    },
     // Errors of chunk level ordering, missing etc.
     /// Ihdr must occur.
     MissingIhdr,
@@ -139,6 +390,8 @@ pub(crate) enum FormatErrorInner {
     MissingFctl,
     /// Image data that was indicated in IHDR or acTL is missing.
     MissingImageData,
+    /// An image following the first in a stream of images has a different header.
+    StreamHeaderMismatch,
     /// 4.3., Must be first.
     ChunkBeforeIhdr {
         kind: ChunkType,
@@ -152,15 +405,19 @@ pub(crate) enum FormatErrorInner {
         kind: ChunkType,
     },
     /// 4.3., some chunks must be between PLTE and IDAT.
-    // FIXME: why are we not using this?
     OutsidePlteIdat {
         kind: ChunkType,
     },
     /// 4.3., some chunks must be unique.
-    // FIXME: why are we not using this?
     DuplicateChunk {
         kind: ChunkType,
     },
+    /// 4.3., IEND must be last.
+    AfterIend {
+        kind: ChunkType,
+    },
+    /// 4.3., multiple IDAT chunks must be consecutive.
+    NonConsecutiveIdat,
     /// Specifically for fdat there is an embedded sequence number for chunks.
     ApngOrder {
         /// The sequence number in the chunk.
@@ -182,6 +439,25 @@ pub(crate) enum FormatErrorInner {
         depth: BitDepth,
     },
     ColorWithBadTrns(ColorType),
+    /// The transparency chunk has more entries than the palette or the color type allow.
+    LongTransparency {
+        len: usize,
+        max: usize,
+    },
+    /// The histogram does not have one entry for each entry of the palette.
+    HistogramLength {
+        len: usize,
+        expected: usize,
+    },
+    /// The background color does not have the length required by the color type.
+    BackgroundLength {
+        len: usize,
+        expected: usize,
+    },
+    /// The name, sample depth or length of the entries of a suggested palette is invalid.
+    MalformedSuggestedPalette,
+    /// The width or the height of the image is zero.
+    InvalidDimensions,
     InvalidBitDepth(u8),
     InvalidColorType(u8),
     InvalidDisposeOp(u8),
@@ -198,32 +474,72 @@ pub(crate) enum FormatErrorInner {
     // Errors specific to the IDAT/fDAT chunks.
     /// The compression of the data stream was faulty.
     CorruptFlateStream {
-        err: miniz_oxide::inflate::TINFLStatus,
+        err: InflateError,
     },
     /// The image data chunk was too short for the expected pixel count.
-    NoMoreImageData,
+    NoMoreImageData {
+        end: ShortImageData,
+    },
+    // Errors specific to textual chunks.
+    /// The structure of a textual chunk is invalid.
+    MalformedText {
+        kind: ChunkType,
+    },
+    /// A textual chunk contained invalid UTF-8, and the decoder is strict about it.
+    InvalidUtf8Text {
+        kind: ChunkType,
+    },
+    /// The zlib header of the image data names another compression method than deflate.
+    ZlibCompressionMethod(u8),
+    /// The zlib header of the image data declares a window larger than 32 KiB.
+    ZlibWindowSize(u8),
+    /// The check bits of the zlib header of the image data are wrong.
+    ZlibHeaderCheck(u16),
+    /// The zlib header of the image data requires a preset dictionary, which PNG does not allow.
+    ZlibPresetDictionary,
+    /// A row of the image data starts with an undefined filter type.
+    UnknownFilterType(u8),
     // TODO: strictly type this.
     /// Filtering of a row has failed.
     BadFilter(&'static str),
 }
 
+#[cfg(feature = "std")]
 impl error::Error for DecodingError {
-    fn cause(&self) -> Option<&(dyn error::Error + 'static)> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             DecodingError::IoError(err) => Some(err),
+            DecodingError::Format(err) => Some(err),
+            DecodingError::Parameter(err) => Some(err),
             _ => None,
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl error::Error for FormatError {}
+
 impl fmt::Display for DecodingError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         use self::DecodingError::*;
         match self {
+            #[cfg(feature = "std")]
             IoError(err) => write!(fmt, "{}", err),
             Parameter(desc) => write!(fmt, "{}", &desc),
             Format(desc) => write!(fmt, "{}", desc),
             LimitsExceeded => write!(fmt, "limits are exceeded"),
+            Truncated {
+                rows_decoded,
+                end: ShortImageData::AtRowBoundary,
+            } => write!(fmt, "image data is truncated after {} rows", rows_decoded),
+            Truncated {
+                rows_decoded,
+                end: ShortImageData::WithinRow,
+            } => write!(
+                fmt,
+                "image data is truncated within the row after {} rows",
+                rows_decoded
+            ),
         }
     }
 }
@@ -233,15 +549,21 @@ impl fmt::Display for FormatError {
         use FormatErrorInner::*;
         match &self.inner {
             CrcMismatch {
-                crc_val, crc_sum, ..
+                crc_val,
+                crc_sum,
+                chunk,
             } => write!(
                 fmt,
-                "CRC error: expected 0x{:x} have 0x{:x}",
-                crc_val, crc_sum
+                "CRC error: expected 0x{:x} have 0x{:x} while decoding {:?} chunk.",
+                crc_val, crc_sum, chunk
             ),
             MissingIhdr => write!(fmt, "IHDR chunk missing"),
             MissingFctl => write!(fmt, "fcTL chunk missing before fdAT chunk."),
             MissingImageData => write!(fmt, "IDAT or fDAT chunk is missing."),
+            StreamHeaderMismatch => write!(
+                fmt,
+                "Image in the stream has a different header than the first image."
+            ),
             ChunkBeforeIhdr { kind } => write!(fmt, "{:?} chunk appeared before IHDR chunk", kind),
             AfterIdat { kind } => write!(fmt, "Chunk {:?} is invalid after IDAT chunk.", kind),
             AfterPlte { kind } => write!(fmt, "Chunk {:?} is invalid after PLTE chunk.", kind),
@@ -251,6 +573,8 @@ impl fmt::Display for FormatError {
                 kind
             ),
             DuplicateChunk { kind } => write!(fmt, "Chunk {:?} must appear at most once.", kind),
+            AfterIend { kind } => write!(fmt, "Chunk {:?} is invalid after IEND chunk.", kind),
+            NonConsecutiveIdat => write!(fmt, "IDAT chunks must be consecutive."),
             ApngOrder { present, expected } => write!(
                 fmt,
                 "Sequence is not in order, expected #{} got #{}.",
@@ -272,7 +596,24 @@ impl fmt::Display for FormatError {
                 "Transparency chunk found for color type {:?}.",
                 color_type
             ),
-            InvalidBitDepth(nr) => write!(fmt, "Invalid dispose operation {}.", nr),
+            LongTransparency { len, max } => write!(
+                fmt,
+                "Transparency chunk of {} entries exceeds the maximum of {}.",
+                len, max
+            ),
+            HistogramLength { len, expected } => write!(
+                fmt,
+                "Histogram of {} entries does not match the {} entries of the palette.",
+                len, expected
+            ),
+            BackgroundLength { len, expected } => write!(
+                fmt,
+                "Background color of {} bytes, expected {} for the color type.",
+                len, expected
+            ),
+            MalformedSuggestedPalette => write!(fmt, "Malformed sPLT chunk."),
+            InvalidDimensions => write!(fmt, "Image width and height must not be zero."),
+            InvalidBitDepth(nr) => write!(fmt, "Invalid bit depth {}.", nr),
             InvalidColorType(nr) => write!(fmt, "Invalid color type {}.", nr),
             InvalidDisposeOp(nr) => write!(fmt, "Invalid dispose op {}.", nr),
             InvalidBlendOp(nr) => write!(fmt, "Invalid blend op {}.", nr),
@@ -285,20 +626,64 @@ impl fmt::Display for FormatError {
             InvalidSignature => write!(fmt, "Invalid PNG signature."),
             UnexpectedEof => write!(fmt, "Unexpected end of data before image end."),
             UnexpectedEndOfChunk => write!(fmt, "Unexpected end of data within a chunk."),
-            NoMoreImageData => write!(fmt, "IDAT or fDAT chunk is has not enough data for image."),
+            InvalidChunkLength { kind, length } => write!(
+                fmt,
+                "Chunk {:?} has a length of {} bytes, more than allowed.",
+                kind, length
+            ),
+            UnknownFilterType(nr) => write!(fmt, "Unknown filter type {} of a row.", nr),
+            ZlibCompressionMethod(nr) => {
+                write!(fmt, "Unknown compression method {} in zlib header.", nr)
+            }
+            ZlibWindowSize(nr) => write!(
+                fmt,
+                "Window size of 2^{} bytes in zlib header exceeds 32 KiB.",
+                u32::from(*nr) + 8
+            ),
+            ZlibHeaderCheck(header) => {
+                write!(fmt, "Check bits of zlib header {:#06x} are wrong.", header)
+            }
+            ZlibPresetDictionary => write!(fmt, "Zlib header requires a preset dictionary."),
+            NoMoreImageData {
+                end: ShortImageData::AtRowBoundary,
+            } => write!(fmt, "IDAT or fDAT chunk is has not enough data for image."),
+            NoMoreImageData {
+                end: ShortImageData::WithinRow,
+            } => write!(fmt, "IDAT or fDAT chunk ends within a row of the image."),
             // TODO: figure out a good way to print the error.
-            CorruptFlateStream { err: _ } => write!(fmt, "Corrupt deflate stream."),
+            CorruptFlateStream { err } => write!(fmt, "Corrupt deflate stream: {}.", err),
             BadFilter(message) => write!(fmt, "{}.", message),
+            MalformedText { kind } => write!(fmt, "Malformed {:?} chunk.", kind),
+            InvalidUtf8Text { kind } => write!(fmt, "Chunk {:?} contains invalid UTF-8.", kind),
+        }?;
+        let chunk = self
+            .chunk
+            .map(|chunk| String::from_utf8_lossy(&chunk.0).into_owned());
+        match (chunk, self.offset) {
+            (Some(chunk), Some(offset)) => write!(fmt, " (in {} chunk at byte {})", chunk, offset),
+            (None, Some(offset)) => write!(fmt, " (at byte {})", offset),
+            (Some(chunk), None) => write!(fmt, " (in {} chunk)", chunk),
+            (None, None) => Ok(()),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<io::Error> for DecodingError {
     fn from(err: io::Error) -> DecodingError {
         DecodingError::IoError(err)
     }
 }
 
+/// A chunk ended before its fields, which is reported as an error of the reader with the standard
+/// library.
+#[cfg(not(feature = "std"))]
+impl From<UnexpectedEof> for DecodingError {
+    fn from(_: UnexpectedEof) -> DecodingError {
+        DecodingError::Format(FormatErrorInner::UnexpectedEndOfChunk.into())
+    }
+}
+
 impl From<FormatError> for DecodingError {
     fn from(err: FormatError) -> DecodingError {
         DecodingError::Format(err)
@@ -307,16 +692,34 @@ impl From<FormatError> for DecodingError {
 
 impl From<FormatErrorInner> for FormatError {
     fn from(inner: FormatErrorInner) -> Self {
-        FormatError { inner }
+        FormatError {
+            inner,
+            chunk: None,
+            offset: None,
+        }
+    }
+}
+
+impl From<ParameterError> for DecodingError {
+    fn from(err: ParameterError) -> DecodingError {
+        DecodingError::Parameter(err)
     }
 }
 
+/// Errors of the underlying reader are returned unchanged. Other errors are wrapped, with a kind
+/// of `UnexpectedEof` for truncated input, `InvalidData` for other format errors and
+/// `InvalidInput` for parameter errors, and can be recovered with `io::Error::into_inner`.
+#[cfg(feature = "std")]
 impl From<DecodingError> for io::Error {
     fn from(err: DecodingError) -> io::Error {
-        match err {
-            DecodingError::IoError(err) => err,
-            err => io::Error::new(io::ErrorKind::Other, err.to_string()),
-        }
+        let kind = match err {
+            DecodingError::IoError(err) => return err,
+            ref err if err.is_truncation() => io::ErrorKind::UnexpectedEof,
+            DecodingError::Format(_) => io::ErrorKind::InvalidData,
+            DecodingError::Parameter(_) => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
     }
 }
 
@@ -332,9 +735,93 @@ pub struct StreamingDecoder {
     current_seq_no: Option<u32>,
     /// Stores where in decoding an `fdAT` chunk we are.
     apng_seq_handled: bool,
+    /// Whether the current chunk is skipped, as text longer than `Limits::text_bytes`.
+    skip_chunk: bool,
+    have_plte: bool,
     have_idat: bool,
+    /// Limits on the size of the image and its chunks.
+    limits: Limits,
+    /// Which chunk checksums are verified.
+    crc_policy: CrcPolicy,
+    /// How invalid UTF-8 in textual chunks is handled.
+    utf8_policy: Utf8Policy,
+    /// Whether textual chunks are skipped instead of stored in the info.
+    ignore_text: bool,
+    /// Whether the ancillary chunks not represented by the info are kept in it.
+    retain_chunks: bool,
+    /// Whether the data and checksum verification of every chunk are reported.
+    chunk_events: bool,
+    /// Whether the image data is passed on as stored instead of decompressed.
+    compressed_image_data: bool,
+    /// Whether the Adler-32 checksum of the image data is ignored.
+    ignore_adler32: bool,
+    /// Whether image data with an invalid zlib header is decoded as raw deflate stream.
+    zlib_fallback: bool,
+    /// Whether the palette and the transparency of indexed images are not copied, because the
+    /// caller borrows them from the input instead.
+    pub(crate) borrow_palette: bool,
+    /// Whether image data is decompressed directly from the input instead of being buffered,
+    /// because the caller passes the unconsumed input again, as a slice or `BufRead` does.
+    pub(crate) direct_image_data: bool,
+    /// The number of bytes of image data buffered before they are decompressed.
+    chunk_buffer_size: usize,
+    /// The maximum number of bytes of image data decompressed by one call to `update`.
+    inflate_budget: usize,
+    /// The number of bytes consumed since the start of the input.
+    position: u64,
+    /// Whether the chunk ordering and other rules of the specification are enforced.
+    strict: bool,
+    /// The chunks seen so far, for the checks of strict mode.
+    order: ChunkOrder,
+    /// Whether violations of strict mode that allow to continue are collected.
+    collect_violations: bool,
+    /// The violations collected so far.
+    violations: Vec<FormatError>,
 }
 
+/// The chunks of the image that constrain which chunks may follow.
+#[derive(Default)]
+struct ChunkOrder {
+    /// Chunks that may appear once, and the first image data and end chunks.
+    seen: Vec<ChunkType>,
+    /// The type of the previous chunk.
+    previous: Option<ChunkType>,
+    /// The number of entries of the palette.
+    palette_entries: usize,
+}
+
+impl ChunkOrder {
+    /// Record a chunk that follows the previous ones.
+    fn record(&mut self, type_str: ChunkType, length: u32) {
+        let recorded = SINGLETON_CHUNKS.contains(&type_str) || type_str == IDAT;
+        if recorded && !self.seen.contains(&type_str) {
+            self.seen.push(type_str);
+        }
+        if type_str == chunk::PLTE {
+            self.palette_entries = length as usize / 3;
+        }
+        self.previous = Some(type_str);
+    }
+}
+
+/// Chunks that may appear at most once.
+const SINGLETON_CHUNKS: &[ChunkType] = &[
+    IHDR,
+    chunk::PLTE,
+    IEND,
+    chunk::acTL,
+    chunk::bKGD,
+    chunk::cHRM,
+    chunk::gAMA,
+    chunk::hIST,
+    chunk::iCCP,
+    chunk::pHYs,
+    chunk::sBIT,
+    chunk::sRGB,
+    chunk::tIME,
+    chunk::tRNS,
+];
+
 struct ChunkState {
     /// The type of the current chunk.
     /// Relevant for `IDAT` and `fdAT` which aggregate consecutive chunks of their own type.
@@ -347,13 +834,14 @@ struct ChunkState {
     remaining: u32,
 
     /// Non-decoded bytes in the chunk.
-    raw_bytes: Vec<u8>,
+    raw_bytes: SmallBuf,
 }
 
 impl StreamingDecoder {
     /// Creates a new StreamingDecoder
     ///
-    /// Allocates the internal buffers.
+    /// The internal buffers hold the chunks and image data of small images inline, they are only
+    /// allocated for larger ones.
     pub fn new() -> StreamingDecoder {
         StreamingDecoder {
             state: Some(State::Signature(0, [0; 7])),
@@ -362,7 +850,247 @@ impl StreamingDecoder {
             info: None,
             current_seq_no: None,
             apng_seq_handled: false,
+            skip_chunk: false,
+            have_plte: false,
             have_idat: false,
+            limits: Limits::default(),
+            crc_policy: CrcPolicy::default(),
+            utf8_policy: Utf8Policy::default(),
+            ignore_text: false,
+            retain_chunks: false,
+            chunk_events: false,
+            compressed_image_data: false,
+            ignore_adler32: false,
+            zlib_fallback: false,
+            borrow_palette: false,
+            direct_image_data: false,
+            chunk_buffer_size: CHUNCK_BUFFER_SIZE,
+            inflate_budget: usize::max_value(),
+            position: 0,
+            strict: false,
+            order: ChunkOrder::default(),
+            collect_violations: false,
+            violations: Vec::new(),
+        }
+    }
+
+    /// Limit the dimensions of the image and the length of the chunks that are accepted.
+    ///
+    /// The limits are kept when the decoder is reset.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Choose which chunk checksums are verified.
+    ///
+    /// The policy is kept when the decoder is reset.
+    pub fn set_crc_policy(&mut self, policy: CrcPolicy) {
+        self.crc_policy = policy;
+    }
+
+    /// Choose which chunk checksums are verified.
+    #[deprecated(note = "use `set_crc_policy` instead")]
+    pub fn set_crc_check(&mut self, policy: CrcPolicy) {
+        self.set_crc_policy(policy);
+    }
+
+    /// Choose how textual chunks with invalid UTF-8 and malformed `iTXt` chunks are handled.
+    ///
+    /// The policy is kept when the decoder is reset.
+    pub fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.utf8_policy = policy;
+    }
+
+    /// Skip textual chunks instead of decoding them into the info.
+    ///
+    /// This is kept when the decoder is reset.
+    pub fn set_ignore_text_chunks(&mut self, ignore: bool) {
+        self.ignore_text = ignore;
+    }
+
+    /// Keep the ancillary chunks that the encoder does not write from the info in
+    /// `Info::retained_chunks`.
+    ///
+    /// This is kept when the decoder is reset.
+    pub fn set_retain_chunks(&mut self, retain: bool) {
+        self.retain_chunks = retain;
+    }
+
+    /// Report the data of every chunk and the verification of their checksums.
+    ///
+    /// The decoder then returns `Decoded::ChunkData` for each piece of chunk data it consumes,
+    /// and `Decoded::ChunkCrcOk` or `Decoded::ChunkCrcError` for each verified checksum, so that
+    /// chunks the decoder does not know about can be handled while streaming. A mismatch of a
+    /// checksum is then no error. This is kept when the decoder is reset.
+    ///
+    /// ```
+    /// use png::{Decoded, StreamingDecoder};
+    /// let data = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+    /// let mut decoder = StreamingDecoder::new();
+    /// decoder.set_chunk_events(true);
+    /// let (mut input, mut image_data) = (&data[..], Vec::new());
+    /// let mut idat = Vec::new();
+    /// loop {
+    ///     let (consumed, event) = decoder.update(input, &mut image_data).unwrap();
+    ///     match event {
+    ///         Decoded::ChunkData(png::chunk::IDAT, len) => {
+    ///             idat.extend_from_slice(&input[consumed - len..consumed])
+    ///         }
+    ///         Decoded::ChunkCrcError(..) => panic!("corrupt chunk"),
+    ///         Decoded::ImageEnd => break,
+    ///         _ => {}
+    ///     }
+    ///     input = &input[consumed..];
+    /// }
+    /// assert_eq!(idat.len(), 0x5b);
+    /// ```
+    pub fn set_chunk_events(&mut self, enable: bool) {
+        self.chunk_events = enable;
+    }
+
+    /// Append the image data to `image_data` as stored, without decompressing it.
+    ///
+    /// The data of consecutive `IDAT` chunks, or of the `fdAT` chunks of a frame without their
+    /// sequence numbers, is then one zlib stream, which is neither decompressed nor verified.
+    /// Images can so be copied without decompressing and compressing them again. The setting
+    /// takes effect with the next chunk of image data and is kept when the decoder is reset.
+    pub fn set_compressed_image_data(&mut self, enable: bool) {
+        self.compressed_image_data = enable;
+    }
+
+    /// Enforce the rules of the specification that are otherwise tolerated.
+    ///
+    /// In strict mode IHDR must be the first chunk and IEND the last one, IDAT chunks must be
+    /// consecutive, chunks that may only appear once and chunks placed before or after PLTE and
+    /// IDAT in violation of the specification are errors. The transparency chunk must not have
+    /// more entries than the palette, and the image must not be empty. The mode is kept when the
+    /// decoder is reset.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Collect the violations of strict mode instead of stopping at the first one.
+    ///
+    /// Violations after which decoding can continue, as it would without strict mode, are
+    /// recorded in `violations` with their chunk and offset. Errors that make the rest of the
+    /// input undecodable are still returned. This has no effect unless strict mode is enabled
+    /// and is kept when the decoder is reset, while the violations are cleared.
+    pub fn set_collect_violations(&mut self, collect: bool) {
+        self.collect_violations = collect;
+    }
+
+    /// The violations of strict mode collected since the decoder was created or reset.
+    pub fn violations(&self) -> &[FormatError] {
+        &self.violations
+    }
+
+    /// The information on the image from the chunks decoded so far, once its header was read.
+    pub fn info(&self) -> Option<&Info<'static>> {
+        self.info.as_ref()
+    }
+
+    /// Set how many bytes of image data are buffered before they are decompressed.
+    ///
+    /// Smaller values report decoded image data sooner, which lowers the latency of progressive
+    /// decoding at the cost of more calls into the decompressor. All other chunks are buffered
+    /// as a whole, within the limits. The size is at least 4 bytes, the default is 32 KiB. It is
+    /// kept when the decoder is reset.
+    pub fn set_chunk_buffer_size(&mut self, size: usize) {
+        self.chunk_buffer_size = size.max(4);
+    }
+
+    /// The number of bytes of image data buffered before they are decompressed.
+    pub fn chunk_buffer_size(&self) -> usize {
+        self.chunk_buffer_size
+    }
+
+    /// Bound the work of a single call to `update`.
+    ///
+    /// Every call returns after at most one event, so it parses at most one chunk. Other chunks
+    /// than image data are read as a whole once complete, which is bounded by the limits. The
+    /// budget bounds the number of bytes of image data that a call decompresses. Otherwise a call
+    /// fills an internal buffer, of at least 32 KiB beyond the first 4 KiB of image data, and
+    /// flushing the end of the image data is only bounded by the compression ratio. A call that used up the budget returns
+    /// `Decoded::ImageData` and the next call continues where it stopped.
+    /// The data appended to `image_data` by one call is at most the budget, except for the call
+    /// finishing the image data, which releases up to 32 KiB that are held back for decompression.
+    ///
+    /// The budget is at least one byte and unbounded by default. It must be set before any image
+    /// data is decoded and is kept when the decoder is reset.
+    pub fn set_inflate_budget(&mut self, budget: usize) {
+        self.inflate_budget = budget.max(1);
+        self.inflater.set_budget(budget);
+    }
+
+    /// Output all image data that was read before the input ended prematurely.
+    pub(crate) fn salvage_image_data(
+        &mut self,
+        image_data: &mut SmallBuf,
+    ) -> Result<(), DecodingError> {
+        let raw_bytes = &self.current_chunk.raw_bytes[..];
+        // If the chunk buffer is still to be cleared, it only contains data decoded before.
+        let pending = match self.state {
+            Some(State::ReadChunk(IDAT, false)) => raw_bytes,
+            Some(State::ReadChunk(chunk::fdAT, false)) if self.apng_seq_handled => raw_bytes,
+            Some(State::ReadChunk(chunk::fdAT, false)) => raw_bytes.get(4..).unwrap_or(&[]),
+            Some(State::DecodeData(_, n)) => &raw_bytes[n..],
+            _ => &[],
+        };
+        self.inflater.salvage_data(pending, image_data)
+    }
+
+    /// Skip the verification of the Adler-32 checksum of the compressed image data.
+    ///
+    /// The image data is already protected by the checksums of the chunks, unless those are
+    /// ignored as well. This must be set before any image data is decoded and is kept when the
+    /// decoder is reset.
+    pub fn set_ignore_adler32(&mut self, ignore: bool) {
+        self.ignore_adler32 = ignore;
+        self.inflater.set_ignore_adler32(ignore);
+    }
+
+    /// Decode image data with an invalid zlib header as raw deflate stream.
+    ///
+    /// Some encoders write the compressed data without its zlib header, or damage the header.
+    /// Without this the header is an error. The same conditions as for `set_ignore_adler32`
+    /// apply, and the checksum is not verified for such streams.
+    pub fn set_zlib_fallback(&mut self, fallback: bool) {
+        self.zlib_fallback = fallback;
+        self.inflater.set_raw_fallback(fallback);
+    }
+
+    /// A new inflater for the image data, with the configuration of this decoder.
+    fn image_inflater(&self) -> ZlibStream {
+        let mut inflater = ZlibStream::new();
+        inflater.set_ignore_adler32(self.ignore_adler32);
+        inflater.set_raw_fallback(self.zlib_fallback);
+        inflater.set_budget(self.inflate_budget);
+        inflater
+    }
+
+    /// Take over the buffers of the chunk data and the decompressor of another decoder.
+    pub(crate) fn reuse_buffers(&mut self, other: StreamingDecoder) {
+        let mut raw_bytes = other.current_chunk.raw_bytes;
+        raw_bytes.clear();
+        self.current_chunk.raw_bytes = raw_bytes;
+        let mut inflater = other.inflater;
+        inflater.reset();
+        inflater.set_ignore_adler32(self.ignore_adler32);
+        inflater.set_raw_fallback(self.zlib_fallback);
+        inflater.set_budget(self.inflate_budget);
+        self.inflater = inflater;
+    }
+
+    /// Whether the checksum of a chunk of this type is computed and verified.
+    fn verify_crc(&self, type_str: ChunkType) -> bool {
+        if CHECKSUM_DISABLED {
+            return false;
+        }
+
+        match self.crc_policy {
+            CrcPolicy::Verify => true,
+            CrcPolicy::SkipAncillary => chunk::is_critical(type_str),
+            CrcPolicy::Ignore => false,
         }
     }
 
@@ -372,11 +1100,16 @@ impl StreamingDecoder {
         self.current_chunk.crc = Crc32::new();
         self.current_chunk.remaining = 0;
         self.current_chunk.raw_bytes.clear();
-        self.inflater = ZlibStream::new();
+        self.inflater = self.image_inflater();
         self.info = None;
         self.current_seq_no = None;
         self.apng_seq_handled = false;
+        self.skip_chunk = false;
+        self.have_plte = false;
         self.have_idat = false;
+        self.position = 0;
+        self.order = ChunkOrder::default();
+        self.violations.clear();
     }
 
     /// Low level StreamingDecoder interface.
@@ -384,29 +1117,88 @@ impl StreamingDecoder {
     /// Allows to stream partial data to the encoder. Returns a tuple containing the bytes that have
     /// been consumed from the input buffer and the current decoding result. If the decoded chunk
     /// was an image data chunk, it also appends the read data to `image_data`.
+    ///
+    /// After an error other than of the underlying reader the decoder can not continue, and any
+    /// further data is rejected with a parameter error until it is `reset`.
     pub fn update(
         &mut self,
-        mut buf: &[u8],
+        buf: &[u8],
         image_data: &mut Vec<u8>,
     ) -> Result<(usize, Decoded), DecodingError> {
+        let mut buffer = SmallBuf::from_vec(mem::replace(image_data, Vec::new()));
+        let result = self.update_buffer(buf, &mut buffer);
+        *image_data = buffer.into_vec();
+        result
+    }
+
+    /// Same as `update`, appending the image data to a buffer that may keep it inline.
+    pub(crate) fn update_buffer(
+        &mut self,
+        mut buf: &[u8],
+        image_data: &mut SmallBuf,
+    ) -> Result<(usize, Decoded), DecodingError> {
+        if self.state.is_none() && !buf.is_empty() {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::PolledAfterFatalError.into(),
+            ));
+        }
         let len = buf.len();
         while !buf.is_empty() && self.state.is_some() {
-            match self.next_state(buf, image_data) {
+            let chunk = self.state_chunk();
+            let collected = self.violations.len();
+            let result = self.next_state(buf, image_data);
+            let offset = self.position + (len - buf.len()) as u64;
+            for err in &mut self.violations[collected..] {
+                err.offset = Some(offset);
+            }
+            match result {
                 Ok((bytes, Decoded::Nothing)) => buf = &buf[bytes..],
                 Ok((bytes, result)) => {
                     buf = &buf[bytes..];
+                    self.position += (len - buf.len()) as u64;
                     return Ok((len - buf.len(), result));
                 }
-                Err(err) => return Err(err),
+                Err(DecodingError::Format(mut err)) => {
+                    err.chunk = err.chunk.or(chunk);
+                    err.offset = Some(offset);
+                    self.position += (len - buf.len()) as u64;
+                    return Err(DecodingError::Format(err));
+                }
+                Err(err) => {
+                    self.position += (len - buf.len()) as u64;
+                    return Err(err);
+                }
             }
         }
+        self.position += (len - buf.len()) as u64;
         Ok((len - buf.len(), Decoded::Nothing))
     }
 
-    fn next_state<'a>(
-        &'a mut self,
+    /// The chunk that the next byte of the input belongs to, for the context of errors.
+    fn state_chunk(&self) -> Option<ChunkType> {
+        use self::State::*;
+        match self.state {
+            None | Some(Signature(..)) => None,
+            Some(U32Byte3(U32Value::Crc(type_), _))
+            | Some(U32Byte2(U32Value::Crc(type_), _))
+            | Some(U32Byte1(U32Value::Crc(type_), _))
+            | Some(U32(U32Value::Crc(type_)))
+            | Some(U32Byte3(U32Value::CheckedCrc(type_), _))
+            | Some(ReadChunk(type_, _))
+            | Some(PartialChunk(type_))
+            | Some(DecodeData(type_, _)) => Some(type_),
+            // Between chunks the image data of the previous one is finished.
+            Some(_) if self.current_chunk.type_ != ChunkType([0; 4]) => {
+                Some(self.current_chunk.type_)
+            }
+            Some(_) => None,
+        }
+    }
+
+    fn next_state(
+        &mut self,
         buf: &[u8],
-        image_data: &mut Vec<u8>,
+        image_data: &mut SmallBuf,
     ) -> Result<(usize, Decoded), DecodingError> {
         use self::State::*;
 
@@ -440,7 +1232,8 @@ impl StreamingDecoder {
                 goto!(Signature(i + 1, signature))
             }
             Signature(_, signature)
-                if signature == [137, 80, 78, 71, 13, 10, 26] && current_byte == 10 =>
+                if signature[..] == chunk::SIGNATURE[..7]
+                    && current_byte == chunk::SIGNATURE[7] =>
             {
                 goto!(U32(U32Value::Length))
             }
@@ -463,8 +1256,17 @@ impl StreamingDecoder {
                             && (self.current_chunk.type_ == IDAT
                                 || self.current_chunk.type_ == chunk::fdAT)
                         {
+                            if !self.compressed_image_data
+                                && !self.inflater.finish_compressed_chunks(image_data)?
+                            {
+                                // Continue with the same byte once the budget is renewed.
+                                return goto!(
+                                    0,
+                                    U32Byte3(Type(length), val & !0xff),
+                                    emit Decoded::ImageData
+                                );
+                            }
                             self.current_chunk.type_ = type_str;
-                            self.inflater.finish_compressed_chunks(image_data)?;
                             self.inflater.reset();
                             return goto!(
                                 0,
@@ -472,6 +1274,34 @@ impl StreamingDecoder {
                                 emit Decoded::ImageDataFlushed
                             );
                         }
+                        if length > 0x7FFF_FFFF {
+                            let mut err = FormatError::from(FormatErrorInner::InvalidChunkLength {
+                                kind: type_str,
+                                length,
+                            });
+                            err.chunk = Some(type_str);
+                            return Err(DecodingError::Format(err));
+                        }
+                        self.skip_chunk = self.check_chunk_length(type_str, length)?;
+                        if self.strict {
+                            if let Some(inner) = self.chunk_order_violation(type_str, length) {
+                                self.violation(type_str, inner)?;
+                            }
+                            self.order.record(type_str, length);
+                        }
+                        if type_str == chunk::iTXt && !self.skip_chunk {
+                            // Textual chunks are parsed as a whole, their size is bounded by the
+                            // limits checked above.
+                            let raw_bytes = &mut self.current_chunk.raw_bytes;
+                            raw_bytes.clear();
+                            raw_bytes.reserve(length as usize);
+                        } else if type_str != IDAT && type_str != chunk::fdAT {
+                            // Other chunks are read in pieces of the buffer, which holds small
+                            // chunks inline and is grown for larger ones.
+                            let raw_bytes = &mut self.current_chunk.raw_bytes;
+                            raw_bytes.clear();
+                            raw_bytes.reserve(min(length as usize, CHUNCK_BUFFER_SIZE));
+                        }
                         self.current_chunk.type_ = type_str;
                         self.current_chunk.crc.reset();
                         self.current_chunk.crc.update(&type_str.0);
@@ -482,21 +1312,26 @@ impl StreamingDecoder {
                             emit Decoded::ChunkBegin(length, type_str)
                         )
                     }
+                    Crc(type_str) if self.chunk_events && self.verify_crc(type_str) => {
+                        let sum = self.current_chunk.crc.clone().finalize();
+                        let event = if val == sum {
+                            Decoded::ChunkCrcOk(type_str)
+                        } else {
+                            Decoded::ChunkCrcError(type_str, val, sum)
+                        };
+                        // Continue with the same byte to complete the chunk.
+                        goto!(0, U32Byte3(CheckedCrc(type_str), val & !0xff), emit event)
+                    }
+                    CheckedCrc(type_str) => {
+                        goto!(State::U32(U32Value::Length), emit chunk_end(type_str, val))
+                    }
                     Crc(type_str) => {
                         let sum = self.current_chunk.crc.clone().finalize();
-                        if CHECKSUM_DISABLED || val == sum {
-                            goto!(
-                                State::U32(U32Value::Length),
-                                emit if type_str == IEND {
-                                    Decoded::ImageEnd
-                                } else {
-                                    Decoded::ChunkComplete(val, type_str)
-                                }
-                            )
+                        if !self.verify_crc(type_str) || val == sum {
+                            goto!(State::U32(U32Value::Length), emit chunk_end(type_str, val))
                         } else {
                             Err(DecodingError::Format(
                                 FormatErrorInner::CrcMismatch {
-                                    recover: 1,
                                     crc_val: val,
                                     crc_sum: sum,
                                     chunk: type_str,
@@ -570,27 +1405,51 @@ impl StreamingDecoder {
                 if clear {
                     self.current_chunk.raw_bytes.clear();
                 }
+                let image_data_chunk =
+                    type_str == IDAT || (type_str == chunk::fdAT && self.apng_seq_handled);
+                if self.direct_image_data
+                    && !self.chunk_events
+                    && image_data_chunk
+                    && self.current_chunk.remaining > 0
+                    && self.current_chunk.raw_bytes.is_empty()
+                {
+                    return self.decode_input(type_str, buf, image_data);
+                }
                 if self.current_chunk.remaining > 0 {
+                    let verify_crc = self.verify_crc(type_str);
+                    let chunk_events = self.chunk_events;
                     let ChunkState {
                         crc,
                         remaining,
                         raw_bytes,
                         type_: _,
                     } = &mut self.current_chunk;
-                    let buf_avail = raw_bytes.capacity() - raw_bytes.len();
+                    let capacity = if type_str == IDAT || type_str == chunk::fdAT {
+                        self.chunk_buffer_size
+                    } else {
+                        raw_bytes.capacity()
+                    };
+                    let buf_avail = capacity.saturating_sub(raw_bytes.len());
                     let bytes_avail = min(buf.len(), buf_avail);
                     let n = min(*remaining, bytes_avail as u32);
                     if buf_avail == 0 {
                         goto!(0, PartialChunk(type_str))
                     } else {
                         let buf = &buf[..n as usize];
-                        crc.update(buf);
+                        if verify_crc {
+                            crc.update(buf);
+                        }
                         raw_bytes.extend_from_slice(buf);
                         *remaining -= n;
-                        if *remaining == 0 {
-                            goto!(n as usize, PartialChunk(type_str))
+                        let next = if *remaining == 0 {
+                            PartialChunk(type_str)
+                        } else {
+                            ReadChunk(type_str, false)
+                        };
+                        if chunk_events {
+                            goto!(n as usize, next, emit Decoded::ChunkData(type_str, n as usize))
                         } else {
-                            goto!(n as usize, ReadChunk(type_str, false))
+                            goto!(n as usize, next)
                         }
                     }
                 } else {
@@ -600,7 +1459,12 @@ impl StreamingDecoder {
             DecodeData(type_str, mut n) => {
                 let chunk_len = self.current_chunk.raw_bytes.len();
                 let chunk_data = &self.current_chunk.raw_bytes[n..];
-                let c = self.inflater.decompress(chunk_data, image_data)?;
+                let c = if self.compressed_image_data {
+                    image_data.extend_from_slice(chunk_data);
+                    chunk_data.len()
+                } else {
+                    self.inflater.decompress(chunk_data, image_data)?
+                };
                 n += c;
                 if n == chunk_len && c == 0 {
                     goto!(
@@ -619,6 +1483,136 @@ impl StreamingDecoder {
         }
     }
 
+    /// Decompress the image data of a chunk from the input, without buffering it first.
+    fn decode_input(
+        &mut self,
+        type_str: ChunkType,
+        buf: &[u8],
+        image_data: &mut SmallBuf,
+    ) -> Result<(usize, Decoded), DecodingError> {
+        let len = min(self.current_chunk.remaining as usize, buf.len());
+        let consumed = if self.compressed_image_data {
+            image_data.extend_from_slice(&buf[..len]);
+            len
+        } else {
+            self.inflater.decompress(&buf[..len], image_data)?
+        };
+        if self.verify_crc(type_str) {
+            self.current_chunk.crc.update(&buf[..consumed]);
+        }
+        self.current_chunk.remaining -= consumed as u32;
+        if type_str == IDAT {
+            self.have_idat = true;
+        }
+        self.state = Some(State::ReadChunk(type_str, false));
+        Ok((consumed, Decoded::ImageData))
+    }
+
+    /// Check the length of a chunk against the limits, returning whether it is skipped.
+    fn check_chunk_length(&self, type_str: ChunkType, length: u32) -> Result<bool, DecodingError> {
+        // The image data of animation frames is limited like `IDAT`, by the size of the frame.
+        if chunk::is_critical(type_str) || type_str == chunk::fdAT {
+            return Ok(false);
+        }
+        if length > self.limits.ancillary_chunk_bytes {
+            return Err(DecodingError::LimitsExceeded);
+        }
+        let text = type_str == chunk::tEXt || type_str == chunk::zTXt || type_str == chunk::iTXt;
+        Ok(text && length > self.limits.text_bytes)
+    }
+
+    /// The rule of strict mode that a chunk at this position violates, if any.
+    fn chunk_order_violation(&self, type_str: ChunkType, length: u32) -> Option<FormatErrorInner> {
+        use FormatErrorInner::*;
+        let order = &self.order;
+        let seen = |kind| order.seen.contains(&kind);
+        let kind = type_str;
+
+        if seen(IEND) {
+            return Some(AfterIend { kind });
+        }
+        if order.previous.is_none() && type_str != IHDR {
+            return Some(ChunkBeforeIhdr { kind });
+        }
+        if SINGLETON_CHUNKS.contains(&type_str) && seen(type_str) {
+            return Some(DuplicateChunk { kind });
+        }
+
+        let indexed = self
+            .info
+            .as_ref()
+            .map_or(false, |info| info.color_type == ColorType::Indexed);
+        match type_str {
+            IDAT if seen(IDAT) && order.previous != Some(IDAT) => return Some(NonConsecutiveIdat),
+            IDAT if indexed && !seen(chunk::PLTE) => return Some(PaletteRequired),
+            IEND if !seen(IDAT) => return Some(MissingImageData),
+            chunk::PLTE | chunk::pHYs | chunk::sPLT | chunk::acTL if seen(IDAT) => {
+                return Some(AfterIdat { kind })
+            }
+            chunk::cHRM | chunk::gAMA | chunk::iCCP | chunk::sBIT | chunk::sRGB => {
+                if seen(IDAT) {
+                    return Some(AfterIdat { kind });
+                } else if seen(chunk::PLTE) {
+                    return Some(AfterPlte { kind });
+                }
+            }
+            chunk::bKGD | chunk::hIST | chunk::tRNS => {
+                if seen(IDAT) {
+                    return Some(AfterIdat { kind });
+                } else if (indexed || type_str == chunk::hIST) && !seen(chunk::PLTE) {
+                    return Some(OutsidePlteIdat { kind });
+                }
+            }
+            _ => {}
+        }
+
+        if type_str == chunk::tRNS {
+            let max = match self.info.as_ref().map(|info| info.color_type) {
+                Some(ColorType::Grayscale) => 2,
+                Some(ColorType::Rgb) => 6,
+                Some(ColorType::Indexed) => order.palette_entries,
+                // Rejected when the chunk is parsed.
+                _ => core::usize::MAX,
+            };
+            if length as usize > max {
+                return Some(LongTransparency {
+                    len: length as usize,
+                    max,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Report a violation of strict mode in the chunk `type_str`.
+    ///
+    /// When violations are collected, those which lenient decoding tolerates are recorded and
+    /// decoding continues.
+    fn violation(
+        &mut self,
+        type_str: ChunkType,
+        inner: FormatErrorInner,
+    ) -> Result<(), DecodingError> {
+        let fatal = match type_str {
+            // Nothing can be decoded before the header.
+            _ if self.order.previous.is_none() => true,
+            // These chunks are rejected by the lenient checks after the image data as well.
+            chunk::acTL | chunk::cHRM | chunk::gAMA | chunk::iCCP | chunk::pHYs | chunk::sRGB => {
+                self.order.seen.contains(&IDAT)
+            }
+            _ => false,
+        };
+        let mut err = FormatError::from(inner);
+        err.chunk = Some(type_str);
+        if self.collect_violations && !fatal {
+            self.violations.push(err);
+            Ok(())
+        } else {
+            Err(DecodingError::Format(err))
+        }
+    }
+
     fn parse_chunk(&mut self, type_str: ChunkType) -> Result<Decoded, DecodingError> {
         self.state = Some(State::U32(U32Value::Crc(type_str)));
         if self.info.is_none() && type_str != IHDR {
@@ -626,6 +1620,25 @@ impl StreamingDecoder {
                 FormatErrorInner::ChunkBeforeIhdr { kind: type_str }.into(),
             ));
         }
+        if self.retain_chunks
+            && !self.skip_chunk
+            && !chunk::is_critical(type_str)
+            && !chunk::INFO_CHUNKS.contains(&type_str)
+        {
+            let info = self.info.as_mut().unwrap();
+            let location = if self.have_idat {
+                ChunkLocation::AfterIdat
+            } else if self.have_plte {
+                ChunkLocation::BeforeIdat
+            } else {
+                ChunkLocation::BeforePlte
+            };
+            info.retained_chunks.push(RetainedChunk {
+                chunk_type: type_str,
+                data: self.current_chunk.raw_bytes.to_vec(),
+                location,
+            });
+        }
         match match type_str {
             IHDR => self.parse_ihdr(),
             chunk::PLTE => self.parse_plte(),
@@ -637,6 +1650,10 @@ impl StreamingDecoder {
             chunk::cHRM => self.parse_chrm(),
             chunk::sRGB => self.parse_srgb(),
             chunk::iCCP => self.parse_iccp(),
+            chunk::iTXt if !self.ignore_text && !self.skip_chunk => self.parse_itxt(),
+            chunk::sPLT => self.parse_splt(),
+            chunk::hIST => self.parse_hist(),
+            chunk::bKGD => self.parse_bkgd(),
             _ => Ok(Decoded::PartialChunk(type_str)),
         } {
             Err(err) => {
@@ -648,7 +1665,7 @@ impl StreamingDecoder {
         }
     }
 
-    fn get_info_or_err(&self) -> Result<&Info, DecodingError> {
+    fn get_info_or_err(&self) -> Result<&Info<'static>, DecodingError> {
         self.info
             .as_ref()
             .ok_or_else(|| DecodingError::Format(FormatErrorInner::MissingIhdr.into()))
@@ -682,7 +1699,7 @@ impl StreamingDecoder {
             }
             0
         });
-        self.inflater = ZlibStream::new();
+        self.inflater = self.image_inflater();
         let fc = FrameControl {
             sequence_number: next_seq_no,
             width: buf.read_be()?,
@@ -714,7 +1731,7 @@ impl StreamingDecoder {
                 }
             },
         };
-        self.info.as_ref().unwrap().validate(&fc)?;
+        self.info.as_ref().unwrap().validate_frame_control(&fc)?;
         self.info.as_mut().unwrap().frame_control = Some(fc);
         Ok(Decoded::FrameControl(fc))
     }
@@ -728,7 +1745,7 @@ impl StreamingDecoder {
             let mut buf = &self.current_chunk.raw_bytes[..];
             let actl = AnimationControl {
                 num_frames: buf.read_be()?,
-                num_plays: buf.read_be()?,
+                num_plays: PlayCount::from_u32(buf.read_be()?),
             };
             self.info.as_mut().unwrap().animation_control = Some(actl);
             Ok(Decoded::AnimationControl(actl))
@@ -736,8 +1753,12 @@ impl StreamingDecoder {
     }
 
     fn parse_plte(&mut self) -> Result<Decoded, DecodingError> {
+        self.have_plte = true;
+        if self.borrow_palette {
+            return Ok(Decoded::Nothing);
+        }
         if let Some(info) = self.info.as_mut() {
-            info.palette = Some(Cow::Owned(self.current_chunk.raw_bytes.clone()))
+            info.palette = Some(Cow::Owned(self.current_chunk.raw_bytes.to_vec()))
         }
         Ok(Decoded::Nothing)
     }
@@ -747,7 +1768,10 @@ impl StreamingDecoder {
             let info = self.get_info_or_err()?;
             (info.color_type, info.bit_depth as u8)
         };
-        let mut vec = self.current_chunk.raw_bytes.clone();
+        if color_type == ColorType::Indexed && self.borrow_palette {
+            return Ok(Decoded::Nothing);
+        }
+        let mut vec = self.current_chunk.raw_bytes.to_vec();
         let len = vec.len();
         let info = match self.info {
             Some(ref mut info) => info,
@@ -800,6 +1824,120 @@ impl StreamingDecoder {
         }
     }
 
+    fn parse_hist(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat || (info.palette.is_none() && !self.borrow_palette) {
+            return Err(DecodingError::Format(
+                FormatErrorInner::OutsidePlteIdat { kind: chunk::hIST }.into(),
+            ));
+        }
+        let data = &self.current_chunk.raw_bytes;
+        // A borrowed palette is not known here, only the length of the entries is checked then.
+        let expected = match &info.palette {
+            Some(palette) => palette.len() / 3,
+            None => data.len() / 2,
+        };
+        if data.len() != 2 * expected {
+            return Err(DecodingError::Format(
+                FormatErrorInner::HistogramLength {
+                    len: data.len() / 2,
+                    expected,
+                }
+                .into(),
+            ));
+        }
+        let histogram = data
+            .chunks(2)
+            .map(|n| u16::from_be_bytes([n[0], n[1]]))
+            .collect();
+        info.histogram = Some(histogram);
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_bkgd(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                FormatErrorInner::AfterIdat { kind: chunk::bKGD }.into(),
+            ));
+        }
+        let data = &self.current_chunk.raw_bytes;
+        let sample = |i: usize| u16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+        let info = self.info.as_mut().unwrap();
+        let expected = match info.color_type {
+            ColorType::Indexed => 1,
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb | ColorType::Rgba => 6,
+        };
+        if data.len() != expected {
+            return Err(DecodingError::Format(
+                FormatErrorInner::BackgroundLength {
+                    len: data.len(),
+                    expected,
+                }
+                .into(),
+            ));
+        }
+        info.background = Some(match expected {
+            1 => BackgroundColor::Index(data[0]),
+            2 => BackgroundColor::Gray(sample(0)),
+            _ => BackgroundColor::Rgb(sample(0), sample(1), sample(2)),
+        });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_splt(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                FormatErrorInner::AfterIdat { kind: chunk::sPLT }.into(),
+            ));
+        }
+        let malformed =
+            || DecodingError::Format(FormatErrorInner::MalformedSuggestedPalette.into());
+        let data = &self.current_chunk.raw_bytes;
+        let null = data.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+        if null == 0 || null > 79 || null + 1 == data.len() {
+            return Err(malformed());
+        }
+        let name = data[..null].iter().map(|&b| char::from(b)).collect();
+        let (sample_depth, entry_len) = match data[null + 1] {
+            8 => (BitDepth::Eight, 6),
+            16 => (BitDepth::Sixteen, 10),
+            _ => return Err(malformed()),
+        };
+        let entries = &data[null + 2..];
+        if entries.len() % entry_len != 0 {
+            return Err(malformed());
+        }
+        let wide = sample_depth == BitDepth::Sixteen;
+        let entries = entries
+            .chunks(entry_len)
+            .map(|entry| {
+                let sample = |i: usize| {
+                    if wide {
+                        u16::from_be_bytes([entry[2 * i], entry[2 * i + 1]])
+                    } else {
+                        u16::from(entry[i])
+                    }
+                };
+                let frequency = &entry[entry_len - 2..];
+                SuggestedPaletteEntry {
+                    red: sample(0),
+                    green: sample(1),
+                    blue: sample(2),
+                    alpha: sample(3),
+                    frequency: u16::from_be_bytes([frequency[0], frequency[1]]),
+                }
+            })
+            .collect();
+        let palette = SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        };
+        self.info.as_mut().unwrap().suggested_palettes.push(palette);
+        Ok(Decoded::Nothing)
+    }
+
     fn parse_phys(&mut self) -> Result<Decoded, DecodingError> {
         if self.have_idat {
             Err(DecodingError::Format(
@@ -940,22 +2078,103 @@ impl StreamingDecoder {
                 }
             }
 
-            let mut profile = Vec::new();
+            let mut profile = SmallBuf::from_vec(Vec::new());
             let mut inflater = ZlibStream::new();
             while !buf.is_empty() {
                 let consumed_bytes = inflater.decompress(buf, &mut profile)?;
-                if profile.len() > 8000000 {
-                    // TODO: this should use Limits.bytes
+                if profile.len() > self.limits.bytes {
                     return Err(DecodingError::LimitsExceeded);
                 }
                 buf = &buf[consumed_bytes..];
             }
 
-            self.info.as_mut().unwrap().icc_profile = Some(Cow::Owned(profile));
+            self.info.as_mut().unwrap().icc_profile = Some(Cow::Owned(profile.into_vec()));
             Ok(Decoded::Nothing)
         }
     }
 
+    fn parse_itxt(&mut self) -> Result<Decoded, DecodingError> {
+        let itxt = match self.decode_itxt() {
+            Ok(itxt) => itxt,
+            // The text is skipped like a chunk longer than the limit.
+            Err(DecodingError::LimitsExceeded) => return Ok(Decoded::Nothing),
+            Err(DecodingError::Format(_)) if self.utf8_policy == Utf8Policy::Lossy => {
+                return Ok(Decoded::Nothing)
+            }
+            Err(err) => return Err(err),
+        };
+        self.info.as_mut().unwrap().utf8_text.push(itxt);
+        Ok(Decoded::Nothing)
+    }
+
+    fn decode_itxt(&self) -> Result<ITXtChunk, DecodingError> {
+        let malformed =
+            || DecodingError::Format(FormatErrorInner::MalformedText { kind: chunk::iTXt }.into());
+
+        let buf = &self.current_chunk.raw_bytes[..];
+        let mut fields = buf.splitn(2, |&b| b == 0);
+        let keyword = fields.next().ok_or_else(malformed)?;
+        let rest = fields.next().ok_or_else(malformed)?;
+        if keyword.is_empty() || keyword.len() > 79 || rest.len() < 2 {
+            return Err(malformed());
+        }
+
+        let compressed = match rest[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(malformed()),
+        };
+        // The compression method is meaningless for uncompressed text.
+        if compressed && rest[1] != 0 {
+            return Err(DecodingError::Format(
+                FormatErrorInner::UnknownCompressionMethod(rest[1]).into(),
+            ));
+        }
+
+        let mut fields = rest[2..].splitn(3, |&b| b == 0);
+        let language_tag = fields.next().ok_or_else(malformed)?;
+        let translated_keyword = fields.next().ok_or_else(malformed)?;
+        let text = fields.next().ok_or_else(malformed)?;
+
+        let raw_text = if compressed {
+            let limit = self.limits.text_bytes as usize;
+            match miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(text, limit) {
+                Ok(text) => text,
+                Err(miniz_oxide::inflate::TINFLStatus::HasMoreOutput) => {
+                    return Err(DecodingError::LimitsExceeded)
+                }
+                Err(err) => {
+                    return Err(DecodingError::Format(
+                        FormatErrorInner::CorruptFlateStream {
+                            err: InflateError::Miniz(err),
+                        }
+                        .into(),
+                    ))
+                }
+            }
+        } else {
+            text.to_vec()
+        };
+
+        let invalid_utf8 =
+            str::from_utf8(translated_keyword).is_err() || str::from_utf8(&raw_text).is_err();
+        if invalid_utf8 && self.utf8_policy == Utf8Policy::Strict {
+            return Err(DecodingError::Format(
+                FormatErrorInner::InvalidUtf8Text { kind: chunk::iTXt }.into(),
+            ));
+        }
+
+        Ok(ITXtChunk {
+            keyword: keyword.iter().map(|&b| char::from(b)).collect(),
+            compressed,
+            language_tag: String::from_utf8_lossy(language_tag).into_owned(),
+            translated_keyword: String::from_utf8_lossy(translated_keyword).into_owned(),
+            text: String::from_utf8_lossy(&raw_text).into_owned(),
+            raw_text,
+            invalid_utf8,
+        })
+    }
+
     fn parse_ihdr(&mut self) -> Result<Decoded, DecodingError> {
         // TODO: check if color/bit depths combination is valid
         let mut buf = &self.current_chunk.raw_bytes[..];
@@ -1006,6 +2225,13 @@ impl StreamingDecoder {
                 ))
             }
         };
+        if self.strict && (width == 0 || height == 0) {
+            self.violation(IHDR, FormatErrorInner::InvalidDimensions)?;
+        }
+        if width > self.limits.width || height > self.limits.height {
+            return Err(DecodingError::LimitsExceeded);
+        }
+
         let mut info = Info::default();
 
         info.width = width;
@@ -1021,7 +2247,7 @@ impl StreamingDecoder {
 }
 
 impl Info<'_> {
-    fn validate(&self, fc: &FrameControl) -> Result<(), DecodingError> {
+    fn validate_frame_control(&self, fc: &FrameControl) -> Result<(), DecodingError> {
         // Validate mathematically: fc.width + fc.x_offset <= self.width
         let in_x_bounds = Some(fc.width) <= self.width.checked_sub(fc.x_offset);
         // Validate mathematically: fc.height + fc.y_offset <= self.height
@@ -1050,17 +2276,258 @@ impl Default for ChunkState {
             type_: ChunkType([0; 4]),
             crc: Crc32::new(),
             remaining: 0,
-            raw_bytes: Vec::with_capacity(CHUNCK_BUFFER_SIZE),
+            raw_bytes: SmallBuf::new(),
         }
     }
 }
 
+/// The event for the end of a chunk with the checksum `crc`.
+fn chunk_end(type_str: ChunkType, crc: u32) -> Decoded {
+    if type_str == IEND {
+        Decoded::ImageEnd
+    } else {
+        Decoded::ChunkComplete(crc, type_str)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::ScaledFloat;
     use super::SourceChromaticities;
+    use super::Utf8Policy;
+    use super::{Decoded, StreamingDecoder, IDAT, IHDR};
     use std::fs::File;
 
+    #[test]
+    fn image_utf8_text() {
+        let mut image = std::fs::read("tests/pngsuite/ctjn0g04.png").unwrap();
+        let decode = |image: &[u8], policy| {
+            let mut decoder = crate::Decoder::new(image);
+            decoder.set_utf8_policy(policy);
+            decoder
+                .read_info()
+                .map(|reader| reader.info().utf8_text.clone())
+        };
+
+        let text = decode(&image, Utf8Policy::Strict).unwrap();
+        assert_eq!(text.len(), 6);
+        assert_eq!(text[0].keyword, "Title");
+        assert_eq!(text[0].language_tag, "ja");
+        assert_eq!(text[0].translated_keyword, "タイトル");
+        assert_eq!(text[0].text, "PngSuite");
+        assert!(!text[0].compressed);
+        assert!(text.iter().all(|itxt| !itxt.invalid_utf8));
+
+        // Corrupt the first byte of the text of the first `iTXt` chunk, which starts at 49.
+        image[81] = 0xff;
+        let crc = crc32fast::hash(&image[53..89]);
+        image[89..93].copy_from_slice(&crc.to_be_bytes());
+
+        let text = decode(&image, Utf8Policy::Lossy).unwrap();
+        assert!(text[0].invalid_utf8);
+        assert_eq!(text[0].text, "\u{FFFD}ngSuite");
+        assert_eq!(text[0].raw_text, b"\xffngSuite");
+        assert!(decode(&image, Utf8Policy::Strict).is_err());
+    }
+
+    #[test]
+    fn ignore_text_chunks() {
+        let mut image = std::fs::read("tests/pngsuite/ctjn0g04.png").unwrap();
+        // Invalid UTF-8 is not noticed in a skipped chunk, see `image_utf8_text`.
+        image[81] = 0xff;
+        let crc = crc32fast::hash(&image[53..89]);
+        image[89..93].copy_from_slice(&crc.to_be_bytes());
+
+        let mut decoder = crate::Decoder::new(&image[..]);
+        decoder.set_utf8_policy(Utf8Policy::Strict);
+        decoder.set_ignore_text_chunks(true);
+        let mut reader = decoder.read_info().unwrap();
+        assert!(reader.info().utf8_text.is_empty());
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+    }
+
+    #[test]
+    fn skipped_itxt() {
+        let long = deflate::deflate_bytes_zlib(&[b'a'; 2000]);
+        let build = |chunks: &[&[u8]]| {
+            let mut image = vec![];
+            {
+                let encoder = crate::Encoder::new(&mut image, 1, 1);
+                let mut writer = encoder.write_header().unwrap();
+                for &data in chunks {
+                    writer.write_chunk(crate::chunk::iTXt, data).unwrap();
+                }
+                writer
+                    .write_chunk(crate::chunk::iTXt, b"Title\0\0\0\0\0Kept")
+                    .unwrap();
+                writer.write_image_data(&[0]).unwrap();
+            }
+            image
+        };
+        let decode = |image: &[u8], policy| {
+            let limits = crate::Limits {
+                text_bytes: 1000,
+                ..crate::Limits::default()
+            };
+            let mut decoder = crate::Decoder::new_with_limits(image, limits);
+            decoder.set_utf8_policy(policy);
+            decoder
+                .read_info()
+                .map(|reader| reader.info().utf8_text.clone())
+        };
+
+        // Text that decompresses beyond the limit.
+        let mut compressed = b"Comment\0\x01\0\0\0".to_vec();
+        compressed.extend_from_slice(&long);
+        let image = build(&[&compressed]);
+        for &policy in &[Utf8Policy::Lossy, Utf8Policy::Strict] {
+            let text = decode(&image, policy).unwrap();
+            assert_eq!(text.len(), 1);
+            assert_eq!(text[0].text, "Kept");
+        }
+
+        // Without the separator of the keyword, and with an unknown compression method.
+        let image = build(&[b"Comment", b"Comment\0\x01\x05\0\0x"]);
+        let text = decode(&image, Utf8Policy::Lossy).unwrap();
+        assert_eq!(text.len(), 1);
+        assert_eq!(text[0].text, "Kept");
+        assert!(decode(&image, Utf8Policy::Strict).is_err());
+    }
+
+    #[test]
+    fn chunk_events() {
+        let mut image = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+        // Corrupt the checksum of the header, which ends at 33.
+        image[32] ^= 1;
+        let stored = u32::from_be_bytes([image[29], image[30], image[31], image[32]]);
+
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_chunk_events(true);
+        let (mut header, mut events, mut image_data) = (Vec::new(), Vec::new(), Vec::new());
+        // Pass one byte at a time, so that the data of a chunk arrives in pieces.
+        for byte in image.chunks(1) {
+            let mut input = byte;
+            while !input.is_empty() {
+                let (consumed, event) = decoder.update(input, &mut image_data).unwrap();
+                match event {
+                    Decoded::ChunkData(IHDR, len) => {
+                        header.extend_from_slice(&input[consumed - len..consumed])
+                    }
+                    Decoded::ChunkData(..) | Decoded::ImageData | Decoded::PartialChunk(_) => {}
+                    Decoded::Nothing => assert_eq!(consumed, input.len()),
+                    event => events.push(event),
+                }
+                input = &input[consumed..];
+            }
+        }
+
+        assert_eq!(header, &image[16..29]);
+        // The header is decoded before its checksum is read.
+        match events[2] {
+            Decoded::ChunkCrcError(IHDR, crc_val, crc_sum) => {
+                assert_eq!(crc_val, stored);
+                assert_eq!(crc_sum, crc32fast::hash(&image[12..29]));
+            }
+            ref other => panic!("unexpected event {:?}", other),
+        }
+        match events[3] {
+            Decoded::ChunkComplete(_, IHDR) => {}
+            ref other => panic!("unexpected event {:?}", other),
+        }
+        match events.last() {
+            Some(Decoded::ImageEnd) => {}
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(events.iter().any(|event| match event {
+            Decoded::ChunkCrcOk(IDAT) => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn inflate_budget() {
+        use super::{Decoded, StreamingDecoder};
+
+        // Feed the whole image, recording how much image data each call returned.
+        fn decode(image: &[u8], budget: Option<usize>) -> (Vec<u8>, Vec<usize>) {
+            let mut decoder = StreamingDecoder::new();
+            if let Some(budget) = budget {
+                decoder.set_inflate_budget(budget);
+            }
+            let (mut image_data, mut sizes) = (Vec::new(), Vec::new());
+            let mut buf = image;
+            loop {
+                let len = image_data.len();
+                let (consumed, event) = decoder.update(buf, &mut image_data).unwrap();
+                buf = &buf[consumed..];
+                sizes.push(image_data.len() - len);
+                if let Decoded::ImageEnd = event {
+                    return (image_data, sizes);
+                }
+            }
+        }
+
+        // Compresses to a few hundred bytes that inflate to 256 KiB in one chunk.
+        let mut image = Vec::new();
+        {
+            let mut encoder = crate::Encoder::new(&mut image, 512, 512);
+            encoder.set_color(crate::ColorType::Grayscale);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&vec![0; 512 * 512]).unwrap();
+        }
+        let (expected, sizes) = decode(&image, None);
+        assert_eq!(expected.len(), 513 * 512);
+        assert!(sizes.iter().any(|&size| size > 4096));
+
+        let (image_data, sizes) = decode(&image, Some(4096));
+        assert_eq!(image_data, expected);
+        assert!(sizes.iter().all(|&size| size <= 4096 + 32 * 1024));
+        assert_eq!(sizes.iter().filter(|&&size| size > 4096).count(), 1);
+
+        for path in &["basi0g01", "basn3p04", "basi6a16", "z09n2c08"] {
+            let image = std::fs::read(format!("tests/pngsuite/{}.png", path)).unwrap();
+            let (expected, _) = decode(&image, None);
+            for &budget in &[1, 7, 1000] {
+                assert_eq!(decode(&image, Some(budget)).0, expected, "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn unfilter_streamed_rows() {
+        use super::{Decoded, StreamingDecoder};
+
+        for path in &["basn0g01", "basn2c08", "basn3p04", "basn6a16", "f04n2c08"] {
+            let image = std::fs::read(format!("tests/pngsuite/{}.png", path)).unwrap();
+            let mut decoder = crate::Decoder::new(&image[..]);
+            decoder.set_transformations(crate::Transformations::IDENTITY);
+            let mut reader = decoder.read_info().unwrap();
+            let mut expected = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut expected).unwrap();
+
+            let mut decoder = StreamingDecoder::new();
+            let mut image_data = Vec::new();
+            let mut buf = &image[..];
+            loop {
+                let (consumed, event) = decoder.update(buf, &mut image_data).unwrap();
+                buf = &buf[consumed..];
+                if let Decoded::ImageEnd = event {
+                    break;
+                }
+            }
+            let info = decoder.info().unwrap();
+            let mut previous: Option<&[u8]> = None;
+            let mut rows = Vec::new();
+            for row in image_data.chunks_mut(info.raw_row_length()) {
+                crate::unfilter_row(info, previous, row).unwrap();
+                rows.extend_from_slice(&row[1..]);
+                previous = Some(&row[1..]);
+            }
+            assert_eq!(rows, expected, "{}", path);
+        }
+    }
+
     #[test]
     fn image_gamma() -> Result<(), ()> {
         fn trial(path: &str, expected: Option<ScaledFloat>) {
@@ -0,0 +1,416 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+
+use super::stream::{Decoded, DecodingError, StreamingDecoder};
+use crate::chunk::{self, ChunkType};
+
+/// The chunk-level structure of a PNG file.
+///
+/// This lists the chunks in the order they appear, with their position in the file and the
+/// fields of the chunks the decoder understands. It is meant for tools and bug reports, for
+/// example to compare the layout of two files. With the `serde` feature the structure can be
+/// serialized, to JSON or any other format.
+///
+/// ```
+/// let file = std::fs::File::open("tests/pngsuite/basn3p08.png")?;
+/// let structure = png::Structure::read(file)?;
+/// let names: Vec<_> = structure.chunks.iter().map(|chunk| &chunk.name[..]).collect();
+/// assert_eq!(names, ["IHDR", "gAMA", "PLTE", "IDAT", "IEND"]);
+/// assert_eq!(structure.chunks[2].fields, Some(png::ChunkFields::Palette { entries: 256 }));
+/// assert!(structure.error.is_none());
+/// # Ok::<(), png::DecodingError>(())
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Structure {
+    /// The chunks of the file, up to `IEND` or the first error.
+    pub chunks: Vec<ChunkRecord>,
+    /// The number of bytes after the `IEND` chunk.
+    pub trailing_bytes: u64,
+    /// The format error that ended the structure before the `IEND` chunk, if any.
+    pub error: Option<String>,
+    /// The violations that the decoder collected, in strict mode.
+    pub violations: Vec<String>,
+}
+
+/// A chunk of a PNG file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChunkRecord {
+    /// The four letters of the chunk type.
+    pub name: String,
+    /// The offset of the length of the chunk from the start of the file.
+    pub offset: u64,
+    /// The length of the data of the chunk.
+    pub length: u32,
+    /// The checksum stored after the chunk, which is not reported for `IEND`.
+    pub crc: Option<u32>,
+    /// The fields of a chunk known to the decoder.
+    pub fields: Option<ChunkFields>,
+}
+
+/// The fields of a known chunk, with the values as stored in the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChunkFields {
+    /// `IHDR`
+    Header {
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: u8,
+        interlaced: bool,
+    },
+    /// `PLTE`
+    Palette { entries: usize },
+    /// `tRNS`
+    Transparency { length: usize },
+    /// `gAMA`, scaled by 100000.
+    Gamma { gamma: u32 },
+    /// `cHRM`, with the white point and the red, green and blue primaries.
+    Chromaticities {
+        white: (u32, u32),
+        red: (u32, u32),
+        green: (u32, u32),
+        blue: (u32, u32),
+    },
+    /// `sRGB`
+    Srgb { rendering_intent: u8 },
+    /// `pHYs`
+    PhysicalDimensions { xppu: u32, yppu: u32, unit: u8 },
+    /// `iCCP`, with the length of the decompressed profile.
+    IccProfile { length: usize },
+    /// `iTXt`
+    Text {
+        keyword: String,
+        compressed: bool,
+        language_tag: String,
+        translated_keyword: String,
+        text: String,
+    },
+    /// `acTL`
+    AnimationControl { num_frames: u32, num_plays: u32 },
+    /// `fcTL`
+    FrameControl {
+        sequence_number: u32,
+        width: u32,
+        height: u32,
+        x_offset: u32,
+        y_offset: u32,
+        delay_num: u16,
+        delay_den: u16,
+        dispose_op: u8,
+        blend_op: u8,
+    },
+}
+
+impl Structure {
+    /// Read the structure of a PNG file.
+    ///
+    /// The image data is decompressed to validate it, but not unfiltered. Format errors end the
+    /// structure and are recorded in `error`, only errors of the reader are returned.
+    pub fn read<R: Read>(r: R) -> Result<Structure, DecodingError> {
+        Structure::read_with_decoder(r, StreamingDecoder::new())
+    }
+
+    /// Read the structure of a PNG file with a configured decoder.
+    ///
+    /// This allows to ignore checksums or to change the limits, for example. With strict mode
+    /// and collected violations, every violation found in the file is listed in `violations`.
+    /// The streaming decoder must not have decoded any data yet.
+    ///
+    /// ```
+    /// use png::StreamingDecoder;
+    /// let mut image = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+    /// // Repeat the IHDR and the gAMA chunk.
+    /// let repeated = image[8..49].to_vec();
+    /// image.splice(49..49, repeated);
+    ///
+    /// let mut decoder = StreamingDecoder::new();
+    /// decoder.set_strict(true);
+    /// decoder.set_collect_violations(true);
+    /// let structure = png::Structure::read_with_decoder(&image[..], decoder)?;
+    /// assert_eq!(structure.chunks.len(), 6);
+    /// assert_eq!(structure.violations.len(), 2);
+    /// # Ok::<(), png::DecodingError>(())
+    /// ```
+    pub fn read_with_decoder<R: Read>(
+        r: R,
+        mut decoder: StreamingDecoder,
+    ) -> Result<Structure, DecodingError> {
+        let mut reader = BufReader::new(r);
+        let mut structure = Structure::default();
+        let mut image_data = Vec::new();
+        let mut position = 0;
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                structure.error = Some("unexpected end of data before image end".into());
+                structure.violations = violations(&decoder);
+                return Ok(structure);
+            }
+            let (consumed, event) = match decoder.update(buf, &mut image_data) {
+                Ok(result) => result,
+                Err(DecodingError::IoError(err)) => return Err(DecodingError::IoError(err)),
+                Err(err) => {
+                    structure.error = Some(err.to_string());
+                    structure.violations = violations(&decoder);
+                    return Ok(structure);
+                }
+            };
+            reader.consume(consumed);
+            position += consumed as u64;
+            image_data.clear();
+            match event {
+                Decoded::ChunkBegin(length, type_) => structure.chunks.push(ChunkRecord {
+                    name: String::from_utf8_lossy(&type_.0).into_owned(),
+                    // The length and type were just read.
+                    offset: position - 8,
+                    length,
+                    crc: None,
+                    fields: None,
+                }),
+                Decoded::ChunkComplete(crc, type_) => {
+                    let fields = chunk_fields(&decoder, type_);
+                    if let Some(record) = structure.chunks.last_mut() {
+                        record.crc = Some(crc);
+                        record.fields = fields;
+                    }
+                }
+                Decoded::ImageEnd => break,
+                _ => {}
+            }
+        }
+        structure.trailing_bytes = io::copy(&mut reader, &mut io::sink())?;
+        structure.violations = violations(&decoder);
+        Ok(structure)
+    }
+}
+
+/// A textual dump of the structure, one line for each chunk followed by its fields.
+///
+/// ```
+/// let file = std::fs::File::open("tests/pngsuite/basn3p08.png")?;
+/// let dump = png::Structure::read(file)?.to_string();
+/// let mut lines = dump.lines();
+/// assert_eq!(lines.next(), Some("IHDR at 8, 13 bytes, crc 44a48ac6"));
+/// assert_eq!(lines.next(), Some("    32 x 32, 8-bit, color type 3, non-interlaced"));
+/// assert_eq!(lines.next(), Some("gAMA at 33, 4 bytes, crc 31e8965f"));
+/// assert_eq!(lines.next(), Some("    gamma 1.00000"));
+/// # Ok::<(), png::DecodingError>(())
+/// ```
+impl fmt::Display for Structure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in &self.chunks {
+            write!(
+                f,
+                "{} at {}, {} bytes",
+                chunk.name, chunk.offset, chunk.length
+            )?;
+            match chunk.crc {
+                Some(crc) => writeln!(f, ", crc {:08x}", crc)?,
+                None if chunk.name == "IEND" => writeln!(f)?,
+                None => writeln!(f, ", incomplete")?,
+            }
+            if let Some(ref fields) = chunk.fields {
+                writeln!(f, "    {}", fields)?;
+            }
+        }
+        if self.trailing_bytes > 0 {
+            writeln!(f, "{} bytes after IEND", self.trailing_bytes)?;
+        }
+        if let Some(ref error) = self.error {
+            writeln!(f, "error: {}", error)?;
+        }
+        for violation in &self.violations {
+            writeln!(f, "violation: {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ChunkFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Values scaled by 100000, as gamma and chromaticities are stored.
+        let scaled = |value: u32| format!("{}.{:05}", value / 100_000, value % 100_000);
+        let point = |(x, y): (u32, u32)| format!("({}, {})", scaled(x), scaled(y));
+        match self {
+            ChunkFields::Header {
+                width,
+                height,
+                bit_depth,
+                color_type,
+                interlaced,
+            } => write!(
+                f,
+                "{} x {}, {}-bit, color type {}, {}",
+                width,
+                height,
+                bit_depth,
+                color_type,
+                if *interlaced {
+                    "interlaced"
+                } else {
+                    "non-interlaced"
+                }
+            ),
+            ChunkFields::Palette { entries } => write!(f, "{} entries", entries),
+            ChunkFields::Transparency { length } => write!(f, "{} bytes of transparency", length),
+            ChunkFields::Gamma { gamma } => write!(f, "gamma {}", scaled(*gamma)),
+            ChunkFields::Chromaticities {
+                white,
+                red,
+                green,
+                blue,
+            } => write!(
+                f,
+                "white {}, red {}, green {}, blue {}",
+                point(*white),
+                point(*red),
+                point(*green),
+                point(*blue)
+            ),
+            ChunkFields::Srgb { rendering_intent } => {
+                write!(f, "rendering intent {}", rendering_intent)
+            }
+            ChunkFields::PhysicalDimensions { xppu, yppu, unit } => {
+                let unit = if *unit == 1 { "meter" } else { "unit" };
+                write!(f, "{} x {} pixels per {}", xppu, yppu, unit)
+            }
+            ChunkFields::IccProfile { length } => write!(f, "profile of {} bytes", length),
+            ChunkFields::Text {
+                keyword,
+                compressed,
+                language_tag,
+                translated_keyword,
+                text,
+            } => write!(
+                f,
+                "{:?} ({:?}, {:?}){}: {:?}",
+                keyword,
+                language_tag,
+                translated_keyword,
+                if *compressed { ", compressed" } else { "" },
+                text
+            ),
+            ChunkFields::AnimationControl {
+                num_frames,
+                num_plays,
+            } => write!(f, "{} frames, {} plays", num_frames, num_plays),
+            ChunkFields::FrameControl {
+                sequence_number,
+                width,
+                height,
+                x_offset,
+                y_offset,
+                delay_num,
+                delay_den,
+                dispose_op,
+                blend_op,
+            } => write!(
+                f,
+                "sequence {}, {} x {} at ({}, {}), delay {}/{}, dispose op {}, blend op {}",
+                sequence_number,
+                width,
+                height,
+                x_offset,
+                y_offset,
+                delay_num,
+                delay_den,
+                dispose_op,
+                blend_op
+            ),
+        }
+    }
+}
+
+/// The violations collected by the decoder, as they are displayed.
+fn violations(decoder: &StreamingDecoder) -> Vec<String> {
+    decoder
+        .violations()
+        .iter()
+        .map(|err| err.to_string())
+        .collect()
+}
+
+/// The fields of the chunk of type `type_` that the decoder just completed.
+fn chunk_fields(decoder: &StreamingDecoder, type_: ChunkType) -> Option<ChunkFields> {
+    let info = decoder.info.as_ref()?;
+    let fields = match type_ {
+        chunk::IHDR => ChunkFields::Header {
+            width: info.width,
+            height: info.height,
+            bit_depth: info.bit_depth as u8,
+            color_type: info.color_type as u8,
+            interlaced: info.interlaced,
+        },
+        chunk::PLTE => ChunkFields::Palette {
+            entries: info.palette.as_ref()?.len() / 3,
+        },
+        chunk::tRNS => ChunkFields::Transparency {
+            length: info.trns.as_ref()?.len(),
+        },
+        chunk::gAMA => ChunkFields::Gamma {
+            gamma: info.source_gamma?.into_scaled(),
+        },
+        chunk::cHRM => {
+            let chromaticities = info.source_chromaticities?;
+            let scaled = |(x, y): (crate::ScaledFloat, crate::ScaledFloat)| {
+                (x.into_scaled(), y.into_scaled())
+            };
+            ChunkFields::Chromaticities {
+                white: scaled(chromaticities.white),
+                red: scaled(chromaticities.red),
+                green: scaled(chromaticities.green),
+                blue: scaled(chromaticities.blue),
+            }
+        }
+        chunk::sRGB => ChunkFields::Srgb {
+            rendering_intent: info.srgb?.into_raw(),
+        },
+        chunk::pHYs => {
+            let dims = info.pixel_dims?;
+            ChunkFields::PhysicalDimensions {
+                xppu: dims.xppu,
+                yppu: dims.yppu,
+                unit: dims.unit as u8,
+            }
+        }
+        chunk::iCCP => ChunkFields::IccProfile {
+            length: info.icc_profile.as_ref()?.len(),
+        },
+        chunk::iTXt => {
+            let text = info.utf8_text.last()?;
+            ChunkFields::Text {
+                keyword: text.keyword.clone(),
+                compressed: text.compressed,
+                language_tag: text.language_tag.clone(),
+                translated_keyword: text.translated_keyword.clone(),
+                text: text.text.clone(),
+            }
+        }
+        chunk::acTL => {
+            let actl = info.animation_control?;
+            ChunkFields::AnimationControl {
+                num_frames: actl.num_frames,
+                num_plays: actl.num_plays.to_u32(),
+            }
+        }
+        chunk::fcTL => {
+            let fctl = info.frame_control?;
+            ChunkFields::FrameControl {
+                sequence_number: fctl.sequence_number,
+                width: fctl.width,
+                height: fctl.height,
+                x_offset: fctl.x_offset,
+                y_offset: fctl.y_offset,
+                delay_num: fctl.delay_num,
+                delay_den: fctl.delay_den,
+                dispose_op: fctl.dispose_op as u8,
+                blend_op: fctl.blend_op as u8,
+            }
+        }
+        _ => return None,
+    };
+    Some(fields)
+}
@@ -1,12 +1,13 @@
-use super::{stream::FormatErrorInner, DecodingError, CHUNCK_BUFFER_SIZE};
+use super::inflate::{Backend, Inflate, InflateError, InflateStatus};
+use super::smallbuf::SmallBuf;
+use super::stream::{FormatErrorInner, CHUNCK_BUFFER_SIZE};
+use super::DecodingError;
+use alloc::vec::Vec;
 
-use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
-use miniz_oxide::inflate::TINFLStatus;
-
-/// Ergonomics wrapper around `miniz_oxide::inflate::stream` for zlib compressed data.
+/// Ergonomics wrapper around the inflate backend for zlib compressed data.
 pub(super) struct ZlibStream {
     /// Current decoding state.
-    state: Box<DecompressorOxide>,
+    state: Backend,
     /// If there has been a call to decompress already.
     started: bool,
     /// A buffer of compressed data.
@@ -26,30 +27,126 @@ pub(super) struct ZlibStream {
     in_pos: usize,
     /// Remaining buffered decoded bytes.
     /// The decoder sometimes wants inspect some already finished bytes for further decoding. So we
-    /// keep a total of 32KB of decoded data available as long as more data may be appended.
-    out_buffer: Vec<u8>,
+    /// keep a total of 32KB of decoded data available as long as more data may be appended. The
+    /// data of small images fits inline, larger ones move the buffer to the heap.
+    out_buffer: SmallBuf,
     /// The cursor position in the output stream as a buffer index.
     out_pos: usize,
+    /// Ignore the Adler-32 checksum of the stream.
+    ///
+    /// `miniz_oxide` always verifies the checksum of a zlib stream, so we decode the data after
+    /// the zlib header as a raw deflate stream instead.
+    ignore_adler32: bool,
+    /// Decode a stream with an invalid zlib header as raw deflate stream.
+    raw_fallback: bool,
+    /// The zlib header, which is validated before the data is decompressed.
+    header: [u8; 2],
+    /// Number of bytes of the header that have been read.
+    header_len: usize,
+    /// Whether the stream is decoded as raw deflate stream, without header and checksum.
+    raw: bool,
+    /// The maximum number of bytes decompressed by one call.
+    budget: usize,
 }
 
 impl ZlibStream {
     pub(crate) fn new() -> Self {
         ZlibStream {
-            state: Box::default(),
+            state: Backend::new(),
             started: false,
-            in_buffer: Vec::with_capacity(CHUNCK_BUFFER_SIZE),
+            // Only needed when the decompressor can not consume a chunk, allocated on demand.
+            in_buffer: Vec::new(),
             in_pos: 0,
-            out_buffer: vec![0; 2 * CHUNCK_BUFFER_SIZE],
+            // Grown by `prepare_vec_for_appending` before the first decompression.
+            out_buffer: SmallBuf::new(),
             out_pos: 0,
+            ignore_adler32: false,
+            raw_fallback: false,
+            header: [0; 2],
+            header_len: 0,
+            raw: false,
+            budget: usize::max_value(),
         }
     }
 
     pub(crate) fn reset(&mut self) {
         self.started = false;
         self.in_buffer.clear();
+        self.in_pos = 0;
         self.out_buffer.clear();
         self.out_pos = 0;
-        *self.state = DecompressorOxide::default();
+        self.header_len = 0;
+        self.raw = false;
+        self.state.reset(true);
+    }
+
+    /// Set whether the Adler-32 checksum at the end of the stream is verified.
+    ///
+    /// This must be set before decompressing the first data.
+    pub(crate) fn set_ignore_adler32(&mut self, ignore: bool) {
+        self.ignore_adler32 = ignore;
+    }
+
+    /// Set whether a stream with an invalid zlib header is decoded as raw deflate stream.
+    ///
+    /// This must be set before decompressing the first data.
+    pub(crate) fn set_raw_fallback(&mut self, fallback: bool) {
+        self.raw_fallback = fallback;
+    }
+
+    /// Set the maximum number of bytes that one call decompresses, at least one.
+    pub(crate) fn set_budget(&mut self, budget: usize) {
+        self.budget = budget.max(1);
+    }
+
+    /// The end of the output buffer available to the next call to the decompressor.
+    fn out_end(&self, budget: usize) -> usize {
+        self.out_buffer
+            .len()
+            .min(self.out_pos.saturating_add(budget))
+    }
+
+    /// Read the zlib header from `data`, returning the number of bytes consumed.
+    ///
+    /// Once the header is complete it is validated and, unless the stream is decoded as raw
+    /// deflate stream, passed on to the decompressor.
+    fn read_header(&mut self, data: &[u8]) -> Result<usize, DecodingError> {
+        let consumed = data.len().min(2 - self.header_len);
+        self.header[self.header_len..][..consumed].copy_from_slice(&data[..consumed]);
+        self.header_len += consumed;
+        if self.header_len < 2 {
+            return Ok(consumed);
+        }
+
+        let header = self.header;
+        let decompress_header = match validate_header(header) {
+            Ok(()) => {
+                self.raw = self.ignore_adler32;
+                !self.raw
+            }
+            // The header may just be missing, then it is the start of the deflate stream.
+            Err(_) if self.raw_fallback => {
+                self.raw = true;
+                true
+            }
+            Err(err) => return Err(DecodingError::Format(err.into())),
+        };
+
+        self.state.reset(!self.raw);
+        if decompress_header {
+            self.prepare_vec_for_appending();
+            let (status, _, out_consumed) =
+                self.state
+                    .inflate(&header, &mut self.out_buffer, self.out_pos, true);
+            self.started = true;
+            self.out_pos += out_consumed;
+            if let Err(err) = status {
+                return Err(DecodingError::Format(
+                    FormatErrorInner::CorruptFlateStream { err }.into(),
+                ));
+            }
+        }
+        Ok(consumed)
     }
 
     /// Fill the decoded buffer as far as possible from `data`.
@@ -57,27 +154,23 @@ impl ZlibStream {
     pub(crate) fn decompress(
         &mut self,
         data: &[u8],
-        image_data: &mut Vec<u8>,
+        image_data: &mut SmallBuf,
     ) -> Result<usize, DecodingError> {
-        const BASE_FLAGS: u32 = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
-            | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF
-            | inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+        if self.header_len < 2 {
+            return self.read_header(data);
+        }
 
         self.prepare_vec_for_appending();
 
+        let out_end = self.out_end(self.budget);
         let (status, mut in_consumed, out_consumed) = {
             let in_data = if self.in_buffer.is_empty() {
                 data
             } else {
                 &self.in_buffer[self.in_pos..]
             };
-            decompress(
-                &mut self.state,
-                in_data,
-                &mut self.out_buffer.as_mut_slice(),
-                self.out_pos,
-                BASE_FLAGS,
-            )
+            self.state
+                .inflate(in_data, &mut self.out_buffer[..out_end], self.out_pos, true)
         };
 
         if !self.in_buffer.is_empty() {
@@ -89,7 +182,9 @@ impl ZlibStream {
             self.in_pos = 0;
         }
 
-        if in_consumed == 0 {
+        // Without any progress the input is kept, while a decompressor that only produced output
+        // is called again until it consumes input.
+        if in_consumed == 0 && out_consumed == 0 {
             self.in_buffer.extend_from_slice(data);
             in_consumed = data.len();
         }
@@ -99,10 +194,8 @@ impl ZlibStream {
         self.transfer_finished_data(image_data);
 
         match status {
-            TINFLStatus::Done | TINFLStatus::HasMoreOutput | TINFLStatus::NeedsMoreInput => {
-                Ok(in_consumed)
-            }
-            err => Err(DecodingError::Format(
+            Ok(_) => Ok(in_consumed),
+            Err(err) => Err(DecodingError::Format(
                 FormatErrorInner::CorruptFlateStream { err }.into(),
             )),
         }
@@ -112,60 +205,61 @@ impl ZlibStream {
     ///
     /// The compressed stream can be split on arbitrary byte boundaries. This enables some cleanup
     /// within the decompressor and flushing additional data which may have been kept back in case
-    /// more data were passed to it.
+    /// more data were passed to it. Returns whether the stream is finished, or needs to be called
+    /// again because the budget of decompressed bytes was used up.
     pub(crate) fn finish_compressed_chunks(
         &mut self,
-        image_data: &mut Vec<u8>,
-    ) -> Result<(), DecodingError> {
-        const BASE_FLAGS: u32 = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
-            | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
-
+        image_data: &mut SmallBuf,
+    ) -> Result<bool, DecodingError> {
         if !self.started {
-            return Ok(());
+            return Ok(true);
         }
 
-        let tail = self.in_buffer.split_off(0);
-        let tail = &tail[self.in_pos..];
-
-        let mut start = 0;
+        let mut budget = self.budget;
         loop {
             self.prepare_vec_for_appending();
 
-            let (status, in_consumed, out_consumed) = {
-                // TODO: we may be able to avoid the indirection through the buffer here.
-                // First append all buffered data and then create a cursor on the image_data
-                // instead.
-                decompress(
-                    &mut self.state,
-                    &tail[start..],
-                    &mut self.out_buffer.as_mut_slice(),
-                    self.out_pos,
-                    BASE_FLAGS,
-                )
-            };
+            let out_end = self.out_end(budget);
+            let (status, in_consumed, out_consumed) = self.state.inflate(
+                &self.in_buffer[self.in_pos..],
+                &mut self.out_buffer[..out_end],
+                self.out_pos,
+                false,
+            );
 
-            start += in_consumed;
+            self.in_pos += in_consumed;
             self.out_pos += out_consumed;
+            budget -= out_consumed;
 
-            match status {
-                TINFLStatus::Done => {
-                    self.out_buffer.truncate(self.out_pos as usize);
-                    image_data.append(&mut self.out_buffer);
-                    return Ok(());
+            let err = match status {
+                Ok(InflateStatus::Done) => {
+                    image_data.extend_from_slice(&self.out_buffer[..self.out_pos]);
+                    self.out_buffer.clear();
+                    self.in_buffer.clear();
+                    self.in_pos = 0;
+                    return Ok(true);
                 }
-                TINFLStatus::HasMoreOutput => {
+                Ok(InflateStatus::HasMoreOutput) => {
                     let transferred = self.transfer_finished_data(image_data);
+                    if budget == 0 {
+                        return Ok(false);
+                    }
                     assert!(
                         transferred > 0 || in_consumed > 0 || out_consumed > 0,
                         "No more forward progress made in stream decoding."
                     );
+                    continue;
                 }
-                err => {
-                    return Err(DecodingError::Format(
-                        FormatErrorInner::CorruptFlateStream { err }.into(),
-                    ));
+                // Only a backend that makes progress may be called again.
+                Ok(InflateStatus::NeedsMoreInput) if in_consumed > 0 || out_consumed > 0 => {
+                    continue
                 }
-            }
+                Ok(InflateStatus::NeedsMoreInput) => InflateError::Incomplete,
+                Err(err) => err,
+            };
+            return Err(DecodingError::Format(
+                FormatErrorInner::CorruptFlateStream { err }.into(),
+            ));
         }
     }
 
@@ -175,6 +269,13 @@ impl ZlibStream {
             return;
         }
 
+        // The inline buffer is used as a whole until it is full.
+        let capacity = self.out_buffer.capacity();
+        if capacity < CHUNCK_BUFFER_SIZE && self.out_pos < capacity {
+            self.out_buffer.resize(capacity, 0u8);
+            return;
+        }
+
         let buffered_len = self.decoding_size(self.out_buffer.len());
         debug_assert!(self.out_buffer.len() <= buffered_len);
         self.out_buffer.resize(buffered_len, 0u8);
@@ -184,7 +285,7 @@ impl ZlibStream {
         // Allocate one more chunk size than currently or double the length while ensuring that the
         // allocation is valid and that any cursor within it will be valid.
         len
-            // This keeps the buffer size a power-of-two, required by miniz_oxide.
+            // The output is not wrapped around, so the size need not be a power-of-two.
             .saturating_add(CHUNCK_BUFFER_SIZE.max(len))
             // Ensure all buffer indices are valid cursor positions.
             // Note: both cut off and zero extension give correct results.
@@ -194,11 +295,58 @@ impl ZlibStream {
             .min(isize::max_value() as usize)
     }
 
-    fn transfer_finished_data(&mut self, image_data: &mut Vec<u8>) -> usize {
+    /// Decompress `pending` data and transfer everything decompressed so far, when the remainder
+    /// of the stream is missing.
+    ///
+    /// The decompressor can not continue afterwards since it loses its window.
+    pub(crate) fn salvage_data(
+        &mut self,
+        mut pending: &[u8],
+        image_data: &mut SmallBuf,
+    ) -> Result<(), DecodingError> {
+        while !pending.is_empty() {
+            let consumed = self.decompress(pending, image_data)?;
+            pending = &pending[consumed..];
+        }
+
+        // Data that could not be consumed is kept in the input buffer.
+        while !self.in_buffer.is_empty() {
+            let progress = (self.in_buffer.len() - self.in_pos, self.out_pos);
+            self.decompress(&[], image_data)?;
+            if (self.in_buffer.len() - self.in_pos, self.out_pos) == progress {
+                break;
+            }
+        }
+
+        image_data.extend_from_slice(&self.out_buffer[..self.out_pos]);
+        self.out_buffer.remove_front(self.out_pos);
+        self.out_pos = 0;
+        Ok(())
+    }
+
+    fn transfer_finished_data(&mut self, image_data: &mut SmallBuf) -> usize {
         let safe = self.out_pos.saturating_sub(CHUNCK_BUFFER_SIZE);
         // TODO: allocation limits.
-        image_data.extend(self.out_buffer.drain(..safe));
+        image_data.extend_from_slice(&self.out_buffer[..safe]);
+        self.out_buffer.remove_front(safe);
         self.out_pos -= safe;
         safe
     }
 }
+
+/// Check the two bytes of a zlib header, as defined by RFC 1950.
+fn validate_header([cmf, flg]: [u8; 2]) -> Result<(), FormatErrorInner> {
+    if cmf & 0x0f != 8 {
+        Err(FormatErrorInner::ZlibCompressionMethod(cmf & 0x0f))
+    } else if cmf >> 4 > 7 {
+        Err(FormatErrorInner::ZlibWindowSize(cmf >> 4))
+    } else if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        Err(FormatErrorInner::ZlibHeaderCheck(
+            u16::from(cmf) << 8 | u16::from(flg),
+        ))
+    } else if flg & 0x20 != 0 {
+        Err(FormatErrorInner::ZlibPresetDictionary)
+    } else {
+        Ok(())
+    }
+}
@@ -5,14 +5,24 @@ use std::{borrow, error, fmt, io, mem, ops, result};
 
 use crc32fast::Hasher as Crc32;
 use deflate::write::ZlibEncoder;
+use miniz_oxide::deflate::core::{
+    compress, create_comp_flags_from_zip_params, CompressionStrategy, CompressorOxide, TDEFLFlush,
+    TDEFLStatus,
+};
 
+use crate::adam7::Adam7Iterator;
 use crate::chunk::{self, ChunkType};
 use crate::common::{
-    AnimationControl, BitDepth, BlendOp, BytesPerPixel, ColorType, Compression, DisposeOp,
-    FrameControl, Info, ParameterError, ParameterErrorKind, ScaledFloat,
+    AnimationControl, BackgroundColor, Bilevel, BitDepth, BlendOp, BytesPerPixel, ChunkLocation,
+    ColorType, Compression, DeflateBackend, DisposeOp, FrameControl, Info, ParameterError,
+    ParameterErrorKind, PlayCount, RetainedChunk, ScaledFloat, SuggestedPalette,
 };
 use crate::filter::{filter, AdaptiveFilterType, FilterType};
 use crate::traits::WriteBytesExt;
+use crate::utils;
+
+#[cfg(feature = "parallel")]
+mod parallel;
 
 pub type Result<T> = result::Result<T, EncodingError>;
 
@@ -35,6 +45,10 @@ enum FormatErrorKind {
     ZeroHeight,
     InvalidColorCombination(BitDepth, ColorType),
     NoPalette,
+    InvalidPaletteLength(usize),
+    PaletteTooLarge(usize, BitDepth),
+    UnexpectedPalette(ColorType),
+    InvalidTrns(usize, ColorType),
     // TODO: wait, what?
     WrittenTooMuch(usize),
     NotAnimated,
@@ -44,17 +58,28 @@ enum FormatErrorKind {
     MissingFrames,
     MissingData(usize),
     Unrecoverable,
+    InvalidWindowBits(u8),
+    InvalidPrivateChunk(ChunkType),
+    ChunkTooLarge(usize),
+    InvalidHistogram(usize),
+    InvalidBackground(BackgroundColor),
+    InvalidSuggestedPalette(usize),
+    InvalidRetainedChunk(ChunkType),
 }
 
 impl error::Error for EncodingError {
-    fn cause(&self) -> Option<&(dyn error::Error + 'static)> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             EncodingError::IoError(err) => Some(err),
+            EncodingError::Format(err) => Some(err),
+            EncodingError::Parameter(err) => Some(err),
             _ => None,
         }
     }
 }
 
+impl error::Error for FormatError {}
+
 impl fmt::Display for EncodingError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
         use self::EncodingError::*;
@@ -80,6 +105,24 @@ impl fmt::Display for FormatError {
                 depth, color
             ),
             NoPalette => write!(fmt, "can't write indexed image without palette"),
+            InvalidPaletteLength(len) => write!(
+                fmt,
+                "a palette of {} bytes is not a multiple of 3 between 3 and 768 bytes",
+                len
+            ),
+            PaletteTooLarge(entries, depth) => write!(
+                fmt,
+                "a palette of {} entries can not be indexed with bit-depth '{:?}'",
+                entries, depth
+            ),
+            UnexpectedPalette(color) => {
+                write!(fmt, "a palette is not allowed for color-type '{:?}'", color)
+            }
+            InvalidTrns(len, color) => write!(
+                fmt,
+                "a transparency chunk of {} bytes is invalid for color-type '{:?}'",
+                len, color
+            ),
             WrittenTooMuch(index) => write!(fmt, "wrong data size, got {} bytes too many", index),
             NotAnimated => write!(fmt, "not an animation"),
             OutOfBounds => write!(
@@ -93,6 +136,41 @@ impl fmt::Display for FormatError {
                 fmt,
                 "a previous error put the writer into an unrecoverable state"
             ),
+            InvalidWindowBits(bits) => write!(
+                fmt,
+                "a deflate window of {} bits is not between 8 and 15 bits",
+                bits
+            ),
+            InvalidPrivateChunk(kind) => write!(
+                fmt,
+                "{:?} is not the type of a private chunk",
+                String::from_utf8_lossy(&kind.0)
+            ),
+            ChunkTooLarge(len) => write!(
+                fmt,
+                "chunk data of {} bytes exceeds the maximum of 2^31 - 1 bytes",
+                len
+            ),
+            InvalidHistogram(len) => write!(
+                fmt,
+                "a histogram of {} entries does not match the palette",
+                len
+            ),
+            InvalidBackground(color) => write!(
+                fmt,
+                "the background color {:?} does not fit the color type or palette",
+                color
+            ),
+            InvalidRetainedChunk(kind) => write!(
+                fmt,
+                "{:?} is not the type of an ancillary chunk that can be retained",
+                String::from_utf8_lossy(&kind.0)
+            ),
+            InvalidSuggestedPalette(index) => write!(
+                fmt,
+                "suggested palette #{} has an invalid or duplicate name or sample depth",
+                index
+            ),
         }
     }
 }
@@ -103,9 +181,29 @@ impl From<io::Error> for EncodingError {
     }
 }
 
+impl From<FormatError> for EncodingError {
+    fn from(err: FormatError) -> EncodingError {
+        EncodingError::Format(err)
+    }
+}
+
+impl From<ParameterError> for EncodingError {
+    fn from(err: ParameterError) -> EncodingError {
+        EncodingError::Parameter(err)
+    }
+}
+
+/// Errors of the underlying writer are returned unchanged. Other errors are wrapped with a kind
+/// of `InvalidInput`, or `Other` for exceeded limits, and can be recovered with
+/// `io::Error::into_inner`.
 impl From<EncodingError> for io::Error {
     fn from(err: EncodingError) -> io::Error {
-        io::Error::new(io::ErrorKind::Other, err.to_string())
+        let kind = match err {
+            EncodingError::IoError(err) => return err,
+            EncodingError::Format(_) | EncodingError::Parameter(_) => io::ErrorKind::InvalidInput,
+            EncodingError::LimitsExceeded => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
     }
 }
 
@@ -116,41 +214,117 @@ impl From<FormatErrorKind> for FormatError {
     }
 }
 
+/// The limits on the images that can be encoded with the settings of an `Encoder`.
+///
+/// See [`Encoder::constraints`](struct.Encoder.html#method.constraints).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodingConstraints {
+    /// The largest width that can be encoded with the configured height.
+    pub max_width: u32,
+    /// The largest height that can be encoded with the configured width.
+    pub max_height: u32,
+    /// The bytes of memory needed to encode an image of the configured dimensions.
+    ///
+    /// This saturates at `u64::MAX` for dimensions that can not be represented at all.
+    pub required_memory: u64,
+}
+
+/// Encode a still image described by `info`.
+///
+/// The header and metadata chunks are written from `info`, followed by the image `data` in the
+/// layout of its color type and bit depth, and by the end of the image. The image is not
+/// interlaced and the animation and frame controls of `info` are ignored. For a choice of filter or compression settings beyond
+/// `info.compression`, or for animations, configure an `Encoder` instead.
+///
+/// ```
+/// let mut info = png::Info::with_size(2, 1);
+/// info.color_type = png::ColorType::Rgb;
+/// let mut png = vec![];
+/// png::encode(&mut png, &info, &[255, 0, 0, 0, 0, 255])?;
+/// # Ok::<(), png::EncodingError>(())
+/// ```
+pub fn encode<W: Write>(w: W, info: &Info<'_>, data: &[u8]) -> Result<()> {
+    let mut encoder = Encoder::new(w, info.width, info.height);
+    encoder.info = info.clone();
+    encoder.info.interlaced = false;
+    encoder.info.animation_control = None;
+    encoder.info.frame_control = None;
+    encoder.write_header()?.write_image_data(data)
+}
+
 /// PNG Encoder
 pub struct Encoder<'a, W: Write> {
     w: W,
     info: Info<'a>,
-    filter: FilterType,
+    filter: Option<FilterType>,
     adaptive_filter: AdaptiveFilterType,
     sep_def_img: bool,
+    deflate_window_bits: u8,
+    deflate_backend: DeflateBackend,
+    #[cfg(feature = "parallel")]
+    parallel: bool,
 }
 
 impl<'a, W: Write> Encoder<'a, W> {
     pub fn new(w: W, width: u32, height: u32) -> Encoder<'static, W> {
+        Encoder::from_info(w, Info::with_size(width, height))
+    }
+
+    fn from_info(w: W, info: Info<'a>) -> Encoder<'a, W> {
         Encoder {
             w,
-            info: Info::with_size(width, height),
-            filter: FilterType::default(),
+            info,
+            filter: None,
             adaptive_filter: AdaptiveFilterType::default(),
             sep_def_img: false,
+            deflate_window_bits: MAX_WINDOW_BITS,
+            deflate_backend: DeflateBackend::default(),
+            #[cfg(feature = "parallel")]
+            parallel: false,
         }
     }
 
+    /// Create an encoder for an image described by `info`.
+    ///
+    /// The info is checked as `write_header` does, so an invalid header or palette is reported
+    /// before any image data is prepared. All metadata is written, an info of a decoded image
+    /// can so be reused to encode a copy of it.
+    ///
+    /// ```
+    /// use png::{BitDepth, ColorType, Encoder, Info};
+    /// let info = Info::with_size(2, 1)
+    ///     .color_type(ColorType::Indexed)
+    ///     .bit_depth(BitDepth::Four)
+    ///     .palette(&[0, 0, 0, 255, 255, 255][..]);
+    /// let mut png = vec![];
+    /// let mut writer = Encoder::with_info(&mut png, info)?.write_header()?;
+    /// writer.write_image_data(&[0x01])?;
+    /// # Ok::<(), png::EncodingError>(())
+    /// ```
+    pub fn with_info(w: W, info: Info<'a>) -> Result<Encoder<'a, W>> {
+        validate_info(&info)?;
+        Ok(Encoder::from_info(w, info))
+    }
+
     /// Specify that the image is animated.
     ///
     /// `num_frames` controls how many frames the animation has, while
     /// `num_plays` controls how many times the animation should be
-    /// repeaded until it stops, if it's zero then it will repeat
-    /// inifinitely
+    /// played until it stops. A plain `u32` is accepted for compatibility,
+    /// where zero means [`PlayCount::Infinite`].
     ///
     /// This method returns an error if `num_frames` is 0.
-    pub fn set_animated(&mut self, num_frames: u32, num_plays: u32) -> Result<()> {
+    pub fn set_animated<P: Into<PlayCount>>(
+        &mut self,
+        num_frames: u32,
+        num_plays: P,
+    ) -> Result<()> {
         if num_frames == 0 {
             return Err(EncodingError::Format(FormatErrorKind::ZeroFrames.into()));
         }
         let actl = AnimationControl {
             num_frames,
-            num_plays,
+            num_plays: num_plays.into(),
         };
         let fctl = FrameControl {
             sequence_number: 0,
@@ -174,16 +348,83 @@ impl<'a, W: Write> Encoder<'a, W> {
 
     /// Sets the raw byte contents of the PLTE chunk. This method accepts
     /// both borrowed and owned byte data.
+    ///
+    /// The palette holds the RGB samples of up to 256 entries, no more than the bit depth of an
+    /// indexed image can address. It is required for `ColorType::Indexed` and may be given as a
+    /// suggestion to viewers for RGB and RGBA images. This is validated by `write_header`.
     pub fn set_palette<T: Into<Cow<'a, [u8]>>>(&mut self, palette: T) {
         self.info.palette = Some(palette.into());
     }
 
     /// Sets the raw byte contents of the tRNS chunk. This method accepts
     /// both borrowed and owned byte data.
+    ///
+    /// For indexed images these are the alpha values of the first palette entries, there must be
+    /// no more of them than entries. Grayscale and RGB images instead mark a single color as
+    /// transparent, given as 16-bit samples of 2 and 6 bytes respectively. Below a bit depth of 16
+    /// the samples can also be given as single bytes, like the decoder stores them. Images with
+    /// an alpha channel can not have one.
     pub fn set_trns<T: Into<Cow<'a, [u8]>>>(&mut self, trns: T) {
         self.info.trns = Some(trns.into());
     }
 
+    /// Add a suggested palette, written as an `sPLT` chunk.
+    ///
+    /// Its name must be unique and 1 to 79 characters of Latin-1 other than NUL, its sample depth
+    /// 8 or 16 bits. This is validated by `write_header`.
+    pub fn add_suggested_palette(&mut self, palette: SuggestedPalette) {
+        self.info.suggested_palettes.push(palette);
+    }
+
+    /// Set the chunks to copy from an image that was decoded with `Decoder::set_retain_chunks`.
+    ///
+    /// Each chunk is written at its location, those after the image data when the writer is
+    /// finished. Only ancillary chunks that are not written from the other settings of the
+    /// encoder can be retained. This is validated by `write_header`. Chunks that are not safe to
+    /// copy, see `chunk::safe_to_copy`, depend on the image data and should only be kept if it is
+    /// unchanged.
+    ///
+    /// ```
+    /// use png::chunk::safe_to_copy;
+    /// let data = std::fs::read("tests/pngsuite/ct1n0g04.png").unwrap();
+    /// let mut decoder = png::Decoder::new(&data[..]);
+    /// decoder.set_retain_chunks(true);
+    /// let mut reader = decoder.read_info()?;
+    /// let mut frame = vec![0; reader.output_buffer_size()];
+    /// reader.next_frame(&mut frame)?;
+    /// let info = reader.finish()?;
+    ///
+    /// // Edit the pixels and write them together with the chunks that are safe to copy.
+    /// frame.iter_mut().for_each(|byte| *byte = !*byte);
+    /// let mut png = Vec::new();
+    /// let mut encoder = png::Encoder::new(&mut png, info.width, info.height);
+    /// encoder.set_color(info.color_type);
+    /// encoder.set_depth(info.bit_depth);
+    /// let mut retained = info.retained_chunks.clone();
+    /// retained.retain(|chunk| safe_to_copy(chunk.chunk_type));
+    /// encoder.set_retained_chunks(retained);
+    /// encoder.write_header()?.write_image_data(&frame)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn set_retained_chunks(&mut self, chunks: Vec<RetainedChunk>) {
+        self.info.retained_chunks = chunks;
+    }
+
+    /// Set the histogram of the palette, the approximate number of uses of each palette entry.
+    ///
+    /// It needs one entry for each entry of the palette. This is validated by `write_header`.
+    pub fn set_histogram(&mut self, histogram: Vec<u16>) {
+        self.info.histogram = Some(histogram);
+    }
+
+    /// Set the background color, on which a viewer may display the image.
+    ///
+    /// Its kind must match the color type: gray for grayscale, RGB for color and an index into
+    /// the palette for indexed images. This is validated by `write_header`.
+    pub fn set_background(&mut self, background: BackgroundColor) {
+        self.info.background = Some(background);
+    }
+
     /// Set the display gamma of the source system on which the image was generated or last edited.
     pub fn set_source_gamma(&mut self, source_gamma: ScaledFloat) {
         self.info.source_gamma = Some(source_gamma);
@@ -207,9 +448,16 @@ impl<'a, W: Write> Encoder<'a, W> {
     }
 
     pub fn write_header(self) -> Result<Writer<W>> {
+        let mut info = PartialInfo::new(&self.info);
+        info.deflate_window_bits = self.deflate_window_bits;
+        info.deflate_backend = self.deflate_backend;
+        #[cfg(feature = "parallel")]
+        {
+            info.parallel = self.parallel;
+        }
         Writer::new(
             self.w,
-            PartialInfo::new(&self.info),
+            info,
             self.filter,
             self.adaptive_filter,
             self.sep_def_img,
@@ -231,6 +479,18 @@ impl<'a, W: Write> Encoder<'a, W> {
         self.info.bit_depth = depth;
     }
 
+    /// Write the image data in the seven passes of Adam7 interlacing.
+    ///
+    /// The image data is still supplied row by row to [`Writer::write_image_data`], which
+    /// reorders the pixels into the passes. Interlaced image data can not be streamed with a
+    /// [`StreamWriter`]. The default is not to interlace.
+    ///
+    /// [`Writer::write_image_data`]: struct.Writer.html#method.write_image_data
+    /// [`StreamWriter`]: struct.StreamWriter.html
+    pub fn set_interlaced(&mut self, interlaced: bool) {
+        self.info.interlaced = interlaced;
+    }
+
     /// Set compression parameters.
     ///
     /// Accepts a `Compression` or any type that can transform into a `Compression`. Notably `deflate::Compression` and
@@ -239,16 +499,64 @@ impl<'a, W: Write> Encoder<'a, W> {
         self.info.compression = compression;
     }
 
+    /// Set the size of the deflate window, as the base-2 logarithm of its size in bytes.
+    ///
+    /// A decoder of the image data needs to keep as many bytes of history as the window holds,
+    /// at most 32KiB for the default of 15 bits. Lowering this allows small devices to decode the
+    /// image with less memory. The zlib header of the image data announces the chosen size.
+    ///
+    /// When the image data of a frame is larger than the window the compression falls back to a
+    /// run-length encoding, or Huffman coding only if that was selected, since these never refer
+    /// to data further back than the smallest window. This can lower the compression ratio
+    /// considerably.
+    ///
+    /// Returns an error if `bits` is not in the range `8..=15`.
+    pub fn set_deflate_window_bits(&mut self, bits: u8) -> Result<()> {
+        if bits < MIN_WINDOW_BITS || bits > MAX_WINDOW_BITS {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidWindowBits(bits).into(),
+            ));
+        }
+        self.deflate_window_bits = bits;
+        Ok(())
+    }
+
+    /// Select the implementation of deflate that compresses the image data.
+    ///
+    /// The default is [`DeflateBackend::Deflate`].
+    ///
+    /// [`DeflateBackend::Deflate`]: enum.DeflateBackend.html#variant.Deflate
+    pub fn set_deflate_backend(&mut self, backend: DeflateBackend) {
+        self.deflate_backend = backend;
+    }
+
+    /// Filter and compress the image data on several threads.
+    ///
+    /// The rows are split into segments of about 256 KiB that are filtered and compressed
+    /// independently with `miniz_oxide`, whichever deflate backend is selected, and joined into
+    /// one zlib stream. As back-references do not reach into the previous segment, the image data
+    /// is usually a little larger than when it is compressed on one thread. Interlaced images and
+    /// the `StreamWriter` are always compressed on one thread.
+    ///
+    /// The default is `false`.
+    #[cfg(feature = "parallel")]
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
     /// Set the used filter type.
     ///
     /// The default filter is [`FilterType::Sub`] which provides a basic prediction algorithm for
     /// sample values based on the previous. For a potentially better compression ratio, at the
-    /// cost of more complex processing, try out [`FilterType::Paeth`].
+    /// cost of more complex processing, try out [`FilterType::Paeth`]. Indexed images and those
+    /// with a bit depth below 8 instead default to [`FilterType::NoFilter`], which almost always
+    /// compresses them better.
     ///
+    /// [`FilterType::NoFilter`]: enum.FilterType.html#variant.NoFilter
     /// [`FilterType::Sub`]: enum.FilterType.html#variant.Sub
     /// [`FilterType::Paeth`]: enum.FilterType.html#variant.Paeth
     pub fn set_filter(&mut self, filter: FilterType) {
-        self.filter = filter;
+        self.filter = Some(filter);
     }
 
     /// Set the adaptive filter type.
@@ -358,14 +666,354 @@ impl<'a, W: Write> Encoder<'a, W> {
             Err(EncodingError::Format(FormatErrorKind::NotAnimated.into()))
         }
     }
+
+    /// Report the largest images that can be encoded with the current settings.
+    ///
+    /// The `memory_budget` bounds the bytes needed by [`Writer::write_image_data`]: the image
+    /// data itself, its compressed form in the worst case and two rows for filtering. Since the
+    /// image data is split into as many chunks as required, the 31-bit chunk length does not
+    /// limit the image beyond the maximum of `2^31 - 1` pixels in each dimension.
+    ///
+    /// This allows validating that an image can be written before generating its data.
+    ///
+    /// ```
+    /// let mut encoder = png::Encoder::new(Vec::new(), 1024, 1024);
+    /// encoder.set_color(png::ColorType::Rgba);
+    /// encoder.set_depth(png::BitDepth::Eight);
+    ///
+    /// let constraints = encoder.constraints(1 << 30);
+    /// assert!(constraints.required_memory > 8 << 20);
+    /// assert!(constraints.max_height > 1024 && constraints.max_height < 1 << 17);
+    /// ```
+    ///
+    /// [`Writer::write_image_data`]: struct.Writer.html#method.write_image_data
+    pub fn constraints(&self, memory_budget: usize) -> EncodingConstraints {
+        let (width, height) = (self.info.width, self.info.height);
+        // Allocations can not be larger than this.
+        let budget = memory_budget.min(isize::max_value() as usize) as u64;
+        let fits = |width, height| {
+            self.encoding_memory(width, height)
+                .map_or(false, |memory| memory <= budget)
+        };
+
+        EncodingConstraints {
+            max_width: largest_dimension(|width| fits(width, height)),
+            max_height: largest_dimension(|height| fits(width, height)),
+            required_memory: self.encoding_memory(width, height).unwrap_or(std::u64::MAX),
+        }
+    }
+
+    /// Check that the image can be encoded with the current settings within `memory_budget`.
+    ///
+    /// Returns the errors that writing the header would return, and `LimitsExceeded` if the
+    /// dimensions are larger than permitted by the [`constraints`].
+    ///
+    /// [`constraints`]: #method.constraints
+    pub fn check_constraints(&self, memory_budget: usize) -> Result<()> {
+        if self.info.width == 0 {
+            return Err(EncodingError::Format(FormatErrorKind::ZeroWidth.into()));
+        }
+
+        if self.info.height == 0 {
+            return Err(EncodingError::Format(FormatErrorKind::ZeroHeight.into()));
+        }
+
+        if self
+            .info
+            .color_type
+            .is_combination_invalid(self.info.bit_depth)
+        {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidColorCombination(self.info.bit_depth, self.info.color_type)
+                    .into(),
+            ));
+        }
+
+        validate_tables(&self.info)?;
+
+        let constraints = self.constraints(memory_budget);
+        if self.info.width > constraints.max_width || self.info.height > constraints.max_height {
+            return Err(EncodingError::LimitsExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Estimate the size of the encoded file without compressing all of `data`.
+    ///
+    /// Only about `sample_rows` rows, taken from a few bands spread over the height of the image,
+    /// are filtered and compressed with the current settings. Their compression ratio is then
+    /// extrapolated to the whole image. If `sample_rows` is at least the height, all rows are
+    /// compressed and the result is the exact size of the file written for an image that is not
+    /// animated.
+    ///
+    /// ```
+    /// let data: Vec<u8> = (0..256 * 256).map(|i| (i % 256) as u8).collect();
+    /// let mut encoder = png::Encoder::new(Vec::new(), 256, 256);
+    /// encoder.set_color(png::ColorType::Grayscale);
+    ///
+    /// let estimate = encoder.estimate_size(&data, 32)?;
+    /// assert!(estimate < data.len() as u64 / 10);
+    /// # Ok::<(), png::EncodingError>(())
+    /// ```
+    pub fn estimate_size(&self, data: &[u8], sample_rows: u32) -> Result<u64> {
+        self.check_constraints(usize::max_value())?;
+        if self.info.color_type == ColorType::Indexed && self.info.palette.is_none() {
+            return Err(EncodingError::Format(FormatErrorKind::NoPalette.into()));
+        }
+
+        let in_len = self.info.raw_row_length() - 1;
+        let height = self.info.height as usize;
+        if data.len() != in_len * height {
+            return Err(EncodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected: in_len * height,
+                    actual: data.len(),
+                }
+                .into(),
+            ));
+        }
+
+        let mut header = Vec::new();
+        self.info.encode(&mut header)?;
+
+        // Consecutive rows, so that the filters can predict from the previous one.
+        let rows: Vec<usize> = if sample_rows as usize >= height {
+            (0..height).collect()
+        } else {
+            let bands = sample_rows.max(1).min(8) as usize;
+            let band_rows = (sample_rows as usize / bands).max(1);
+            (0..bands)
+                .flat_map(|band| {
+                    let start = band * (height - band_rows) / (bands - 1).max(1);
+                    start..start + band_rows
+                })
+                .collect()
+        };
+
+        let mut info = PartialInfo::new(&self.info);
+        info.deflate_window_bits = self.deflate_window_bits;
+        info.deflate_backend = self.deflate_backend;
+        let mut zlib = info.zlib_encoder(Vec::new(), data.len() + height);
+        let bpp = self.info.bpp_in_prediction();
+        let method = self
+            .filter
+            .unwrap_or_else(|| FilterType::for_image(self.info.color_type, self.info.bit_depth));
+        let zeros = vec![0; in_len];
+        let mut current = vec![0; in_len];
+        for &row in &rows {
+            let prev = match row {
+                0 => &zeros[..],
+                _ => &data[(row - 1) * in_len..][..in_len],
+            };
+            current.copy_from_slice(&data[row * in_len..][..in_len]);
+            let filter_type = filter(method, self.adaptive_filter, bpp, prev, &mut current);
+            zlib.write_all(&[filter_type as u8])?;
+            zlib.write_all(&current)?;
+        }
+        let compressed = zlib.finish()?.len() as u64;
+
+        // Extrapolate the deflate data, without the zlib header and checksum.
+        let stream = (compressed - 6) * height as u64 / rows.len() as u64 + 6;
+        let max_chunk = u64::from(MAX_IDAT_CHUNK_LEN);
+        let chunks = (stream + max_chunk - 1) / max_chunk;
+        // The signature, the header chunks, the IDAT chunks and IEND.
+        Ok(8 + header.len() as u64 + stream + 12 * chunks + 12)
+    }
+
+    /// The bytes of memory needed to encode an image with `width` and `height`.
+    fn encoding_memory(&self, width: u32, height: u32) -> Option<u64> {
+        let row = self
+            .info
+            .color_type
+            .checked_raw_row_length(self.info.bit_depth, width)? as u64;
+        let height = u64::from(height);
+        let data = (row - 1).checked_mul(height)?;
+        // Every row is preceded by its filter type.
+        let stream = row.checked_mul(height)?;
+        // Incompressible data is stored in blocks of at most 65535 bytes with a header of 5 bytes,
+        // the zlib stream adds a 2 byte header and a 4 byte checksum.
+        let compressed = stream.checked_add(5 * (stream / 65535 + 1) + 6)?;
+        data.checked_add(compressed)?.checked_add(2 * (row - 1))
+    }
+}
+
+/// The largest width and height permitted by the specification.
+const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+/// The largest dimension that `fits`, which must be monotonic, or zero if there is none.
+fn largest_dimension<F: Fn(u32) -> bool>(fits: F) -> u32 {
+    let (mut low, mut high) = (0, MAX_DIMENSION);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+/// Check that the header of an image is valid, and its chunks for the color type and bit depth.
+pub(crate) fn validate_info(info: &Info<'_>) -> Result<()> {
+    if info.width == 0 {
+        return Err(EncodingError::Format(FormatErrorKind::ZeroWidth.into()));
+    }
+
+    if info.height == 0 {
+        return Err(EncodingError::Format(FormatErrorKind::ZeroHeight.into()));
+    }
+
+    if info.color_type.is_combination_invalid(info.bit_depth) {
+        return Err(EncodingError::Format(
+            FormatErrorKind::InvalidColorCombination(info.bit_depth, info.color_type).into(),
+        ));
+    }
+
+    validate_tables(info)
+}
+
+/// Check that the PLTE and tRNS chunks are valid for the color type and bit depth.
+fn validate_tables(info: &Info<'_>) -> Result<()> {
+    let color = info.color_type;
+    if let Some(ref palette) = info.palette {
+        let entries = palette.len() / 3;
+        if palette.len() % 3 != 0 || entries == 0 || entries > 256 {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidPaletteLength(palette.len()).into(),
+            ));
+        }
+        if color == ColorType::Grayscale || color == ColorType::GrayscaleAlpha {
+            return Err(EncodingError::Format(
+                FormatErrorKind::UnexpectedPalette(color).into(),
+            ));
+        }
+        if color == ColorType::Indexed && entries > 1 << info.bit_depth as u8 {
+            return Err(EncodingError::Format(
+                FormatErrorKind::PaletteTooLarge(entries, info.bit_depth).into(),
+            ));
+        }
+    }
+
+    if let Some(ref trns) = info.trns {
+        let narrow = info.bit_depth != BitDepth::Sixteen;
+        let valid = match color {
+            ColorType::Indexed => {
+                trns.len() <= info.palette.as_ref().map_or(0, |palette| palette.len() / 3)
+            }
+            ColorType::Grayscale => trns.len() == 2 || trns.len() == 1 && narrow,
+            ColorType::Rgb => trns.len() == 6 || trns.len() == 3 && narrow,
+            ColorType::GrayscaleAlpha | ColorType::Rgba => false,
+        };
+        if !valid {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidTrns(trns.len(), color).into(),
+            ));
+        }
+    }
+
+    if let Some(ref histogram) = info.histogram {
+        if info.palette.as_ref().map(|palette| palette.len() / 3) != Some(histogram.len()) {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidHistogram(histogram.len()).into(),
+            ));
+        }
+    }
+
+    if let Some(background) = info.background {
+        let entries = info.palette.as_ref().map_or(0, |palette| palette.len() / 3);
+        let valid = background.fits(info.color_type)
+            && match background {
+                BackgroundColor::Index(index) => usize::from(index) < entries,
+                _ => true,
+            };
+        if !valid {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidBackground(background).into(),
+            ));
+        }
+    }
+
+    for (i, palette) in info.suggested_palettes.iter().enumerate() {
+        let name = &palette.name;
+        let chars = name.chars().count();
+        let latin1 = name.chars().all(|c| c != '\0' && u32::from(c) <= 0xff);
+        let samples_fit = match palette.sample_depth {
+            BitDepth::Sixteen => true,
+            BitDepth::Eight => palette
+                .entries
+                .iter()
+                .all(|entry| entry.red.max(entry.green).max(entry.blue).max(entry.alpha) <= 0xff),
+            _ => false,
+        };
+        let unique = info.suggested_palettes[..i]
+            .iter()
+            .all(|other| &other.name != name);
+        if chars == 0 || chars > 79 || !latin1 || !samples_fit || !unique {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidSuggestedPalette(i).into(),
+            ));
+        }
+    }
+
+    for retained in &info.retained_chunks {
+        let kind = retained.chunk_type;
+        let letters = kind.0.iter().all(u8::is_ascii_alphabetic);
+        if !letters
+            || chunk::is_critical(kind)
+            || chunk::reserved_set(kind)
+            || chunk::INFO_CHUNKS.contains(&kind)
+        {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidRetainedChunk(kind).into(),
+            ));
+        }
+        if retained.data.len() > MAX_IDAT_CHUNK_LEN as usize {
+            return Err(EncodingError::Format(
+                FormatErrorKind::ChunkTooLarge(retained.data.len()).into(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that a `row` given on its own holds the `in_len` bytes of a scanline.
+fn check_row_len(row: &[u8], in_len: usize) -> Result<()> {
+    if row.len() == in_len {
+        Ok(())
+    } else {
+        Err(EncodingError::Parameter(
+            ParameterErrorKind::ImageBufferSize {
+                expected: in_len,
+                actual: row.len(),
+            }
+            .into(),
+        ))
+    }
+}
+
+/// The filter of the row at `index`, from the `row_filters` of a frame if any were set.
+fn row_filter(
+    row_filters: &[FilterType],
+    index: usize,
+    method: FilterType,
+    adaptive: AdaptiveFilterType,
+) -> (FilterType, AdaptiveFilterType) {
+    match row_filters.get(index) {
+        Some(&filter) => (filter, AdaptiveFilterType::NonAdaptive),
+        None => (method, adaptive),
+    }
 }
 
 /// PNG writer
 pub struct Writer<W: Write> {
     w: W,
     info: PartialInfo,
-    filter: FilterType,
+    filter: Option<FilterType>,
     adaptive_filter: AdaptiveFilterType,
+    /// The filters of the rows of the next frame, if they were set.
+    row_filters: Vec<FilterType>,
     sep_def_img: bool,
     written: u64,
 }
@@ -380,6 +1028,13 @@ struct PartialInfo {
     animation_control: Option<AnimationControl>,
     compression: Compression,
     has_palette: bool,
+    interlaced: bool,
+    deflate_window_bits: u8,
+    deflate_backend: DeflateBackend,
+    #[cfg(feature = "parallel")]
+    parallel: bool,
+    /// The retained chunks that are written after the image data.
+    trailing_chunks: Vec<RetainedChunk>,
 }
 
 impl PartialInfo {
@@ -393,9 +1048,25 @@ impl PartialInfo {
             animation_control: info.animation_control,
             compression: info.compression,
             has_palette: info.palette.is_some(),
+            interlaced: info.interlaced,
+            deflate_window_bits: MAX_WINDOW_BITS,
+            deflate_backend: DeflateBackend::default(),
+            #[cfg(feature = "parallel")]
+            parallel: false,
+            trailing_chunks: info
+                .retained_chunks
+                .iter()
+                .filter(|chunk| chunk.location == ChunkLocation::AfterIdat)
+                .cloned()
+                .collect(),
         }
     }
 
+    /// The filter that was set, or the default for the image.
+    fn filter(&self, filter: Option<FilterType>) -> FilterType {
+        filter.unwrap_or_else(|| FilterType::for_image(self.color_type, self.bit_depth))
+    }
+
     fn bpp_in_prediction(&self) -> BytesPerPixel {
         // Passthrough
         self.to_info().bpp_in_prediction()
@@ -411,6 +1082,27 @@ impl PartialInfo {
         self.to_info().raw_row_length_from_width(width)
     }
 
+    /// The compression for a zlib stream of `stream_len` bytes.
+    ///
+    /// Makes sure that no back-reference reaches further than the configured window.
+    fn stream_compression(&self, stream_len: usize) -> Compression {
+        if self.deflate_window_bits >= MAX_WINDOW_BITS
+            || stream_len <= 1 << self.deflate_window_bits
+        {
+            return self.compression;
+        }
+
+        match self.compression {
+            Compression::Huffman => Compression::Huffman,
+            _ => Compression::Rle,
+        }
+    }
+
+    /// An encoder of a zlib stream of `stream_len` bytes, writing to `w`.
+    fn zlib_encoder<Z: Write>(&self, w: Z, stream_len: usize) -> ZlibWriter<Z> {
+        ZlibWriter::new(w, self.deflate_backend, self.stream_compression(stream_len))
+    }
+
     /// Converts this partial info to an owned Info struct,
     /// setting missing values to their defaults
     fn to_info(&self) -> Info<'static> {
@@ -422,12 +1114,33 @@ impl PartialInfo {
         info.frame_control = self.frame_control;
         info.animation_control = self.animation_control;
         info.compression = self.compression;
+        info.interlaced = self.interlaced;
         info
     }
 }
 
 const DEFAULT_BUFFER_LENGTH: usize = 4 * 1024;
 
+const MAX_IDAT_CHUNK_LEN: u32 = std::u32::MAX >> 1;
+#[allow(non_upper_case_globals)]
+const MAX_fdAT_CHUNK_LEN: u32 = (std::u32::MAX >> 1) - 4;
+
+/// Bounds of the deflate window size, as the base-2 logarithm.
+const MIN_WINDOW_BITS: u8 = 8;
+const MAX_WINDOW_BITS: u8 = 15;
+
+/// The zlib header announcing a window of `window_bits`.
+///
+/// The `deflate` encoder always writes a header with the maximum window and the default
+/// compression level, this header only differs in the window size.
+fn zlib_header(window_bits: u8) -> [u8; 2] {
+    let cmf = (window_bits - 8) << 4 | 8;
+    // Default compression level, no preset dictionary.
+    let flg = 2 << 6;
+    let rem = (u16::from(cmf) << 8 | flg) % 31;
+    [cmf, (flg + 31 - rem) as u8]
+}
+
 pub(crate) fn write_chunk<W: Write>(mut w: W, name: chunk::ChunkType, data: &[u8]) -> Result<()> {
     w.write_be(data.len() as u32)?;
     w.write_all(&name.0)?;
@@ -443,7 +1156,7 @@ impl<W: Write> Writer<W> {
     fn new(
         w: W,
         info: PartialInfo,
-        filter: FilterType,
+        filter: Option<FilterType>,
         adaptive_filter: AdaptiveFilterType,
         sep_def_img: bool,
     ) -> Writer<W> {
@@ -452,33 +1165,17 @@ impl<W: Write> Writer<W> {
             info,
             filter,
             adaptive_filter,
+            row_filters: Vec::new(),
             sep_def_img,
             written: 0,
         }
     }
 
     fn init(mut self, info: &Info<'_>) -> Result<Self> {
-        if self.info.width == 0 {
-            return Err(EncodingError::Format(FormatErrorKind::ZeroWidth.into()));
-        }
+        validate_info(info)?;
 
-        if self.info.height == 0 {
-            return Err(EncodingError::Format(FormatErrorKind::ZeroHeight.into()));
-        }
-
-        if self
-            .info
-            .color_type
-            .is_combination_invalid(self.info.bit_depth)
-        {
-            return Err(EncodingError::Format(
-                FormatErrorKind::InvalidColorCombination(self.info.bit_depth, self.info.color_type)
-                    .into(),
-            ));
-        }
-
-        self.w.write_all(&[137, 80, 78, 71, 13, 10, 26, 10])?; // PNG signature
-        info.encode(&mut self.w)?;
+        self.w.write_all(&chunk::SIGNATURE)?;
+        info.encode(&mut self.w)?;
 
         Ok(self)
     }
@@ -487,6 +1184,43 @@ impl<W: Write> Writer<W> {
         write_chunk(&mut self.w, name, data)
     }
 
+    /// Writes a private chunk, to embed data of an application in the image.
+    ///
+    /// The type must consist of four ASCII letters, the second lowercase to mark the chunk as
+    /// private and the third uppercase as the specification reserves. A lowercase first letter
+    /// marks the chunk as ancillary, other decoders skip it. Decoders that do not know a critical
+    /// chunk, one with an uppercase first letter, reject the image. A lowercase last letter
+    /// marks the chunk as safe to copy by editors that change the image data.
+    ///
+    /// The chunk follows the header chunks if it is written before the image data, the image
+    /// data otherwise. Either is allowed for private chunks.
+    ///
+    /// ```
+    /// use png::chunk::ChunkType;
+    ///
+    /// let mut png = Vec::new();
+    /// let mut encoder = png::Encoder::new(&mut png, 1, 1);
+    /// let mut writer = encoder.write_header()?;
+    /// writer.write_private_chunk(ChunkType(*b"grAb"), &[0, 0, 0, 1, 0, 0, 0, 2])?;
+    /// writer.write_image_data(&[0])?;
+    /// assert!(writer.write_private_chunk(ChunkType(*b"GRAB"), &[]).is_err());
+    /// # Ok::<(), png::EncodingError>(())
+    /// ```
+    pub fn write_private_chunk(&mut self, chunk_type: ChunkType, data: &[u8]) -> Result<()> {
+        let letters = chunk_type.0.iter().all(u8::is_ascii_alphabetic);
+        if !letters || !chunk::is_private(chunk_type) || chunk::reserved_set(chunk_type) {
+            return Err(EncodingError::Format(
+                FormatErrorKind::InvalidPrivateChunk(chunk_type).into(),
+            ));
+        }
+        if data.len() > MAX_IDAT_CHUNK_LEN as usize {
+            return Err(EncodingError::Format(
+                FormatErrorKind::ChunkTooLarge(data.len()).into(),
+            ));
+        }
+        self.write_chunk(chunk_type, data)
+    }
+
     fn max_frames(&self) -> u64 {
         match self.info.animation_control {
             Some(a) if self.sep_def_img => a.num_frames as u64 + 1,
@@ -495,31 +1229,100 @@ impl<W: Write> Writer<W> {
         }
     }
 
-    /// Writes the image data.
-    pub fn write_image_data(&mut self, data: &[u8]) -> Result<()> {
-        const MAX_IDAT_CHUNK_LEN: u32 = std::u32::MAX >> 1;
-        #[allow(non_upper_case_globals)]
-        const MAX_fdAT_CHUNK_LEN: u32 = (std::u32::MAX >> 1) - 4;
+    /// Writes image data that holds each sample in a byte of its own.
+    ///
+    /// For grayscale and indexed images with a bit depth of 1, 2 or 4 the samples are packed into
+    /// the scanlines, so that masks or images with few colors can be generated without packing
+    /// them manually. Each sample must fit into the bit depth. For all other images this is the
+    /// same as [`write_image_data`].
+    ///
+    /// ```
+    /// let mut png = vec![];
+    /// let mut encoder = png::Encoder::new(&mut png, 3, 2);
+    /// encoder.set_depth(png::BitDepth::One);
+    /// let mut writer = encoder.write_header()?;
+    /// writer.write_unpacked_image_data(&[1, 0, 1, 0, 1, 0])?;
+    /// # Ok::<(), png::EncodingError>(())
+    /// ```
+    ///
+    /// [`write_image_data`]: #method.write_image_data
+    pub fn write_unpacked_image_data(&mut self, data: &[u8]) -> Result<()> {
+        let bit_depth = self.info.bit_depth as u8;
+        if bit_depth >= 8 {
+            return self.write_image_data(data);
+        }
 
-        if self.info.color_type == ColorType::Indexed && !self.info.has_palette {
-            return Err(EncodingError::Format(FormatErrorKind::NoPalette.into()));
+        let (width, height) = match self.info.frame_control {
+            Some(ref fctl) => (fctl.width as usize, fctl.height as usize),
+            None => (self.info.width as usize, self.info.height as usize),
+        };
+        // Only grayscale and indexed images have sub-byte depths, with one sample per pixel.
+        if width * height != data.len() {
+            return Err(EncodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected: width * height,
+                    actual: data.len(),
+                }
+                .into(),
+            ));
         }
 
-        if self.written > self.max_frames() {
-            return Err(EncodingError::Format(FormatErrorKind::EndReached.into()));
+        let in_len = self.info.raw_row_length_from_width(width as u32) - 1;
+        let mut packed = vec![0; in_len * height];
+        for (row, out) in data.chunks(width).zip(packed.chunks_mut(in_len)) {
+            utils::pack_bits(row, out, bit_depth).map_err(|sample| {
+                EncodingError::Parameter(
+                    ParameterErrorKind::SampleOutOfRange { sample, bit_depth }.into(),
+                )
+            })?;
         }
+        self.write_image_data(&packed)
+    }
 
-        let width: usize;
-        let height: usize;
-        if let Some(ref mut fctl) = self.info.frame_control {
-            width = fctl.width as usize;
-            height = fctl.height as usize;
-        } else {
-            width = self.info.width as usize;
-            height = self.info.height as usize;
+    /// Writes 8-bit grayscale samples as a 1-bit grayscale image.
+    ///
+    /// The image must have been configured with `ColorType::Grayscale` and `BitDepth::One`. The
+    /// `data` holds one byte per pixel, which `mode` reduces to black or white before the pixels
+    /// are packed into the scanlines.
+    ///
+    /// ```
+    /// let mut png = vec![];
+    /// let mut encoder = png::Encoder::new(&mut png, 4, 1);
+    /// encoder.set_depth(png::BitDepth::One);
+    /// let mut writer = encoder.write_header()?;
+    /// writer.write_bilevel_image_data(&[0, 100, 200, 255], png::Bilevel::Threshold(128))?;
+    /// # Ok::<(), png::EncodingError>(())
+    /// ```
+    pub fn write_bilevel_image_data(&mut self, data: &[u8], mode: Bilevel) -> Result<()> {
+        if self.info.color_type != ColorType::Grayscale || self.info.bit_depth != BitDepth::One {
+            return Err(EncodingError::Parameter(
+                ParameterErrorKind::NotBilevel {
+                    color_type: self.info.color_type,
+                    bit_depth: self.info.bit_depth,
+                }
+                .into(),
+            ));
         }
 
-        let in_len = self.info.raw_row_length_from_width(width as u32) - 1;
+        let width = match self.info.frame_control {
+            Some(ref fctl) => fctl.width as usize,
+            None => self.info.width as usize,
+        };
+        let mut samples = data.to_vec();
+        match mode {
+            Bilevel::Threshold(threshold) => {
+                for sample in &mut samples {
+                    *sample = (*sample >= threshold) as u8;
+                }
+            }
+            Bilevel::Dither => utils::dither_bilevel(&mut samples, width),
+        }
+        self.write_unpacked_image_data(&samples)
+    }
+
+    /// Writes the image data.
+    pub fn write_image_data(&mut self, data: &[u8]) -> Result<()> {
+        let (width, height, in_len) = self.frame_layout()?;
         let data_size = in_len * height;
         if data_size != data.len() {
             return Err(EncodingError::Parameter(
@@ -531,29 +1334,199 @@ impl<W: Write> Writer<W> {
             ));
         }
 
-        let prev = vec![0; in_len];
-        let mut prev = prev.as_slice();
-        let mut current = vec![0; in_len];
+        let filter_method = self.info.filter(self.filter);
+        let adaptive_method = self.adaptive_filter;
+        #[cfg(feature = "parallel")]
+        {
+            if self.info.parallel && !self.info.interlaced && self.row_filters.is_empty() {
+                let zlib_encoded = parallel::compress_image(
+                    &self.info,
+                    filter_method,
+                    adaptive_method,
+                    data,
+                    in_len,
+                )?;
+                return self.write_zlib_encoded(&zlib_encoded);
+            }
+        }
+        if !self.info.interlaced {
+            return self.write_rows(data.chunks(in_len), in_len, height);
+        }
 
-        let mut zlib = deflate::write::ZlibEncoder::new(
-            Vec::new(),
-            self.info.compression.clone().to_options(),
-        );
+        let passes = Adam7Iterator::new(width as u32, height as u32);
+        let row_filters = self.take_row_filters(passes.len())?;
+        let mut zlib = self.info.zlib_encoder(Vec::new(), data_size + height);
         let bpp = self.info.bpp_in_prediction();
-        let filter_method = self.filter;
+        let bits_pp = self.info.to_info().bits_per_pixel() as u8;
+        let mut prev = Vec::new();
+        let mut line = Vec::new();
+        let mut current = Vec::new();
+        for (index, (pass, line_no, line_width)) in passes.enumerate() {
+            let line_len = self.info.raw_row_length_from_width(line_width) - 1;
+            // The first line of every pass is filtered against a line of zeros.
+            if line_no == 0 {
+                prev.clear();
+                prev.resize(line_len, 0);
+            }
+            line.resize(line_len, 0);
+            utils::collect_pass(data, width as u32, &mut line, pass, line_no, bits_pp);
+            current.clear();
+            current.extend_from_slice(&line);
+            let (method, adaptive) =
+                row_filter(&row_filters, index, filter_method, adaptive_method);
+            let filter_type = filter(method, adaptive, bpp, &prev, &mut current);
+            zlib.write_all(&[filter_type as u8])?;
+            zlib.write_all(&current)?;
+            mem::swap(&mut prev, &mut line);
+        }
+        let mut zlib_encoded = zlib.finish()?;
+        zlib_encoded[..2].copy_from_slice(&zlib_header(self.info.deflate_window_bits));
+        self.write_zlib_encoded(&zlib_encoded)
+    }
+
+    /// Writes the image data of a frame given as its rows, from top to bottom.
+    ///
+    /// Each row holds the bytes of one scanline without the filter type, as in
+    /// `write_image_data`. Rows that are produced one after another, by a renderer or while
+    /// converting another format, can so be encoded without a buffer for the whole frame. Only
+    /// interlaced frames and frames compressed on several threads are still collected first.
+    ///
+    /// ```
+    /// let mut png = vec![];
+    /// let mut encoder = png::Encoder::new(&mut png, 3, 2);
+    /// encoder.set_color(png::ColorType::Grayscale);
+    /// let mut writer = encoder.write_header()?;
+    /// let rows = [[0, 1, 2], [1, 2, 3]];
+    /// writer.write_image_rows(rows.iter().map(|row| &row[..]))?;
+    /// # Ok::<(), png::EncodingError>(())
+    /// ```
+    pub fn write_image_rows<'r, I>(&mut self, rows: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'r [u8]>,
+    {
+        let (_, height, in_len) = self.frame_layout()?;
+        #[cfg(feature = "parallel")]
+        let collect = self.info.interlaced || self.info.parallel;
+        #[cfg(not(feature = "parallel"))]
+        let collect = self.info.interlaced;
+        if collect {
+            let mut data = Vec::with_capacity(in_len * height);
+            for row in rows {
+                check_row_len(row, in_len)?;
+                data.extend_from_slice(row);
+            }
+            return self.write_image_data(&data);
+        }
+        self.write_rows(rows.into_iter(), in_len, height)
+    }
+
+    /// Writes the image data of a frame that is already filtered and compressed.
+    ///
+    /// The `zlib` stream is written as is, as the data of the `IDAT` or `fdAT` chunks, for
+    /// example as read with `Reader::next_frame_compressed`. Images can so be copied with other
+    /// metadata, or their frames combined into an animation, without compressing them again. The
+    /// stream is not verified, it must hold the filtered rows of a frame with the header and
+    /// frame control of this writer.
+    ///
+    /// ```
+    /// let file = std::fs::File::open("tests/pngsuite/basn0g01.png")?;
+    /// let mut reader = png::Decoder::new(file).read_info()?;
+    /// let mut zlib = vec![];
+    /// reader.next_frame_compressed(&mut zlib)?;
+    ///
+    /// let mut png = vec![];
+    /// let mut encoder = png::Encoder::new(&mut png, 32, 32);
+    /// encoder.set_depth(png::BitDepth::One);
+    /// encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+    /// let mut writer = encoder.write_header()?;
+    /// writer.write_compressed_image_data(&zlib)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn write_compressed_image_data(&mut self, zlib: &[u8]) -> Result<()> {
+        self.frame_layout()?;
+        self.write_zlib_encoded(zlib)
+    }
+
+    /// The width, height and bytes per row of the next frame, once it may be written.
+    fn frame_layout(&self) -> Result<(usize, usize, usize)> {
+        if self.info.color_type == ColorType::Indexed && !self.info.has_palette {
+            return Err(EncodingError::Format(FormatErrorKind::NoPalette.into()));
+        }
+
+        if self.written > self.max_frames() {
+            return Err(EncodingError::Format(FormatErrorKind::EndReached.into()));
+        }
+
+        let (width, height) = match self.info.frame_control {
+            Some(ref fctl) => (fctl.width as usize, fctl.height as usize),
+            None => (self.info.width as usize, self.info.height as usize),
+        };
+        let in_len = self.info.raw_row_length_from_width(width as u32) - 1;
+        Ok((width, height, in_len))
+    }
+
+    /// Filter and compress the `height` rows of `in_len` bytes of a frame that is not interlaced.
+    fn write_rows<'r, I>(&mut self, rows: I, in_len: usize, height: usize) -> Result<()>
+    where
+        I: Iterator<Item = &'r [u8]>,
+    {
+        let row_filters = self.take_row_filters(height)?;
+        let filter_method = self.info.filter(self.filter);
         let adaptive_method = self.adaptive_filter;
-        for line in data.chunks(in_len) {
-            current.copy_from_slice(&line);
-            let filter_type = filter(filter_method, adaptive_method, bpp, &prev, &mut current);
+        let mut zlib = self.info.zlib_encoder(Vec::new(), (in_len + 1) * height);
+        let bpp = self.info.bpp_in_prediction();
+        let zeros = vec![0; in_len];
+        let mut prev = zeros.as_slice();
+        let mut current = vec![0; in_len];
+        let mut written = 0;
+        for line in rows {
+            check_row_len(line, in_len)?;
+            written += 1;
+            if written > height {
+                break;
+            }
+            current.copy_from_slice(line);
+            let (method, adaptive) =
+                row_filter(&row_filters, written - 1, filter_method, adaptive_method);
+            let filter_type = filter(method, adaptive, bpp, prev, &mut current);
             zlib.write_all(&[filter_type as u8])?;
             zlib.write_all(&current)?;
             prev = line;
         }
-        let zlib_encoded = zlib.finish()?;
+        if written != height {
+            return Err(EncodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected: in_len * height,
+                    actual: in_len * written,
+                }
+                .into(),
+            ));
+        }
+        let mut zlib_encoded = zlib.finish()?;
+        zlib_encoded[..2].copy_from_slice(&zlib_header(self.info.deflate_window_bits));
+        self.write_zlib_encoded(&zlib_encoded)
+    }
+
+    /// Takes the row filters of the next frame, which must be one for each of its stored `rows`.
+    fn take_row_filters(&mut self, rows: usize) -> Result<Vec<FilterType>> {
+        if !self.row_filters.is_empty() && self.row_filters.len() != rows {
+            return Err(EncodingError::Parameter(
+                ParameterErrorKind::RowFilterCount {
+                    expected: rows,
+                    actual: self.row_filters.len(),
+                }
+                .into(),
+            ));
+        }
+        Ok(mem::replace(&mut self.row_filters, Vec::new()))
+    }
+
+    /// Write the compressed image data of a frame as IDAT or fdAT chunks.
+    fn write_zlib_encoded(&mut self, zlib_encoded: &[u8]) -> Result<()> {
         if self.sep_def_img || self.info.frame_control.is_none() {
             self.sep_def_img = false;
             for chunk in zlib_encoded.chunks(MAX_IDAT_CHUNK_LEN as usize) {
-                self.write_chunk(chunk::IDAT, &chunk)?;
+                self.write_chunk(chunk::IDAT, chunk)?;
             }
         } else if let Some(ref mut fctl) = self.info.frame_control {
             fctl.encode(&mut self.w)?;
@@ -561,7 +1534,7 @@ impl<W: Write> Writer<W> {
 
             if self.written == 0 {
                 for chunk in zlib_encoded.chunks(MAX_IDAT_CHUNK_LEN as usize) {
-                    self.write_chunk(chunk::IDAT, &chunk)?;
+                    self.write_chunk(chunk::IDAT, chunk)?;
                 }
             } else {
                 let buff_size = zlib_encoded.len().min(MAX_fdAT_CHUNK_LEN as usize);
@@ -584,12 +1557,15 @@ impl<W: Write> Writer<W> {
     ///
     /// The default filter is [`FilterType::Sub`] which provides a basic prediction algorithm for
     /// sample values based on the previous. For a potentially better compression ratio, at the
-    /// cost of more complex processing, try out [`FilterType::Paeth`].
+    /// cost of more complex processing, try out [`FilterType::Paeth`]. Indexed images and those
+    /// with a bit depth below 8 instead default to [`FilterType::NoFilter`], which almost always
+    /// compresses them better.
     ///
+    /// [`FilterType::NoFilter`]: enum.FilterType.html#variant.NoFilter
     /// [`FilterType::Sub`]: enum.FilterType.html#variant.Sub
     /// [`FilterType::Paeth`]: enum.FilterType.html#variant.Paeth
     pub fn set_filter(&mut self, filter: FilterType) {
-        self.filter = filter;
+        self.filter = Some(filter);
     }
 
     /// Set the adaptive filter type for the following frames.
@@ -604,6 +1580,28 @@ impl<W: Write> Writer<W> {
         self.adaptive_filter = adaptive_filter;
     }
 
+    /// Set the filter of every row of the next frame, instead of the filter and adaptive filter.
+    ///
+    /// The filters are given in the order the rows are stored, from top to bottom and for
+    /// interlaced frames pass by pass, skipping empty passes. Writing the frame with
+    /// `write_image_data` or `write_image_rows` fails if their number differs from the number of
+    /// rows. The frame is then filtered on a single thread. Later frames use the filter and
+    /// adaptive filter again, and so does a `StreamWriter`.
+    ///
+    /// ```
+    /// use png::FilterType::{NoFilter, Paeth, Sub};
+    /// let mut png = vec![];
+    /// let mut encoder = png::Encoder::new(&mut png, 2, 3);
+    /// encoder.set_color(png::ColorType::Grayscale);
+    /// let mut writer = encoder.write_header()?;
+    /// writer.set_row_filters(&[NoFilter, Paeth, Sub]);
+    /// writer.write_image_data(&[0, 1, 2, 3, 4, 5])?;
+    /// # Ok::<(), png::EncodingError>(())
+    /// ```
+    pub fn set_row_filters(&mut self, filters: &[FilterType]) {
+        self.row_filters = filters.to_vec();
+    }
+
     /// Set the fraction of time the following frames are going to be displayed,
     /// in seconds
     ///
@@ -771,7 +1769,7 @@ impl<W: Write> Writer<W> {
     ///
     /// This borrows the writer which allows for manually appending additional
     /// chunks after the image data has been written.
-    pub fn stream_writer(&mut self) -> Result<StreamWriter<W>> {
+    pub fn stream_writer(&mut self) -> Result<StreamWriter<'_, W>> {
         self.stream_writer_with_size(DEFAULT_BUFFER_LENGTH)
     }
 
@@ -780,7 +1778,7 @@ impl<W: Write> Writer<W> {
     /// See [`stream_writer`].
     ///
     /// [`stream_writer`]: #fn.stream_writer
-    pub fn stream_writer_with_size(&mut self, size: usize) -> Result<StreamWriter<W>> {
+    pub fn stream_writer_with_size(&mut self, size: usize) -> Result<StreamWriter<'_, W>> {
         StreamWriter::new(ChunkOutput::Borrowed(self), size)
     }
 
@@ -805,6 +1803,9 @@ impl<W: Write> Writer<W> {
 
 impl<W: Write> Drop for Writer<W> {
     fn drop(&mut self) {
+        for retained in mem::replace(&mut self.info.trailing_chunks, Vec::new()) {
+            let _ = self.write_chunk(retained.chunk_type, &retained.data);
+        }
         let _ = self.write_chunk(chunk::IEND, &[]);
     }
 }
@@ -855,6 +1856,10 @@ struct ChunkWriter<'a, W: Write> {
     /// keeps track of where the last byte was written
     index: usize,
     curr_chunk: ChunkType,
+    /// the header of each zlib stream, replacing the one written by the encoder
+    zlib_header: [u8; 2],
+    /// how many bytes of the header of the current zlib stream have been written
+    zlib_header_pos: usize,
 }
 
 impl<'a, W: Write> ChunkWriter<'a, W> {
@@ -866,20 +1871,33 @@ impl<'a, W: Write> ChunkWriter<'a, W> {
         // TODO (maybe): find a way to hold two chunks at a time if `usize`
         //               is 64 bits.
         const CAP: usize = std::u32::MAX as usize >> 1;
-        let curr_chunk;
-        if writer.sep_def_img || writer.info.frame_control.is_none() || writer.written == 0 {
-            curr_chunk = chunk::IDAT;
-        } else {
-            curr_chunk = chunk::fdAT;
-        }
+        let curr_chunk =
+            if writer.sep_def_img || writer.info.frame_control.is_none() || writer.written == 0 {
+                chunk::IDAT
+            } else {
+                chunk::fdAT
+            };
+        let zlib_header = zlib_header(writer.info.deflate_window_bits);
         ChunkWriter {
             writer,
             buffer: vec![0; CAP.min(buf_len)],
             index: 0,
             curr_chunk,
+            zlib_header,
+            zlib_header_pos: 0,
         }
     }
 
+    /// Begins the zlib stream of the next frame, compressing into this writer.
+    fn begin_zlib_stream(mut self) -> ZlibWriter<Self> {
+        let (line_len, size) = self.next_frame_info();
+        self.zlib_header_pos = 0;
+        // Each line is preceded by its filter type.
+        let compression = self.writer.info.stream_compression(size + size / line_len);
+        let backend = self.writer.info.deflate_backend;
+        ZlibWriter::new(self, backend, compression)
+    }
+
     /// Returns the size of each scanline for the next frame
     /// paired with the size of the whole frame
     ///
@@ -967,11 +1985,13 @@ impl<'a, W: Write> Write for ChunkWriter<'a, W> {
         if self.index == 0 {
             let wrt = self.writer.deref_mut();
             // ??? maybe use self.curr_chunk == chunk::fdAT ???
-            if !wrt.sep_def_img && wrt.info.frame_control.is_some() && wrt.written > 0 {
-                let fctl = wrt.info.frame_control.as_mut().unwrap();
-                self.buffer[0..4].copy_from_slice(&fctl.sequence_number.to_be_bytes());
-                fctl.sequence_number += 1;
-                self.index = 4;
+            match wrt.info.frame_control {
+                Some(ref mut fctl) if !wrt.sep_def_img && wrt.written > 0 => {
+                    self.buffer[0..4].copy_from_slice(&fctl.sequence_number.to_be_bytes());
+                    fctl.sequence_number += 1;
+                    self.index = 4;
+                }
+                _ => {}
             }
         }
 
@@ -981,6 +2001,13 @@ impl<'a, W: Write> Write for ChunkWriter<'a, W> {
         data = &data[..written];
 
         self.buffer[self.index..][..written].copy_from_slice(data);
+        for byte in &mut self.buffer[self.index..][..written] {
+            match self.zlib_header.get(self.zlib_header_pos) {
+                Some(&header) => *byte = header,
+                None => break,
+            }
+            self.zlib_header_pos += 1;
+        }
         self.index += written;
 
         // if the maximum data for this chunk as been reached it needs to be flushed
@@ -1018,9 +2045,10 @@ impl<W: Write> Drop for ChunkWriter<'_, W> {
 /// If the `finish` function fails then there is nothing much to
 /// do as the `ChunkWriter` would get lost so the `Unrecoverable`
 /// variant is used to signal that.
+#[allow(clippy::large_enum_variant)]
 enum Wrapper<'a, W: Write> {
     Chunk(ChunkWriter<'a, W>),
-    Zlib(ZlibEncoder<ChunkWriter<'a, W>>),
+    Zlib(ZlibWriter<ChunkWriter<'a, W>>),
     Unrecoverable,
     /// This is used in-between, should never be matched
     None,
@@ -1064,7 +2092,6 @@ pub struct StreamWriter<'a, W: Write> {
     filter: FilterType,
     adaptive_filter: AdaptiveFilterType,
     fctl: Option<FrameControl>,
-    compression: Compression,
 }
 
 impl<'a, W: Write> StreamWriter<'a, W> {
@@ -1073,17 +2100,22 @@ impl<'a, W: Write> StreamWriter<'a, W> {
             return Err(EncodingError::Format(FormatErrorKind::EndReached.into()));
         }
 
+        if writer.info.interlaced {
+            return Err(EncodingError::Parameter(
+                ParameterErrorKind::InterlacedStream.into(),
+            ));
+        }
+
         let PartialInfo {
             width,
             height,
             frame_control: fctl,
-            compression,
             ..
         } = writer.info;
 
         let bpp = writer.info.bpp_in_prediction();
         let in_len = writer.info.raw_row_length() - 1;
-        let filter = writer.filter;
+        let filter = writer.info.filter(writer.filter);
         let adaptive_filter = writer.adaptive_filter;
         let prev_buf = vec![0; in_len];
         let curr_buf = vec![0; in_len];
@@ -1091,7 +2123,7 @@ impl<'a, W: Write> StreamWriter<'a, W> {
         let mut chunk_writer = ChunkWriter::new(writer, buf_len);
         let (line_len, to_write) = chunk_writer.next_frame_info();
         chunk_writer.write_header()?;
-        let zlib = ZlibEncoder::new(chunk_writer, compression.to_options());
+        let zlib = chunk_writer.begin_zlib_stream();
 
         Ok(StreamWriter {
             writer: Wrapper::Zlib(zlib),
@@ -1107,7 +2139,6 @@ impl<'a, W: Write> StreamWriter<'a, W> {
             line_len,
             to_write,
             fctl,
-            compression,
         })
     }
 
@@ -1115,8 +2146,11 @@ impl<'a, W: Write> StreamWriter<'a, W> {
     ///
     /// The default filter is [`FilterType::Sub`] which provides a basic prediction algorithm for
     /// sample values based on the previous. For a potentially better compression ratio, at the
-    /// cost of more complex processing, try out [`FilterType::Paeth`].
+    /// cost of more complex processing, try out [`FilterType::Paeth`]. Indexed images and those
+    /// with a bit depth below 8 instead default to [`FilterType::NoFilter`], which almost always
+    /// compresses them better.
     ///
+    /// [`FilterType::NoFilter`]: enum.FilterType.html#variant.NoFilter
     /// [`FilterType::Sub`]: enum.FilterType.html#variant.Sub
     /// [`FilterType::Paeth`]: enum.FilterType.html#variant.Paeth
     pub fn set_filter(&mut self, filter: FilterType) {
@@ -1327,7 +2361,7 @@ impl<'a, W: Write> StreamWriter<'a, W> {
             Wrapper::Chunk(wrt) => wrt,
             _ => unreachable!(),
         };
-        self.writer = Wrapper::Zlib(ZlibEncoder::new(wrt, self.compression.to_options()));
+        self.writer = Wrapper::Zlib(wrt.begin_zlib_stream());
         Ok(())
     }
 }
@@ -1405,6 +2439,160 @@ impl<W: Write> Drop for StreamWriter<'_, W> {
     }
 }
 
+/// A zlib encoder of either backend.
+#[allow(clippy::large_enum_variant)]
+enum ZlibWriter<W: Write> {
+    Deflate(ZlibEncoder<W>),
+    MinizOxide(MinizEncoder<W>),
+}
+
+impl<W: Write> ZlibWriter<W> {
+    fn new(w: W, backend: DeflateBackend, compression: Compression) -> Self {
+        match backend {
+            DeflateBackend::Deflate => {
+                ZlibWriter::Deflate(ZlibEncoder::new(w, compression.to_options()))
+            }
+            DeflateBackend::MinizOxide => ZlibWriter::MinizOxide(MinizEncoder::new(w, compression)),
+        }
+    }
+
+    /// Completes the stream and returns the inner writer.
+    fn finish(self) -> io::Result<W> {
+        match self {
+            ZlibWriter::Deflate(zlib) => zlib.finish(),
+            ZlibWriter::MinizOxide(zlib) => zlib.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for ZlibWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            ZlibWriter::Deflate(zlib) => zlib.write(data),
+            ZlibWriter::MinizOxide(zlib) => zlib.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ZlibWriter::Deflate(zlib) => zlib.flush(),
+            ZlibWriter::MinizOxide(zlib) => zlib.flush(),
+        }
+    }
+}
+
+/// The flags of the `miniz_oxide` compressor for a `compression`.
+///
+/// A negative `window_bits` selects a raw deflate stream, without the zlib header and checksum.
+fn miniz_flags(compression: Compression, window_bits: i32) -> u32 {
+    let (level, strategy) = match compression {
+        Compression::Default => (6, CompressionStrategy::Default),
+        Compression::Fast => (1, CompressionStrategy::Default),
+        Compression::Best => (9, CompressionStrategy::Default),
+        Compression::Huffman => (1, CompressionStrategy::HuffmanOnly),
+        Compression::Rle => (6, CompressionStrategy::RLE),
+    };
+    create_comp_flags_from_zip_params(level, window_bits, strategy as i32)
+}
+
+/// A zlib encoder writing the output of the `miniz_oxide` compressor.
+///
+/// As the encoder of `deflate`, it completes the stream when dropped.
+struct MinizEncoder<W: Write> {
+    /// The output, until it is returned when the stream is finished.
+    w: Option<W>,
+    compressor: Box<CompressorOxide>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> MinizEncoder<W> {
+    fn new(w: W, compression: Compression) -> Self {
+        let flags = miniz_flags(compression, i32::from(MAX_WINDOW_BITS));
+        MinizEncoder {
+            w: Some(w),
+            compressor: Box::new(CompressorOxide::new(flags)),
+            buffer: vec![0; DEFAULT_BUFFER_LENGTH],
+        }
+    }
+
+    /// Compress `data` and write the output.
+    ///
+    /// Returns the status and the number of bytes consumed and produced.
+    fn compress(
+        &mut self,
+        data: &[u8],
+        flush: TDEFLFlush,
+    ) -> io::Result<(TDEFLStatus, usize, usize)> {
+        let (status, consumed, produced) =
+            compress(&mut self.compressor, data, &mut self.buffer, flush);
+        let w = self.w.as_mut().expect("Stream was finished.");
+        w.write_all(&self.buffer[..produced])?;
+        match status {
+            TDEFLStatus::Okay | TDEFLStatus::Done => Ok((status, consumed, produced)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("compression failed with {:?}", status),
+            )),
+        }
+    }
+
+    fn finish_stream(&mut self) -> io::Result<()> {
+        while self.compress(&[], TDEFLFlush::Finish)?.0 != TDEFLStatus::Done {}
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.finish_stream()?;
+        Ok(self.w.take().unwrap())
+    }
+}
+
+impl<W: Write> Drop for MinizEncoder<W> {
+    fn drop(&mut self) {
+        if self.w.is_some() {
+            let _ = self.finish_stream();
+        }
+    }
+}
+
+impl<W: Write> Write for MinizEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        // Input is only refused while the output buffer is full, which was emptied.
+        loop {
+            let (_, consumed, _) = self.compress(data, TDEFLFlush::None)?;
+            if consumed > 0 {
+                return Ok(consumed);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Output the pending data, as the `deflate` encoder does. The output fits the buffer
+        // once it is not filled completely.
+        while self.compress(&[], TDEFLFlush::Sync)?.2 == self.buffer.len() {}
+        self.w.as_mut().expect("Stream was finished.").flush()
+    }
+}
+
+/// Mod to encapsulate the converters depending on the `deflate` crate.
+///
+/// Since this only contains trait impls, there is no need to make this public, they are simply
+/// available when the mod is compiled as well.
+impl crate::common::Compression {
+    fn to_options(self) -> deflate::CompressionOptions {
+        match self {
+            Compression::Default => deflate::CompressionOptions::default(),
+            Compression::Fast => deflate::CompressionOptions::fast(),
+            Compression::Best => deflate::CompressionOptions::high(),
+            Compression::Huffman => deflate::CompressionOptions::huffman_only(),
+            Compression::Rle => deflate::CompressionOptions::rle(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1419,14 +2607,7 @@ mod tests {
     fn roundtrip() {
         // More loops = more random testing, but also more test wait time
         for _ in 0..10 {
-            for path in glob::glob("tests/pngsuite/*.png")
-                .unwrap()
-                .map(|r| r.unwrap())
-            {
-                if path.file_name().unwrap().to_str().unwrap().starts_with('x') {
-                    // x* files are expected to fail to decode
-                    continue;
-                }
+            for path in crate::test_utils::pngsuite_files() {
                 eprintln!("{}", path.display());
                 // Decode image
                 let decoder = Decoder::new(File::open(path).unwrap());
@@ -1465,14 +2646,7 @@ mod tests {
     fn roundtrip_stream() {
         // More loops = more random testing, but also more test wait time
         for _ in 0..10 {
-            for path in glob::glob("tests/pngsuite/*.png")
-                .unwrap()
-                .map(|r| r.unwrap())
-            {
-                if path.file_name().unwrap().to_str().unwrap().starts_with('x') {
-                    // x* files are expected to fail to decode
-                    continue;
-                }
+            for path in crate::test_utils::pngsuite_files() {
                 // Decode image
                 let decoder = Decoder::new(File::open(path).unwrap());
                 let mut reader = decoder.read_info().unwrap();
@@ -1772,6 +2946,163 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn default_filter() -> Result<()> {
+        let encode = |color: ColorType, depth: BitDepth, filter: Option<FilterType>| -> Result<_> {
+            let mut buffer = vec![];
+            let mut encoder = Encoder::new(&mut buffer, 16, 16);
+            encoder.set_color(color);
+            encoder.set_depth(depth);
+            if color == ColorType::Indexed {
+                encoder.set_palette(vec![0; 3 << (depth as u8)]);
+            }
+            if let Some(filter) = filter {
+                encoder.set_filter(filter);
+            }
+            let mut info = Info::with_size(16, 16);
+            info.color_type = color;
+            info.bit_depth = depth;
+            let len = (info.raw_row_length() - 1) * 16;
+            let data: Vec<u8> = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+            encoder.write_header()?.write_image_data(&data)?;
+            Ok(buffer)
+        };
+
+        for &(color, depth) in [
+            (ColorType::Indexed, BitDepth::Eight),
+            (ColorType::Indexed, BitDepth::Two),
+            (ColorType::Grayscale, BitDepth::Four),
+        ]
+        .iter()
+        {
+            let default = encode(color, depth, None)?;
+            assert_eq!(default, encode(color, depth, Some(FilterType::NoFilter))?);
+            assert_ne!(default, encode(color, depth, Some(FilterType::Sub))?);
+        }
+        let default = encode(ColorType::Rgb, BitDepth::Eight, None)?;
+        assert_eq!(
+            default,
+            encode(ColorType::Rgb, BitDepth::Eight, Some(FilterType::Sub))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unpacked_image_data() -> Result<()> {
+        for &color in [ColorType::Grayscale, ColorType::Indexed].iter() {
+            for &depth in [
+                BitDepth::One,
+                BitDepth::Two,
+                BitDepth::Four,
+                BitDepth::Eight,
+            ]
+            .iter()
+            {
+                let max = (1u16 << (depth as u8)) - 1;
+                let samples: Vec<u8> = (0..7 * 5).map(|i| (i * 5 % (max + 1)) as u8).collect();
+
+                let mut buffer = vec![];
+                let mut encoder = Encoder::new(&mut buffer, 7, 5);
+                encoder.set_color(color);
+                encoder.set_depth(depth);
+                if color == ColorType::Indexed {
+                    encoder.set_palette(vec![0; 3 * (usize::from(max) + 1)]);
+                }
+                encoder
+                    .write_header()?
+                    .write_unpacked_image_data(&samples)?;
+
+                let mut decoder = crate::Decoder::new(&buffer[..]);
+                decoder.set_transformations(crate::Transformations::PACKING);
+                let mut reader = decoder.read_info().unwrap();
+                let mut decoded = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut decoded).unwrap();
+                assert_eq!(decoded, samples, "{:?} {:?}", color, depth);
+            }
+        }
+
+        let mut encoder = Encoder::new(io::sink(), 2, 1);
+        encoder.set_depth(BitDepth::Two);
+        let mut writer = encoder.write_header()?;
+        assert!(writer.write_unpacked_image_data(&[0]).is_err());
+        assert!(writer.write_unpacked_image_data(&[3, 4]).is_err());
+        writer.write_unpacked_image_data(&[3, 2])?;
+        Ok(())
+    }
+
+    #[test]
+    fn bilevel_image_data() -> Result<()> {
+        let decode = |png: &[u8]| {
+            let mut decoder = crate::Decoder::new(png);
+            decoder.set_transformations(crate::Transformations::PACKING);
+            let mut reader = decoder.read_info().unwrap();
+            let mut decoded = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut decoded).unwrap();
+            decoded
+        };
+        let encode = |width: u32, height: u32, samples: &[u8], mode: Bilevel| -> Result<Vec<u8>> {
+            let mut png = vec![];
+            let mut encoder = Encoder::new(&mut png, width, height);
+            encoder.set_depth(BitDepth::One);
+            encoder
+                .write_header()?
+                .write_bilevel_image_data(samples, mode)?;
+            Ok(decode(&png))
+        };
+
+        let samples: Vec<u8> = (0..=255).collect();
+        let bits = encode(16, 16, &samples, Bilevel::Threshold(100))?;
+        let expected: Vec<u8> = samples.iter().map(|&s| (s >= 100) as u8).collect();
+        assert_eq!(bits, expected);
+
+        // Dithering keeps black and white, and renders gray with the matching share of white.
+        assert_eq!(encode(9, 3, &[0; 27], Bilevel::Dither)?, vec![0; 27]);
+        assert_eq!(encode(9, 3, &[255; 27], Bilevel::Dither)?, vec![1; 27]);
+        for &gray in [64u8, 128, 192].iter() {
+            let bits = encode(32, 32, &[gray; 1024], Bilevel::Dither)?;
+            let white = bits.iter().filter(|&&bit| bit == 1).count() as i32;
+            assert!(
+                (white - i32::from(gray) * 1024 / 255).abs() < 16,
+                "{}",
+                gray
+            );
+        }
+
+        let mut encoder = Encoder::new(io::sink(), 2, 1);
+        encoder.set_depth(BitDepth::Two);
+        let mut writer = encoder.write_header()?;
+        match writer.write_bilevel_image_data(&[0, 255], Bilevel::Dither) {
+            Err(EncodingError::Parameter(_)) => {}
+            other => panic!("expected a parameter error, got {:?}", other),
+        }
+        assert!(encode(2, 1, &[0], Bilevel::Dither).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode() -> Result<()> {
+        for path in crate::test_utils::pngsuite_files() {
+            let mut reader = crate::Decoder::new(File::open(&path).unwrap())
+                .read_info()
+                .unwrap();
+            let mut data = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut data).unwrap();
+            let info = reader.info().clone();
+
+            let mut png = vec![];
+            super::encode(&mut png, &info, &data)?;
+
+            let mut reader = crate::Decoder::new(&png[..]).read_info().unwrap();
+            let mut decoded = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut decoded).unwrap();
+            assert_eq!(reader.info().size(), info.size(), "{:?}", path);
+            assert_eq!(reader.info().palette, info.palette, "{:?}", path);
+            assert_eq!(reader.info().trns, info.trns, "{:?}", path);
+            assert!(decoded == data, "{:?}", path);
+        }
+        Ok(())
+    }
+
     #[test]
     fn some_gamma_roundtrip() -> io::Result<()> {
         let pixel: Vec<_> = (0..48).collect();
@@ -1814,6 +3145,733 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn indexed_with_trns() -> Result<()> {
+        for &(depth, bits) in [
+            (BitDepth::One, 1),
+            (BitDepth::Two, 2),
+            (BitDepth::Four, 4),
+            (BitDepth::Eight, 8),
+        ]
+        .iter()
+        {
+            let entries = 1usize << bits;
+            let palette: Vec<u8> = (0..entries * 3).map(|i| (i * 7) as u8).collect();
+            let trns: Vec<u8> = (0..entries / 2).map(|i| (i * 50) as u8).collect();
+            // Every index once per row of 8 pixels.
+            let data: Vec<u8> = (0..8u32 * bits)
+                .map(|i| (i as u8).wrapping_mul(0x35))
+                .collect();
+
+            let mut out = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut out, 8, 8);
+                encoder.set_color(ColorType::Indexed);
+                encoder.set_depth(depth);
+                encoder.set_palette(&palette[..]);
+                encoder.set_trns(&trns[..]);
+                encoder.write_header()?.write_image_data(&data)?;
+            }
+
+            let mut reader = Decoder::new(&*out).read_info().unwrap();
+            assert_eq!(reader.info().palette.as_ref().unwrap()[..], palette[..]);
+            assert_eq!(reader.info().trns.as_ref().unwrap()[..], trns[..]);
+            let mut decoded = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut decoded).unwrap();
+            assert_eq!(decoded, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_tables() {
+        let check = |color, depth, palette: Option<&[u8]>, trns: Option<&[u8]>| {
+            let mut encoder = Encoder::new(Vec::new(), 1, 1);
+            encoder.set_color(color);
+            encoder.set_depth(depth);
+            if let Some(palette) = palette {
+                encoder.set_palette(palette.to_vec());
+            }
+            if let Some(trns) = trns {
+                encoder.set_trns(trns.to_vec());
+            }
+            let checked = encoder.check_constraints(1 << 20).is_ok();
+            assert_eq!(checked, encoder.write_header().is_ok());
+            checked
+        };
+        let (indexed, one, eight) = (ColorType::Indexed, BitDepth::One, BitDepth::Eight);
+
+        assert!(check(indexed, one, Some(&[0; 6]), Some(&[0; 2])));
+        assert!(check(indexed, eight, Some(&[0; 768]), None));
+        // More entries than the bit depth can index, or than a palette may have.
+        assert!(!check(indexed, one, Some(&[0; 9]), None));
+        assert!(!check(indexed, eight, Some(&[0; 771]), None));
+        // Not a whole number of entries, or none.
+        assert!(!check(indexed, eight, Some(&[0; 4]), None));
+        assert!(!check(indexed, eight, Some(&[]), None));
+        // More alpha values than entries.
+        assert!(!check(indexed, eight, Some(&[0; 6]), Some(&[0; 3])));
+
+        assert!(check(ColorType::Rgb, eight, Some(&[0; 6]), Some(&[0; 6])));
+        assert!(!check(ColorType::Rgb, eight, None, Some(&[0; 4])));
+        // Single bytes per sample, only below 16 bits.
+        assert!(check(ColorType::Rgb, eight, None, Some(&[0; 3])));
+        assert!(!check(
+            ColorType::Rgb,
+            BitDepth::Sixteen,
+            None,
+            Some(&[0; 3])
+        ));
+        assert!(check(ColorType::Grayscale, eight, None, Some(&[0; 2])));
+        assert!(check(ColorType::Grayscale, eight, None, Some(&[0; 1])));
+        assert!(!check(ColorType::Grayscale, eight, Some(&[0; 6]), None));
+        assert!(!check(ColorType::Rgba, eight, None, Some(&[0; 8])));
+        assert!(!check(
+            ColorType::GrayscaleAlpha,
+            eight,
+            None,
+            Some(&[0; 2])
+        ));
+    }
+
+    #[test]
+    fn estimate_size() -> Result<()> {
+        for path in crate::test_utils::pngsuite_files() {
+            let mut reader = Decoder::new(File::open(&path).unwrap())
+                .read_info()
+                .unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buf).unwrap();
+
+            let mut out = Vec::new();
+            let estimate = {
+                let mut encoder = Encoder::new(&mut out, info.width, info.height);
+                encoder.set_color(info.color_type);
+                encoder.set_depth(info.bit_depth);
+                if let Some(palette) = &reader.info().palette {
+                    encoder.set_palette(palette.clone());
+                }
+                let estimate = encoder.estimate_size(&buf, info.height)?;
+                encoder.write_header()?.write_image_data(&buf)?;
+                estimate
+            };
+            assert_eq!(estimate, out.len() as u64, "{:?}", path);
+        }
+
+        // A smooth gradient compresses similarly in all parts of the image.
+        let data: Vec<u8> = (0..512 * 512)
+            .map(|i| ((i % 512 + i / 512) / 4) as u8)
+            .collect();
+        let mut out = Vec::new();
+        let estimate = {
+            let mut encoder = Encoder::new(&mut out, 512, 512);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_filter(FilterType::Paeth);
+            assert!(encoder.estimate_size(&data[1..], 16).is_err());
+            let estimate = encoder.estimate_size(&data, 16)?;
+            encoder.write_header()?.write_image_data(&data)?;
+            estimate
+        };
+        let actual = out.len() as u64;
+        assert!(
+            estimate < actual * 3 / 2 && estimate > actual / 2,
+            "{} {}",
+            estimate,
+            actual
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel_roundtrip() -> Result<()> {
+        // Larger than several segments, with rows that do not divide them evenly.
+        let (width, height) = (700, 320);
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|i| ((i % 253) ^ (i / 2900)) as u8)
+            .collect();
+        for &filter in &[FilterType::Sub, FilterType::Paeth] {
+            for &adaptive in &[
+                AdaptiveFilterType::NonAdaptive,
+                AdaptiveFilterType::Adaptive,
+            ] {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = Encoder::new(&mut out, width as u32, height as u32);
+                    encoder.set_color(ColorType::Rgba);
+                    encoder.set_filter(filter);
+                    encoder.set_adaptive_filter(adaptive);
+                    encoder.set_parallel(true);
+                    encoder.write_header()?.write_image_data(&data)?;
+                }
+
+                let mut reader = Decoder::new(&*out).read_info().unwrap();
+                let mut decoded = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut decoded).unwrap();
+                assert!(decoded == data, "{:?} {:?}", filter, adaptive);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn write_image_rows() -> Result<()> {
+        let (width, height) = (13, 7);
+        let data: Vec<u8> = (0..width * height * 3)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        for &interlaced in &[false, true] {
+            let encode = |rows: bool| -> Result<Vec<u8>> {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = Encoder::new(&mut out, width as u32, height as u32);
+                    encoder.set_color(ColorType::Rgb);
+                    encoder.set_interlaced(interlaced);
+                    let mut writer = encoder.write_header()?;
+                    if rows {
+                        writer.write_image_rows(data.chunks(width * 3))?;
+                    } else {
+                        writer.write_image_data(&data)?;
+                    }
+                }
+                Ok(out)
+            };
+            assert_eq!(encode(true)?, encode(false)?);
+        }
+
+        let mut encoder = Encoder::new(Vec::new(), width as u32, height as u32);
+        encoder.set_color(ColorType::Rgb);
+        let mut writer = encoder.write_header()?;
+        assert!(writer
+            .write_image_rows(data.chunks(width * 3).skip(1))
+            .is_err());
+        assert!(writer.write_image_rows(data.chunks(width * 2)).is_err());
+        let twice = data.chunks(width * 3).chain(data.chunks(width * 3));
+        assert!(writer.write_image_rows(twice).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deflate_backends() -> Result<()> {
+        let data: Vec<u8> = (0..96 * 64 * 3)
+            .map(|i| ((i % 251) ^ (i / 640)) as u8)
+            .collect();
+        let compressions = [
+            Compression::Default,
+            Compression::Fast,
+            Compression::Best,
+            Compression::Huffman,
+            Compression::Rle,
+        ];
+        for &backend in &[DeflateBackend::Deflate, DeflateBackend::MinizOxide] {
+            for &compression in compressions.iter() {
+                for &(bits, streaming) in &[(15, false), (15, true), (9, false), (9, true)] {
+                    let mut out = Vec::new();
+                    {
+                        let mut encoder = Encoder::new(&mut out, 96, 64);
+                        encoder.set_color(ColorType::Rgb);
+                        encoder.set_compression(compression);
+                        encoder.set_deflate_window_bits(bits)?;
+                        encoder.set_deflate_backend(backend);
+                        let mut writer = encoder.write_header()?;
+                        if streaming {
+                            let mut stream = writer.stream_writer_with_size(100)?;
+                            // Flushing is only allowed after complete rows.
+                            for piece in data.chunks(4 * 96 * 3) {
+                                stream.write_all(piece)?;
+                                stream.flush()?;
+                            }
+                        } else {
+                            writer.write_image_data(&data)?;
+                        }
+                    }
+
+                    let mut reader = Decoder::new(&*out).read_info().unwrap();
+                    let mut decoded = vec![0; reader.output_buffer_size()];
+                    reader.next_frame(&mut decoded).unwrap();
+                    assert!(
+                        decoded == data,
+                        "{:?} {:?} {} {}",
+                        backend,
+                        compression,
+                        bits,
+                        streaming
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn deflate_window_bits() -> Result<()> {
+        // Repeats far apart from each other, so matches would exceed a small window.
+        let data: Vec<u8> = (0..64 * 64).map(|i| (i % 777 * 13) as u8).collect();
+
+        let mut encoder = Encoder::new(Vec::new(), 64, 64);
+        assert!(encoder.set_deflate_window_bits(7).is_err());
+        assert!(encoder.set_deflate_window_bits(16).is_err());
+
+        for &bits in &[8u8, 9, 12, 15] {
+            for &streaming in &[false, true] {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = Encoder::new(&mut out, 64, 64);
+                    encoder.set_color(ColorType::Grayscale);
+                    encoder.set_compression(Compression::Best);
+                    encoder.set_deflate_window_bits(bits)?;
+                    let mut writer = encoder.write_header()?;
+                    if streaming {
+                        let mut stream = writer.stream_writer_with_size(1)?;
+                        stream.write_all(&data)?;
+                    } else {
+                        writer.write_image_data(&data)?;
+                    }
+                }
+
+                // Collect the zlib stream from all chunks.
+                let mut zlib = Vec::new();
+                let mut chunks = &out[8..];
+                while !chunks.is_empty() {
+                    let len = u32::from_be_bytes([chunks[0], chunks[1], chunks[2], chunks[3]]);
+                    let (chunk, rest) = chunks[8..].split_at(len as usize);
+                    if &chunks[4..8] == b"IDAT" {
+                        zlib.extend_from_slice(chunk);
+                    }
+                    chunks = &rest[4..];
+                }
+                let (cmf, flg) = (zlib[0], zlib[1]);
+                assert_eq!(cmf, (bits - 8) << 4 | 8);
+                assert_eq!((u16::from(cmf) << 8 | u16::from(flg)) % 31, 0);
+
+                let mut reader = Decoder::new(&*out).read_info().unwrap();
+                let mut decoded = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut decoded).unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn animation_play_count() -> Result<()> {
+        let roundtrip = |num_plays: PlayCount| -> Result<PlayCount> {
+            let mut buffer = vec![];
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_animated(1, num_plays)?;
+            encoder.write_header()?.write_image_data(&[0])?;
+
+            let reader = Decoder::new(&*buffer).read_info().unwrap();
+            Ok(reader.info().animation_control().unwrap().num_plays)
+        };
+
+        assert_eq!(roundtrip(PlayCount::Infinite)?, PlayCount::Infinite);
+        assert_eq!(roundtrip(PlayCount::from_u32(0))?, PlayCount::Infinite);
+        let three = PlayCount::Finite(std::num::NonZeroU32::new(3).unwrap());
+        assert_eq!(roundtrip(three)?, three);
+        assert_eq!(three.to_u32(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn private_chunks() -> Result<()> {
+        use crate::chunk::{ChunkReader, ChunkType};
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut png, 2, 2);
+            encoder.set_color(ColorType::Grayscale);
+            let mut writer = encoder.write_header()?;
+            writer.write_private_chunk(ChunkType(*b"meTa"), b"before")?;
+            for &invalid in &[b"META", b"meta", b"mEta", b"me1a"] {
+                assert!(writer
+                    .write_private_chunk(ChunkType(*invalid), &[])
+                    .is_err());
+            }
+            writer.write_image_data(&[1, 2, 3, 4])?;
+            writer.write_private_chunk(ChunkType(*b"thUm"), b"after")?;
+        }
+
+        let chunks: Vec<_> = ChunkReader::new(&png[..])
+            .map(|chunk| chunk.map(|chunk| (chunk.chunk_type, chunk.data)))
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            chunks,
+            [
+                (chunk::IHDR, chunks[0].1.clone()),
+                (ChunkType(*b"meTa"), b"before".to_vec()),
+                (chunk::IDAT, chunks[2].1.clone()),
+                (ChunkType(*b"thUm"), b"after".to_vec()),
+                (chunk::IEND, vec![]),
+            ]
+        );
+
+        let mut reader = Decoder::new(&png[..]).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn suggested_palettes_and_histogram() -> Result<()> {
+        use crate::SuggestedPaletteEntry;
+
+        let entry = |red, alpha, frequency| SuggestedPaletteEntry {
+            red,
+            green: 2,
+            blue: 3,
+            alpha,
+            frequency,
+        };
+        let palettes = vec![
+            SuggestedPalette {
+                name: "caf\u{e9}".into(),
+                sample_depth: BitDepth::Eight,
+                entries: vec![entry(1, 255, 10), entry(200, 0, 0)],
+            },
+            SuggestedPalette {
+                name: "wide".into(),
+                sample_depth: BitDepth::Sixteen,
+                entries: vec![entry(60_000, 65_535, 7)],
+            },
+        ];
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut png, 2, 1);
+            encoder.set_color(ColorType::Indexed);
+            encoder.set_palette(&[0, 0, 0, 255, 255, 255][..]);
+            encoder.set_histogram(vec![3, 1]);
+            for palette in &palettes {
+                encoder.add_suggested_palette(palette.clone());
+            }
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0, 1])?;
+        }
+        let reader = Decoder::new(&png[..]).read_info().unwrap();
+        assert_eq!(reader.info().suggested_palettes, palettes);
+        assert_eq!(reader.info().histogram, Some(vec![3, 1]));
+
+        let rejected = |histogram: Vec<u16>, palettes: &[SuggestedPalette]| {
+            let mut encoder = Encoder::new(Vec::new(), 2, 1);
+            encoder.set_color(ColorType::Indexed);
+            encoder.set_palette(&[0, 0, 0, 255, 255, 255][..]);
+            encoder.set_histogram(histogram);
+            for palette in palettes {
+                encoder.add_suggested_palette(palette.clone());
+            }
+            encoder.write_header().is_err()
+        };
+        let named = |name: &str, sample_depth| SuggestedPalette {
+            name: name.into(),
+            sample_depth,
+            entries: vec![entry(300, 0, 0)],
+        };
+        assert!(rejected(vec![1, 2, 3], &[]));
+        assert!(rejected(vec![1, 2], &[named("", BitDepth::Sixteen)]));
+        assert!(rejected(vec![1, 2], &[named("\u{100}", BitDepth::Sixteen)]));
+        assert!(rejected(vec![1, 2], &[named("a", BitDepth::Eight)]));
+        assert!(rejected(vec![1, 2], &[named("a", BitDepth::Four)]));
+        assert!(rejected(
+            vec![1, 2],
+            &[palettes[0].clone(), palettes[0].clone()]
+        ));
+        assert!(!rejected(vec![1, 2], &[named("a", BitDepth::Sixteen)]));
+        Ok(())
+    }
+
+    #[test]
+    fn retained_chunks() -> Result<()> {
+        use crate::chunk::ChunkType;
+        use crate::{ChunkLocation, RetainedChunk};
+
+        let decode = |png: &[u8]| {
+            let mut decoder = Decoder::new(png);
+            decoder.set_retain_chunks(true);
+            let mut reader = decoder.read_info().unwrap();
+            let mut frame = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut frame).unwrap();
+            let info = reader.finish().unwrap();
+            (info.retained_chunks.clone(), frame)
+        };
+        // The time stamp is retained, a private chunk after the image data is added.
+        let data = std::fs::read("tests/pngsuite/cm0n0g04.png").unwrap();
+        let (mut retained, frame) = decode(&data);
+        let types: Vec<_> = retained
+            .iter()
+            .map(|c| (c.chunk_type, c.location))
+            .collect();
+        assert_eq!(types, [(chunk::tIME, ChunkLocation::BeforePlte)]);
+        retained.push(RetainedChunk {
+            chunk_type: ChunkType(*b"prIv"),
+            data: b"after".to_vec(),
+            location: ChunkLocation::AfterIdat,
+        });
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut png, 32, 32);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_depth(BitDepth::Four);
+            encoder.set_retained_chunks(retained.clone());
+            encoder.write_header()?.write_image_data(&frame)?;
+        }
+        assert_eq!(decode(&png), (retained, frame));
+
+        for &kind in &[chunk::gAMA, chunk::IDAT, ChunkType(*b"ab1c")] {
+            let mut encoder = Encoder::new(Vec::new(), 1, 1);
+            encoder.set_retained_chunks(vec![RetainedChunk {
+                chunk_type: kind,
+                data: vec![],
+                location: ChunkLocation::BeforeIdat,
+            }]);
+            assert!(encoder.write_header().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn background() -> Result<()> {
+        let encode = |color_type, background| {
+            let mut png = Vec::new();
+            let mut encoder = Encoder::new(&mut png, 2, 1);
+            encoder.set_color(color_type);
+            if color_type == ColorType::Indexed {
+                encoder.set_palette(&[0, 0, 0, 255, 255, 255][..]);
+            }
+            encoder.set_background(background);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&vec![1; 2 * color_type.samples()])?;
+            drop(writer);
+            Ok(png)
+        };
+        for &(color_type, background) in &[
+            (ColorType::Grayscale, BackgroundColor::Gray(7)),
+            (ColorType::Rgba, BackgroundColor::Rgb(1, 2, 300)),
+            (ColorType::Indexed, BackgroundColor::Index(1)),
+        ] {
+            let png = encode(color_type, background)?;
+            let reader = Decoder::new(&png[..]).read_info().unwrap();
+            assert_eq!(reader.info().background, Some(background));
+        }
+
+        for &(color_type, background) in &[
+            (ColorType::Rgb, BackgroundColor::Gray(7)),
+            (ColorType::GrayscaleAlpha, BackgroundColor::Index(0)),
+            (ColorType::Indexed, BackgroundColor::Index(2)),
+        ] {
+            match encode(color_type, background) {
+                Err(EncodingError::Format(_)) => {}
+                other => panic!("unexpected result {:?}", other.map(drop)),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_image_data() -> Result<()> {
+        for path in crate::test_utils::pngsuite_files() {
+            let mut reader = Decoder::new(File::open(&path).unwrap())
+                .read_info()
+                .unwrap();
+            let mut zlib = Vec::new();
+            reader.next_frame_compressed(&mut zlib).unwrap();
+            let info = reader.info();
+
+            let mut out = Vec::new();
+            let mut encoder = Encoder::new(&mut out, info.width, info.height);
+            encoder.set_color(info.color_type);
+            encoder.set_depth(info.bit_depth);
+            encoder.set_interlaced(info.interlaced);
+            if let Some(palette) = &info.palette {
+                encoder.set_palette(palette.clone());
+            }
+            let mut writer = encoder.write_header()?;
+            writer.write_compressed_image_data(&zlib)?;
+            drop(writer);
+
+            let mut reader = Decoder::new(File::open(&path).unwrap())
+                .read_info()
+                .unwrap();
+            let mut expected = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut expected).unwrap();
+            let mut reader = Decoder::new(&out[..]).read_info().unwrap();
+            let mut copied = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut copied).unwrap();
+            assert!(copied == expected, "{:?}", path);
+        }
+
+        // The frames of an animation are copied from their fdAT chunks.
+        let frames = [[0u8, 1, 2, 3], [4, 5, 6, 7], [8, 9, 10, 11]];
+        let mut animation = Vec::new();
+        let mut encoder = Encoder::new(&mut animation, 2, 2);
+        encoder.set_color(ColorType::Grayscale);
+        encoder.set_animated(3, 0)?;
+        let mut writer = encoder.write_header()?;
+        for frame in &frames {
+            writer.write_image_data(frame)?;
+        }
+        drop(writer);
+
+        let mut reader = Decoder::new(&animation[..]).read_info().unwrap();
+        let mut copy = Vec::new();
+        let mut encoder = Encoder::new(&mut copy, 2, 2);
+        encoder.set_color(ColorType::Grayscale);
+        encoder.set_animated(3, 0)?;
+        let mut writer = encoder.write_header()?;
+        for _ in &frames {
+            let mut zlib = Vec::new();
+            reader.next_frame_compressed(&mut zlib).unwrap();
+            writer.write_compressed_image_data(&zlib)?;
+        }
+        drop(writer);
+
+        let mut reader = Decoder::new(&copy[..]).read_info().unwrap();
+        for frame in &frames {
+            let mut buf = [0; 4];
+            reader.next_frame(&mut buf).unwrap();
+            assert_eq!(&buf, frame);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn with_info() -> Result<()> {
+        for &name in &["basn3p04", "basi0g16", "tbrn2c08"] {
+            let path = format!("tests/pngsuite/{}.png", name);
+            let mut reader = Decoder::new(File::open(&path).unwrap())
+                .read_info()
+                .unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            let info = reader.info().to_owned_snapshot();
+
+            let mut out = Vec::new();
+            let mut writer = Encoder::with_info(&mut out, info.clone())?.write_header()?;
+            writer.write_image_data(&buf)?;
+            drop(writer);
+
+            let mut reader = Decoder::new(&out[..]).read_info().unwrap();
+            let mut copy = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut copy).unwrap();
+            assert!(copy == buf, "{}", name);
+            let copied = reader.info();
+            assert_eq!(copied.interlaced, info.interlaced);
+            assert_eq!(copied.palette, info.palette);
+            assert_eq!(copied.trns, info.trns);
+        }
+
+        for info in vec![
+            Info::with_size(0, 1),
+            Info::with_size(1, 1)
+                .color_type(ColorType::Rgb)
+                .bit_depth(BitDepth::Four),
+            Info::with_size(1, 1).palette(&[0, 0, 0][..]),
+            Info::with_size(1, 1)
+                .color_type(ColorType::Indexed)
+                .bit_depth(BitDepth::One)
+                .palette(&[0; 9][..]),
+            Info::with_size(1, 1).trns(&[0; 6][..]),
+        ] {
+            assert!(info.validate().is_err());
+            match Encoder::with_info(Vec::new(), info) {
+                Err(EncodingError::Format(_)) => {}
+                other => panic!("unexpected result {:?}", other.map(drop)),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn row_filters() -> Result<()> {
+        use FilterType::*;
+        let cycle = [Paeth, NoFilter, Avg, Up, Sub];
+        for &interlaced in &[false, true] {
+            let (width, height) = (11, 9);
+            let rows = if interlaced {
+                Adam7Iterator::new(width, height).len()
+            } else {
+                height as usize
+            };
+            let filters: Vec<FilterType> = cycle.iter().cycle().take(rows).cloned().collect();
+            let data: Vec<u8> = (0..width * height * 3).map(|i| (i * 13) as u8).collect();
+
+            let mut buffer = vec![];
+            let mut encoder = Encoder::new(&mut buffer, width, height);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_interlaced(interlaced);
+            encoder.set_adaptive_filter(AdaptiveFilterType::Adaptive);
+            let mut writer = encoder.write_header()?;
+            writer.set_row_filters(&filters[1..]);
+            match writer.write_image_data(&data) {
+                Err(EncodingError::Parameter(_)) => {}
+                other => panic!("unexpected result {:?}", other.map(drop)),
+            }
+            writer.set_row_filters(&filters);
+            writer.write_image_data(&data)?;
+            drop(writer);
+
+            let mut reader = Decoder::new(&*buffer).read_info().unwrap();
+            let mut decoded = vec![];
+            while let Some(row) = reader.next_row().unwrap() {
+                decoded.push(row.info().filter);
+            }
+            assert_eq!(decoded, filters);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn interlaced_stream_rejected() -> Result<()> {
+        let mut buffer = vec![];
+        let mut encoder = Encoder::new(&mut buffer, 4, 4);
+        encoder.set_color(ColorType::Grayscale);
+        encoder.set_interlaced(true);
+        let mut writer = encoder.write_header()?;
+        assert!(match writer.stream_writer() {
+            Err(EncodingError::Parameter(_)) => true,
+            _ => false,
+        });
+        writer.write_image_data(&[0; 16])?;
+        drop(writer);
+
+        let reader = Decoder::new(&*buffer).read_info().unwrap();
+        assert!(reader.info().interlaced);
+        Ok(())
+    }
+
+    #[test]
+    fn encoding_constraints() {
+        let mut encoder = Encoder::new(Vec::new(), 100, 100);
+        encoder.set_color(ColorType::Grayscale);
+        let constraints = encoder.constraints(std::usize::MAX);
+        assert_eq!(constraints.max_width, (1 << 31) - 1);
+        assert_eq!(constraints.max_height, (1 << 31) - 1);
+
+        // Exactly the memory of the configured image.
+        let required = constraints.required_memory as usize;
+        let constraints = encoder.constraints(required);
+        assert_eq!((constraints.max_width, constraints.max_height), (100, 100));
+        assert!(encoder.check_constraints(required).is_ok());
+        assert!(matches_limits(encoder.check_constraints(required - 1)));
+
+        let encoder = Encoder::new(Vec::new(), 0, 100);
+        assert!(encoder.check_constraints(std::usize::MAX).is_err());
+    }
+
+    fn matches_limits(result: Result<()>) -> bool {
+        if let Err(EncodingError::LimitsExceeded) = result {
+            true
+        } else {
+            false
+        }
+    }
+
     /// A Writer that only writes a few bytes at a time
     struct RandomChunkWriter<R: Rng, W: Write> {
         rng: R,
@@ -1833,19 +3891,3 @@ mod tests {
         }
     }
 }
-
-/// Mod to encapsulate the converters depending on the `deflate` crate.
-///
-/// Since this only contains trait impls, there is no need to make this public, they are simply
-/// available when the mod is compiled as well.
-impl crate::common::Compression {
-    fn to_options(self) -> deflate::CompressionOptions {
-        match self {
-            Compression::Default => deflate::CompressionOptions::default(),
-            Compression::Fast => deflate::CompressionOptions::fast(),
-            Compression::Best => deflate::CompressionOptions::high(),
-            Compression::Huffman => deflate::CompressionOptions::huffman_only(),
-            Compression::Rle => deflate::CompressionOptions::rle(),
-        }
-    }
-}
@@ -0,0 +1,135 @@
+//! Filtering and compressing the image data on several threads.
+//!
+//! The rows are split into segments that are filtered and deflated independently. Every segment
+//! but the last ends with a full flush, which aligns it to a byte and keeps the stream open, so
+//! the compressed segments can be concatenated into one zlib stream as `pigz` does. Their
+//! checksums are combined without reading the data again.
+use std::io;
+
+use miniz_oxide::deflate::core::{compress_to_output, CompressorOxide, TDEFLFlush, TDEFLStatus};
+use rayon::prelude::*;
+
+use super::{miniz_flags, zlib_header, PartialInfo, MAX_WINDOW_BITS};
+use crate::common::Compression;
+use crate::filter::{filter, AdaptiveFilterType, FilterType};
+
+/// The number of bytes of image data in a segment, which holds at least one row.
+const SEGMENT_LEN: usize = 256 * 1024;
+
+/// The modulus of the Adler-32 checksum.
+const ADLER_BASE: u32 = 65521;
+
+/// Filter and compress the rows of `data`, each `in_len` bytes long, to a zlib stream.
+pub(super) fn compress_image(
+    info: &PartialInfo,
+    filter_method: FilterType,
+    adaptive_method: AdaptiveFilterType,
+    data: &[u8],
+    in_len: usize,
+) -> io::Result<Vec<u8>> {
+    let bpp = info.bpp_in_prediction();
+    let segment_len = (SEGMENT_LEN / in_len).max(1) * in_len;
+    let segments = (data.len() + segment_len - 1) / segment_len;
+    let zeros = vec![0; in_len];
+
+    let compressed = data
+        .par_chunks(segment_len)
+        .enumerate()
+        .map(|(index, segment)| {
+            // The first row of a segment is predicted from the last row of the one before.
+            let start = index * segment_len;
+            let mut prev = if index == 0 {
+                &zeros[..]
+            } else {
+                &data[start - in_len..start]
+            };
+            let mut filtered = Vec::with_capacity(segment.len() + segment.len() / in_len);
+            let mut current = vec![0; in_len];
+            for line in segment.chunks(in_len) {
+                current.copy_from_slice(line);
+                let filter_type = filter(filter_method, adaptive_method, bpp, prev, &mut current);
+                filtered.push(filter_type as u8);
+                filtered.extend_from_slice(&current);
+                prev = line;
+            }
+
+            let flush = if index + 1 == segments {
+                TDEFLFlush::Finish
+            } else {
+                TDEFLFlush::Full
+            };
+            let compression = info.stream_compression(filtered.len());
+            let deflated = deflate(&filtered, compression, flush)?;
+            Ok((deflated, adler32(&filtered), filtered.len()))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut zlib = zlib_header(info.deflate_window_bits).to_vec();
+    let mut checksum = 1;
+    for (deflated, adler, len) in compressed {
+        zlib.extend_from_slice(&deflated);
+        checksum = adler32_combine(checksum, adler, len);
+    }
+    zlib.extend_from_slice(&checksum.to_be_bytes());
+    Ok(zlib)
+}
+
+/// Compress `data` to a raw deflate stream that ends with `flush`.
+fn deflate(data: &[u8], compression: Compression, flush: TDEFLFlush) -> io::Result<Vec<u8>> {
+    let flags = miniz_flags(compression, -i32::from(MAX_WINDOW_BITS));
+    let mut compressor = Box::new(CompressorOxide::new(flags));
+    let mut out = Vec::with_capacity(data.len() / 2);
+    let (status, _) = compress_to_output(&mut compressor, data, flush, |chunk| {
+        out.extend_from_slice(chunk);
+        true
+    });
+    match status {
+        TDEFLStatus::Okay | TDEFLStatus::Done => Ok(out),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("compression failed with {:?}", status),
+        )),
+    }
+}
+
+/// The Adler-32 checksum of `data`.
+fn adler32(data: &[u8]) -> u32 {
+    // The largest number of bytes that can be summed before the sums may overflow.
+    const CHUNK_LEN: usize = 5552;
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(CHUNK_LEN) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= ADLER_BASE;
+        b %= ADLER_BASE;
+    }
+    b << 16 | a
+}
+
+/// The Adler-32 checksum of two concatenated blocks, from their checksums and the `len` of the
+/// second block.
+fn adler32_combine(first: u32, second: u32, len: usize) -> u32 {
+    let base = u64::from(ADLER_BASE);
+    let rem = len as u64 % base;
+    let (first_a, first_b) = (u64::from(first & 0xffff), u64::from(first >> 16));
+    let (second_a, second_b) = (u64::from(second & 0xffff), u64::from(second >> 16));
+    let a = (first_a + second_a + base - 1) % base;
+    let b = (rem * first_a % base + first_b + second_b + base - rem) % base;
+    (b << 16 | a) as u32
+}
+
+#[test]
+fn test_adler32_combine() {
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 253) as u8).collect();
+    assert_eq!(adler32(&[]), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    for &split in &[0, 1, 5552, 12_345, data.len()] {
+        let (first, second) = data.split_at(split);
+        assert_eq!(
+            adler32_combine(adler32(first), adler32(second), second.len()),
+            adler32(&data)
+        );
+    }
+}
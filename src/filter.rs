@@ -1,4 +1,6 @@
-use crate::common::BytesPerPixel;
+use crate::common::{BitDepth, BytesPerPixel, ColorType, Info};
+use crate::decoder::{DecodingError, FormatErrorInner};
+use alloc::vec;
 
 /// The byte level filter applied to scanlines to prepare them for compression.
 ///
@@ -22,6 +24,21 @@ impl Default for FilterType {
 }
 
 impl FilterType {
+    /// The filter used by the encoder for an image unless another one was set.
+    ///
+    /// Indexed and sub-byte images are not filtered: their bytes are not smooth sample values
+    /// that a prediction could improve on, and filtering them usually increases the compressed
+    /// size. All other images use [`FilterType::default`].
+    ///
+    /// [`FilterType::default`]: #impl-Default
+    pub(crate) fn for_image(color: ColorType, depth: BitDepth) -> FilterType {
+        if color == ColorType::Indexed || (depth as u8) < 8 {
+            FilterType::NoFilter
+        } else {
+            FilterType::default()
+        }
+    }
+
     /// u8 -> Self. Temporary solution until Rust provides a canonical one.
     pub fn from_u8(n: u8) -> Option<FilterType> {
         match n {
@@ -79,7 +96,7 @@ pub(crate) fn unfilter(
     tbpp: BytesPerPixel,
     previous: &[u8],
     current: &mut [u8],
-) -> std::result::Result<(), &'static str> {
+) -> Result<(), &'static str> {
     use self::FilterType::*;
     let bpp = tbpp.into_usize();
     let len = current.len();
@@ -204,6 +221,43 @@ pub(crate) fn unfilter(
     }
 }
 
+/// Unfilter the first line of a pass, whose implicit predecessor is a line of zeros.
+///
+/// This is equivalent to `unfilter` with a zeroed previous line but does not require one.
+pub(crate) fn unfilter_first_row(
+    filter: FilterType,
+    tbpp: BytesPerPixel,
+    current: &mut [u8],
+) -> Result<(), &'static str> {
+    use self::FilterType::*;
+    let bpp = tbpp.into_usize();
+    let len = current.len();
+
+    match filter {
+        // Adding zeros does not change the line.
+        NoFilter | Up => Ok(()),
+        // With a zero line above, the Paeth predictor always selects the left pixel.
+        Sub | Paeth => {
+            if filter == Paeth && bpp > len {
+                return Err("Filtering failed: bytes per pixel is greater than length of row");
+            }
+            for i in bpp..len {
+                current[i] = current[i].wrapping_add(current[i - bpp]);
+            }
+            Ok(())
+        }
+        Avg => {
+            if bpp > len {
+                return Err("Filtering failed: bytes per pixel is greater than length of row");
+            }
+            for i in bpp..len {
+                current[i] = current[i].wrapping_add(current[i - bpp] / 2);
+            }
+            Ok(())
+        }
+    }
+}
+
 fn filter_internal(
     method: FilterType,
     bpp: usize,
@@ -256,6 +310,37 @@ fn filter_internal(
     }
 }
 
+/// Reverse the filter of a row of image data in place.
+///
+/// This decodes the image data of a `StreamingDecoder` without a `Reader`, which is also available
+/// without the standard library. The `row` starts with the byte of its filter type, the bytes
+/// after it are unfiltered. `previous` holds the unfiltered bytes of the preceding row of the same
+/// interlace pass, without its filter type, and is `None` for the first row of a pass.
+pub fn unfilter_row(
+    info: &Info,
+    previous: Option<&[u8]>,
+    row: &mut [u8],
+) -> Result<(), DecodingError> {
+    let (kind, current) = match row.split_first_mut() {
+        Some((&mut kind, current)) => (kind, current),
+        None => return Ok(()),
+    };
+    let filter = match FilterType::from_u8(kind) {
+        Some(filter) => filter,
+        None => {
+            return Err(DecodingError::Format(
+                FormatErrorInner::UnknownFilterType(kind).into(),
+            ))
+        }
+    };
+    let bpp = info.bpp_in_prediction();
+    let unfiltered = match previous {
+        Some(previous) => unfilter(filter, bpp, previous, current),
+        None => unfilter_first_row(filter, bpp, current),
+    };
+    unfiltered.map_err(|message| DecodingError::Format(FormatErrorInner::BadFilter(message).into()))
+}
+
 pub(crate) fn filter(
     method: FilterType,
     adaptive: AdaptiveFilterType,
@@ -274,7 +359,7 @@ pub(crate) fn filter(
             // values of each filtered buffer treating the bytes as signed
             // integers. Choose the filter with the smallest sum.
             let mut filtered_buffer = vec![0; len];
-            filtered_buffer.copy_from_slice(&current);
+            filtered_buffer.copy_from_slice(current);
             let mut scratch = vec![0; len];
 
             // Initialize min_sum with the NoFilter buffer sum
@@ -282,7 +367,7 @@ pub(crate) fn filter(
             let mut filter_choice = FilterType::NoFilter;
 
             for &filter in [Sub, Up, Avg, Paeth].iter() {
-                scratch.copy_from_slice(&current);
+                scratch.copy_from_slice(current);
                 filter_internal(filter, bpp, len, previous, &mut scratch);
                 let sum = sum_buffer(&scratch);
                 if sum < min_sum {
@@ -301,13 +386,16 @@ pub(crate) fn filter(
 
 // Helper function for Adaptive filter buffer summation
 fn sum_buffer(buf: &[u8]) -> usize {
-    buf.iter()
-        .fold(0, |acc, &x| acc.saturating_add((x as i8).abs() as usize))
+    buf.iter().fold(0, |acc, &x| {
+        acc.saturating_add(usize::from((x as i8).wrapping_abs() as u8))
+    })
 }
 
 #[cfg(test)]
 mod test {
-    use super::{filter, unfilter, AdaptiveFilterType, BytesPerPixel, FilterType};
+    use super::{
+        filter, unfilter, unfilter_first_row, AdaptiveFilterType, BytesPerPixel, FilterType,
+    };
     use core::iter;
 
     #[test]
@@ -395,4 +483,42 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn first_row_matches_zero_previous_line() {
+        const LEN: u8 = 240;
+        let zeros = vec![0; LEN.into()];
+        let filtered: Vec<_> = (0..LEN).map(|i| i.wrapping_mul(37)).collect();
+
+        let filters = [
+            FilterType::NoFilter,
+            FilterType::Sub,
+            FilterType::Up,
+            FilterType::Avg,
+            FilterType::Paeth,
+        ];
+
+        let bpps = [
+            BytesPerPixel::One,
+            BytesPerPixel::Two,
+            BytesPerPixel::Three,
+            BytesPerPixel::Four,
+            BytesPerPixel::Six,
+            BytesPerPixel::Eight,
+        ];
+
+        for &kind in filters.iter() {
+            for &bpp in bpps.iter() {
+                let mut expected = filtered.clone();
+                unfilter(kind, bpp, &zeros, &mut expected).expect("Unfilter worked");
+                let mut current = filtered.clone();
+                unfilter_first_row(kind, bpp, &mut current).expect("Unfilter worked");
+                assert_eq!(
+                    current, expected,
+                    "Unfiltering the first row with {:?} and {:?} differs",
+                    bpp, kind
+                );
+            }
+        }
+    }
 }
@@ -43,7 +43,6 @@
 //! let mut encoder = png::Encoder::new(w, 2, 1); // Width is 2 pixels and height is 1.
 //! encoder.set_color(png::ColorType::Rgba);
 //! encoder.set_depth(png::BitDepth::Eight);
-//! encoder.set_trns(vec!(0xFFu8, 0xFFu8, 0xFFu8, 0xFFu8));
 //! encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455)); // 1.0 / 2.2, scaled by 100000
 //! encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));     // 1.0 / 2.2, unscaled, but rounded
 //! let source_chromaticities = png::SourceChromaticities::new(     // Using unscaled instantiation here
@@ -59,24 +58,67 @@
 //! writer.write_image_data(&data).unwrap(); // Save
 //! ```
 //!
+//! ## Without the standard library
+//!
+//! The encoder and the `Read` based decoder require the `std` feature, which is enabled by
+//! default. Without it the crate is `no_std` and only needs the `alloc` crate, which requires Rust
+//! 1.36 or later. The [`StreamingDecoder`](struct.StreamingDecoder.html) then decodes the chunks of
+//! an image from byte slices, and [`unfilter_row`](fn.unfilter_row.html) reverses the filters of the
+//! rows of its image data.
+//!
 
-#![forbid(unsafe_code)]
+// Only the C interface needs unsafe code, for the pointers passed to it.
+#![cfg_attr(not(feature = "capi"), forbid(unsafe_code))]
+#![cfg_attr(feature = "capi", deny(unsafe_code))]
+#![cfg_attr(not(feature = "std"), no_std)]
+// Many internal helpers are only used by the encoder and the `Read` based decoder.
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
 
 #[macro_use]
 extern crate bitflags;
 
+// The collections are used through `alloc` paths. With the standard library these are its own, so
+// that the `alloc` crate and its higher Rust version are only required without it.
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std as alloc;
+
+pub mod adam7;
+#[cfg(feature = "capi")]
+#[allow(unsafe_code)]
+pub mod capi;
 pub mod chunk;
 mod common;
 mod decoder;
+#[cfg(feature = "std")]
 mod encoder;
 mod filter;
+#[cfg(feature = "quantize")]
+mod quantize;
 mod srgb;
+#[cfg(test)]
+mod test_utils;
 mod traits;
 mod utils;
 
+pub use crate::chunk::is_png;
 pub use crate::common::*;
+#[cfg(feature = "async")]
+pub use crate::decoder::AsyncDecoder;
+#[cfg(feature = "std")]
 pub use crate::decoder::{
-    Decoded, Decoder, DecodingError, Limits, OutputInfo, Reader, StreamingDecoder,
+    decode, ChunkFields, ChunkRecord, Decoder, DecodingBuffers, Downscale, InterlaceHandling,
+    Progress, ProgressiveCallbacks, ProgressiveDecoder, Reader, RowBatch, RowFormat, Structure,
+};
+pub use crate::decoder::{
+    ColorStatistics, CrcPolicy, Decoded, DecodingError, FormatError, FormatErrorKind,
+    FrameProgress, Limits, OutputInfo, RowInfo, ShortImageData, StreamingDecoder, Utf8Policy,
+};
+#[cfg(feature = "std")]
+pub use crate::encoder::{
+    encode, Encoder, EncodingConstraints, EncodingError, StreamWriter, Writer,
 };
-pub use crate::encoder::{Encoder, EncodingError, StreamWriter, Writer};
-pub use crate::filter::{AdaptiveFilterType, FilterType};
+pub use crate::filter::{unfilter_row, AdaptiveFilterType, FilterType};
+#[cfg(feature = "quantize")]
+pub use crate::quantize::{QuantizedImage, Quantizer};
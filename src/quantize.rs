@@ -0,0 +1,423 @@
+//! Reducing true color images to a palette, for a smaller indexed image.
+//!
+//! The colors are chosen with the median cut algorithm: the colors of the image, weighted by how
+//! often they occur, are split into boxes along the channel of the widest range until there are
+//! as many boxes as colors of the palette. Each box is represented by the mean of its colors.
+//! Pixels are then mapped to the closest entry of the palette, optionally with Floyd–Steinberg
+//! dithering, which spreads the error of each pixel to its neighbours.
+//!
+//! ```
+//! use png::{ColorType, Quantizer};
+//! // A gradient from black to white, with more shades than the palette will have.
+//! let data: Vec<u8> = (0..64u32).flat_map(|x| vec![x as u8 * 4; 3]).collect();
+//! let mut quantizer = Quantizer::new();
+//! quantizer.set_max_colors(16);
+//! quantizer.set_dithering(true);
+//! let image = quantizer.quantize(&data, 64, 1, ColorType::Rgb)?;
+//! assert_eq!(image.palette().len(), 16 * 3);
+//!
+//! let mut png = vec![];
+//! let mut writer = image.encoder(&mut png).write_header()?;
+//! writer.write_image_data(image.data())?;
+//! # Ok::<(), png::EncodingError>(())
+//! ```
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::common::{BitDepth, ColorType, ParameterErrorKind};
+use crate::encoder::{Encoder, EncodingError};
+
+/// Chooses a palette for a true color image and maps its pixels to it.
+#[derive(Clone, Debug)]
+pub struct Quantizer {
+    max_colors: usize,
+    dithering: bool,
+}
+
+/// An image reduced to a palette, with the data of an indexed PNG image.
+#[derive(Clone, Debug)]
+pub struct QuantizedImage {
+    width: u32,
+    height: u32,
+    palette: Vec<u8>,
+    trns: Vec<u8>,
+    bit_depth: BitDepth,
+    data: Vec<u8>,
+}
+
+/// A color with its number of pixels.
+type Entry = ([u8; 4], u32);
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Quantizer::new()
+    }
+}
+
+impl Quantizer {
+    /// A quantizer for a palette of up to 256 colors, without dithering.
+    pub fn new() -> Quantizer {
+        Quantizer {
+            max_colors: 256,
+            dithering: false,
+        }
+    }
+
+    /// Set the largest number of colors of the palette, from 1 to 256.
+    ///
+    /// Fewer colors allow a lower bit depth. An image with at most this many colors keeps them
+    /// exactly.
+    pub fn set_max_colors(&mut self, colors: usize) {
+        self.max_colors = colors.max(1).min(256);
+    }
+
+    /// Set whether the pixels are dithered with Floyd–Steinberg error diffusion.
+    ///
+    /// Dithering avoids bands in gradients at the cost of noise, which compresses worse.
+    pub fn set_dithering(&mut self, dithering: bool) {
+        self.dithering = dithering;
+    }
+
+    /// Reduce an image of `width` by `height` pixels to a palette.
+    ///
+    /// The `data` holds the pixels of 8-bit `ColorType::Rgb` or `ColorType::Rgba` samples, row
+    /// by row. Other color types are rejected with a parameter error, as is data of another
+    /// length.
+    pub fn quantize(
+        &self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+    ) -> Result<QuantizedImage, EncodingError> {
+        let channels = match color_type {
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+            _ => {
+                return Err(EncodingError::Parameter(
+                    ParameterErrorKind::UnsupportedQuantization(color_type).into(),
+                ))
+            }
+        };
+        let expected = width as usize * height as usize * channels;
+        if data.len() != expected {
+            return Err(EncodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected,
+                    actual: data.len(),
+                }
+                .into(),
+            ));
+        }
+        let pixels: Vec<[u8; 4]> = data
+            .chunks(channels)
+            .map(|p| [p[0], p[1], p[2], if channels == 4 { p[3] } else { 0xff }])
+            .collect();
+
+        let mut counts = HashMap::new();
+        for &pixel in &pixels {
+            *counts.entry(pixel).or_insert(0u32) += 1;
+        }
+        let mut colors: Vec<Entry> = counts.into_iter().collect();
+        // Sorted for a palette that does not depend on the order of the hash map.
+        colors.sort_unstable();
+        let exact = colors.len() <= self.max_colors;
+        let mut palette = if exact {
+            colors.iter().map(|&(color, _)| color).collect()
+        } else {
+            median_cut(colors, self.max_colors)
+        };
+        // Translucent entries come first, so that the tRNS chunk stops at the last of them.
+        palette.sort_by_key(|color| color[3] == 0xff);
+
+        let mut indices = Vec::with_capacity(pixels.len());
+        if self.dithering && !exact {
+            dither(&pixels, width as usize, &palette, &mut indices);
+        } else {
+            let mut cache = HashMap::new();
+            for pixel in &pixels {
+                let index = *cache
+                    .entry(*pixel)
+                    .or_insert_with(|| closest(&palette, *pixel));
+                indices.push(index);
+            }
+        }
+
+        let bit_depth = match palette.len() {
+            0..=2 => BitDepth::One,
+            3..=4 => BitDepth::Two,
+            5..=16 => BitDepth::Four,
+            _ => BitDepth::Eight,
+        };
+        let translucent = palette.iter().filter(|color| color[3] != 0xff).count();
+        Ok(QuantizedImage {
+            width,
+            height,
+            palette: palette
+                .iter()
+                .flat_map(|color| color[..3].to_vec())
+                .collect(),
+            trns: palette[..translucent]
+                .iter()
+                .map(|color| color[3])
+                .collect(),
+            bit_depth,
+            data: pack(&indices, width as usize, bit_depth as usize),
+        })
+    }
+}
+
+impl QuantizedImage {
+    /// The palette, with three bytes of red, green and blue for each entry.
+    pub fn palette(&self) -> &[u8] {
+        &self.palette
+    }
+
+    /// The alpha of the first entries of the palette, the others are opaque.
+    ///
+    /// This is empty for an opaque image.
+    pub fn trns(&self) -> &[u8] {
+        &self.trns
+    }
+
+    /// The smallest bit depth that holds the indices of the palette.
+    pub fn bit_depth(&self) -> BitDepth {
+        self.bit_depth
+    }
+
+    /// The packed indices of the pixels, for `Writer::write_image_data`.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// An encoder for the indexed image, with its palette and transparency.
+    ///
+    /// Further settings can be made before writing the header. The image data to write is
+    /// `data`.
+    pub fn encoder<W: Write>(&self, w: W) -> Encoder<'_, W> {
+        let mut encoder: Encoder<'_, W> = Encoder::new(w, self.width, self.height);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(self.bit_depth);
+        encoder.set_palette(&self.palette[..]);
+        if !self.trns.is_empty() {
+            encoder.set_trns(&self.trns[..]);
+        }
+        encoder
+    }
+}
+
+/// Choose up to `max_colors` colors that represent the weighted `colors`.
+fn median_cut(colors: Vec<Entry>, max_colors: usize) -> Vec<[u8; 4]> {
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        // Split the box with the widest range of one channel, weighted by its pixels.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(index, colors)| {
+                let (channel, range) = widest_channel(colors);
+                let pixels: u64 = colors.iter().map(|&(_, count)| u64::from(count)).sum();
+                (u64::from(range) * pixels, index, channel)
+            })
+            .max();
+        let (index, channel) = match widest {
+            Some((_, index, channel)) => (index, channel),
+            None => break,
+        };
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|&(color, _)| color[channel]);
+        let total: u64 = colors.iter().map(|&(_, count)| u64::from(count)).sum();
+        let mut below = 0;
+        let median = colors
+            .iter()
+            .position(|&(_, count)| {
+                below += u64::from(count);
+                2 * below >= total
+            })
+            .unwrap_or(0);
+        // Both halves keep at least one color.
+        let split = (median + 1).min(colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|colors| mean(colors)).collect()
+}
+
+/// The channel with the widest range of values in `colors`, and that range.
+fn widest_channel(colors: &[Entry]) -> (usize, u8) {
+    let mut widest = (0, 0);
+    for channel in 0..4 {
+        let values = colors.iter().map(|&(color, _)| color[channel]);
+        let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+        if range > widest.1 {
+            widest = (channel, range);
+        }
+    }
+    widest
+}
+
+/// The mean of the colors, weighted by their number of pixels.
+fn mean(colors: &[Entry]) -> [u8; 4] {
+    let mut sums = [0u64; 4];
+    let mut total = 0u64;
+    for &(color, count) in colors {
+        for (sum, &sample) in sums.iter_mut().zip(color.iter()) {
+            *sum += u64::from(sample) * u64::from(count);
+        }
+        total += u64::from(count);
+    }
+    let mut mean = [0; 4];
+    for (sample, &sum) in mean.iter_mut().zip(sums.iter()) {
+        *sample = ((sum + total / 2) / total) as u8;
+    }
+    mean
+}
+
+/// The index of the entry of the palette closest to `color`.
+fn closest(palette: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    let distance = |entry: &[u8; 4]| -> u32 {
+        entry
+            .iter()
+            .zip(color.iter())
+            .map(|(&a, &b)| {
+                let d = i32::from(a) - i32::from(b);
+                (d * d) as u32
+            })
+            .sum()
+    };
+    (0..palette.len())
+        .min_by_key(|&index| distance(&palette[index]))
+        .unwrap_or(0) as u8
+}
+
+/// Map the rows of `width` pixels to the palette, spreading the error of each pixel to the
+/// following ones as Floyd and Steinberg do.
+fn dither(pixels: &[[u8; 4]], width: usize, palette: &[[u8; 4]], indices: &mut Vec<u8>) {
+    // The errors of the current and the next row, with a pixel of margin on either side.
+    let mut errors = vec![[0i32; 4]; width + 2];
+    let mut next = vec![[0i32; 4]; width + 2];
+    for row in pixels.chunks(width) {
+        for (x, pixel) in row.iter().enumerate() {
+            let mut color = [0; 4];
+            for channel in 0..4 {
+                let value = i32::from(pixel[channel]) + errors[x + 1][channel] / 16;
+                color[channel] = value.max(0).min(255) as u8;
+            }
+            let index = closest(palette, color);
+            indices.push(index);
+            for channel in 0..4 {
+                let error = i32::from(color[channel]) - i32::from(palette[index as usize][channel]);
+                errors[x + 2][channel] += error * 7;
+                next[x][channel] += error * 3;
+                next[x + 1][channel] += error * 5;
+                next[x + 2][channel] += error;
+            }
+        }
+        std::mem::swap(&mut errors, &mut next);
+        for error in next.iter_mut() {
+            *error = [0; 4];
+        }
+    }
+}
+
+/// Pack the `indices` of rows of `width` pixels with `bits` bits each, rows start at a byte.
+fn pack(indices: &[u8], width: usize, bits: usize) -> Vec<u8> {
+    let row_len = (width * bits + 7) / 8;
+    let mut data = Vec::with_capacity(row_len * (indices.len() / width.max(1)));
+    for row in indices.chunks(width.max(1)) {
+        let mut packed = vec![0; row_len];
+        for (x, &index) in row.iter().enumerate() {
+            let bit = x * bits;
+            packed[bit / 8] |= index << (8 - bits - bit % 8);
+        }
+        data.extend_from_slice(&packed);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decoder;
+
+    /// Decode an encoded quantized image to RGBA.
+    fn decode(image: &QuantizedImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut writer = image.encoder(&mut png).write_header().unwrap();
+        writer.write_image_data(image.data()).unwrap();
+        drop(writer);
+        let mut decoder = Decoder::new(&png[..]);
+        decoder.set_transformations(crate::Transformations::EXPAND);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        if info.color_type == ColorType::Rgb {
+            buf = buf
+                .chunks(3)
+                .flat_map(|p| vec![p[0], p[1], p[2], 0xff])
+                .collect();
+        }
+        buf
+    }
+
+    #[test]
+    fn exact_colors() {
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 128], [0, 0, 0, 0]];
+        let data: Vec<u8> = (0..35)
+            .flat_map(|i| colors[i % 3 * (i % 2)].to_vec())
+            .collect();
+        let image = Quantizer::new()
+            .quantize(&data, 7, 5, ColorType::Rgba)
+            .unwrap();
+        assert_eq!(image.bit_depth(), BitDepth::Two);
+        assert_eq!(image.palette().len(), 9);
+        assert_eq!(image.trns().len(), 2);
+        assert_eq!(decode(&image), data);
+    }
+
+    #[test]
+    fn reduced_colors() {
+        let (width, height) = (37, 23);
+        let data: Vec<u8> = (0..width * height)
+            .flat_map(|i| vec![(i % width * 7) as u8, (i / width * 11) as u8, 0x80])
+            .collect();
+        for &dithering in &[false, true] {
+            let mut quantizer = Quantizer::new();
+            quantizer.set_max_colors(16);
+            quantizer.set_dithering(dithering);
+            let image = quantizer
+                .quantize(&data, width, height, ColorType::Rgb)
+                .unwrap();
+            assert_eq!(image.bit_depth(), BitDepth::Four);
+            assert_eq!(image.palette().len(), 16 * 3);
+            assert!(image.trns().is_empty());
+
+            let decoded = decode(&image);
+            let pixels = data.chunks(3).zip(decoded.chunks(4));
+            let error: u64 = pixels
+                .flat_map(|(a, b)| {
+                    a.iter()
+                        .zip(b)
+                        .map(|(&a, &b)| (i32::from(a) - i32::from(b)).abs() as u64)
+                })
+                .sum();
+            // The mean error of a sample stays well below the spacing of the gradients.
+            assert!(error < data.len() as u64 * 24, "{} {}", dithering, error);
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        let quantizer = Quantizer::new();
+        match quantizer.quantize(&[0; 4], 2, 2, ColorType::Grayscale) {
+            Err(EncodingError::Parameter(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        match quantizer.quantize(&[0; 11], 2, 2, ColorType::Rgb) {
+            Err(EncodingError::Parameter(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Helpers shared by the unit tests and the integration tests.
+use std::path::PathBuf;
+
+/// The images of the PNG suite, without the `x*` files that are expected to fail to decode.
+pub(crate) fn pngsuite_files() -> impl Iterator<Item = PathBuf> {
+    glob::glob("tests/pngsuite/*.png")
+        .unwrap()
+        .map(|r| r.unwrap())
+        .filter(|path| !path.file_name().unwrap().to_str().unwrap().starts_with('x'))
+}
+
+/// The images of the PNG suite whose file names start with one of the `prefixes`.
+pub(crate) fn pngsuite_files_with<'a>(
+    prefixes: &'a [&'a str],
+) -> impl Iterator<Item = PathBuf> + 'a {
+    pngsuite_files().filter(move |path| {
+        let name = path.file_name().unwrap().to_str().unwrap();
+        prefixes.iter().any(|prefix| name.starts_with(prefix))
+    })
+}
+
+/// The animated images of the test suite.
+pub(crate) fn animated_files() -> impl Iterator<Item = PathBuf> {
+    glob::glob("tests/animated/*.png")
+        .unwrap()
+        .map(|r| r.unwrap())
+}
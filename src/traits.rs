@@ -1,18 +1,36 @@
+#[cfg(feature = "std")]
 use std::io;
 
 macro_rules! read_bytes_ext {
     ($output_type:ty) => {
+        #[cfg(feature = "std")]
         impl<W: io::Read + ?Sized> ReadBytesExt<$output_type> for W {
             #[inline]
             fn read_be(&mut self) -> io::Result<$output_type> {
-                let mut bytes = [0u8; std::mem::size_of::<$output_type>()];
+                let mut bytes = [0u8; core::mem::size_of::<$output_type>()];
                 self.read_exact(&mut bytes)?;
                 Ok(<$output_type>::from_be_bytes(bytes))
             }
         }
+
+        #[cfg(not(feature = "std"))]
+        impl ReadBytesExt<$output_type> for &[u8] {
+            #[inline]
+            fn read_be(&mut self) -> Result<$output_type, UnexpectedEof> {
+                let mut bytes = [0u8; core::mem::size_of::<$output_type>()];
+                if self.len() < bytes.len() {
+                    return Err(UnexpectedEof);
+                }
+                let (head, rest) = self.split_at(bytes.len());
+                bytes.copy_from_slice(head);
+                *self = rest;
+                Ok(<$output_type>::from_be_bytes(bytes))
+            }
+        }
     };
 }
 
+#[cfg(feature = "std")]
 macro_rules! write_bytes_ext {
     ($input_type:ty) => {
         impl<W: io::Write + ?Sized> WriteBytesExt<$input_type> for W {
@@ -25,12 +43,26 @@ macro_rules! write_bytes_ext {
 }
 
 /// Read extension to read big endian data
+#[cfg(feature = "std")]
 pub trait ReadBytesExt<T>: io::Read {
     /// Read `T` from a bytes stream. Most significant byte first.
     fn read_be(&mut self) -> io::Result<T>;
 }
 
+/// Read extension to read big endian data from a slice, without the standard library.
+#[cfg(not(feature = "std"))]
+pub trait ReadBytesExt<T> {
+    /// Read `T` from the start of the slice. Most significant byte first.
+    fn read_be(&mut self) -> Result<T, UnexpectedEof>;
+}
+
+/// The slice ended before the data that was read, without the standard library.
+#[cfg(not(feature = "std"))]
+#[derive(Debug)]
+pub struct UnexpectedEof;
+
 /// Write extension to write big endian data
+#[cfg(feature = "std")]
 pub trait WriteBytesExt<T>: io::Write {
     /// Writes `T` to a bytes stream. Most significant byte first.
     fn write_be(&mut self, _: T) -> io::Result<()>;
@@ -40,4 +72,5 @@ read_bytes_ext!(u8);
 read_bytes_ext!(u16);
 read_bytes_ext!(u32);
 
+#[cfg(feature = "std")]
 write_bytes_ext!(u32);
@@ -1,6 +1,8 @@
 //! Utility functions
-use std::iter::{repeat, StepBy};
-use std::ops::Range;
+use alloc::vec;
+use core::iter::{repeat, StepBy};
+use core::mem;
+use core::ops::Range;
 
 #[inline(always)]
 pub fn unpack_bits<F>(buf: &mut [u8], channels: usize, bit_depth: u8, func: F)
@@ -38,6 +40,48 @@ where
     }
 }
 
+/// Packs `samples` of `bit_depth` bits, one per byte, into `buf`, the inverse of `unpack_bits`.
+///
+/// The samples are stored from the most significant bits on and the unused bits of the last byte
+/// are zero. Returns the first sample that does not fit into `bit_depth` bits, if any.
+pub fn pack_bits(samples: &[u8], buf: &mut [u8], bit_depth: u8) -> Result<(), u8> {
+    let bits = usize::from(bit_depth);
+    let max = ((1u16 << bit_depth) - 1) as u8;
+    for (byte, chunk) in buf.iter_mut().zip(samples.chunks(8 / bits)) {
+        *byte = 0;
+        for (i, &sample) in chunk.iter().enumerate() {
+            if sample > max {
+                return Err(sample);
+            }
+            *byte |= sample << (8 - bits * (i + 1));
+        }
+    }
+    Ok(())
+}
+
+/// Reduces rows of `width` 8-bit gray samples to 0 or 1 with Floyd-Steinberg dithering.
+pub fn dither_bilevel(samples: &mut [u8], width: usize) {
+    // The errors diffused onto the current and the next row, with a pixel of margin on each side.
+    let mut current = vec![0i32; width + 2];
+    let mut next = vec![0i32; width + 2];
+    for row in samples.chunks_mut(width.max(1)) {
+        for (x, sample) in row.iter_mut().enumerate() {
+            let value = i32::from(*sample) + current[x + 1] / 16;
+            let white = value >= 128;
+            let error = if white { value - 255 } else { value };
+            *sample = white as u8;
+            current[x + 2] += error * 7;
+            next[x] += error * 3;
+            next[x + 1] += error * 5;
+            next[x + 2] += error;
+        }
+        mem::swap(&mut current, &mut next);
+        for error in next.iter_mut() {
+            *error = 0;
+        }
+    }
+}
+
 pub fn expand_trns_line(buf: &mut [u8], trns: &[u8], channels: usize) {
     // Return early if empty. This enables to subtract `channels` later without overflow.
     if buf.len() < (channels + 1) {
@@ -62,6 +106,291 @@ pub fn expand_trns_line(buf: &mut [u8], trns: &[u8], channels: usize) {
     }
 }
 
+/// Convert the first `pixels` pixels of 8-bit samples to RGBA, in place.
+///
+/// One and two channels are grayscale with and without alpha, three channels are RGB. The buffer
+/// must have room for the RGBA pixels.
+pub fn expand_rgba8_line(buf: &mut [u8], channels: usize, pixels: usize) {
+    if channels == 4 {
+        return;
+    }
+    for i in (0..pixels).rev() {
+        let pixel = &buf[i * channels..(i + 1) * channels];
+        let rgba = match *pixel {
+            [v] => [v, v, v, 0xFF],
+            [v, a] => [v, v, v, a],
+            [r, g, b] => [r, g, b, 0xFF],
+            _ => unreachable!(),
+        };
+        buf[4 * i..4 * i + 4].copy_from_slice(&rgba);
+    }
+}
+
+/// Convert the first `pixels` pixels to 16-bit RGBA, in place.
+///
+/// The channels are interpreted as in `expand_rgba8_line`. Samples are 16-bit if `wide` is set,
+/// 8-bit samples are scaled to the full 16-bit range. The buffer must have room for the RGBA
+/// pixels.
+pub fn expand_rgba16_line(buf: &mut [u8], channels: usize, wide: bool, pixels: usize) {
+    if channels == 4 && wide {
+        return;
+    }
+    let sample_bytes = if wide { 2 } else { 1 };
+    for i in (0..pixels).rev() {
+        let mut samples = [0u16; 4];
+        for (k, sample) in samples.iter_mut().enumerate().take(channels) {
+            let at = (i * channels + k) * sample_bytes;
+            *sample = if wide {
+                u16::from(buf[at]) << 8 | u16::from(buf[at + 1])
+            } else {
+                u16::from(buf[at]) * 257
+            };
+        }
+        let rgba = match channels {
+            1 => [samples[0], samples[0], samples[0], 0xFFFF],
+            2 => [samples[0], samples[0], samples[0], samples[1]],
+            3 => [samples[0], samples[1], samples[2], 0xFFFF],
+            _ => samples,
+        };
+        for (k, sample) in rgba.iter().enumerate() {
+            buf[8 * i + 2 * k..8 * i + 2 * k + 2].copy_from_slice(&sample.to_be_bytes());
+        }
+    }
+}
+
+/// Remove the alpha sample, the last `sample_bytes` of every pixel, in place.
+///
+/// Returns the length of the remaining data.
+pub fn strip_alpha_line(buf: &mut [u8], pixel_bytes: usize, sample_bytes: usize) -> usize {
+    let color_bytes = pixel_bytes - sample_bytes;
+    let pixels = buf.len() / pixel_bytes;
+    for i in 0..pixels {
+        for k in 0..color_bytes {
+            buf[i * color_bytes + k] = buf[i * pixel_bytes + k];
+        }
+    }
+    pixels * color_bytes
+}
+
+/// Append an opaque alpha sample of `sample_bytes` to the first `pixels` pixels, in place.
+///
+/// The buffer must have room for the added samples. Returns the length of the resulting data.
+pub fn add_alpha_line(
+    buf: &mut [u8],
+    pixel_bytes: usize,
+    sample_bytes: usize,
+    pixels: usize,
+) -> usize {
+    let alpha_bytes = pixel_bytes + sample_bytes;
+    for i in (0..pixels).rev() {
+        for k in (0..pixel_bytes).rev() {
+            buf[i * alpha_bytes + k] = buf[i * pixel_bytes + k];
+        }
+        for sample in &mut buf[i * alpha_bytes + pixel_bytes..(i + 1) * alpha_bytes] {
+            *sample = 0xFF;
+        }
+    }
+    pixels * alpha_bytes
+}
+
+/// Composite pixels onto a `background` color in place, removing their alpha sample.
+///
+/// The background has a sample for every color channel, the pixels have an alpha sample after
+/// them. Samples are 16-bit if `wide` is set. Returns the length of the remaining data.
+pub fn composite_line(buf: &mut [u8], background: &[u16], wide: bool) -> usize {
+    let colors = background.len();
+    let (sample_bytes, max) = if wide { (2, 0xFFFF) } else { (1, 0xFF) };
+    let pixel_bytes = (colors + 1) * sample_bytes;
+    let sample = |buf: &[u8], at: usize| -> u32 {
+        if wide {
+            u32::from(u16::from_be_bytes([buf[at], buf[at + 1]]))
+        } else {
+            u32::from(buf[at])
+        }
+    };
+    let pixels = buf.len() / pixel_bytes;
+    for i in 0..pixels {
+        let start = i * pixel_bytes;
+        let alpha = sample(buf, start + colors * sample_bytes);
+        for (k, &back) in background.iter().enumerate() {
+            let color = sample(buf, start + k * sample_bytes);
+            let value = (color * alpha + u32::from(back) * (max - alpha) + max / 2) / max;
+            // Never after a sample of this pixel that is still to be read.
+            let at = (i * colors + k) * sample_bytes;
+            if wide {
+                buf[at..at + 2].copy_from_slice(&(value as u16).to_be_bytes());
+            } else {
+                buf[at] = value as u8;
+            }
+        }
+    }
+    pixels * colors * sample_bytes
+}
+
+/// Reorder the samples of every pixel, in place.
+///
+/// Red and blue are swapped if `bgr` is set and the alpha sample is moved to the front if
+/// `alpha_first` is set. `channels` includes the alpha channel.
+pub fn reorder_channels_line(
+    buf: &mut [u8],
+    channels: usize,
+    sample_bytes: usize,
+    bgr: bool,
+    alpha_first: bool,
+) {
+    let has_alpha = channels % 2 == 0;
+    for pixel in buf.chunks_mut(channels * sample_bytes) {
+        if bgr && channels >= 3 {
+            for k in 0..sample_bytes {
+                pixel.swap(k, 2 * sample_bytes + k);
+            }
+        }
+        if alpha_first && has_alpha {
+            pixel.rotate_right(sample_bytes);
+        }
+    }
+}
+
+/// Keep only the alpha sample of the first `pixels` pixels of 8-bit RGBA, in place.
+pub fn alpha_mask_line(buf: &mut [u8], pixels: usize) {
+    for i in 0..pixels {
+        buf[i] = buf[4 * i + 3];
+    }
+}
+
+/// Reverse the order of the packed pixels of `bits_pp` bits within each byte, in place.
+pub fn swap_packed_line(buf: &mut [u8], bits_pp: u8) {
+    let mask = (1u8 << bits_pp) - 1;
+    for byte in buf.iter_mut() {
+        let mut swapped = 0;
+        for shift in (0..8).step_by(bits_pp as usize) {
+            swapped |= ((*byte >> shift) & mask) << (8 - bits_pp - shift);
+        }
+        *byte = swapped;
+    }
+}
+
+/// Move the first `rows` lines of `line_size` bytes of `buf` apart to start every `stride` bytes.
+///
+/// The lines are moved from the last one on, so that none is overwritten before it is moved.
+pub fn spread_lines(buf: &mut [u8], line_size: usize, stride: usize, rows: usize) {
+    for row in (1..rows).rev() {
+        let (src, dst) = (row * line_size, row * stride);
+        if dst >= src + line_size {
+            let (head, tail) = buf.split_at_mut(dst);
+            tail[..line_size].copy_from_slice(&head[src..src + line_size]);
+        } else {
+            for i in (0..line_size).rev() {
+                buf[dst + i] = buf[src + i];
+            }
+        }
+    }
+}
+
+/// Copy `pixels` pixels of `bits_pp` bits of `line`, starting with pixel `x`, into `out`.
+///
+/// Packed pixels are shifted to start at the most significant bits of `out` and the unused bits
+/// of its last byte are zero.
+pub fn crop_line(line: &[u8], out: &mut [u8], x: usize, pixels: usize, bits_pp: usize) {
+    if bits_pp % 8 == 0 {
+        let bytes_pp = bits_pp / 8;
+        out.copy_from_slice(&line[x * bytes_pp..][..pixels * bytes_pp]);
+        return;
+    }
+
+    let mask = (1u8 << bits_pp) - 1;
+    for byte in out.iter_mut() {
+        *byte = 0;
+    }
+    for i in 0..pixels {
+        let src = (x + i) * bits_pp;
+        let pixel = (line[src / 8] >> (8 - src % 8 - bits_pp)) & mask;
+        let dst = i * bits_pp;
+        out[dst / 8] |= pixel << (8 - dst % 8 - bits_pp);
+    }
+}
+
+/// Invert the gray samples of grayscale pixels, in place.
+///
+/// With two channels the second, the alpha channel, is left unchanged.
+pub fn invert_gray_line(buf: &mut [u8], channels: usize, sample_bytes: usize) {
+    for pixel in buf.chunks_mut(channels * sample_bytes) {
+        for byte in &mut pixel[..sample_bytes] {
+            *byte = !*byte;
+        }
+    }
+}
+
+/// Swap the bytes of all 16-bit samples, in place.
+pub fn swap_endian_line(buf: &mut [u8]) {
+    for sample in buf.chunks_mut(2) {
+        sample.swap(0, 1);
+    }
+}
+
+/// Scale a 16-bit sample to 8 bits, rounding to the nearest value.
+pub fn scale_16(sample: u16) -> u8 {
+    ((u32::from(sample) * 255 + 32895) >> 16) as u8
+}
+
+/// Scale the 16-bit samples of a line to 8 bits, in place.
+///
+/// Color samples are looked up in the `table` of `scale_16_gamma_table` if one is given. The
+/// alpha sample, the last of every pixel if `channels` is even, is always scaled linearly.
+/// Returns the length of the scaled data.
+pub fn scale_16_line(buf: &mut [u8], channels: usize, table: Option<&[u8]>) -> usize {
+    let len = buf.len() / 2;
+    let has_alpha = channels % 2 == 0;
+    for i in 0..len {
+        let sample = u16::from_be_bytes([buf[2 * i], buf[2 * i + 1]]);
+        buf[i] = match table {
+            Some(table) if !(has_alpha && i % channels == channels - 1) => table[sample as usize],
+            _ => scale_16(sample),
+        };
+    }
+    len
+}
+
+/// A table mapping every 16-bit sample to 8 bits, raising the normalized value to `exponent`.
+#[cfg(feature = "std")]
+pub fn scale_16_gamma_table(exponent: f64) -> Vec<u8> {
+    (0..=0xffff)
+        .map(|sample| {
+            let value = f64::from(sample) / 65535.0;
+            (value.powf(exponent) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// A table mapping every sample up to `max` to the normalized sample raised to `exponent`.
+#[cfg(feature = "std")]
+pub fn gamma_table(exponent: f64, max: u16) -> Vec<u16> {
+    let scale = f64::from(max);
+    (0..=max)
+        .map(|sample| ((f64::from(sample) / scale).powf(exponent) * scale).round() as u16)
+        .collect()
+}
+
+/// Look up the samples of a line in a `table` of `gamma_table`, in place.
+///
+/// The alpha sample, the last of every pixel if `channels` is even, is not changed. Samples are
+/// 16-bit if `wide` is set.
+pub fn gamma_line(buf: &mut [u8], channels: usize, wide: bool, table: &[u16]) {
+    let has_alpha = channels % 2 == 0;
+    let sample_bytes = if wide { 2 } else { 1 };
+    for (i, sample) in buf.chunks_exact_mut(sample_bytes).enumerate() {
+        if has_alpha && i % channels == channels - 1 {
+            continue;
+        }
+        if wide {
+            let value = table[usize::from(u16::from_be_bytes([sample[0], sample[1]]))];
+            sample.copy_from_slice(&value.to_be_bytes());
+        } else {
+            sample[0] = table[usize::from(sample[0])] as u8;
+        }
+    }
+}
+
 pub fn expand_trns_line16(buf: &mut [u8], trns: &[u8], channels: usize) {
     let c2 = 2 * channels;
     // Return early if empty. This enables to subtract `channels` later without overflow.
@@ -87,81 +416,49 @@ pub fn expand_trns_line16(buf: &mut [u8], trns: &[u8], channels: usize) {
     }
 }
 
-/// This iterator iterates over the different passes of an image Adam7 encoded
-/// PNG image
-/// The pattern is:
-///     16462646
-///     77777777
-///     56565656
-///     77777777
-///     36463646
-///     77777777
-///     56565656
-///     77777777
+/// Write the pixels of `row` into an image of `width` by `height` pixels, each filling a block.
 ///
-#[derive(Clone)]
-pub(crate) struct Adam7Iterator {
-    line: u32,
-    lines: u32,
-    line_width: u32,
-    current_pass: u8,
-    width: u32,
-    height: u32,
-}
-
-impl Adam7Iterator {
-    pub fn new(width: u32, height: u32) -> Adam7Iterator {
-        let mut this = Adam7Iterator {
-            line: 0,
-            lines: 0,
-            line_width: 0,
-            current_pass: 1,
-            width,
-            height,
-        };
-        this.init_pass();
-        this
-    }
-
-    /// Calculates the bounds of the current pass
-    fn init_pass(&mut self) {
-        let w = f64::from(self.width);
-        let h = f64::from(self.height);
-        let (line_width, lines) = match self.current_pass {
-            1 => (w / 8.0, h / 8.0),
-            2 => ((w - 4.0) / 8.0, h / 8.0),
-            3 => (w / 4.0, (h - 4.0) / 8.0),
-            4 => ((w - 2.0) / 4.0, h / 4.0),
-            5 => (w / 2.0, (h - 2.0) / 4.0),
-            6 => ((w - 1.0) / 2.0, h / 2.0),
-            7 => (w, (h - 1.0) / 2.0),
-            _ => unreachable!(),
-        };
-        self.line_width = line_width.ceil() as u32;
-        self.lines = lines.ceil() as u32;
-        self.line = 0;
-    }
-
-    /// The current pass#.
-    pub fn current_pass(&self) -> u8 {
-        self.current_pass
-    }
-}
-
-/// Iterates over the (passes, lines, widths)
-impl Iterator for Adam7Iterator {
-    type Item = (u8, u32, u32);
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.line < self.lines && self.line_width > 0 {
-            let this_line = self.line;
-            self.line += 1;
-            Some((self.current_pass, this_line, self.line_width))
-        } else if self.current_pass < 7 {
-            self.current_pass += 1;
-            self.init_pass();
-            self.next()
+/// The first pixel of the row is placed in column `x` of row `y` of the image and the following
+/// ones every `step` columns. Blocks are cut off at the edges of the image. Pixels of less than
+/// 8 bits are stored from the least significant bits of each byte on if they are `swapped`.
+pub fn fill_blocks(
+    img: &mut [u8],
+    (width, height): (u32, u32),
+    row: &[u8],
+    (x, y, step): (u32, u32, u32),
+    (block_width, block_height): (u32, u32),
+    bits_pp: usize,
+    swapped: bool,
+) {
+    let line_size = (width as usize * bits_pp + 7) / 8;
+    let rows = y..height.min(y + block_height);
+    for (i, x) in (x..width).step_by(step as usize).enumerate() {
+        let columns = x..width.min(x + block_width);
+        if bits_pp % 8 == 0 {
+            let bytes_pp = bits_pp / 8;
+            let pixel = &row[i * bytes_pp..][..bytes_pp];
+            for y in rows.clone() {
+                let line = &mut img[y as usize * line_size..][..line_size];
+                for x in columns.clone() {
+                    line[x as usize * bytes_pp..][..bytes_pp].copy_from_slice(pixel);
+                }
+            }
         } else {
-            None
+            let mask = (1u8 << bits_pp) - 1;
+            let src = i * bits_pp;
+            let pixel = (row[src / 8] >> (8 - src % 8 - bits_pp)) & mask;
+            for y in rows.clone() {
+                let line = &mut img[y as usize * line_size..][..line_size];
+                for x in columns.clone() {
+                    let dst = x as usize * bits_pp;
+                    let shift = if swapped {
+                        dst % 8
+                    } else {
+                        8 - dst % 8 - bits_pp
+                    };
+                    line[dst / 8] = line[dst / 8] & !(mask << shift) | pixel << shift;
+                }
+            }
         }
     }
 }
@@ -251,28 +548,96 @@ pub fn expand_pass(
     }
 }
 
-#[test]
-fn test_adam7() {
-    /*
-        1646
-        7777
-        5656
-        7777
-    */
-    let it = Adam7Iterator::new(4, 4);
-    let passes: Vec<_> = it.collect();
-    assert_eq!(
-        &*passes,
-        &[
-            (1, 0, 1),
-            (4, 0, 1),
-            (5, 0, 2),
-            (6, 0, 2),
-            (6, 1, 2),
-            (7, 0, 4),
-            (7, 1, 4)
-        ]
-    );
+/// Collects the line `line_no` of an Adam 7 pass from an image, the reverse of `expand_pass`.
+pub fn collect_pass(
+    img: &[u8],
+    width: u32,
+    scanline: &mut [u8],
+    pass: u8,
+    line_no: u32,
+    bits_pp: u8,
+) {
+    let width = width as usize;
+    let line_no = line_no as usize;
+    let bits_pp = bits_pp as usize;
+
+    if pass == 0 || pass > 7 {
+        return;
+    }
+
+    let bit_indices = expand_adam7_bits(pass, width, line_no, bits_pp);
+
+    if bits_pp < 8 {
+        let mask = (1u8 << bits_pp) - 1;
+        for byte in scanline.iter_mut() {
+            *byte = 0;
+        }
+        for (n, pos) in bit_indices.enumerate() {
+            let px = (img[pos / 8] >> (8 - pos % 8 - bits_pp)) & mask;
+            let out = n * bits_pp;
+            scanline[out / 8] |= px << (8 - out % 8 - bits_pp);
+        }
+    } else {
+        let bytes_pp = bits_pp / 8;
+
+        for (bitpos, px) in bit_indices.zip(scanline.chunks_mut(bytes_pp)) {
+            px.copy_from_slice(&img[bitpos / 8..][..bytes_pp]);
+        }
+    }
+}
+
+/// Expands an Adam 7 pass of pixels of `bytes_pp` bytes into a column-major image.
+///
+/// The pixel in column `x` and row `y` of the image is stored at pixel index `x * height + y`.
+pub fn expand_pass_transposed(
+    img: &mut [u8],
+    width: u32,
+    height: u32,
+    scanline: &[u8],
+    pass: u8,
+    line_no: u32,
+    bytes_pp: usize,
+) {
+    if pass == 0 || pass > 7 {
+        return;
+    }
+
+    let width = width as usize;
+    let height = height as usize;
+    let bit_indices = expand_adam7_bits(pass, width, line_no as usize, bytes_pp * 8);
+    for (bitpos, px) in bit_indices.zip(scanline.chunks(bytes_pp)) {
+        let pixel = bitpos / 8 / bytes_pp;
+        let (x, y) = (pixel % width, pixel / width);
+        let start = (x * height + y) * bytes_pp;
+        img[start..start + bytes_pp].copy_from_slice(px);
+    }
+}
+
+/// Writes `rows` consecutive row-major rows of `width` pixels, which start at row `first_row`
+/// of the image, into a column-major image of `height` rows.
+///
+/// The rows are copied column by column, so that a band of a few rows is read while each
+/// column's bytes are written one after another.
+pub fn transpose_band(
+    img: &mut [u8],
+    height: u32,
+    band: &[u8],
+    width: u32,
+    first_row: u32,
+    bytes_pp: usize,
+) {
+    let row_len = width as usize * bytes_pp;
+    let rows = band.len() / row_len.max(1);
+    let column_len = height as usize * bytes_pp;
+    let band_start = first_row as usize * bytes_pp;
+    for x in 0..width as usize {
+        let column = x * column_len + band_start;
+        let column = &mut img[column..column + rows * bytes_pp];
+        for (y, px) in column.chunks_exact_mut(bytes_pp).enumerate() {
+            let start = y * row_len + x * bytes_pp;
+            px.copy_from_slice(&band[start..start + bytes_pp]);
+        }
+    }
 }
 
 #[test]
@@ -310,7 +675,7 @@ fn test_expand_adam7_bits() {
             expected(start, 8, 4)
         );
 
-        let start = (8 * line_no + 4) as usize * width as usize;
+        let start = (8 * line_no + 4) * width;
 
         assert_eq!(
             expand_adam7_bits(3, width, line_no, bits_pp).collect::<Vec<_>>(),
@@ -467,3 +832,80 @@ fn test_expand_pass_subbyte() {
         img
     );
 }
+
+#[test]
+fn test_expand_trns_line16() {
+    // Two gray pixels, the second one is transparent.
+    let mut gray = [0x12, 0x34, 0xab, 0xcd, 0, 0, 0, 0];
+    expand_trns_line16(&mut gray, &[0xab, 0xcd], 1);
+    assert_eq!(gray, [0x12, 0x34, 0xff, 0xff, 0xab, 0xcd, 0, 0]);
+
+    // Only the full 16-bit sample matches the tRNS color.
+    let mut rgb = [1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 7, 0, 0, 0, 0];
+    expand_trns_line16(&mut rgb, &[1, 2, 3, 4, 5, 6], 3);
+    assert_eq!(rgb, [1, 2, 3, 4, 5, 6, 0, 0, 1, 2, 3, 4, 5, 7, 0xff, 0xff]);
+}
+
+#[test]
+fn test_pack_bits() {
+    for &bit_depth in [1u8, 2, 4].iter() {
+        let max = (1u8 << bit_depth) - 1;
+        for len in 0..20 {
+            let samples: Vec<u8> = (0..len).map(|i| (i * 7 % 11) as u8 & max).collect();
+            let mut packed = vec![0xff; (len * usize::from(bit_depth) + 7) / 8];
+            assert_eq!(pack_bits(&samples, &mut packed, bit_depth), Ok(()));
+
+            let mut unpacked = vec![0; len.max(packed.len())];
+            unpacked[..packed.len()].copy_from_slice(&packed);
+            unpack_bits(&mut unpacked[..len], 1, bit_depth, |sample, out| {
+                out[0] = sample
+            });
+            assert_eq!(&unpacked[..len], &samples[..]);
+
+            // The padding of the last byte is zero.
+            let padding = packed.len() * 8 - len * usize::from(bit_depth);
+            if let Some(&last) = packed.last() {
+                assert_eq!(last & ((1u16 << padding) - 1) as u8, 0);
+            }
+        }
+        let mut packed = [0; 2];
+        assert_eq!(
+            pack_bits(&[0, max + 1], &mut packed, bit_depth),
+            Err(max + 1)
+        );
+    }
+}
+
+#[test]
+fn test_transpose() {
+    // A 3x4 image of 2 bytes per pixel, numbered in row-major order.
+    let pixels: Vec<u8> = (0..12).flat_map(|i| vec![i, 0x80 | i]).collect();
+    let expected: Vec<u8> = (0..3)
+        .flat_map(|x| (0..4).map(move |y| 3 * y + x))
+        .flat_map(|i| vec![i, 0x80 | i])
+        .collect();
+
+    let mut img = vec![0; 24];
+    transpose_band(&mut img, 4, &pixels[..6 * 3], 3, 0, 2);
+    transpose_band(&mut img, 4, &pixels[6 * 3..], 3, 3, 2);
+    assert_eq!(img, expected);
+
+    let mut img = vec![0; 24];
+    for (pass, line, width) in crate::adam7::Adam7Iterator::new(3, 4) {
+        let (step, first) = match pass {
+            1 | 3 => (8, 0),
+            2 => (8, 4),
+            4 => (4, 2),
+            5 => (2, 0),
+            6 => (2, 1),
+            _ => (1, 0),
+        };
+        let y = crate::adam7::image_row(pass, line) as u8;
+        let scanline: Vec<u8> = (0..width as u8)
+            .map(|i| 3 * y + first + step * i)
+            .flat_map(|i| vec![i, 0x80 | i])
+            .collect();
+        expand_pass_transposed(&mut img, 3, 4, &scanline, pass, line, 2);
+    }
+    assert_eq!(img, expected);
+}
@@ -0,0 +1,108 @@
+//! Counts the heap allocations made while decoding small images.
+//!
+//! This is a separate test binary since it replaces the global allocator.
+extern crate png;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn encode_rgba8(width: u32, height: u32) -> Vec<u8> {
+    let data: Vec<u8> = (0..width * height * 4).map(|i| (i * 7) as u8).collect();
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_filter(png::FilterType::Paeth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&data).unwrap();
+    }
+    png
+}
+
+/// Decode the image into a preallocated buffer, returning the number of allocations.
+fn count_decode_allocations(png: &[u8], transform: png::Transformations, buf: &mut [u8]) -> usize {
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    {
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(transform);
+        let mut reader = decoder.read_info().unwrap();
+        reader.next_frame(buf).unwrap();
+    }
+    ALLOCATIONS.load(Ordering::SeqCst) - before
+}
+
+/// Same as `count_decode_allocations`, reading from the slice without an additional buffer.
+fn count_buffered_decode_allocations(png: &[u8], buf: &mut [u8]) -> usize {
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    {
+        let decoder = png::Decoder::new(png);
+        let mut reader = decoder.read_info_buffered().unwrap();
+        reader.next_frame(buf).unwrap();
+    }
+    ALLOCATIONS.load(Ordering::SeqCst) - before
+}
+
+// Only a single test, other tests running concurrently would disturb the count.
+#[test]
+fn small_rgba8_images() {
+    let icon = encode_rgba8(16, 16);
+    let tall = encode_rgba8(16, 256);
+    let taller = encode_rgba8(16, 512);
+    let mut buf = vec![0; 16 * 512 * 4];
+
+    // The slice is already buffered and the chunks, the decompressor and the image data of a
+    // small image are kept inline. The zlib backend allocates its state and window itself.
+    let expected = if cfg!(feature = "zlib") { 2 } else { 0 };
+    let buffered_allocations = count_buffered_decode_allocations(&icon, &mut buf[..16 * 16 * 4]);
+    assert_eq!(
+        buffered_allocations, expected,
+        "decoding a 16x16 image allocated {} times",
+        buffered_allocations
+    );
+
+    // Otherwise only the buffer of the reader is allocated.
+    let identity = png::Transformations::IDENTITY;
+    let icon_allocations = count_decode_allocations(&icon, identity, &mut buf[..16 * 16 * 4]);
+    assert_eq!(icon_allocations, buffered_allocations + 1);
+
+    // Rows are unfiltered in place, no allocations are made per row once larger images moved the
+    // buffers to the heap.
+    let tall_allocations = count_decode_allocations(&tall, identity, &mut buf[..16 * 256 * 4]);
+    let taller_allocations = count_decode_allocations(&taller, identity, &mut buf);
+    assert_eq!(tall_allocations, taller_allocations);
+
+    // Transformations without effect on RGBA8 images do not need a buffer for processed rows.
+    for &transform in [
+        png::Transformations::normalize_to_color8(),
+        png::Transformations::RGBA8,
+    ]
+    .iter()
+    {
+        let allocations = count_decode_allocations(&icon, transform, &mut buf[..16 * 16 * 4]);
+        assert_eq!(allocations, icon_allocations);
+    }
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "async")]
+#![allow(clippy::incompatible_msrv)]
+extern crate glob;
+extern crate png;
+
+// Not every helper is used by this test.
+#[allow(dead_code)]
+#[path = "../src/test_utils.rs"]
+mod test_utils;
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct Noop;
+
+impl Wake for Noop {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Poll the future until it is ready.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Noop));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// A source that alternates between being pending and reading at most 5 bytes.
+struct Trickle<'a> {
+    data: &'a [u8],
+    pending: bool,
+}
+
+impl futures_io::AsyncRead for Trickle<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.pending = !self.pending;
+        if self.pending {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let len = buf.len().min(self.data.len()).min(5);
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Poll::Ready(Ok(len))
+    }
+}
+
+#[test]
+fn matches_sync_decoder() {
+    for path in test_utils::pngsuite_files() {
+        let data = std::fs::read(&path).unwrap();
+        let mut decoder = png::Decoder::new(&data[..]);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().unwrap();
+        let mut expected = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut expected).unwrap();
+
+        let buf = block_on(async {
+            let mut decoder = png::AsyncDecoder::new(Trickle {
+                data: &data,
+                pending: false,
+            });
+            decoder.set_transformations(png::Transformations::EXPAND);
+            decoder.read_info().await?;
+            let mut buf = vec![0; decoder.output_buffer_size()];
+            decoder.next_frame(&mut buf).await?;
+            Ok::<_, png::DecodingError>(buf)
+        })
+        .unwrap();
+
+        assert!(buf == expected, "{:?}", path);
+    }
+}
+
+#[test]
+fn truncated_data() {
+    let data = std::fs::read("tests/pngsuite/basn0g01.png").unwrap();
+    let result = block_on(async {
+        let mut decoder = png::AsyncDecoder::new(&data[..100]);
+        decoder.read_info().await?;
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.next_frame(&mut buf).await
+    });
+    assert!(result.is_err());
+}
@@ -10,9 +10,9 @@ use std::path::{Component, Path, PathBuf};
 
 use crc32fast::Hasher as Crc32;
 
-const BASE_PATH: [&'static str; 2] = [".", "tests"];
-const TEST_SUITES: [&'static str; 3] = ["pngsuite", "pngsuite-extra", "bugfixes"];
-const APNG_SUITES: [&'static str; 1] = ["animated"];
+const BASE_PATH: [&str; 2] = [".", "tests"];
+const TEST_SUITES: [&str; 3] = ["pngsuite", "pngsuite-extra", "bugfixes"];
+const APNG_SUITES: [&str; 1] = ["animated"];
 
 fn process_images<F>(results_path: &str, test_suites: &[&'static str], func: F)
 where
@@ -85,10 +85,9 @@ where
     );
     for (path, crc) in results.iter() {
         assert_eq!(
-            ref_results.get(path).expect(&format!(
-                "reference for {} is missing, expected {}",
-                path, crc
-            )),
+            ref_results
+                .get(path)
+                .unwrap_or_else(|| panic!("reference for {} is missing, expected {}", path, crc)),
             crc,
             "{}",
             path
@@ -126,9 +125,9 @@ fn render_images_identity() {
         let mut reader = decoder.read_info()?;
         let mut img_data = vec![0; reader.output_buffer_size()];
         let info = reader.next_frame(&mut img_data)?;
-        let bits = (info.width as usize * info.color_type.samples() * info.bit_depth as usize + 7
-            & !7)
-            * info.height as usize;
+        let bits =
+            ((info.width as usize * info.color_type.samples() * info.bit_depth as usize + 7) & !7)
+                * info.height as usize;
         // First sanity check:
         assert_eq!(
             img_data.len() * 8,
@@ -184,6 +183,199 @@ fn apng_images() {
     })
 }
 
+#[test]
+fn render_images_rgba() {
+    process_images("results_rgba.txt", &TEST_SUITES, |path| {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::RGBA8);
+        let mut reader = decoder.read_info()?;
+        let mut img_data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut img_data)?;
+        assert_eq!(
+            img_data.len(),
+            info.width as usize * info.height as usize * 4
+        );
+        let mut crc = Crc32::new();
+        crc.update(&img_data);
+        Ok(crc.finalize())
+    })
+}
+
+/// Decode an image without transformations.
+fn decode_identity(path: &Path) -> (png::Info<'static>, Vec<u8>) {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+    (reader.info().clone(), data)
+}
+
+/// Convert the samples of an image as stored to 16-bit RGBA, independently of the decoder.
+fn reference_rgba16(info: &png::Info, data: &[u8]) -> Vec<u16> {
+    use png::ColorType::*;
+    let depth = info.bit_depth as usize;
+    let samples = info.color_type.samples();
+    let row_len = (info.width as usize * samples * depth + 7) / 8;
+    let max = (1u32 << depth) - 1;
+    let trns = info.trns.as_ref().map(|trns| &trns[..]);
+    // The samples of the transparent color. The info keeps one byte for each below 16 bits.
+    let key = |i: usize| -> u32 {
+        let trns = trns.unwrap();
+        match depth {
+            16 => u32::from(trns[2 * i]) << 8 | u32::from(trns[2 * i + 1]),
+            _ => u32::from(trns[i]),
+        }
+    };
+
+    let mut rgba = Vec::new();
+    for row in data.chunks(row_len).take(info.height as usize) {
+        let sample = |index: usize| -> u32 {
+            match depth {
+                16 => u32::from(row[2 * index]) << 8 | u32::from(row[2 * index + 1]),
+                _ => {
+                    let bit = index * depth;
+                    u32::from(row[bit / 8]) >> (8 - bit % 8 - depth) & max
+                }
+            }
+        };
+        let scale = |value: u32| (value * 65535 / max) as u16;
+        for x in 0..info.width as usize {
+            let pixel: Vec<u32> = (0..samples).map(|c| sample(x * samples + c)).collect();
+            let opaque = |transparent: bool| if transparent { 0 } else { 65535 };
+            let out = match info.color_type {
+                Grayscale => {
+                    let gray = scale(pixel[0]);
+                    let alpha = opaque(trns.map_or(false, |_| pixel[0] == key(0)));
+                    [gray, gray, gray, alpha]
+                }
+                GrayscaleAlpha => {
+                    let gray = scale(pixel[0]);
+                    [gray, gray, gray, scale(pixel[1])]
+                }
+                Rgb => {
+                    let transparent = trns.map_or(false, |_| (0..3).all(|c| pixel[c] == key(c)));
+                    [
+                        scale(pixel[0]),
+                        scale(pixel[1]),
+                        scale(pixel[2]),
+                        opaque(transparent),
+                    ]
+                }
+                Rgba => [
+                    scale(pixel[0]),
+                    scale(pixel[1]),
+                    scale(pixel[2]),
+                    scale(pixel[3]),
+                ],
+                Indexed => {
+                    let index = pixel[0] as usize;
+                    let palette = info.palette.as_ref().unwrap();
+                    let color = |c: usize| u16::from(palette[3 * index + c]) * 257;
+                    let alpha = trns.and_then(|trns| trns.get(index)).map_or(255, |&a| a);
+                    [color(0), color(1), color(2), u16::from(alpha) * 257]
+                }
+            };
+            rgba.extend_from_slice(&out);
+        }
+    }
+    rgba
+}
+
+#[test]
+fn rgba_matches_reference() {
+    for path in glob::glob("tests/pngsuite/[!x]*.png").unwrap() {
+        let path = path.unwrap();
+        let (info, data) = decode_identity(&path);
+        let expected = reference_rgba16(&info, &data);
+
+        let decode = |transform| {
+            let mut decoder = png::Decoder::new(File::open(&path).unwrap());
+            decoder.set_transformations(transform);
+            let mut reader = decoder.read_info().unwrap();
+            let mut data = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut data).unwrap();
+            data
+        };
+        let rgba16: Vec<u16> = decode(png::Transformations::RGBA16)
+            .chunks(2)
+            .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+            .collect();
+        assert!(rgba16 == expected, "16-bit RGBA of {}", path.display());
+        let rgba8: Vec<u8> = expected.iter().map(|&sample| (sample >> 8) as u8).collect();
+        assert!(
+            decode(png::Transformations::RGBA8) == rgba8,
+            "8-bit RGBA of {}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn interlaced_matches_progressive() {
+    for path in glob::glob("tests/pngsuite/basi*.png").unwrap() {
+        let interlaced = path.unwrap();
+        let name = interlaced.file_name().unwrap().to_str().unwrap();
+        let progressive = interlaced.with_file_name(name.replacen("basi", "basn", 1));
+        let (info, data) = decode_identity(&interlaced);
+        assert!(info.interlaced);
+        assert!(
+            data == decode_identity(&progressive).1,
+            "{} differs from {}",
+            interlaced.display(),
+            progressive.display()
+        );
+    }
+}
+
+#[test]
+fn corrupt_images_fail() {
+    for path in glob::glob("tests/pngsuite/x*.png").unwrap() {
+        let path = path.unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        let frame = std::panic::catch_unwind(|| -> Result<(), png::DecodingError> {
+            let mut reader = png::Decoder::new(&data[..]).read_info()?;
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf)?;
+            reader.finish()?;
+            Ok(())
+        });
+        let rows = std::panic::catch_unwind(|| -> Result<(), png::DecodingError> {
+            let mut decoder = png::Decoder::new(&data[..]);
+            decoder.set_transformations(png::Transformations::RGBA8);
+            let mut reader = decoder.read_info()?;
+            while reader.next_row()?.is_some() {}
+            reader.finish()?;
+            Ok(())
+        });
+        // The streaming decoder leaves some checks to the reader, it only must not panic.
+        let stream = std::panic::catch_unwind(|| -> Result<(), png::DecodingError> {
+            let mut decoder = png::StreamingDecoder::new();
+            let (mut input, mut image_data) = (&data[..], Vec::new());
+            loop {
+                let (consumed, event) = decoder.update(input, &mut image_data)?;
+                input = &input[consumed..];
+                match event {
+                    png::Decoded::ImageEnd => return Ok(()),
+                    png::Decoded::Nothing if input.is_empty() => return Ok(()),
+                    _ => {}
+                }
+            }
+        });
+
+        if stream.is_err() {
+            panic!("stream panicked on {}", path.display());
+        }
+        for (api, result) in [("frame", frame), ("rows", rows)].iter() {
+            match result {
+                Ok(Err(_)) => {}
+                Ok(Ok(())) => panic!("{} decoded {} without error", api, path.display()),
+                Err(_) => panic!("{} panicked on {}", api, path.display()),
+            }
+        }
+    }
+}
+
 // until rust standardizes path normalization, see https://github.com/rust-lang/rfcs/issues/2208
 fn normalize_path(path: &Path) -> PathBuf {
     let mut components = path.components().peekable();
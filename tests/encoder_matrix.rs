@@ -0,0 +1,305 @@
+//! Encodes every combination of color type, bit depth, interlacing and transparency with known
+//! pixel patterns and decodes them again.
+//!
+//! Unlike the test images this does not depend on any fixtures, the expected pixels are computed
+//! from the same patterns the images were generated from.
+extern crate png;
+
+use png::{BitDepth, ColorType, Transformations};
+
+const COLOR_DEPTHS: [(ColorType, &[BitDepth]); 5] = [
+    (
+        ColorType::Grayscale,
+        &[
+            BitDepth::One,
+            BitDepth::Two,
+            BitDepth::Four,
+            BitDepth::Eight,
+            BitDepth::Sixteen,
+        ],
+    ),
+    (ColorType::Rgb, &[BitDepth::Eight, BitDepth::Sixteen]),
+    (
+        ColorType::Indexed,
+        &[
+            BitDepth::One,
+            BitDepth::Two,
+            BitDepth::Four,
+            BitDepth::Eight,
+        ],
+    ),
+    (
+        ColorType::GrayscaleAlpha,
+        &[BitDepth::Eight, BitDepth::Sixteen],
+    ),
+    (ColorType::Rgba, &[BitDepth::Eight, BitDepth::Sixteen]),
+];
+
+/// Sizes that leave some of the Adam7 passes empty or partially filled.
+const SIZES: [(u32, u32); 5] = [(1, 1), (3, 2), (8, 8), (13, 9), (33, 5)];
+
+/// One image of the matrix, with its samples one per `u16`.
+struct Case {
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    interlaced: bool,
+    width: u32,
+    height: u32,
+    samples: Vec<u16>,
+    palette: Option<Vec<u8>>,
+    /// The tRNS chunk in the layout the encoder accepts.
+    trns: Option<Vec<u8>>,
+}
+
+impl Case {
+    fn new(
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        interlaced: bool,
+        with_trns: bool,
+        (width, height): (u32, u32),
+    ) -> Case {
+        let depth = bit_depth as u32;
+        let channels = color_type.samples();
+        let mut samples = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..channels as u32 {
+                    samples.push(pattern(x, y, c, depth));
+                }
+            }
+        }
+
+        let entries = 1usize << depth.min(8);
+        let palette = if color_type == ColorType::Indexed {
+            let mut palette = Vec::new();
+            for i in 0..entries {
+                palette.extend_from_slice(&[i as u8, 255 - i as u8, (i * 3 + 1) as u8]);
+            }
+            Some(palette)
+        } else {
+            None
+        };
+
+        let trns = if !with_trns {
+            None
+        } else if color_type == ColorType::Indexed {
+            Some((0..(entries / 2).max(1)).map(|i| (i * 37) as u8).collect())
+        } else {
+            // Make the first pixel transparent, so that the color shows up in the image.
+            let mut trns = Vec::new();
+            for &sample in &samples[..channels] {
+                trns.extend_from_slice(&sample.to_be_bytes());
+            }
+            Some(trns)
+        };
+
+        Case {
+            color_type,
+            bit_depth,
+            interlaced,
+            width,
+            height,
+            samples,
+            palette,
+            trns,
+        }
+    }
+
+    fn pixels(&self) -> std::slice::Chunks<'_, u16> {
+        self.samples.chunks(self.color_type.samples())
+    }
+
+    /// The image data in the layout of its color type and bit depth.
+    fn raw(&self) -> Vec<u8> {
+        let depth = self.bit_depth as usize;
+        let row_len = self.width as usize * self.color_type.samples();
+        let mut raw = Vec::new();
+        for row in self.samples.chunks(row_len) {
+            match depth {
+                16 => {
+                    for &sample in row {
+                        raw.extend_from_slice(&sample.to_be_bytes());
+                    }
+                }
+                8 => raw.extend(row.iter().map(|&sample| sample as u8)),
+                _ => {
+                    for chunk in row.chunks(8 / depth) {
+                        let mut byte = 0;
+                        for (i, &sample) in chunk.iter().enumerate() {
+                            byte |= (sample as u8) << (8 - depth * (i + 1));
+                        }
+                        raw.push(byte);
+                    }
+                }
+            }
+        }
+        raw
+    }
+
+    /// The image data after the `EXPAND` transformation, which keeps 16-bit samples.
+    fn expanded(&self) -> Vec<u8> {
+        let depth = self.bit_depth as u32;
+        let max = (1u32 << depth) - 1;
+        let push = |out: &mut Vec<u8>, sample: u16| {
+            if depth == 16 {
+                out.extend_from_slice(&sample.to_be_bytes());
+            } else {
+                out.push((u32::from(sample) * (255 / max)) as u8);
+            }
+        };
+
+        let mut out = Vec::new();
+        for pixel in self.pixels() {
+            match (self.color_type, &self.palette) {
+                (ColorType::Indexed, Some(palette)) => {
+                    let index = pixel[0] as usize;
+                    out.extend_from_slice(&palette[3 * index..][..3]);
+                    if let Some(ref trns) = self.trns {
+                        out.push(*trns.get(index).unwrap_or(&0xff));
+                    }
+                }
+                _ => {
+                    for &sample in pixel {
+                        push(&mut out, sample);
+                    }
+                    if let Some(ref trns) = self.trns {
+                        let color: Vec<u8> = pixel.iter().flat_map(|s| s.to_be_bytes()).collect();
+                        let alpha = if color == *trns { 0 } else { 0xffff };
+                        if depth == 16 {
+                            out.extend_from_slice(&[alpha as u8; 2]);
+                        } else {
+                            out.push(alpha as u8);
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(self.color_type);
+        encoder.set_depth(self.bit_depth);
+        encoder.set_interlaced(self.interlaced);
+        if let Some(ref palette) = self.palette {
+            encoder.set_palette(palette.clone());
+        }
+        if let Some(ref trns) = self.trns {
+            encoder.set_trns(trns.clone());
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&self.raw()).unwrap();
+        drop(writer);
+        png
+    }
+
+    fn decode(&self, png: &[u8], transform: Transformations) -> Vec<u8> {
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(transform);
+        let mut reader = decoder.read_info().unwrap();
+        {
+            let info = reader.info();
+            assert_eq!(info.color_type, self.color_type);
+            assert_eq!(info.bit_depth, self.bit_depth);
+            assert_eq!(info.interlaced, self.interlaced);
+            assert_eq!(info.trns.is_some(), self.trns.is_some());
+        }
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let output = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(output.buffer_size());
+        buf
+    }
+
+    fn name(&self) -> String {
+        format!(
+            "{:?} {:?} {}x{}{}{}",
+            self.color_type,
+            self.bit_depth,
+            self.width,
+            self.height,
+            if self.interlaced { " interlaced" } else { "" },
+            if self.trns.is_some() {
+                " with tRNS"
+            } else {
+                ""
+            },
+        )
+    }
+}
+
+/// A sample of `depth` bits that differs between neighbouring pixels and channels.
+fn pattern(x: u32, y: u32, c: u32, depth: u32) -> u16 {
+    let hash = (x * 7 + y * 13 + c * 31 + x * y).wrapping_mul(2_654_435_761);
+    (hash >> 8) as u16 & ((1u32 << depth) - 1) as u16
+}
+
+fn cases() -> Vec<Case> {
+    let mut cases = Vec::new();
+    for &(color_type, depths) in &COLOR_DEPTHS {
+        let trns_allowed = match color_type {
+            ColorType::Grayscale | ColorType::Rgb | ColorType::Indexed => true,
+            ColorType::GrayscaleAlpha | ColorType::Rgba => false,
+        };
+        for &bit_depth in depths {
+            for &interlaced in &[false, true] {
+                for &with_trns in &[false, true] {
+                    if with_trns && !trns_allowed {
+                        continue;
+                    }
+                    for &size in &SIZES {
+                        cases.push(Case::new(
+                            color_type, bit_depth, interlaced, with_trns, size,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    cases
+}
+
+#[test]
+fn decode_identity() {
+    let cases = cases();
+    // 15 color types and bit depths, 11 of them also with tRNS, interlaced or not, in all sizes.
+    assert_eq!(cases.len(), 26 * 2 * SIZES.len());
+    for case in cases {
+        let png = case.encode();
+        let data = case.decode(&png, Transformations::IDENTITY);
+        assert!(data == case.raw(), "{}", case.name());
+    }
+}
+
+#[test]
+fn decode_expanded() {
+    for case in cases() {
+        let png = case.encode();
+        let data = case.decode(&png, Transformations::EXPAND);
+        assert!(data == case.expanded(), "{}", case.name());
+    }
+}
+
+#[test]
+fn interlacing_keeps_pixels() {
+    for case in cases().into_iter().filter(|case| case.interlaced) {
+        let png = case.encode();
+        let progressive = Case::new(
+            case.color_type,
+            case.bit_depth,
+            false,
+            case.trns.is_some(),
+            (case.width, case.height),
+        );
+        let progressive_png = progressive.encode();
+        assert!(png != progressive_png, "{}", case.name());
+        assert!(
+            case.decode(&png, Transformations::EXPAND)
+                == progressive.decode(&progressive_png, Transformations::EXPAND),
+            "{}",
+            case.name()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+extern crate glob;
+extern crate png;
+extern crate rand;
+
+use std::io::{self, Read};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const TEST_SUITES: [&str; 4] = ["pngsuite", "pngsuite-extra", "bugfixes", "animated"];
+
+/// A reader that returns a single byte per call.
+struct OneByte<'a>(&'a [u8]);
+
+impl Read for OneByte<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(1);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+/// A reader returning a random number of bytes, or `WouldBlock` when no data is available.
+struct ShortReads<'a> {
+    data: &'a [u8],
+    rng: StdRng,
+}
+
+impl Read for ShortReads<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.rng.gen_bool(0.3) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(self.rng.gen_range(1, 17));
+        self.data.read(&mut buf[..len])
+    }
+}
+
+/// Retry the operation for as long as the input would block.
+fn retry<T>(mut f: impl FnMut() -> Result<T, png::DecodingError>) -> Result<T, png::DecodingError> {
+    loop {
+        match f() {
+            Err(png::DecodingError::IoError(ref err))
+                if err.kind() == io::ErrorKind::WouldBlock => {}
+            result => return result,
+        }
+    }
+}
+
+/// Decode all frames, returning the error message if decoding fails.
+fn decode_all<R: Read>(input: R, transform: png::Transformations) -> Result<Vec<Vec<u8>>, String> {
+    let mut decoder = png::Decoder::new(input);
+    decoder.set_transformations(transform);
+    let mut reader = decoder.into_reader();
+    retry(|| reader.read_info().map(|_| ())).map_err(|err| err.to_string())?;
+    let mut frames = vec![];
+    loop {
+        let mut buf = vec![0; reader.output_buffer_size()];
+        match retry(|| reader.next_frame(&mut buf)) {
+            Ok(_) => frames.push(buf),
+            Err(png::DecodingError::Parameter(_)) => return Ok(frames),
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+}
+
+#[test]
+fn short_reads() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    for suite in TEST_SUITES.iter() {
+        for path in glob::glob(&format!("tests/{}/*.png", suite))
+            .unwrap()
+            .map(|r| r.unwrap())
+        {
+            let data = std::fs::read(&path).unwrap();
+            for &transform in [png::Transformations::IDENTITY, png::Transformations::EXPAND].iter()
+            {
+                let expected = decode_all(&data[..], transform);
+
+                let one_byte = decode_all(OneByte(&data), transform);
+                assert!(one_byte == expected, "{:?}", path);
+
+                let short = ShortReads {
+                    data: &data,
+                    rng: StdRng::seed_from_u64(rng.gen()),
+                };
+                let short = decode_all(short, transform);
+                assert!(short == expected, "{:?}", path);
+            }
+        }
+    }
+}